@@ -0,0 +1,399 @@
+//! Instruction table and operand encoding
+//!
+//! Registers are written `r0`-`r15` (or `r0`-`rf`), pairs `r0r1`-`rerf`,
+//! `p0`-`p7` or `0p`-`7p`; a plain expression also works for either. JCN
+//! takes a condition name or a 4-bit value.
+
+use std::fmt;
+
+use crate::error::short_page;
+use crate::expr::{evaluate, Symbols};
+use crate::AsmError;
+
+/// CPU the program is assembled for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Target {
+    /// 4004: the 46 MCS-4 instructions
+    #[default]
+    I4004,
+    /// 4040: adds HLT, BBS, LCR, OR4/5, AN6/7, DB0/1, SB0/1, EIN, DIN, RPM
+    I4040,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::I4004 => write!(f, "4004"),
+            Target::I4040 => write!(f, "4040"),
+        }
+    }
+}
+
+/// Operand layout of an instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Form {
+    /// No operands
+    Implied,
+    /// Register in OPA
+    Register,
+    /// Register pair in OPA bits 3-1, OPA bit 0 fixed by the opcode
+    Pair,
+    /// 4-bit immediate in OPA (LDM, BBL)
+    Nibble,
+    /// Pair then 8-bit data (FIM)
+    PairData,
+    /// Condition then address within the page (JCN)
+    Condition,
+    /// Register then address within the page (ISZ)
+    RegisterAddress,
+    /// 12-bit address (JUN, JMS)
+    Long,
+}
+
+impl Form {
+    /// Encoded length in bytes
+    pub fn length(self) -> u16 {
+        match self {
+            Form::PairData | Form::Condition | Form::RegisterAddress | Form::Long => 2,
+            _ => 1,
+        }
+    }
+
+    fn operand_count(self) -> usize {
+        match self {
+            Form::Implied => 0,
+            Form::Register | Form::Pair | Form::Nibble | Form::Long => 1,
+            Form::PairData | Form::Condition | Form::RegisterAddress => 2,
+        }
+    }
+}
+
+/// An instruction's opcode (OPR, or the whole byte for implied forms) and
+/// operand layout
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Opcode {
+    pub code: u8,
+    pub form: Form,
+    /// Only on the 4040
+    pub ext: bool,
+}
+
+const fn op(code: u8, form: Form) -> Opcode {
+    Opcode {
+        code,
+        form,
+        ext: false,
+    }
+}
+
+const fn ext(code: u8) -> Opcode {
+    Opcode {
+        code,
+        form: Form::Implied,
+        ext: true,
+    }
+}
+
+/// Look up a lower-case mnemonic
+pub(crate) fn lookup(mnemonic: &str) -> Option<Opcode> {
+    use Form::*;
+    Some(match mnemonic {
+        "nop" => op(0x00, Implied),
+        "hlt" => ext(0x01),
+        "bbs" => ext(0x02),
+        "lcr" => ext(0x03),
+        "or4" => ext(0x04),
+        "or5" => ext(0x05),
+        "an6" => ext(0x06),
+        "an7" => ext(0x07),
+        "db0" => ext(0x08),
+        "db1" => ext(0x09),
+        "sb0" => ext(0x0A),
+        "sb1" => ext(0x0B),
+        "ein" => ext(0x0C),
+        "din" => ext(0x0D),
+        "rpm" => ext(0x0E),
+        "jcn" => op(0x10, Condition),
+        "fim" => op(0x20, PairData),
+        "src" => op(0x21, Pair),
+        "fin" => op(0x30, Pair),
+        "jin" => op(0x31, Pair),
+        "jun" => op(0x40, Long),
+        "jms" => op(0x50, Long),
+        "inc" => op(0x60, Register),
+        "isz" => op(0x70, RegisterAddress),
+        "add" => op(0x80, Register),
+        "sub" => op(0x90, Register),
+        "ld" => op(0xA0, Register),
+        "xch" => op(0xB0, Register),
+        "bbl" => op(0xC0, Nibble),
+        "ldm" => op(0xD0, Nibble),
+        "wrm" => op(0xE0, Implied),
+        "wmp" => op(0xE1, Implied),
+        "wrr" => op(0xE2, Implied),
+        "wpm" => op(0xE3, Implied),
+        "wr0" => op(0xE4, Implied),
+        "wr1" => op(0xE5, Implied),
+        "wr2" => op(0xE6, Implied),
+        "wr3" => op(0xE7, Implied),
+        "sbm" => op(0xE8, Implied),
+        "rdm" => op(0xE9, Implied),
+        "rdr" => op(0xEA, Implied),
+        "adm" => op(0xEB, Implied),
+        "rd0" => op(0xEC, Implied),
+        "rd1" => op(0xED, Implied),
+        "rd2" => op(0xEE, Implied),
+        "rd3" => op(0xEF, Implied),
+        "clb" => op(0xF0, Implied),
+        "clc" => op(0xF1, Implied),
+        "iac" => op(0xF2, Implied),
+        "cmc" => op(0xF3, Implied),
+        "cma" => op(0xF4, Implied),
+        "ral" => op(0xF5, Implied),
+        "rar" => op(0xF6, Implied),
+        "tcc" => op(0xF7, Implied),
+        "dac" => op(0xF8, Implied),
+        "tcs" => op(0xF9, Implied),
+        "stc" => op(0xFA, Implied),
+        "daa" => op(0xFB, Implied),
+        "kbp" => op(0xFC, Implied),
+        "dcl" => op(0xFD, Implied),
+        _ => return None,
+    })
+}
+
+/// Operand context for encoding one statement
+pub(crate) struct Operands<'a> {
+    pub line: usize,
+    /// Address of the instruction
+    pub pc: u16,
+    pub symbols: &'a Symbols,
+}
+
+impl Operands<'_> {
+    fn value(&self, text: &str, max: i64) -> Result<u8, AsmError> {
+        let value = evaluate(text, self.line, self.pc, self.symbols)?;
+        if !(0..=max).contains(&value) {
+            return Err(AsmError::ValueOutOfRange {
+                line: self.line,
+                value,
+                max,
+            });
+        }
+        Ok(value as u8)
+    }
+
+    fn register(&self, text: &str) -> Result<u8, AsmError> {
+        match register_name(text) {
+            Some(reg) => Ok(reg),
+            None => self.value(text, 15),
+        }
+    }
+
+    fn pair(&self, text: &str) -> Result<u8, AsmError> {
+        match pair_name(text) {
+            Some(pair) => Ok(pair),
+            None => self.value(text, 7),
+        }
+    }
+
+    fn condition(&self, text: &str) -> Result<u8, AsmError> {
+        match condition_name(text) {
+            Some(cond) => Ok(cond),
+            None => self.value(text, 15),
+        }
+    }
+
+    /// Target of a JCN/ISZ, which must lie in the page the jump reaches
+    fn short_target(&self, text: &str) -> Result<u8, AsmError> {
+        let target = self.address(text)?;
+        if target >> 8 != short_page(self.pc) {
+            return Err(AsmError::PageCrossing {
+                line: self.line,
+                address: self.pc,
+                target,
+            });
+        }
+        Ok(target as u8)
+    }
+
+    /// FIM data: 8 bits, or the in-page part of a program address
+    fn low_byte(&self, text: &str) -> Result<u8, AsmError> {
+        let value = evaluate(text, self.line, self.pc, self.symbols)?;
+        if !(0..=0xFFF).contains(&value) {
+            return Err(AsmError::ValueOutOfRange {
+                line: self.line,
+                value,
+                max: 0xFFF,
+            });
+        }
+        Ok(value as u8)
+    }
+
+    fn address(&self, text: &str) -> Result<u16, AsmError> {
+        let value = evaluate(text, self.line, self.pc, self.symbols)?;
+        if !(0..=0xFFF).contains(&value) {
+            return Err(AsmError::ValueOutOfRange {
+                line: self.line,
+                value,
+                max: 0xFFF,
+            });
+        }
+        Ok(value as u16)
+    }
+
+    /// Encode `opcode` with its operand texts
+    pub fn encode(
+        &self,
+        mnemonic: &str,
+        opcode: Opcode,
+        args: &[String],
+    ) -> Result<Vec<u8>, AsmError> {
+        let form = opcode.form;
+        if args.len() != form.operand_count() {
+            return Err(AsmError::Syntax {
+                line: self.line,
+                message: format!(
+                    "'{}' takes {} operand{}, found {}",
+                    mnemonic,
+                    form.operand_count(),
+                    if form.operand_count() == 1 { "" } else { "s" },
+                    args.len()
+                ),
+            });
+        }
+        let code = opcode.code;
+        Ok(match form {
+            Form::Implied => vec![code],
+            Form::Register => vec![code | self.register(&args[0])?],
+            Form::Pair => vec![code | self.pair(&args[0])? << 1],
+            Form::Nibble => vec![code | self.value(&args[0], 15)?],
+            Form::PairData => vec![code | self.pair(&args[0])? << 1, self.low_byte(&args[1])?],
+            Form::Condition => vec![
+                code | self.condition(&args[0])?,
+                self.short_target(&args[1])?,
+            ],
+            Form::RegisterAddress => vec![
+                code | self.register(&args[0])?,
+                self.short_target(&args[1])?,
+            ],
+            Form::Long => {
+                let addr = self.address(&args[0])?;
+                vec![code | (addr >> 8) as u8, addr as u8]
+            }
+        })
+    }
+}
+
+/// `r0`-`r15`, or `r0`-`rf` with a hex digit
+fn register_name(text: &str) -> Option<u8> {
+    let lower = text.trim().to_ascii_lowercase();
+    let digits = lower.strip_prefix('r')?;
+    match digits.len() {
+        1 => u8::from_str_radix(digits, 16).ok(),
+        2 if digits.starts_with('1') => digits.parse().ok().filter(|&reg| reg < 16),
+        _ => None,
+    }
+}
+
+/// `r0r1`-`rerf`, `p0`-`p7` or `0p`-`7p`
+fn pair_name(text: &str) -> Option<u8> {
+    let lower = text.trim().to_ascii_lowercase();
+    if let Some(n) = lower.strip_prefix('p').or_else(|| lower.strip_suffix('p')) {
+        return n.parse().ok().filter(|&pair: &u8| pair < 8);
+    }
+    let even = lower.get(..2).and_then(register_name)?;
+    let odd = lower.get(2..).and_then(register_name)?;
+    (even % 2 == 0 && odd == even + 1).then_some(even / 2)
+}
+
+/// JCN conditions: the i400x names and the Intel ones
+fn condition_name(text: &str) -> Option<u8> {
+    Some(match text.trim().to_ascii_lowercase().as_str() {
+        "t" | "tz" => 0x1,
+        "c" | "cn" => 0x2,
+        "z" | "az" => 0x4,
+        "nt" | "tn" => 0x9,
+        "nc" | "cz" => 0xA,
+        "nz" | "an" => 0xC,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(mnemonic: &str, args: &[&str]) -> Result<Vec<u8>, AsmError> {
+        let symbols = Symbols::new();
+        let ops = Operands {
+            line: 1,
+            pc: 0x100,
+            symbols: &symbols,
+        };
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        ops.encode(mnemonic, lookup(mnemonic).unwrap(), &args)
+    }
+
+    #[test]
+    fn test_register_and_pair_names() {
+        assert_eq!(register_name("r0"), Some(0));
+        assert_eq!(register_name("R15"), Some(15));
+        assert_eq!(register_name("rb"), Some(11));
+        assert_eq!(register_name("r16"), None);
+        assert_eq!(pair_name("rerf"), Some(7));
+        assert_eq!(pair_name("r2r3"), Some(1));
+        assert_eq!(pair_name("r1r2"), None);
+        assert_eq!(pair_name("P3"), Some(3));
+        assert_eq!(pair_name("6p"), Some(6));
+    }
+
+    #[test]
+    fn test_encode_forms() {
+        assert_eq!(encode("nop", &[]), Ok(vec![0x00]));
+        assert_eq!(encode("xch", &["r5"]), Ok(vec![0xB5]));
+        assert_eq!(encode("add", &["4"]), Ok(vec![0x84]));
+        assert_eq!(encode("src", &["r0r1"]), Ok(vec![0x21]));
+        assert_eq!(encode("fin", &["p1"]), Ok(vec![0x32]));
+        assert_eq!(encode("jin", &["r8r9"]), Ok(vec![0x39]));
+        assert_eq!(encode("ldm", &["0fh"]), Ok(vec![0xDF]));
+        assert_eq!(encode("fim", &["rarb", "0abh"]), Ok(vec![0x2A, 0xAB]));
+        assert_eq!(encode("jcn", &["nz", "113h"]), Ok(vec![0x1C, 0x13]));
+        assert_eq!(encode("jcn", &["6", "$"]), Ok(vec![0x16, 0x00]));
+        assert_eq!(encode("isz", &["r1", "1a6h"]), Ok(vec![0x71, 0xA6]));
+        assert_eq!(encode("jms", &["0abch"]), Ok(vec![0x5A, 0xBC]));
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        assert_eq!(
+            encode("ldm", &["16"]),
+            Err(AsmError::ValueOutOfRange {
+                line: 1,
+                value: 16,
+                max: 15
+            })
+        );
+        assert_eq!(
+            encode("jun", &["1000h"]),
+            Err(AsmError::ValueOutOfRange {
+                line: 1,
+                value: 0x1000,
+                max: 0xFFF
+            })
+        );
+        assert!(matches!(encode("xch", &[]), Err(AsmError::Syntax { .. })));
+        assert_eq!(
+            encode("jcn", &["z", "0ffh"]),
+            Err(AsmError::PageCrossing {
+                line: 1,
+                address: 0x100,
+                target: 0xFF
+            })
+        );
+        assert!(matches!(
+            encode("fim", &["8", "0"]),
+            Err(AsmError::ValueOutOfRange { .. })
+        ));
+    }
+}
@@ -0,0 +1,146 @@
+//! Assembly errors and warnings, each tied to the source line that caused it
+
+use std::fmt;
+
+use crate::Target;
+
+/// Error raised while assembling a program
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AsmError {
+    /// A statement or operand is malformed
+    Syntax { line: usize, message: String },
+    /// Not an instruction or directive
+    UnknownMnemonic { line: usize, mnemonic: String },
+    /// An instruction the target CPU does not have
+    Unsupported {
+        line: usize,
+        mnemonic: String,
+        target: Target,
+    },
+    /// A symbol used but never defined
+    UndefinedSymbol { line: usize, name: String },
+    /// A label defined twice
+    DuplicateSymbol {
+        line: usize,
+        name: String,
+        first: usize,
+    },
+    /// An operand does not fit its field
+    ValueOutOfRange { line: usize, value: i64, max: i64 },
+    /// Code placed outside the 4 KB program space
+    AddressOutOfRange { line: usize, address: i64 },
+    /// Two statements place bytes at the same address
+    Overlap { line: usize, address: u16 },
+    /// A JCN/ISZ target outside the page the jump can reach
+    PageCrossing {
+        line: usize,
+        address: u16,
+        target: u16,
+    },
+    /// An included file could not be read
+    Include {
+        line: usize,
+        path: String,
+        message: String,
+    },
+    /// An error inside an included file
+    InFile { file: String, error: Box<AsmError> },
+}
+
+impl AsmError {
+    /// Source line the error refers to (1-based)
+    pub fn line(&self) -> usize {
+        match self {
+            AsmError::Syntax { line, .. }
+            | AsmError::UnknownMnemonic { line, .. }
+            | AsmError::Unsupported { line, .. }
+            | AsmError::UndefinedSymbol { line, .. }
+            | AsmError::DuplicateSymbol { line, .. }
+            | AsmError::ValueOutOfRange { line, .. }
+            | AsmError::AddressOutOfRange { line, .. }
+            | AsmError::Overlap { line, .. }
+            | AsmError::PageCrossing { line, .. }
+            | AsmError::Include { line, .. } => *line,
+            AsmError::InFile { error, .. } => error.line(),
+        }
+    }
+
+    /// Included file the error is in; `None` for the main source
+    pub fn file(&self) -> Option<&str> {
+        match self {
+            AsmError::InFile { file, .. } => Some(file),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            AsmError::UnknownMnemonic { line, mnemonic } => {
+                write!(f, "line {}: unknown instruction or directive '{}'", line, mnemonic)
+            }
+            AsmError::Unsupported { line, mnemonic, target } => {
+                write!(f, "line {}: '{}' is not a {} instruction", line, mnemonic, target)
+            }
+            AsmError::UndefinedSymbol { line, name } => {
+                write!(f, "line {}: undefined symbol '{}'", line, name)
+            }
+            AsmError::DuplicateSymbol { line, name, first } => write!(
+                f,
+                "line {}: '{}' is already defined on line {}",
+                line, name, first
+            ),
+            AsmError::ValueOutOfRange { line, value, max } => {
+                write!(f, "line {}: value {} is outside 0-{}", line, value, max)
+            }
+            AsmError::AddressOutOfRange { line, address } => write!(
+                f,
+                "line {}: address 0x{:X} is outside program memory (0x000-0xFFF)",
+                line, address
+            ),
+            AsmError::Overlap { line, address } => {
+                write!(f, "line {}: address 0x{:03X} is already occupied", line, address)
+            }
+            AsmError::PageCrossing { line, address, target } => write!(
+                f,
+                "line {}: target 0x{:03X} is not in page {:X}, the only page reachable from 0x{:03X}",
+                line,
+                target,
+                short_page(*address),
+                address
+            ),
+            AsmError::Include { line, path, message } => {
+                write!(f, "line {}: cannot include '{}': {}", line, path, message)
+            }
+            AsmError::InFile { file, error } => write!(f, "{}: {}", file, error),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Page a two-byte short jump at `address` can reach: that of the byte
+/// after it, so a jump in the last two bytes of a page lands in the next
+pub(crate) fn short_page(address: u16) -> u16 {
+    ((address + 2) & 0x0FFF) >> 8
+}
+
+/// Something that assembles but is probably wrong
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+    /// Included file; `None` for the main source
+    pub file: Option<String>,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file)?;
+        }
+        write!(f, "line {}: warning: {}", self.line, self.message)
+    }
+}
@@ -0,0 +1,345 @@
+//! Operand expressions
+//!
+//! Numbers follow the Intel convention of a radix suffix (`0abh`, `1010b`,
+//! `17o`/`17q`, `99d`) and also accept `0x`/`0b` prefixes. Hex constants
+//! written with a suffix must start with a digit so `abh` stays a symbol.
+//! `'A'` is a character constant and `$` (or a leading `*`) the address of
+//! the current statement.
+//!
+//! Operators, loosest first: `||`, `&&`, `|`, `^`, `&`, `==` `!=`,
+//! `<` `<=` `>` `>=`, `<<` `>>`, `+` `-`, `*` `/` `%`, then unary `-` `~` `!`.
+//! Comparisons and logical operators give 1 or 0.
+
+use std::collections::BTreeMap;
+
+use crate::AsmError;
+
+/// How a symbol was defined
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SymbolKind {
+    Label,
+    /// `equ`: fixed once defined
+    Equ,
+    /// `set`: may be redefined
+    Set,
+}
+
+/// A defined symbol and where it was defined
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Symbol {
+    pub value: i64,
+    pub line: usize,
+    pub kind: SymbolKind,
+}
+
+/// Symbol table shared by both passes
+pub(crate) type Symbols = BTreeMap<String, Symbol>;
+
+/// Parse a numeric or character constant
+pub(crate) fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    let bytes = lower.as_bytes();
+    if bytes.len() == 3 && bytes[0] == b'\'' && bytes[2] == b'\'' {
+        return Some(text.as_bytes()[1] as i64);
+    }
+    if !bytes.first()?.is_ascii_digit() {
+        return None;
+    }
+
+    let (digits, radix) = if let Some(hex) = lower.strip_prefix("0x") {
+        (hex, 16)
+    } else if let Some(bin) = lower
+        .strip_prefix("0b")
+        .filter(|b| !b.is_empty() && !lower.ends_with('h'))
+    {
+        (bin, 2)
+    } else if let Some(hex) = lower.strip_suffix('h') {
+        (hex, 16)
+    } else if let Some(oct) = lower.strip_suffix('o').or_else(|| lower.strip_suffix('q')) {
+        (oct, 8)
+    } else if let Some(bin) = lower
+        .strip_suffix('b')
+        .filter(|b| b.bytes().all(|c| c == b'0' || c == b'1'))
+    {
+        (bin, 2)
+    } else if let Some(dec) = lower.strip_suffix('d') {
+        (dec, 10)
+    } else {
+        (lower.as_str(), 10)
+    };
+    i64::from_str_radix(digits, radix).ok()
+}
+
+/// Can `c` appear in a symbol name?
+pub(crate) fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+/// Is `text` usable as a symbol name?
+pub(crate) fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(is_name_char)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 21] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "|", "^", "&", "<", ">", "+", "-", "*", "/",
+    "%", "~", "!", "$",
+];
+
+/// Binary operators by binding strength, loosest first
+const LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+fn tokenize(text: &str, line: usize) -> Result<Vec<Token>, AsmError> {
+    let syntax = |message: String| AsmError::Syntax { line, message };
+    let mut tokens = Vec::new();
+    let mut rest = text.trim();
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = rest.trim_start();
+            continue;
+        }
+        let len = if c == '\'' {
+            rest.char_indices()
+                .nth(2)
+                .filter(|&(_, q)| q == '\'')
+                .map(|(i, _)| i + 1)
+                .unwrap_or(0)
+        } else if is_name_char(c) {
+            rest.find(|c| !is_name_char(c)).unwrap_or(rest.len())
+        } else {
+            0
+        };
+
+        if len > 0 {
+            let word = &rest[..len];
+            let token = match parse_number(word) {
+                Some(value) => Token::Number(value),
+                None if is_identifier(word) => Token::Name(word.to_string()),
+                None => return Err(syntax(format!("invalid number '{}'", word))),
+            };
+            tokens.push(token);
+            rest = &rest[len..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else if c == '(' || c == ')' {
+            tokens.push(Token::Op(if c == '(' { "(" } else { ")" }));
+            rest = &rest[1..];
+        } else {
+            return Err(syntax(format!("unexpected '{}' in expression", c)));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Evaluator<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    line: usize,
+    pc: u16,
+    symbols: &'a Symbols,
+}
+
+impl Evaluator<'_> {
+    fn syntax(&self, message: String) -> AsmError {
+        AsmError::Syntax {
+            line: self.line,
+            message,
+        }
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, AsmError> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(op) = self.peek_op().filter(|op| LEVELS[level].contains(op)) {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = match op {
+                "||" => (lhs != 0 || rhs != 0) as i64,
+                "&&" => (lhs != 0 && rhs != 0) as i64,
+                "|" => lhs | rhs,
+                "^" => lhs ^ rhs,
+                "&" => lhs & rhs,
+                "==" => (lhs == rhs) as i64,
+                "!=" => (lhs != rhs) as i64,
+                "<" => (lhs < rhs) as i64,
+                "<=" => (lhs <= rhs) as i64,
+                ">" => (lhs > rhs) as i64,
+                ">=" => (lhs >= rhs) as i64,
+                "<<" => lhs.wrapping_shl(rhs as u32),
+                ">>" => lhs.wrapping_shr(rhs as u32),
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "*" => lhs.wrapping_mul(rhs),
+                "/" | "%" if rhs == 0 => return Err(self.syntax("division by zero".into())),
+                "/" => lhs / rhs,
+                _ => lhs % rhs,
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<i64, AsmError> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Name(name)) => {
+                self.symbols
+                    .get(&name)
+                    .map(|sym| sym.value)
+                    .ok_or(AsmError::UndefinedSymbol {
+                        line: self.line,
+                        name,
+                    })
+            }
+            Some(Token::Op("$")) => Ok(self.pc as i64),
+            Some(Token::Op("*")) => Ok(self.pc as i64),
+            Some(Token::Op("-")) => Ok(self.unary()?.wrapping_neg()),
+            Some(Token::Op("+")) => self.unary(),
+            Some(Token::Op("~")) => Ok(!self.unary()?),
+            Some(Token::Op("!")) => Ok((self.unary()? == 0) as i64),
+            Some(Token::Op("(")) => {
+                let value = self.binary(0)?;
+                match self.tokens.get(self.pos) {
+                    Some(Token::Op(")")) => {
+                        self.pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err(self.syntax("missing ')'".into())),
+                }
+            }
+            Some(Token::Op(op)) => Err(self.syntax(format!("unexpected '{}' in expression", op))),
+            None => Err(self.syntax("expression expected".into())),
+        }
+    }
+}
+
+/// Evaluate an operand expression at program counter `pc`
+pub(crate) fn evaluate(
+    text: &str,
+    line: usize,
+    pc: u16,
+    symbols: &Symbols,
+) -> Result<i64, AsmError> {
+    let tokens = tokenize(text, line)?;
+    let mut eval = Evaluator {
+        tokens,
+        pos: 0,
+        line,
+        pc,
+        symbols,
+    };
+    let value = eval.binary(0)?;
+    match eval.tokens.get(eval.pos) {
+        None => Ok(value),
+        Some(_) => Err(AsmError::Syntax {
+            line,
+            message: format!("cannot evaluate '{}'", text.trim()),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str) -> Result<i64, AsmError> {
+        let mut symbols = Symbols::new();
+        symbols.insert(
+            "loop".into(),
+            Symbol {
+                value: 0x123,
+                line: 1,
+                kind: SymbolKind::Label,
+            },
+        );
+        symbols.insert(
+            "n".into(),
+            Symbol {
+                value: 4,
+                line: 1,
+                kind: SymbolKind::Equ,
+            },
+        );
+        evaluate(text, 2, 0x40, &symbols)
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(parse_number("10"), Some(10));
+        assert_eq!(parse_number("012h"), Some(0x12));
+        assert_eq!(parse_number("0abh"), Some(0xAB));
+        assert_eq!(parse_number("0x1F"), Some(0x1F));
+        assert_eq!(parse_number("1010b"), Some(10));
+        assert_eq!(parse_number("0b101"), Some(5));
+        assert_eq!(parse_number("0bh"), Some(0x0B));
+        assert_eq!(parse_number("17o"), Some(15));
+        assert_eq!(parse_number("'A'"), Some(0x41));
+        assert_eq!(parse_number("abh"), None);
+        assert_eq!(parse_number("12g"), None);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(eval("loop"), Ok(0x123));
+        assert_eq!(eval("$"), Ok(0x40));
+        assert_eq!(eval("*"), Ok(0x40));
+        assert_eq!(
+            eval("missing"),
+            Err(AsmError::UndefinedSymbol {
+                line: 2,
+                name: "missing".into()
+            })
+        );
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("loop >> 8"), Ok(1));
+        assert_eq!(eval("loop & 0ffh"), Ok(0x23));
+        assert_eq!(eval("-n + 10"), Ok(6));
+        assert_eq!(eval("~0 & 0fh"), Ok(15));
+        assert_eq!(eval("$ + 2 - 1"), Ok(0x41));
+        assert_eq!(eval("n == 4 && loop > 100h"), Ok(1));
+        assert_eq!(eval("!n || 7 % 4 != 3"), Ok(0));
+        assert_eq!(eval("1 << n | 1"), Ok(17));
+        assert_eq!(eval("'A' + 1"), Ok(0x42));
+        assert!(matches!(eval("4 / 0"), Err(AsmError::Syntax { .. })));
+        assert!(matches!(eval("(1 + 2"), Err(AsmError::Syntax { .. })));
+        assert!(matches!(eval("1 2"), Err(AsmError::Syntax { .. })));
+        assert!(matches!(eval("12g"), Err(AsmError::Syntax { .. })));
+    }
+}
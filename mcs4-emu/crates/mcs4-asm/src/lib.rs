@@ -0,0 +1,792 @@
+//! MCS-4/MCS-40 Assembler
+//!
+//! Two-pass assembler for 4004 and 4040 programs in the classic Intel/i400x
+//! syntax:
+//!
+//! ```text
+//!         org     0
+//! count   equ     4               ; constants may refer back to symbols
+//! start:  fim     r0r1, table
+//!         src     r0r1
+//! loop:   ldm     count - 1
+//!         jcn     nz, loop        ; t/nt/c/nc/z/nz or a 4-bit value
+//!         isz     r1, loop
+//!         jun     start
+//!         if      count > 2
+//! table:  db      1, 2, 'A'
+//!         endif
+//! ```
+//!
+//! Includes and macros are expanded first; pass one then assigns every
+//! label an address and pass two encodes. The result is a [`Program`]
+//! that writes raw binary, Intel HEX, a symbol file and a line map.
+//!
+//! Directives: `org`, `db`, `ds`, `end`, `equ`, `set`, `if`/`ifdef`/`ifndef`
+//! /`else`/`endif`, `include`, `macro`/`local`/`endm`. Operands of `org`,
+//! `ds`, `equ`, `set` and `if` may only use symbols defined above them.
+//!
+//! JCN and ISZ can only reach the page of the byte after them, so a target
+//! anywhere else is an error. FIN and JIN at the last byte of a page, and
+//! `db` tables that straddle a page boundary, assemble with a warning.
+
+mod encode;
+mod error;
+mod expr;
+mod output;
+mod parser;
+mod source;
+
+pub use encode::Target;
+pub use error::{AsmError, Warning};
+pub use output::{Program, SourceLine};
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use encode::{lookup, Form, Operands};
+use expr::{evaluate, Symbol, SymbolKind, Symbols};
+use parser::Line;
+use source::Preprocessor;
+
+/// Highest program address
+const ADDRESS_LIMIT: i64 = 0xFFF;
+
+/// Assemble 4004 source
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    Assembler::new(Target::I4004).assemble(source)
+}
+
+/// Two-pass assembler for one target CPU
+#[derive(Clone, Debug, Default)]
+pub struct Assembler {
+    target: Target,
+    include_dirs: Vec<PathBuf>,
+}
+
+impl Assembler {
+    pub fn new(target: Target) -> Self {
+        Self {
+            target,
+            include_dirs: Vec::new(),
+        }
+    }
+
+    /// Also search `dir` for included files
+    pub fn include_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.include_dirs.push(dir.into());
+        self
+    }
+
+    /// Assemble `source`; includes resolve against the search directories
+    /// and then the working directory
+    pub fn assemble(&self, source: &str) -> Result<Program, AsmError> {
+        self.assemble_at(source, None)
+    }
+
+    /// Assemble `source` read from `path`, resolving includes against its
+    /// directory first
+    pub fn assemble_from(&self, source: &str, path: &Path) -> Result<Program, AsmError> {
+        self.assemble_at(source, path.parent())
+    }
+
+    fn assemble_at(&self, source: &str, dir: Option<&Path>) -> Result<Program, AsmError> {
+        let lines = Preprocessor::new(&self.include_dirs).run(source, dir)?;
+
+        let mut symbols = Symbols::new();
+        Pass::new(self.target, &mut symbols, None).run(&lines)?;
+        let mut program = Program::default();
+        Pass::new(self.target, &mut symbols, Some(&mut program)).run(&lines)?;
+        program.symbols = symbols
+            .into_iter()
+            .map(|(name, sym)| (name, sym.value))
+            .collect();
+        Ok(program)
+    }
+}
+
+/// An open `if` block
+struct Condition {
+    /// The `if` line, for an unterminated block
+    at: Line,
+    /// Was the enclosing block assembling?
+    parent: bool,
+    /// Is this branch assembling?
+    active: bool,
+    /// Has a branch been taken?
+    taken: bool,
+    in_else: bool,
+}
+
+/// One walk over the source. Pass one (no program) defines symbols; pass
+/// two re-runs `set` in order and encodes into the program.
+struct Pass<'a> {
+    target: Target,
+    symbols: &'a mut Symbols,
+    program: Option<&'a mut Program>,
+    /// Pass two: symbols defined so far, for `ifdef`
+    seen: BTreeSet<String>,
+    conditions: Vec<Condition>,
+    pc: i64,
+}
+
+impl<'a> Pass<'a> {
+    fn new(target: Target, symbols: &'a mut Symbols, program: Option<&'a mut Program>) -> Self {
+        Self {
+            target,
+            symbols,
+            program,
+            seen: BTreeSet::new(),
+            conditions: Vec::new(),
+            pc: 0,
+        }
+    }
+
+    fn run(mut self, lines: &[Line]) -> Result<(), AsmError> {
+        for line in lines {
+            match self.line(line) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => return Err(line.locate(err)),
+            }
+        }
+        match self.conditions.pop() {
+            Some(open) => Err(open.at.locate(AsmError::Syntax {
+                line: open.at.number,
+                message: "if without endif".into(),
+            })),
+            None => Ok(()),
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.conditions.last().is_none_or(|cond| cond.active)
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        match self.program {
+            Some(_) => self.seen.contains(name),
+            None => self.symbols.contains_key(name),
+        }
+    }
+
+    /// Assemble one line; `false` stops at `end`
+    fn line(&mut self, line: &Line) -> Result<bool, AsmError> {
+        let Some(mnemonic) = line.mnemonic.as_deref() else {
+            if let Some(name) = &line.label {
+                if self.active() {
+                    self.define(name, self.pc, line.number, SymbolKind::Label)?;
+                }
+            }
+            return Ok(true);
+        };
+        if self.conditional(mnemonic, line)? || !self.active() {
+            return Ok(true);
+        }
+
+        match mnemonic {
+            "equ" | "set" => {
+                let Some(name) = &line.label else {
+                    return Err(AsmError::Syntax {
+                        line: line.number,
+                        message: format!("{} needs a name", mnemonic),
+                    });
+                };
+                let value = evaluate(single(line)?, line.number, self.pc as u16, self.symbols)?;
+                let kind = if mnemonic == "equ" {
+                    SymbolKind::Equ
+                } else {
+                    SymbolKind::Set
+                };
+                self.define(name, value, line.number, kind)?;
+                return Ok(true);
+            }
+            "end" => return Ok(false),
+            _ => {}
+        }
+
+        if let Some(name) = &line.label {
+            self.define(name, self.pc, line.number, SymbolKind::Label)?;
+        }
+        let statement = self.statement(line, mnemonic)?;
+        if self.program.is_some() {
+            self.emit(line, mnemonic)?;
+        }
+        match statement {
+            Statement::Origin(addr) => self.pc = addr,
+            Statement::Bytes(len) => self.pc += len,
+        }
+        Ok(true)
+    }
+
+    fn define(
+        &mut self,
+        name: &str,
+        value: i64,
+        line: usize,
+        kind: SymbolKind,
+    ) -> Result<(), AsmError> {
+        if self.program.is_some() {
+            // Pass two: labels and equates already hold their values
+            self.seen.insert(name.to_string());
+            if kind == SymbolKind::Set {
+                self.symbols
+                    .insert(name.to_string(), Symbol { value, line, kind });
+            }
+            return Ok(());
+        }
+        match self.symbols.get(name) {
+            Some(sym) if !(sym.kind == SymbolKind::Set && kind == SymbolKind::Set) => {
+                Err(AsmError::DuplicateSymbol {
+                    line,
+                    name: name.to_string(),
+                    first: sym.line,
+                })
+            }
+            _ => {
+                self.symbols
+                    .insert(name.to_string(), Symbol { value, line, kind });
+                Ok(())
+            }
+        }
+    }
+
+    /// Handle `if`/`ifdef`/`ifndef`/`else`/`endif`; `false` for anything else
+    fn conditional(&mut self, mnemonic: &str, line: &Line) -> Result<bool, AsmError> {
+        let syntax = |message: &str| AsmError::Syntax {
+            line: line.number,
+            message: message.into(),
+        };
+        match mnemonic {
+            "if" | "ifdef" | "ifndef" => {
+                let parent = self.active();
+                let active = parent
+                    && match mnemonic {
+                        "if" => {
+                            evaluate(single(line)?, line.number, self.pc as u16, self.symbols)? != 0
+                        }
+                        "ifdef" => self.is_defined(single(line)?),
+                        _ => !self.is_defined(single(line)?),
+                    };
+                let at = Line {
+                    label: None,
+                    mnemonic: None,
+                    operands: Vec::new(),
+                    ..line.clone()
+                };
+                self.conditions.push(Condition {
+                    at,
+                    parent,
+                    active,
+                    taken: active,
+                    in_else: false,
+                });
+            }
+            "else" => {
+                let cond = self
+                    .conditions
+                    .last_mut()
+                    .ok_or_else(|| syntax("else without if"))?;
+                if cond.in_else {
+                    return Err(syntax("second else for one if"));
+                }
+                cond.active = cond.parent && !cond.taken;
+                cond.in_else = true;
+            }
+            "endif" => {
+                self.conditions
+                    .pop()
+                    .ok_or_else(|| syntax("endif without if"))?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// How a statement moves the location counter
+    fn statement(&self, line: &Line, mnemonic: &str) -> Result<Statement, AsmError> {
+        let number = line.number;
+        let pc = self.pc;
+        let statement = match mnemonic {
+            "org" => Statement::Origin(evaluate(single(line)?, number, pc as u16, self.symbols)?),
+            "ds" => Statement::Bytes(evaluate(single(line)?, number, pc as u16, self.symbols)?),
+            "db" => Statement::Bytes(line.operands.iter().map(|op| data_length(op)).sum()),
+            _ => {
+                let opcode = lookup(mnemonic).ok_or_else(|| AsmError::UnknownMnemonic {
+                    line: number,
+                    mnemonic: mnemonic.to_string(),
+                })?;
+                if opcode.ext && self.target != Target::I4040 {
+                    return Err(AsmError::Unsupported {
+                        line: number,
+                        mnemonic: mnemonic.to_string(),
+                        target: self.target,
+                    });
+                }
+                Statement::Bytes(opcode.form.length() as i64)
+            }
+        };
+        match statement {
+            Statement::Origin(addr) if !(0..=ADDRESS_LIMIT).contains(&addr) => {
+                Err(AsmError::AddressOutOfRange {
+                    line: number,
+                    address: addr,
+                })
+            }
+            Statement::Bytes(len) if len < 0 => Err(AsmError::ValueOutOfRange {
+                line: number,
+                value: len,
+                max: ADDRESS_LIMIT,
+            }),
+            Statement::Bytes(len) if len > 0 && pc + len - 1 > ADDRESS_LIMIT => {
+                Err(AsmError::AddressOutOfRange {
+                    line: number,
+                    address: pc + len - 1,
+                })
+            }
+            _ => Ok(statement),
+        }
+    }
+
+    /// Pass two: encode a statement and check its page constraints
+    fn emit(&mut self, line: &Line, mnemonic: &str) -> Result<(), AsmError> {
+        let pc = self.pc as u16;
+        let mut warning = None;
+        let bytes = match mnemonic {
+            "db" => {
+                let bytes = data_bytes(line, self.pc, self.symbols)?;
+                let last = pc + (bytes.len() as u16).saturating_sub(1);
+                if !bytes.is_empty() && last >> 8 != pc >> 8 {
+                    warning = Some(format!(
+                        "data at 0x{:03X}-0x{:03X} crosses into page {:X}; a FIN table cannot span pages",
+                        pc,
+                        last,
+                        last >> 8
+                    ));
+                }
+                bytes
+            }
+            _ => match lookup(mnemonic) {
+                Some(opcode) => {
+                    if opcode.form == Form::Pair
+                        && matches!(mnemonic, "fin" | "jin")
+                        && pc & 0xFF == 0xFF
+                    {
+                        warning = Some(format!(
+                            "{} at the last byte of page {:X} uses page {:X}",
+                            mnemonic.to_ascii_uppercase(),
+                            pc >> 8,
+                            ((pc + 1) & 0xFFF) >> 8
+                        ));
+                    }
+                    let ops = Operands {
+                        line: line.number,
+                        pc,
+                        symbols: self.symbols,
+                    };
+                    ops.encode(mnemonic, opcode, &line.operands)?
+                }
+                None => Vec::new(),
+            },
+        };
+
+        let program = self.program.as_deref_mut().expect("emit runs in pass two");
+        if let Some(message) = warning {
+            program.warnings.push(Warning {
+                file: line.file.as_deref().map(str::to_string),
+                line: line.number,
+                message,
+            });
+        }
+        if mnemonic != "db" && !bytes.is_empty() {
+            program.lines.insert(
+                pc,
+                SourceLine {
+                    file: line.file.as_deref().map(str::to_string),
+                    line: line.number,
+                },
+            );
+        }
+        for (i, byte) in bytes.into_iter().enumerate() {
+            let addr = pc + i as u16;
+            if program.bytes.insert(addr, byte).is_some() {
+                return Err(AsmError::Overlap {
+                    line: line.number,
+                    address: addr,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Effect of a statement on the location counter
+enum Statement {
+    Origin(i64),
+    Bytes(i64),
+}
+
+/// The one operand of a directive
+fn single(line: &Line) -> Result<&str, AsmError> {
+    match line.operands.as_slice() {
+        [operand] => Ok(operand),
+        _ => Err(AsmError::Syntax {
+            line: line.number,
+            message: format!(
+                "'{}' takes one operand",
+                line.mnemonic.as_deref().unwrap_or("")
+            ),
+        }),
+    }
+}
+
+/// Bytes a `db` operand occupies: one per character of a string
+fn data_length(operand: &str) -> i64 {
+    quoted(operand).map_or(1, |text| text.len() as i64)
+}
+
+fn quoted(operand: &str) -> Option<&str> {
+    let inner = operand.strip_prefix('"').and_then(|s| s.strip_suffix('"'));
+    inner.or_else(|| {
+        operand
+            .strip_prefix('\'')
+            .and_then(|s| s.strip_suffix('\''))
+    })
+}
+
+/// Encode `db` operands
+fn data_bytes(line: &Line, pc: i64, symbols: &Symbols) -> Result<Vec<u8>, AsmError> {
+    let mut bytes = Vec::new();
+    for operand in &line.operands {
+        match quoted(operand) {
+            Some(text) => bytes.extend(text.bytes()),
+            None => {
+                let value = evaluate(operand, line.number, pc as u16, symbols)?;
+                if !(-128..=255).contains(&value) {
+                    return Err(AsmError::ValueOutOfRange {
+                        line: line.number,
+                        value,
+                        max: 255,
+                    });
+                }
+                bytes.push(value as u8);
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_ASM: &str = include_str!("../../../../docs/emulators/sample.asm");
+    const SAMPLE_HEX: &str = include_str!("../../../../docs/emulators/sample.hex");
+
+    #[test]
+    fn test_sample_matches_hex() {
+        let program = assemble(SAMPLE_ASM).unwrap();
+        assert_eq!(program.len(), 170);
+        assert_eq!(program.get(0xA9), Some(0xA3));
+        assert_eq!(program.to_intel_hex(), SAMPLE_HEX);
+        assert_eq!(program.symbol("loc_jin"), Some(0x72));
+    }
+
+    #[test]
+    fn test_sample_disassembles_back() {
+        use mcs4_chips::disasm::{CpuType, Disassembler};
+
+        let rom = assemble(SAMPLE_ASM).unwrap().to_binary();
+        let lines = Disassembler::new(CpuType::I4004).disasm_all(&rom);
+        assert_eq!(lines[0].text(), "NOP");
+        assert_eq!(lines[2].text(), "JUN 0x0B");
+        assert!(lines.iter().all(|line| line.mnemonic != "DB"));
+    }
+
+    #[test]
+    fn test_labels_org_and_data() {
+        let source = "\
+            org 10h
+start:  jun later
+        db 1, 'A', \"hi\", start
+        ds 2
+later:  bbl 0
+        end
+        nop";
+        let program = assemble(source).unwrap();
+        assert_eq!(program.symbol("later"), Some(0x19));
+        let bytes: Vec<(u16, u8)> = program.iter().collect();
+        assert_eq!(
+            bytes,
+            [
+                (0x10, 0x40),
+                (0x11, 0x19),
+                (0x12, 1),
+                (0x13, b'A'),
+                (0x14, b'h'),
+                (0x15, b'i'),
+                (0x16, 0x10),
+                (0x19, 0xC0)
+            ]
+        );
+        assert_eq!(program.symbol_file(), "010 start\n019 later\n");
+        // Data is not in the line map
+        assert_eq!(program.line_map(), "010 2\n019 5\n");
+    }
+
+    #[test]
+    fn test_4040_instructions() {
+        assert_eq!(
+            assemble("hlt"),
+            Err(AsmError::Unsupported {
+                line: 1,
+                mnemonic: "hlt".into(),
+                target: Target::I4004
+            })
+        );
+        let program = Assembler::new(Target::I4040)
+            .assemble("din\nsb1\nbbs")
+            .unwrap();
+        assert_eq!(program.to_binary(), [0x0D, 0x0B, 0x02]);
+    }
+
+    #[test]
+    fn test_errors_carry_line_numbers() {
+        let err = assemble("nop\n  foo r1").unwrap_err();
+        assert_eq!(
+            err,
+            AsmError::UnknownMnemonic {
+                line: 2,
+                mnemonic: "foo".into()
+            }
+        );
+        assert_eq!(err.line(), 2);
+        assert_eq!(
+            assemble("a: nop\na: nop"),
+            Err(AsmError::DuplicateSymbol {
+                line: 2,
+                name: "a".into(),
+                first: 1
+            })
+        );
+        assert_eq!(
+            assemble("jun nowhere"),
+            Err(AsmError::UndefinedSymbol {
+                line: 1,
+                name: "nowhere".into()
+            })
+        );
+        assert_eq!(
+            assemble("org 5\nnop\norg 5\nnop"),
+            Err(AsmError::Overlap {
+                line: 4,
+                address: 5
+            })
+        );
+        assert_eq!(
+            assemble("org 0fffh\njun 0"),
+            Err(AsmError::AddressOutOfRange {
+                line: 2,
+                address: 0x1000
+            })
+        );
+        assert!(assemble("org 0fffh\nnop").is_ok());
+    }
+
+    #[test]
+    fn test_constants_and_expressions() {
+        let source = "\
+base    equ 20h
+n       set 1
+        ldm n + 2
+n       set n * 4
+        ldm n
+        fim p1, base | 3
+        jun base + (n << 4)
+        db base >> 4, -1, $ & 0fh";
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            program.to_binary(),
+            [0xD3, 0xD4, 0x22, 0x23, 0x40, 0x60, 0x02, 0xFF, 0x06]
+        );
+        assert_eq!(program.symbol("n"), Some(4));
+        assert_eq!(
+            assemble("x equ 1\nx equ 2"),
+            Err(AsmError::DuplicateSymbol {
+                line: 2,
+                name: "x".into(),
+                first: 1
+            })
+        );
+        assert_eq!(
+            assemble("x equ 1\nx set 2"),
+            Err(AsmError::DuplicateSymbol {
+                line: 2,
+                name: "x".into(),
+                first: 1
+            })
+        );
+        // Location-affecting operands cannot look ahead
+        assert_eq!(
+            assemble("org later\nlater: nop"),
+            Err(AsmError::UndefinedSymbol {
+                line: 1,
+                name: "later".into()
+            })
+        );
+    }
+
+    #[test]
+    fn test_conditional_assembly() {
+        let source = "\
+debug   equ 0
+        if debug
+        ldm 1
+        else
+        if debug == 0
+        ldm 2
+        endif
+        ifdef later
+        ldm 3
+        endif
+        endif
+        ifndef debug
+        ldm 4
+        else
+        ldm 5
+        endif
+later:  nop";
+        let program = assemble(source).unwrap();
+        assert_eq!(program.to_binary(), [0xD2, 0xD5, 0x00]);
+        assert_eq!(program.symbol("later"), Some(2));
+
+        assert!(matches!(
+            assemble(" if 1\n nop"),
+            Err(AsmError::Syntax { line: 1, .. })
+        ));
+        assert!(matches!(
+            assemble(" nop\n endif"),
+            Err(AsmError::Syntax { line: 2, .. })
+        ));
+        assert!(matches!(
+            assemble(" if 0\n else\n else\n endif"),
+            Err(AsmError::Syntax { line: 3, .. })
+        ));
+        // Inactive blocks are not evaluated
+        assert!(assemble(" if 0\n if undefined_name\n foo\n endif\n endif").is_ok());
+    }
+
+    #[test]
+    fn test_macros() {
+        let source = "\
+clear   macro reg
+        clb
+        xch reg
+        endm
+wait    macro reg
+        local spin
+spin:   isz reg, spin
+        endm
+start:  clear r2
+        wait r3
+        wait r4
+        jun start";
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            program.to_binary(),
+            [0xF0, 0xB2, 0x73, 0x02, 0x74, 0x04, 0x40, 0x00]
+        );
+        assert_eq!(program.symbol("spin.2"), Some(2));
+        assert_eq!(program.symbol("spin.3"), Some(4));
+    }
+
+    #[test]
+    fn test_includes() {
+        let dir = std::env::temp_dir().join(format!("mcs4-asm-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(
+            dir.join("defs.inc"),
+            "port equ 3\n include \"lib/util.inc\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("lib/util.inc"),
+            "out macro n\n ldm n\n wrr\n endm\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("bad.inc"), "\n\n jun missing\n").unwrap();
+
+        let asm = Assembler::new(Target::I4004);
+        let main = dir.join("main.asm");
+        let program = asm
+            .assemble_from(" include \"defs.inc\"\n out port\n", &main)
+            .unwrap();
+        assert_eq!(program.to_binary(), [0xD3, 0xE2]);
+        // Macro lines map to the macro body in its file
+        assert_eq!(program.line_map(), "000 2 lib/util.inc\n001 3 lib/util.inc\n");
+
+        // Errors inside an include name the file and its line
+        let err = asm
+            .assemble_from(" nop\n include bad.inc\n", &main)
+            .unwrap_err();
+        assert_eq!(err.file(), Some("bad.inc"));
+        assert_eq!(err.line(), 3);
+        assert_eq!(
+            err.to_string(),
+            "bad.inc: line 3: undefined symbol 'missing'"
+        );
+
+        // Search directories are used when the file is not next to the source
+        let program = Assembler::new(Target::I4004)
+            .include_dir(dir.join("lib"))
+            .assemble(" include util.inc\n out 9")
+            .unwrap();
+        assert_eq!(program.to_binary(), [0xD9, 0xE2]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_page_boundary_diagnostics() {
+        // JCN in the last two bytes of page 0 reaches page 1, not page 0
+        assert!(assemble(" org 0fdh\n jcn z, 0f0h").is_ok());
+        assert!(assemble(" org 0feh\n jcn z, 104h").is_ok());
+        assert_eq!(
+            assemble(" org 0feh\n jcn z, 0f0h"),
+            Err(AsmError::PageCrossing {
+                line: 2,
+                address: 0xFE,
+                target: 0xF0
+            })
+        );
+        let err = assemble(" org 10h\n nop\n isz r1, far\n org 200h\nfar: nop").unwrap_err();
+        assert_eq!(
+            err,
+            AsmError::PageCrossing {
+                line: 3,
+                address: 0x11,
+                target: 0x200
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "line 3: target 0x200 is not in page 0, the only page reachable from 0x011"
+        );
+
+        let program = assemble(" org 0ffh\n fin p1\n org 1fch\ntable: db 1, 2, 3, 4, 5").unwrap();
+        let warnings: Vec<(usize, String)> = program
+            .warnings()
+            .iter()
+            .map(|w| (w.line, w.to_string()))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                (2, "line 2: warning: FIN at the last byte of page 0 uses page 1".to_string()),
+                (
+                    4,
+                    "line 4: warning: data at 0x1FC-0x200 crosses into page 2; a FIN table cannot span pages"
+                        .to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! mcs4-asm: assemble 4004/4040 source into a ROM image
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, ValueEnum};
+use mcs4_asm::{Assembler, Target};
+
+/// Output image formats
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Format {
+    Hex,
+    Bin,
+}
+
+/// Target CPUs
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Cpu {
+    #[value(name = "4004")]
+    I4004,
+    #[value(name = "4040")]
+    I4040,
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "mcs4-asm", about = "Intel 4004/4040 assembler")]
+struct Args {
+    /// Assembly source
+    source: PathBuf,
+
+    /// Output image; defaults to the source name with .hex or .bin
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Output format; guessed from the output extension if omitted
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+
+    /// Target CPU
+    #[arg(long, value_enum, default_value = "4004")]
+    cpu: Cpu,
+
+    /// Also write a symbol file
+    #[arg(long)]
+    symbols: Option<PathBuf>,
+
+    /// Also write a line map (instruction address to source line)
+    #[arg(long)]
+    lines: Option<PathBuf>,
+
+    /// Directory to search for included files
+    #[arg(short = 'I', long = "include")]
+    include_dirs: Vec<PathBuf>,
+
+    /// Treat warnings (FIN/JIN at a page end, tables across pages) as errors
+    #[arg(long)]
+    deny_warnings: bool,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let source = match fs::read_to_string(&args.source) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("mcs4-asm: {}: {}", args.source.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let target = match args.cpu {
+        Cpu::I4004 => Target::I4004,
+        Cpu::I4040 => Target::I4040,
+    };
+    let assembler = args
+        .include_dirs
+        .iter()
+        .fold(Assembler::new(target), |asm, dir| asm.include_dir(dir));
+    let program = match assembler.assemble_from(&source, &args.source) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("mcs4-asm: {}: {}", args.source.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    for warning in program.warnings() {
+        eprintln!("mcs4-asm: {}: {}", args.source.display(), warning);
+    }
+    if args.deny_warnings && !program.warnings().is_empty() {
+        return ExitCode::FAILURE;
+    }
+
+    let format = args.format.unwrap_or(match &args.output {
+        Some(path) if path.extension().is_some_and(|ext| ext == "bin") => Format::Bin,
+        _ => Format::Hex,
+    });
+    let output = args.output.unwrap_or_else(|| {
+        args.source.with_extension(match format {
+            Format::Hex => "hex",
+            Format::Bin => "bin",
+        })
+    });
+    let image = match format {
+        Format::Hex => program.to_intel_hex().into_bytes(),
+        Format::Bin => program.to_binary(),
+    };
+
+    let mut written = vec![(output, image)];
+    if let Some(path) = args.symbols {
+        written.push((path, program.symbol_file().into_bytes()));
+    }
+    if let Some(path) = args.lines {
+        written.push((path, program.line_map().into_bytes()));
+    }
+    for (path, contents) in written {
+        if let Err(err) = fs::write(&path, contents) {
+            eprintln!("mcs4-asm: {}: {}", path.display(), err);
+            return ExitCode::FAILURE;
+        }
+    }
+    println!(
+        "Assembled {} bytes from {}",
+        program.len(),
+        args.source.display()
+    );
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,176 @@
+//! Assembled program and its output formats: raw binary, Intel HEX, a
+//! symbol file and a line map
+
+use std::collections::BTreeMap;
+
+use crate::Warning;
+
+/// Bytes placed by the assembler plus the symbols it defined
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Program {
+    pub(crate) bytes: BTreeMap<u16, u8>,
+    pub(crate) symbols: BTreeMap<String, i64>,
+    pub(crate) warnings: Vec<Warning>,
+    /// Source line of each instruction, by address of its first byte
+    pub(crate) lines: BTreeMap<u16, SourceLine>,
+}
+
+/// Where an instruction came from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLine {
+    /// Included file as named in the `include`; `None` for the main source
+    pub file: Option<String>,
+    /// 1-based line number
+    pub line: usize,
+}
+
+impl Program {
+    /// Byte at a program address
+    pub fn get(&self, addr: u16) -> Option<u8> {
+        self.bytes.get(&addr).copied()
+    }
+
+    /// Number of bytes placed
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Was nothing placed?
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Highest address placed
+    pub fn end_address(&self) -> Option<u16> {
+        self.bytes.keys().next_back().copied()
+    }
+
+    /// Iterate over (address, byte) in address order
+    pub fn iter(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.bytes.iter().map(|(&addr, &byte)| (addr, byte))
+    }
+
+    /// Value of a symbol (label or constant)
+    pub fn symbol(&self, name: &str) -> Option<i64> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Iterate over (name, value) in name order
+    pub fn symbols(&self) -> impl Iterator<Item = (&str, i64)> + '_ {
+        self.symbols
+            .iter()
+            .map(|(name, &value)| (name.as_str(), value))
+    }
+
+    /// Source line of the instruction starting at `addr`
+    pub fn source_line(&self, addr: u16) -> Option<&SourceLine> {
+        self.lines.get(&addr)
+    }
+
+    /// Iterate over (address, source line) of every instruction in address
+    /// order
+    pub fn source_lines(&self) -> impl Iterator<Item = (u16, &SourceLine)> + '_ {
+        self.lines.iter().map(|(&addr, line)| (addr, line))
+    }
+
+    /// Warnings raised while assembling
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Flat image from address 0, gaps zero-filled
+    pub fn to_binary(&self) -> Vec<u8> {
+        let len = self.end_address().map_or(0, |end| end as usize + 1);
+        let mut flat = vec![0; len];
+        for (addr, byte) in self.iter() {
+            flat[addr as usize] = byte;
+        }
+        flat
+    }
+
+    /// Intel HEX: 16-byte data records over each contiguous run, then EOF
+    pub fn to_intel_hex(&self) -> String {
+        let mut out = String::new();
+        let mut record: Vec<u8> = Vec::new();
+        let mut start = 0u16;
+        for (addr, byte) in self.iter() {
+            if !record.is_empty() && (addr != start + record.len() as u16 || record.len() == 16) {
+                out.push_str(&hex_record(start, &record));
+                record.clear();
+            }
+            if record.is_empty() {
+                start = addr;
+            }
+            record.push(byte);
+        }
+        if !record.is_empty() {
+            out.push_str(&hex_record(start, &record));
+        }
+        out.push_str(":00000001FF\n");
+        out
+    }
+
+    /// Symbol file: `ADDR NAME` per line, sorted by value then name
+    pub fn symbol_file(&self) -> String {
+        let mut symbols: Vec<(&str, i64)> = self.symbols().collect();
+        symbols.sort_by_key(|&(name, value)| (value, name));
+        symbols
+            .iter()
+            .map(|(name, value)| format!("{:03X} {}\n", value, name))
+            .collect()
+    }
+
+    /// Line map for debuggers: `ADDR LINE` per instruction, followed by
+    /// the file name for lines from an included file
+    pub fn line_map(&self) -> String {
+        self.source_lines()
+            .map(|(addr, source)| match &source.file {
+                Some(file) => format!("{:03X} {} {}\n", addr, source.line, file),
+                None => format!("{:03X} {}\n", addr, source.line),
+            })
+            .collect()
+    }
+}
+
+fn hex_record(addr: u16, data: &[u8]) -> String {
+    let mut line = format!(":{:02X}{:04X}00", data.len(), addr);
+    let mut sum = data.len() as u8;
+    sum = sum.wrapping_add((addr >> 8) as u8).wrapping_add(addr as u8);
+    for &byte in data {
+        line.push_str(&format!("{:02X}", byte));
+        sum = sum.wrapping_add(byte);
+    }
+    line.push_str(&format!("{:02X}\n", sum.wrapping_neg()));
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intel_hex_runs() {
+        let mut program = Program::default();
+        for addr in 0..18u16 {
+            program.bytes.insert(addr, addr as u8);
+        }
+        program.bytes.insert(0x100, 0xAA);
+        assert_eq!(
+            program.to_intel_hex(),
+            ":10000000000102030405060708090A0B0C0D0E0F78\n\
+             :020010001011CD\n\
+             :01010000AA54\n\
+             :00000001FF\n"
+        );
+        assert_eq!(program.to_binary().len(), 0x101);
+    }
+
+    #[test]
+    fn test_symbol_file() {
+        let mut program = Program::default();
+        program.symbols.insert("start".into(), 0);
+        program.symbols.insert("loop".into(), 0x12);
+        program.symbols.insert("alpha".into(), 0x12);
+        assert_eq!(program.symbol_file(), "000 start\n012 alpha\n012 loop\n");
+    }
+}
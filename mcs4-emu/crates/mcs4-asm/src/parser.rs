@@ -0,0 +1,150 @@
+//! Source line parsing: `[label:] [mnemonic [operand, ...]] [; comment]`
+//!
+//! `equ`, `set` and `macro` also take their name without a colon:
+//! `count equ 4`.
+
+use std::rc::Rc;
+
+use crate::expr::is_identifier;
+use crate::AsmError;
+
+/// Directives whose label may be written without a colon
+const NAMING_DIRECTIVES: [&str; 3] = ["equ", "set", "macro"];
+
+/// One parsed source line
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Line {
+    /// Included file the line came from; `None` for the main source
+    pub file: Option<Rc<str>>,
+    /// 1-based line number
+    pub number: usize,
+    pub label: Option<String>,
+    /// Lower-cased instruction or directive
+    pub mnemonic: Option<String>,
+    pub operands: Vec<String>,
+}
+
+/// Split a line into label, mnemonic and operands
+pub(crate) fn parse_line(number: usize, text: &str) -> Result<Line, AsmError> {
+    let mut rest = strip_comment(text).trim();
+    let mut line = Line {
+        number,
+        ..Line::default()
+    };
+
+    let name_end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'));
+    if let Some(colon) = name_end.filter(|&end| rest[end..].starts_with(':')) {
+        let name = &rest[..colon];
+        if !is_identifier(name) {
+            return Err(AsmError::Syntax {
+                line: number,
+                message: format!("invalid label '{}'", name),
+            });
+        }
+        line.label = Some(name.to_string());
+        rest = rest[colon + 1..].trim();
+    } else if let Some((name, after)) = rest.split_once(char::is_whitespace) {
+        let directive = after
+            .trim_start()
+            .split(char::is_whitespace)
+            .next()
+            .unwrap_or("");
+        if is_identifier(name)
+            && NAMING_DIRECTIVES.contains(&directive.to_ascii_lowercase().as_str())
+        {
+            line.label = Some(name.to_string());
+            rest = after.trim();
+        }
+    }
+    if rest.is_empty() {
+        return Ok(line);
+    }
+
+    let (mnemonic, operands) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    line.mnemonic = Some(mnemonic.to_ascii_lowercase());
+    line.operands = split_operands(operands.trim());
+    Ok(line)
+}
+
+impl Line {
+    /// Attach the included file's name to an error raised on this line
+    pub fn locate(&self, error: AsmError) -> AsmError {
+        locate(self.file.as_ref(), error)
+    }
+}
+
+/// Attach an included file's name to an error raised in it
+pub(crate) fn locate(file: Option<&Rc<str>>, error: AsmError) -> AsmError {
+    match file {
+        Some(file) => AsmError::InFile {
+            file: file.to_string(),
+            error: Box::new(error),
+        },
+        None => error,
+    }
+}
+
+/// Drop everything from the first `;` outside a quoted string
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (';', None) => return &text[..i],
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Split on commas outside quoted strings
+fn split_operands(text: &str) -> Vec<String> {
+    if text.is_empty() {
+        return Vec::new();
+    }
+    let mut operands = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (',', None) => {
+                operands.push(text[start..i].trim().to_string());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    operands.push(text[start..].trim().to_string());
+    operands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let line = parse_line(3, "loc_jun:\tJMS\tsub_1 ; call").unwrap();
+        assert_eq!(line.label.as_deref(), Some("loc_jun"));
+        assert_eq!(line.mnemonic.as_deref(), Some("jms"));
+        assert_eq!(line.operands, ["sub_1"]);
+
+        let line = parse_line(4, "\t\tfim\tr8r9, loc_jin").unwrap();
+        assert_eq!(line.label, None);
+        assert_eq!(line.operands, ["r8r9", "loc_jin"]);
+
+        let line = parse_line(5, "\tdb 'a;b', 1").unwrap();
+        assert_eq!(line.operands, ["'a;b'", "1"]);
+
+        let line = parse_line(6, "count\tEQU 4 * 2").unwrap();
+        assert_eq!(line.label.as_deref(), Some("count"));
+        assert_eq!(line.mnemonic.as_deref(), Some("equ"));
+        assert_eq!(line.operands, ["4 * 2"]);
+
+        assert_eq!(parse_line(6, "   ; only a comment").unwrap().mnemonic, None);
+        assert!(parse_line(7, "1bad: nop").is_err());
+    }
+}
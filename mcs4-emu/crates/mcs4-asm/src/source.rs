@@ -0,0 +1,347 @@
+//! Source preprocessing: include files and macro expansion
+//!
+//! Both happen before the passes, so an `include` or macro definition
+//! inside a false `if` block still takes effect; the conditional only
+//! decides whether the lines it encloses are assembled.
+//!
+//! ```text
+//! inc2    macro   reg             ; parameters are replaced as whole words
+//!         local   skip            ; becomes skip.N, unique per expansion
+//!         isz     reg, skip
+//! skip:   isz     reg, $ + 2
+//!         endm
+//! ```
+//!
+//! Lines expanded from a macro keep the line numbers of its body.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::expr::{is_identifier, is_name_char};
+use crate::parser::{locate, parse_line, Line};
+use crate::{encode, AsmError};
+
+/// How deeply includes and macro expansions may nest
+const MAX_DEPTH: usize = 16;
+
+/// Directives that cannot be used as macro names
+const DIRECTIVES: [&str; 15] = [
+    "org", "db", "ds", "end", "equ", "set", "if", "ifdef", "ifndef", "else", "endif", "include",
+    "macro", "endm", "local",
+];
+
+/// Raw source lines with their numbers
+type Block = [(usize, String)];
+
+struct Macro {
+    params: Vec<String>,
+    locals: Vec<String>,
+    body: Vec<(usize, String)>,
+    file: Option<Rc<str>>,
+    dir: Option<PathBuf>,
+}
+
+/// Expands includes and macros into a flat list of lines
+pub(crate) struct Preprocessor<'a> {
+    include_dirs: &'a [PathBuf],
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    lines: Vec<Line>,
+}
+
+impl<'a> Preprocessor<'a> {
+    pub fn new(include_dirs: &'a [PathBuf]) -> Self {
+        Self {
+            include_dirs,
+            macros: HashMap::new(),
+            expansions: 0,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Expand `source`; includes resolve against `dir` first
+    pub fn run(mut self, source: &str, dir: Option<&Path>) -> Result<Vec<Line>, AsmError> {
+        let raw: Vec<(usize, String)> = source
+            .lines()
+            .enumerate()
+            .map(|(i, text)| (i + 1, text.to_string()))
+            .collect();
+        self.block(&raw, None, dir, 0)?;
+        Ok(self.lines)
+    }
+
+    fn block(
+        &mut self,
+        raw: &Block,
+        file: Option<&Rc<str>>,
+        dir: Option<&Path>,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        let mut i = 0;
+        while i < raw.len() {
+            let (number, text) = &raw[i];
+            i += 1;
+            let mut line = parse_line(*number, text).map_err(|err| locate(file, err))?;
+            line.file = file.cloned();
+            let syntax = |message: String| {
+                line.locate(AsmError::Syntax {
+                    line: *number,
+                    message,
+                })
+            };
+
+            match line.mnemonic.as_deref() {
+                Some("include") => {
+                    self.label_only(&line);
+                    self.include(&line, dir, depth)?;
+                }
+                Some("macro") => {
+                    let Some(name) = line.label.clone() else {
+                        return Err(syntax("macro needs a name".into()));
+                    };
+                    let mnemonic = name.to_ascii_lowercase();
+                    if encode::lookup(&mnemonic).is_some()
+                        || DIRECTIVES.contains(&mnemonic.as_str())
+                    {
+                        return Err(syntax(format!("'{}' is an instruction or directive", name)));
+                    }
+                    if let Some(param) = line.operands.iter().find(|p| !is_identifier(p)) {
+                        return Err(syntax(format!("invalid macro parameter '{}'", param)));
+                    }
+
+                    let mut mac = Macro {
+                        params: line.operands.clone(),
+                        locals: Vec::new(),
+                        body: Vec::new(),
+                        file: file.cloned(),
+                        dir: dir.map(Path::to_path_buf),
+                    };
+                    loop {
+                        let Some((body_number, body_text)) = raw.get(i) else {
+                            return Err(syntax(format!("macro '{}' has no endm", name)));
+                        };
+                        i += 1;
+                        let body_line =
+                            parse_line(*body_number, body_text).map_err(|err| locate(file, err))?;
+                        match body_line.mnemonic.as_deref() {
+                            Some("endm") => break,
+                            Some("macro") => {
+                                let err = AsmError::Syntax {
+                                    line: *body_number,
+                                    message: "nested macro definition".into(),
+                                };
+                                return Err(locate(file, err));
+                            }
+                            Some("local") => mac.locals.extend(body_line.operands),
+                            _ => mac.body.push((*body_number, body_text.clone())),
+                        }
+                    }
+                    self.macros.insert(mnemonic, mac);
+                }
+                Some("endm") => return Err(syntax("endm without macro".into())),
+                Some("local") => return Err(syntax("local outside a macro".into())),
+                Some(mnemonic) if self.macros.contains_key(mnemonic) => {
+                    if depth >= MAX_DEPTH {
+                        return Err(syntax(format!("macro '{}' nested too deeply", mnemonic)));
+                    }
+                    self.label_only(&line);
+                    self.expand(&line, depth)?;
+                }
+                _ => self.lines.push(line),
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep a label written on an include or macro call
+    fn label_only(&mut self, line: &Line) {
+        if line.label.is_some() {
+            self.lines.push(Line {
+                mnemonic: None,
+                operands: Vec::new(),
+                ..line.clone()
+            });
+        }
+    }
+
+    fn include(&mut self, line: &Line, dir: Option<&Path>, depth: usize) -> Result<(), AsmError> {
+        let error = |path: &str, message: String| {
+            line.locate(AsmError::Include {
+                line: line.number,
+                path: path.to_string(),
+                message,
+            })
+        };
+        let [operand] = line.operands.as_slice() else {
+            return Err(line.locate(AsmError::Syntax {
+                line: line.number,
+                message: "include takes one file name".into(),
+            }));
+        };
+        let name = operand.trim_matches(|c| c == '"' || c == '\'');
+        if depth >= MAX_DEPTH {
+            return Err(error(name, "includes nested too deeply".into()));
+        }
+
+        let candidates = dir
+            .map(|dir| dir.join(name))
+            .into_iter()
+            .chain(self.include_dirs.iter().map(|dir| dir.join(name)));
+        let path = candidates
+            .chain([PathBuf::from(name)])
+            .find(|path| path.is_file());
+        let Some(path) = path else {
+            return Err(error(name, "file not found".into()));
+        };
+        let text = fs::read_to_string(&path).map_err(|err| error(name, err.to_string()))?;
+
+        let raw: Vec<(usize, String)> = text
+            .lines()
+            .enumerate()
+            .map(|(i, text)| (i + 1, text.to_string()))
+            .collect();
+        let file: Rc<str> = Rc::from(name);
+        self.block(&raw, Some(&file), path.parent(), depth + 1)
+    }
+
+    fn expand(&mut self, line: &Line, depth: usize) -> Result<(), AsmError> {
+        self.expansions += 1;
+        let mnemonic = line.mnemonic.as_deref().unwrap_or_default();
+        let mac = &self.macros[mnemonic];
+        if line.operands.len() != mac.params.len() {
+            let message = format!(
+                "macro '{}' takes {} argument{}, found {}",
+                mnemonic,
+                mac.params.len(),
+                if mac.params.len() == 1 { "" } else { "s" },
+                line.operands.len()
+            );
+            return Err(line.locate(AsmError::Syntax {
+                line: line.number,
+                message,
+            }));
+        }
+
+        let mut names: HashMap<&str, String> = mac
+            .params
+            .iter()
+            .map(String::as_str)
+            .zip(line.operands.iter().cloned())
+            .collect();
+        for local in &mac.locals {
+            names.insert(local, format!("{}.{}", local, self.expansions));
+        }
+        let body: Vec<(usize, String)> = mac
+            .body
+            .iter()
+            .map(|(number, text)| (*number, substitute(text, &names)))
+            .collect();
+        let file = mac.file.clone();
+        let dir = mac.dir.clone();
+        self.block(&body, file.as_ref(), dir.as_deref(), depth + 1)
+    }
+}
+
+/// Replace whole-word occurrences of the names in `map`
+fn substitute(text: &str, map: &HashMap<&str, String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut word = String::new();
+    for c in text.chars().chain(std::iter::once('\n')) {
+        if is_name_char(c) {
+            word.push(c);
+            continue;
+        }
+        out.push_str(map.get(word.as_str()).unwrap_or(&word));
+        word.clear();
+        out.push(c);
+    }
+    out.pop();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(source: &str) -> Result<Vec<Line>, AsmError> {
+        Preprocessor::new(&[]).run(source, None)
+    }
+
+    #[test]
+    fn test_substitute() {
+        let map: HashMap<&str, String> = [("reg", "r3".to_string()), ("n", "4".to_string())]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            substitute("xch reg ; n, reg2, n+1", &map),
+            "xch r3 ; 4, reg2, 4+1"
+        );
+    }
+
+    #[test]
+    fn test_macro_expansion() {
+        let lines = expand(
+            "twice macro op, reg\n\
+             \tlocal again\n\
+             again: op reg\n\
+             \top reg\n\
+             \tendm\n\
+             start: twice inc, r2\n\
+             \ttwice xch, r5",
+        )
+        .unwrap();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            (
+                lines[0].number,
+                lines[0].label.as_deref(),
+                lines[0].mnemonic.as_deref()
+            ),
+            (6, Some("start"), None)
+        );
+        assert_eq!(
+            (
+                lines[1].number,
+                lines[1].label.as_deref(),
+                lines[1].mnemonic.as_deref()
+            ),
+            (3, Some("again.1"), Some("inc"))
+        );
+        assert_eq!(lines[1].operands, ["r2"]);
+        assert_eq!(lines[3].label.as_deref(), Some("again.2"));
+        assert_eq!(lines[4].mnemonic.as_deref(), Some("xch"));
+        assert_eq!(lines[4].operands, ["r5"]);
+    }
+
+    #[test]
+    fn test_macro_errors() {
+        assert!(matches!(
+            expand("m macro\n nop"),
+            Err(AsmError::Syntax { line: 1, .. })
+        ));
+        assert!(matches!(
+            expand("m macro a\n endm\n m"),
+            Err(AsmError::Syntax { line: 3, .. })
+        ));
+        assert!(matches!(
+            expand(" endm"),
+            Err(AsmError::Syntax { line: 1, .. })
+        ));
+        assert!(matches!(
+            expand("nop macro\n endm"),
+            Err(AsmError::Syntax { line: 1, .. })
+        ));
+        assert!(matches!(
+            expand("m macro\n m\n endm\n m"),
+            Err(AsmError::Syntax { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_missing_include() {
+        let err = expand("\n include \"no/such/file.inc\"").unwrap_err();
+        assert!(matches!(err, AsmError::Include { line: 2, .. }), "{err}");
+    }
+}
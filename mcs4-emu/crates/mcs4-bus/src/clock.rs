@@ -3,10 +3,12 @@
 //! The MCS-4 uses a two-phase clock with PHI1 and PHI2 that must not overlap.
 //! Typical clock frequency is 740 kHz (1.35 us period).
 
+use rkyv::{Archive, Deserialize, Serialize};
 use mcs4_core::prelude::*;
 
 /// Clock configuration parameters
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct ClockConfig {
     /// Clock period (PHI1 rising to next PHI1 rising)
     pub period: Time,
@@ -72,7 +74,8 @@ impl ClockConfig {
 }
 
 /// Two-phase clock generator
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct TwoPhaseClockTwoPhaseClock {
     /// Configuration
     pub config: ClockConfig,
@@ -246,12 +249,10 @@ mod tests {
     #[test]
     fn test_non_overlapping() {
         let mut clock = TwoPhaseClockTwoPhaseClock::default_config();
-        let mut t = 0;
 
         // Run through multiple cycles
-        for _ in 0..10 {
+        for t in 0..10 {
             let edge = clock.tick(t);
-            t += 1;
 
             // PHI1 and PHI2 should never both be high
             assert!(!(clock.phi1_high() && clock.phi2_high()),
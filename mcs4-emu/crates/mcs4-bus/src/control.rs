@@ -1,5 +1,6 @@
 //! Control signals for MCS-4/MCS-40 bus
 
+use rkyv::{Archive, Deserialize, Serialize};
 use mcs4_core::prelude::*;
 
 /// Chip select signals
@@ -14,7 +15,8 @@ pub enum ChipSelect {
 }
 
 /// Control signals for the MCS-4 bus
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct ControlSignals {
     /// SYNC - Machine cycle synchronization
     pub sync: Signal,
@@ -115,11 +117,16 @@ impl ControlSignals {
         }
     }
 
-    /// Select RAM bank (0-15)
+    /// Drive CM-RAM from the DCL register (0-7)
+    ///
+    /// Code 0 asserts CM-RAM0 alone; any other code puts its bits 0-2 on
+    /// CM-RAM1-3, which either select banks directly or feed a 3-to-8
+    /// decoder for up to eight banks.
     pub fn select_ram(&mut self, bank: u8, time: Time) {
-        let bank = bank & 0x0F;
+        let bank = bank & 0x07;
+        let lines = if bank == 0 { 0b0001 } else { bank << 1 };
         for (i, signal) in self.cm_ram.iter_mut().enumerate() {
-            let level = if (bank >> i) & 1 == 1 {
+            let level = if (lines >> i) & 1 == 1 {
                 SignalLevel::High
             } else {
                 SignalLevel::Low
@@ -128,6 +135,38 @@ impl ControlSignals {
         }
     }
 
+    /// Assert a single CM-ROM line (0-3), leaving the others low
+    pub fn assert_cm_rom(&mut self, line: usize, time: Time) {
+        for (i, signal) in self.cm_rom.iter_mut().enumerate() {
+            let level = if i == line { SignalLevel::High } else { SignalLevel::Low };
+            signal.update(time, level);
+        }
+    }
+
+    /// Is the given CM-ROM line (0-3) asserted?
+    pub fn cm_rom_line(&self, line: usize) -> bool {
+        self.cm_rom
+            .get(line)
+            .map(|s| s.current == SignalLevel::High)
+            .unwrap_or(false)
+    }
+
+    /// Assert a single CM-RAM line (0-3), leaving the others low
+    pub fn assert_cm_ram(&mut self, line: usize, time: Time) {
+        for (i, signal) in self.cm_ram.iter_mut().enumerate() {
+            let level = if i == line { SignalLevel::High } else { SignalLevel::Low };
+            signal.update(time, level);
+        }
+    }
+
+    /// Is the given CM-RAM line (0-3) asserted?
+    pub fn cm_ram_line(&self, line: usize) -> bool {
+        self.cm_ram
+            .get(line)
+            .map(|s| s.current == SignalLevel::High)
+            .unwrap_or(false)
+    }
+
     /// Deselect all ROM banks
     pub fn deselect_rom(&mut self, time: Time) {
         for signal in &mut self.cm_rom {
@@ -157,19 +196,13 @@ impl ControlSignals {
         if any_selected { Some(bank) } else { None }
     }
 
-    /// Get currently selected RAM bank (if any)
+    /// Get the DCL code (0-7) currently driven on CM-RAM (if any)
     pub fn selected_ram(&self) -> Option<u8> {
-        let mut bank = 0u8;
-        let mut any_selected = false;
-
-        for (i, signal) in self.cm_ram.iter().enumerate() {
-            if signal.current == SignalLevel::High {
-                bank |= 1 << i;
-                any_selected = true;
-            }
+        match self.cm_ram() {
+            0 => None,
+            lines if lines & 0b1110 != 0 => Some(lines >> 1),
+            _ => Some(0),
         }
-
-        if any_selected { Some(bank) } else { None }
     }
 
     /// Check if TEST input is active (active low)
@@ -210,6 +243,38 @@ impl ControlSignals {
             .unwrap_or(false)
     }
 
+    /// Drive the INT input (4040 only)
+    pub fn set_interrupt(&mut self, active: bool, time: Time) {
+        if let Some(int) = self.int.as_mut() {
+            let level = if active { SignalLevel::High } else { SignalLevel::Low };
+            int.update(time, level);
+        }
+    }
+
+    /// Drive the STOP input (4040 only)
+    pub fn set_stop(&mut self, active: bool, time: Time) {
+        if let Some(stop) = self.stop.as_mut() {
+            let level = if active { SignalLevel::High } else { SignalLevel::Low };
+            stop.update(time, level);
+        }
+    }
+
+    /// Drive the STP (stop acknowledge) output (4040 only)
+    pub fn set_stop_ack(&mut self, active: bool, time: Time) {
+        if let Some(stp) = self.stp.as_mut() {
+            let level = if active { SignalLevel::High } else { SignalLevel::Low };
+            stp.update(time, level);
+        }
+    }
+
+    /// Is the CPU acknowledging stop mode on STP? (4040 only)
+    pub fn stop_acknowledged(&self) -> bool {
+        self.stp
+            .as_ref()
+            .map(|s| s.current == SignalLevel::High)
+            .unwrap_or(false)
+    }
+
     /// Get CM-ROM value as a 4-bit number
     pub fn cm_rom(&self) -> u8 {
         let mut value = 0u8;
@@ -232,19 +297,15 @@ impl ControlSignals {
         value
     }
 
-    /// Check if an I/O write operation is in progress
-    /// This is set during X2 phase for WRR/WRM/WMP/WRx instructions
-    pub fn is_io_write(&self) -> bool {
-        // In a full implementation, this would be decoded from control lines
-        // For now, we use a simplified check based on ROM selection
-        self.selected_rom().is_some()
-    }
-
-    /// Check if an I/O read operation is in progress
-    /// This is set during X3 phase for RDR/RDM/RDx instructions
-    pub fn is_io_read(&self) -> bool {
-        // In a full implementation, this would be decoded from control lines
-        self.selected_rom().is_some()
+    /// Drop the transition history of every signal, keeping current levels
+    pub fn clear_history(&mut self) {
+        let optional = [&mut self.stp, &mut self.stop, &mut self.int];
+        self.cm_rom
+            .iter_mut()
+            .chain(self.cm_ram.iter_mut())
+            .chain([&mut self.sync, &mut self.test, &mut self.reset])
+            .chain(optional.into_iter().flatten())
+            .for_each(Signal::clear_history);
     }
 }
 
@@ -263,12 +324,43 @@ mod tests {
         assert_eq!(ctrl.selected_rom(), None);
     }
 
+    #[test]
+    fn test_cm_rom_lines() {
+        let mut ctrl = ControlSignals::mcs40();
+
+        ctrl.assert_cm_rom(1, 0);
+        assert!(ctrl.cm_rom_line(1));
+        assert!(!ctrl.cm_rom_line(0));
+        assert_eq!(ctrl.cm_rom(), 0b0010);
+
+        ctrl.deselect_rom(10);
+        assert!(!ctrl.cm_rom_line(1));
+        assert!(!ctrl.cm_rom_line(7));
+
+        ctrl.assert_cm_ram(2, 20);
+        assert!(ctrl.cm_ram_line(2));
+        assert!(!ctrl.cm_ram_line(0));
+        assert_eq!(ctrl.cm_ram(), 0b0100);
+    }
+
     #[test]
     fn test_ram_select() {
         let mut ctrl = ControlSignals::mcs4();
+        assert_eq!(ctrl.selected_ram(), None);
+
+        // DCL 0 drives CM-RAM0 only
+        ctrl.select_ram(0, 0);
+        assert_eq!(ctrl.cm_ram(), 0b0001);
+        assert_eq!(ctrl.selected_ram(), Some(0));
 
-        ctrl.select_ram(10, 0); // Binary 1010
-        assert_eq!(ctrl.selected_ram(), Some(10));
+        // Other codes go out on CM-RAM1-3
+        ctrl.select_ram(5, 0);
+        assert_eq!(ctrl.cm_ram(), 0b1010);
+        assert_eq!(ctrl.selected_ram(), Some(5));
+
+        // Only ACC bits 0-2 are latched
+        ctrl.select_ram(0xC, 0);
+        assert_eq!(ctrl.selected_ram(), Some(4));
     }
 
     #[test]
@@ -292,4 +384,21 @@ mod tests {
         assert!(ctrl.stop.is_some());
         assert!(ctrl.int.is_some());
     }
+
+    #[test]
+    fn test_stop_ack() {
+        let mut ctrl = ControlSignals::mcs40();
+        ctrl.set_interrupt(true, 0);
+        ctrl.set_stop(true, 0);
+        assert!(ctrl.interrupt_pending());
+        assert!(ctrl.stop_requested());
+
+        ctrl.set_stop_ack(true, 0);
+        assert!(ctrl.stop_acknowledged());
+
+        // No STP pin on a 4004 bus
+        let mut ctrl = ControlSignals::mcs4();
+        ctrl.set_stop_ack(true, 0);
+        assert!(!ctrl.stop_acknowledged());
+    }
 }
@@ -2,11 +2,14 @@
 //!
 //! The MCS-4 uses an 8-phase machine cycle:
 //! - A1, A2, A3: Address output phases (CPU sends 12-bit ROM address)
-//! - M1, M2: Memory read phases (ROM outputs 8-bit instruction)
+//! - M1, M2: Memory read phases (ROM outputs OPR, then OPA)
 //! - X1, X2, X3: Execution phases (varies by instruction)
 
+use rkyv::{Archive, Deserialize, Serialize};
+
 /// Bus cycle phase within a machine cycle
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 #[repr(u8)]
 pub enum BusCycle {
     /// Address phase 1 - CPU outputs address bits 0-3
@@ -15,9 +18,9 @@ pub enum BusCycle {
     A2 = 1,
     /// Address phase 3 - CPU outputs address bits 8-11
     A3 = 2,
-    /// Memory read phase 1 - ROM outputs instruction bits 0-3 (OPA)
+    /// Memory read phase 1 - ROM outputs instruction bits 4-7 (OPR)
     M1 = 3,
-    /// Memory read phase 2 - ROM outputs instruction bits 4-7 (OPR)
+    /// Memory read phase 2 - ROM outputs instruction bits 0-3 (OPA)
     M2 = 4,
     /// Execution phase 1
     X1 = 5,
@@ -64,7 +67,8 @@ impl BusCycle {
 }
 
 /// Higher-level machine state for multi-cycle instructions
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub enum MachineState {
     /// Fetching first instruction byte (all instructions)
     Fetch1,
@@ -86,7 +90,8 @@ impl MachineState {
 }
 
 /// Complete cycle state tracking
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct CycleState {
     /// Current bus phase
     pub phase: BusCycle,
@@ -1,12 +1,14 @@
 //! 4-bit bidirectional data bus implementation
 
+use rkyv::{Archive, Deserialize, Serialize};
 use mcs4_core::prelude::*;
 
 /// 4-bit bidirectional data bus
 ///
 /// The MCS-4 data bus carries addresses (A0-A11, sent as three 4-bit nibbles),
 /// instructions (8-bit, sent as two 4-bit nibbles), and data.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct DataBus {
     /// D0-D3 signal lines
     pub lines: [Signal; 4],
@@ -16,7 +18,8 @@ pub struct DataBus {
 }
 
 /// A device that can drive the bus
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct BusDriver {
     /// Driver name (for debugging)
     pub name: String,
@@ -1 +1,26 @@
-use criterion::{criterion_group, criterion_main, Criterion, black_box};\nuse mcs4_chips::i4004::I4004;\n\nfn bench_4004_tick(c: &mut Criterion) {\n    let mut cpu = I4004::new();\n    c.bench_function("4004_tick_1k_phases", |b| {\n        b.iter(|| {\n            for _ in 0..black_box(1000u32) {\n                cpu.tick(mcs4_bus::BusCycle::A1);\n                cpu.tick(mcs4_bus::BusCycle::A2);\n                cpu.tick(mcs4_bus::BusCycle::A3);\n                cpu.tick(mcs4_bus::BusCycle::M1);\n                cpu.tick(mcs4_bus::BusCycle::M2);\n                cpu.tick(mcs4_bus::BusCycle::X1);\n                cpu.tick(mcs4_bus::BusCycle::X2);\n                cpu.tick(mcs4_bus::BusCycle::X3);\n            }\n        })\n    });\n}\n\ncriterion_group!(benches, bench_4004_tick);\ncriterion_main!(benches);
+use criterion::{criterion_group, criterion_main, Criterion, black_box};
+use mcs4_bus::{BusCycle, ControlSignals, DataBus};
+use mcs4_chips::i4004::I4004;
+
+fn bench_4004_tick(c: &mut Criterion) {
+    let mut cpu = I4004::new();
+    let mut bus = DataBus::new();
+    let mut ctrl = ControlSignals::mcs4();
+    c.bench_function("4004_tick_1k_phases", |b| {
+        b.iter(|| {
+            for _ in 0..black_box(1000u32) {
+                cpu.tick(BusCycle::A1, &mut bus, &mut ctrl);
+                cpu.tick(BusCycle::A2, &mut bus, &mut ctrl);
+                cpu.tick(BusCycle::A3, &mut bus, &mut ctrl);
+                cpu.tick(BusCycle::M1, &mut bus, &mut ctrl);
+                cpu.tick(BusCycle::M2, &mut bus, &mut ctrl);
+                cpu.tick(BusCycle::X1, &mut bus, &mut ctrl);
+                cpu.tick(BusCycle::X2, &mut bus, &mut ctrl);
+                cpu.tick(BusCycle::X3, &mut bus, &mut ctrl);
+            }
+        })
+    });
+}
+
+criterion_group!(benches, bench_4004_tick);
+criterion_main!(benches);
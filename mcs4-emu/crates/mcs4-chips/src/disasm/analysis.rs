@@ -0,0 +1,356 @@
+//! Control-flow analysis: code/data separation for whole-ROM listings
+//!
+//! Walks the program from its entry points (reset, plus the 4040 interrupt
+//! vector) following JUN/JMS/JCN/ISZ and stopping at BBL/BBS, so table bytes
+//! and the second bytes of two-byte instructions are never decoded as code.
+//!
+//! JIN and FIN are resolved when the pair they use was loaded by a FIM
+//! earlier in the same straight-line run; the FIN table byte is then marked
+//! as data.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{Decoded, DisasmLine, Disassembler, SymbolTable};
+use crate::i4004::Instruction;
+use crate::i4040::Opcode4040;
+
+/// Reset vector
+pub const RESET_VECTOR: u16 = 0x000;
+
+/// 4040 interrupt vector
+pub const INTERRUPT_VECTOR: u16 = 0x003;
+
+/// Result of following the program's control flow
+#[derive(Clone, Debug, Default)]
+pub struct Analysis {
+    /// Instructions reached, keyed by address
+    pub code: BTreeMap<u16, DisasmLine>,
+    /// Bytes read as data by FIN
+    pub data: BTreeSet<u16>,
+    /// Supplied symbols plus generated `sub_`/`loc_`/`tbl_` labels
+    pub labels: SymbolTable,
+    /// For each referenced address, the instructions referring to it
+    pub xrefs: BTreeMap<u16, BTreeSet<u16>>,
+    /// For each routine (entry point or JMS target), the routines it calls
+    pub calls: BTreeMap<u16, BTreeSet<u16>>,
+    /// Where the walk started
+    pub entry_points: Vec<u16>,
+}
+
+impl Analysis {
+    /// Is `addr` the first byte of a reached instruction?
+    pub fn is_code(&self, addr: u16) -> bool {
+        self.code.contains_key(&addr)
+    }
+
+    /// Is `addr` part of a reached instruction (either byte)?
+    pub fn covers(&self, addr: u16) -> bool {
+        self.is_code(addr)
+            || addr
+                .checked_sub(1)
+                .and_then(|prev| self.code.get(&prev))
+                .is_some_and(|line| line.length() == 2)
+    }
+
+    /// Full listing of `rom`: labels, instructions with their callers and
+    /// referrers, and everything not reached as `DB` bytes
+    pub fn listing(&self, dis: &Disassembler, rom: &[u8]) -> String {
+        let dis = Disassembler::with_symbols(dis.cpu_type, self.labels.clone());
+        let end = rom.len().min(0x1000) as u16;
+        let mut out = String::new();
+
+        let mut addr = 0u16;
+        while addr < end {
+            if let Some(label) = self.labels.get(addr) {
+                out.push_str(&format!("{}:", label));
+                if let Some(refs) = self.xrefs.get(&addr) {
+                    let refs: Vec<String> = refs.iter().map(|r| format!("{:03X}", r)).collect();
+                    out.push_str(&format!("  ; xref {}", refs.join(", ")));
+                }
+                if let Some(callees) = self.calls.get(&addr).filter(|c| !c.is_empty()) {
+                    let callees: Vec<String> =
+                        callees.iter().map(|&c| dis.symbols.get(c).unwrap_or("?").to_string()).collect();
+                    out.push_str(&format!("  ; calls {}", callees.join(", ")));
+                }
+                out.push('\n');
+            }
+            let line = match self.code.get(&addr) {
+                Some(_) => dis.disasm_one(rom, addr),
+                None => data_line(addr, rom[addr as usize]),
+            };
+            out.push_str(&format!("    {}\n", line));
+            addr += line.length();
+        }
+        out
+    }
+}
+
+fn data_line(addr: u16, byte: u8) -> DisasmLine {
+    DisasmLine {
+        address: addr,
+        bytes: vec![byte],
+        mnemonic: "DB".into(),
+        operands: format!("0x{:02X}", byte),
+        decoded: Decoded::Base(Instruction::Invalid { opcode: byte }),
+        target: None,
+        indirect_page: None,
+    }
+}
+
+/// How control leaves an instruction
+struct Flow {
+    /// Execution continues with the next instruction
+    falls_through: bool,
+    /// Jump destination (JUN/JCN/ISZ/resolved JIN)
+    jump: Option<u16>,
+    /// Subroutine called (JMS)
+    call: Option<u16>,
+}
+
+impl Disassembler {
+    /// Follow control flow through `rom` from the reset vector (and the
+    /// interrupt vector on a 4040)
+    pub fn analyze(&self, rom: &[u8]) -> Analysis {
+        let mut entries = vec![RESET_VECTOR];
+        if self.cpu_type == super::CpuType::I4040 {
+            entries.push(INTERRUPT_VECTOR);
+        }
+        self.analyze_from(rom, &entries)
+    }
+
+    /// Follow control flow through `rom` from the given entry points
+    pub fn analyze_from(&self, rom: &[u8], entries: &[u16]) -> Analysis {
+        let end = rom.len().min(0x1000) as u16;
+        let mut analysis = Analysis {
+            entry_points: entries.to_vec(),
+            ..Analysis::default()
+        };
+        let mut routines: BTreeSet<u16> = entries.iter().copied().collect();
+        let mut jump_targets = BTreeSet::new();
+
+        // Each work item is a straight-line run with its known pair values
+        let mut work: Vec<(u16, [Option<u8>; 8])> =
+            entries.iter().map(|&e| (e, [None; 8])).collect();
+        while let Some((start, mut pairs)) = work.pop() {
+            let mut addr = start;
+            while addr < end && !analysis.covers(addr) && !analysis.data.contains(&addr) {
+                let line = self.disasm_one(rom, addr);
+                let flow = self.flow(&line, &mut pairs, &mut analysis);
+                let next = line.next_address();
+                analysis.code.insert(addr, line);
+
+                if let Some(dest) = flow.call {
+                    analysis.xrefs.entry(dest).or_default().insert(addr);
+                    if routines.insert(dest) {
+                        work.push((dest, [None; 8]));
+                    }
+                }
+                if let Some(dest) = flow.jump {
+                    analysis.xrefs.entry(dest).or_default().insert(addr);
+                    jump_targets.insert(dest);
+                    work.push((dest, pairs));
+                }
+                if !flow.falls_through || next == 0 {
+                    break;
+                }
+                // A subroutine may change any register
+                if flow.call.is_some() {
+                    pairs = [None; 8];
+                }
+                addr = next;
+            }
+        }
+
+        // Call graph: walk each routine without descending into callees
+        for &routine in &routines {
+            let callees = self.callees(&analysis, routine);
+            analysis.calls.insert(routine, callees);
+        }
+
+        let mut labels = self.symbols.clone();
+        let mut name = |addr: u16, label: String| {
+            if labels.get(addr).is_none() {
+                labels.insert(addr, &label);
+            }
+        };
+        if entries.contains(&RESET_VECTOR) {
+            name(RESET_VECTOR, "reset".into());
+        }
+        if entries.contains(&INTERRUPT_VECTOR) && self.cpu_type == super::CpuType::I4040 {
+            name(INTERRUPT_VECTOR, "interrupt".into());
+        }
+        for &addr in &routines {
+            name(addr, format!("sub_{:03X}", addr));
+        }
+        for &addr in &jump_targets {
+            name(addr, format!("loc_{:03X}", addr));
+        }
+        for &addr in &analysis.data {
+            name(addr, format!("tbl_{:03X}", addr));
+        }
+        analysis.labels = labels;
+        analysis
+    }
+
+    /// Successors of one instruction, tracking FIM-loaded pair values so FIN
+    /// and JIN can be resolved
+    fn flow(&self, line: &DisasmLine, pairs: &mut [Option<u8>; 8], analysis: &mut Analysis) -> Flow {
+        let mut flow = Flow { falls_through: true, jump: None, call: None };
+        let clobber = |pairs: &mut [Option<u8>; 8], reg: u8| pairs[(reg >> 1) as usize & 7] = None;
+
+        match line.decoded {
+            Decoded::Base(instr) => match instr {
+                Instruction::Jun { .. } => {
+                    flow.falls_through = false;
+                    flow.jump = line.target;
+                }
+                Instruction::Jms { .. } => flow.call = line.target,
+                Instruction::Jcn { .. } => flow.jump = line.target,
+                Instruction::Isz { reg, .. } => {
+                    clobber(pairs, reg);
+                    flow.jump = line.target;
+                }
+                Instruction::Bbl { .. } => flow.falls_through = false,
+                Instruction::Fim { pair, data } => pairs[pair as usize] = Some(data),
+                Instruction::Fin { pair } => {
+                    if let (Some(low), Some(page)) = (pairs[0], line.indirect_page) {
+                        let table = page | low as u16;
+                        analysis.data.insert(table);
+                        analysis.xrefs.entry(table).or_default().insert(line.address);
+                    }
+                    pairs[pair as usize] = None;
+                }
+                Instruction::Jin { pair } => {
+                    flow.falls_through = false;
+                    if let (Some(low), Some(page)) = (pairs[pair as usize], line.indirect_page) {
+                        flow.jump = Some(page | low as u16);
+                    }
+                }
+                Instruction::Inc { reg } | Instruction::Xch { reg } => clobber(pairs, reg),
+                _ => {}
+            },
+            Decoded::Ext(op) => match op {
+                Opcode4040::Bbs => flow.falls_through = false,
+                // The other bank's registers hold unknown values
+                Opcode4040::Sb0 | Opcode4040::Sb1 => *pairs = [None; 8],
+                _ => {}
+            },
+        }
+        flow
+    }
+
+    /// Routines called from `routine`, following its jumps but not its calls
+    fn callees(&self, analysis: &Analysis, routine: u16) -> BTreeSet<u16> {
+        let mut callees = BTreeSet::new();
+        let mut seen = BTreeSet::new();
+        let mut work = vec![routine];
+        while let Some(mut addr) = work.pop() {
+            while let Some(line) = analysis.code.get(&addr) {
+                if !seen.insert(addr) {
+                    break;
+                }
+                let mut falls_through = true;
+                match line.decoded {
+                    Decoded::Base(Instruction::Jms { .. }) => callees.extend(line.target),
+                    Decoded::Base(Instruction::Jun { .. }) => {
+                        work.extend(line.target);
+                        falls_through = false;
+                    }
+                    Decoded::Base(Instruction::Jcn { .. } | Instruction::Isz { .. }) => {
+                        work.extend(line.target);
+                    }
+                    Decoded::Base(Instruction::Bbl { .. } | Instruction::Jin { .. })
+                    | Decoded::Ext(Opcode4040::Bbs) => falls_through = false,
+                    _ => {}
+                }
+                if !falls_through {
+                    break;
+                }
+                addr = line.next_address();
+            }
+        }
+        callees
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::CpuType;
+    use super::*;
+
+    /// reset: JUN 0x010; two unreached bytes; 0x010: JMS 0x020;
+    /// FIM P0, 0x30; FIN P1; JCN Z, 0x010; FIM P2, 0x40; JIN P2
+    /// 0x020: LDM 1; BBL 0; 0x030: table; 0x040: JUN 0x040
+    fn program() -> Vec<u8> {
+        let mut rom = vec![0u8; 0x50];
+        rom[0x000..0x004].copy_from_slice(&[0x40, 0x10, 0xFE, 0x41]);
+        rom[0x010..0x01B].copy_from_slice(&[
+            0x50, 0x20, 0x20, 0x30, 0x32, 0x14, 0x10, 0x24, 0x40, 0x35, 0x00,
+        ]);
+        rom[0x020..0x022].copy_from_slice(&[0xD1, 0xC0]);
+        rom[0x030] = 0x40;
+        rom[0x040..0x042].copy_from_slice(&[0x40, 0x40]);
+        rom
+    }
+
+    #[test]
+    fn test_code_data_separation() {
+        let dis = Disassembler::new(CpuType::I4004);
+        let rom = program();
+        let analysis = dis.analyze(&rom);
+
+        let code: Vec<u16> = analysis.code.keys().copied().collect();
+        assert_eq!(code, [0x000, 0x010, 0x012, 0x014, 0x015, 0x017, 0x019, 0x020, 0x021, 0x040]);
+        // The FIN table and the bytes after JUN are not code
+        assert!(analysis.data.contains(&0x030));
+        assert!(!analysis.covers(0x002));
+        assert!(analysis.covers(0x011));
+        assert!(!analysis.is_code(0x011));
+    }
+
+    #[test]
+    fn test_labels_xrefs_and_calls() {
+        let dis = Disassembler::new(CpuType::I4004);
+        let analysis = dis.analyze(&program());
+
+        assert_eq!(analysis.labels.get(0x000), Some("reset"));
+        assert_eq!(analysis.labels.get(0x010), Some("loc_010"));
+        assert_eq!(analysis.labels.get(0x020), Some("sub_020"));
+        assert_eq!(analysis.labels.get(0x030), Some("tbl_030"));
+        assert_eq!(analysis.labels.get(0x040), Some("loc_040"));
+
+        let refs: Vec<u16> = analysis.xrefs[&0x010].iter().copied().collect();
+        assert_eq!(refs, [0x000, 0x015]);
+        assert_eq!(analysis.xrefs[&0x030].iter().copied().collect::<Vec<_>>(), [0x014]);
+
+        assert_eq!(analysis.calls[&0x000].iter().copied().collect::<Vec<_>>(), [0x020]);
+        assert!(analysis.calls[&0x020].is_empty());
+    }
+
+    #[test]
+    fn test_listing() {
+        let mut dis = Disassembler::new(CpuType::I4004);
+        dis.add_symbol(0x020, "one");
+        let rom = program();
+        let listing = dis.analyze(&rom).listing(&dis, &rom);
+
+        assert!(listing.contains("reset:  ; calls one\n    000  40 10  JUN loc_010\n"));
+        assert!(listing.contains("    002  FE     DB 0xFE\n    003  41     DB 0x41\n"));
+        assert!(listing.contains("loc_010:  ; xref 000, 015\n"));
+        assert!(listing.contains("    010  50 20  JMS one\n"));
+        assert!(listing.contains("tbl_030:  ; xref 014\n    030  40     DB 0x40\n"));
+    }
+
+    #[test]
+    fn test_interrupt_vector() {
+        // 0x000: JUN 0x000 never reaches 0x003; the 4040 enters there on INT
+        let rom = [0x40, 0x00, 0x00, 0xD5, 0x02];
+        let analysis = Disassembler::new(CpuType::I4004).analyze(&rom);
+        assert!(!analysis.is_code(0x003));
+
+        let analysis = Disassembler::new(CpuType::I4040).analyze(&rom);
+        assert!(analysis.is_code(0x003));
+        assert!(analysis.is_code(0x004));
+        assert_eq!(analysis.labels.get(0x003), Some("interrupt"));
+    }
+}
@@ -0,0 +1,358 @@
+//! 4004/4040 disassembler
+//!
+//! Decodes through the CPU's own [`InstructionDecoder`] (plus the 4040
+//! extensions) so a listing always agrees with what executes. Output uses
+//! Intel syntax: `JCN NZ, 0x1A`, `FIM P0, 0x42`, `SRC P3`.
+//!
+//! Short jumps (JCN, ISZ) and the indirect FIN/JIN stay within the page of
+//! the *following* instruction, so one sitting at the end of a page reaches
+//! into the next.
+
+mod analysis;
+mod symbols;
+
+pub use analysis::{Analysis, INTERRUPT_VECTOR, RESET_VECTOR};
+pub use symbols::SymbolTable;
+
+use std::fmt;
+
+use crate::i4004::{Instruction, InstructionDecoder};
+use crate::i4040::{decode_ext, Opcode4040};
+
+/// Instruction set to decode
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CpuType {
+    /// 46 instructions
+    #[default]
+    I4004,
+    /// 60 instructions (4004 set plus the OPR=0 extensions)
+    I4040,
+}
+
+/// What a disassembled byte sequence decodes to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decoded {
+    /// A 4004 instruction (also part of the 4040 set)
+    Base(Instruction),
+    /// A 4040-only instruction
+    Ext(Opcode4040),
+}
+
+/// One disassembled instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisasmLine {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    pub operands: String,
+    pub decoded: Decoded,
+    /// Resolved destination of JCN, JUN, JMS and ISZ
+    pub target: Option<u16>,
+    /// Page FIN reads its table from, or JIN jumps into
+    pub indirect_page: Option<u16>,
+}
+
+impl DisasmLine {
+    /// Instruction length in bytes
+    pub fn length(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    /// Address of the following instruction
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.length()) & 0x0FFF
+    }
+
+    /// Mnemonic and operands, e.g. `JCN NZ, 0x1A`
+    pub fn text(&self) -> String {
+        if self.operands.is_empty() {
+            self.mnemonic.clone()
+        } else {
+            format!("{} {}", self.mnemonic, self.operands)
+        }
+    }
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "{:03X}  {:<6} {}", self.address, bytes.join(" "), self.text())
+    }
+}
+
+/// Destination of a short jump or indirect access: `low` within the page of
+/// the instruction following the `len`-byte one at `addr`
+pub fn page_target(addr: u16, len: u16, low: u8) -> u16 {
+    (addr.wrapping_add(len) & 0x0F00) | low as u16
+}
+
+/// JCN condition in assembler syntax: T, NT, C, NC, Z, NZ, or the raw code
+pub fn condition_name(condition: u8) -> String {
+    match condition & 0x0F {
+        0x1 => "T".into(),
+        0x9 => "NT".into(),
+        0x2 => "C".into(),
+        0xA => "NC".into(),
+        0x4 => "Z".into(),
+        0xC => "NZ".into(),
+        code => format!("0x{:X}", code),
+    }
+}
+
+/// Disassembler for MCS-4 (4004) and MCS-40 (4040) instruction sets
+#[derive(Clone, Debug, Default)]
+pub struct Disassembler {
+    pub cpu_type: CpuType,
+    /// Labels shown in place of jump targets
+    pub symbols: SymbolTable,
+}
+
+impl Disassembler {
+    pub fn new(cpu_type: CpuType) -> Self {
+        Self { cpu_type, symbols: SymbolTable::new() }
+    }
+
+    /// Disassembler that labels targets from `symbols`
+    pub fn with_symbols(cpu_type: CpuType, symbols: SymbolTable) -> Self {
+        Self { cpu_type, symbols }
+    }
+
+    /// Add symbol at address
+    pub fn add_symbol(&mut self, addr: u16, name: &str) {
+        self.symbols.insert(addr, name);
+    }
+
+    /// Disassemble the instruction at `addr` (bytes past the end read as 0)
+    pub fn disasm_one(&self, rom: &[u8], addr: u16) -> DisasmLine {
+        let addr = addr & 0x0FFF;
+        let byte = |a: u16| rom.get((a & 0x0FFF) as usize).copied().unwrap_or(0x00);
+        let op = byte(addr);
+
+        if self.cpu_type == CpuType::I4040 {
+            if let Some(ext) = decode_ext(op) {
+                return DisasmLine {
+                    address: addr,
+                    bytes: vec![op],
+                    mnemonic: ext.mnemonic().into(),
+                    operands: String::new(),
+                    decoded: Decoded::Ext(ext),
+                    target: None,
+                    indirect_page: None,
+                };
+            }
+        }
+
+        let mut decoder = InstructionDecoder::new();
+        decoder.decode_first(op);
+        let mut bytes = vec![op];
+        if decoder.needs_second_byte() {
+            let operand = byte(addr + 1);
+            decoder.decode_second(operand);
+            bytes.push(operand);
+        }
+        let instr = decoder.get_instruction().unwrap_or(Instruction::Invalid { opcode: op });
+        let len = bytes.len() as u16;
+
+        let mut target = None;
+        let mut indirect_page = None;
+        let operands = match instr {
+            Instruction::Jcn { condition, addr_low } => {
+                let dest = page_target(addr, len, addr_low);
+                target = Some(dest);
+                format!("{}, {}", condition_name(condition), self.address_name(dest))
+            }
+            Instruction::Isz { reg, addr_low } => {
+                let dest = page_target(addr, len, addr_low);
+                target = Some(dest);
+                format!("R{}, {}", reg, self.address_name(dest))
+            }
+            Instruction::Jun { addr_high, addr_low } | Instruction::Jms { addr_high, addr_low } => {
+                let dest = ((addr_high as u16) << 8) | addr_low as u16;
+                target = Some(dest);
+                self.address_name(dest)
+            }
+            Instruction::Fin { pair } | Instruction::Jin { pair } => {
+                indirect_page = Some(page_target(addr, len, 0));
+                format!("P{}", pair)
+            }
+            Instruction::Fim { pair, data } => format!("P{}, 0x{:02X}", pair, data),
+            Instruction::Src { pair } => format!("P{}", pair),
+            Instruction::Inc { reg }
+            | Instruction::Add { reg }
+            | Instruction::Sub { reg }
+            | Instruction::Ld { reg }
+            | Instruction::Xch { reg } => format!("R{}", reg),
+            Instruction::Bbl { data } | Instruction::Ldm { data } => format!("{}", data),
+            Instruction::Invalid { opcode } => format!("0x{:02X}", opcode),
+            _ => String::new(),
+        };
+        let mnemonic = match instr {
+            // Unassigned opcodes are shown as data
+            Instruction::Invalid { .. } => "DB",
+            _ => instr.mnemonic(),
+        };
+
+        DisasmLine {
+            address: addr,
+            bytes,
+            mnemonic: mnemonic.into(),
+            operands,
+            decoded: Decoded::Base(instr),
+            target,
+            indirect_page,
+        }
+    }
+
+    /// Disassemble linearly from `start` through `end` (inclusive)
+    pub fn disasm_range(&self, rom: &[u8], start: u16, end: u16) -> Vec<DisasmLine> {
+        let mut lines = Vec::new();
+        let mut addr = start as u32;
+        while addr <= end as u32 && addr <= 0x0FFF {
+            let line = self.disasm_one(rom, addr as u16);
+            addr += line.length() as u32;
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Disassemble an entire ROM image linearly
+    pub fn disasm_all(&self, rom: &[u8]) -> Vec<DisasmLine> {
+        match rom.len() {
+            0 => Vec::new(),
+            len => self.disasm_range(rom, 0, (len - 1).min(0x0FFF) as u16),
+        }
+    }
+
+    /// Format lines as an assembly listing, with a label line wherever the
+    /// symbol table names an address
+    pub fn format_listing(&self, lines: &[DisasmLine]) -> String {
+        let mut out = String::new();
+        for line in lines {
+            if let Some(label) = self.symbols.get(line.address) {
+                out.push_str(label);
+                out.push_str(":\n");
+            }
+            out.push_str(&format!("    {}\n", line));
+        }
+        out
+    }
+
+    fn address_name(&self, addr: u16) -> String {
+        match self.symbols.get(addr) {
+            Some(label) => label.to_string(),
+            None => format!("0x{:02X}", addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(dis: &Disassembler, rom: &[u8], addr: u16) -> String {
+        dis.disasm_one(rom, addr).text()
+    }
+
+    #[test]
+    fn test_4004_instructions() {
+        let dis = Disassembler::new(CpuType::I4004);
+        let cases: &[(&[u8], &str)] = &[
+            (&[0x00], "NOP"),
+            (&[0x1C, 0x1A], "JCN NZ, 0x1A"),
+            (&[0x19, 0x05], "JCN NT, 0x05"),
+            (&[0x16, 0x05], "JCN 0x6, 0x05"),
+            (&[0x20, 0x42], "FIM P0, 0x42"),
+            (&[0x27], "SRC P3"),
+            (&[0x32], "FIN P1"),
+            (&[0x35], "JIN P2"),
+            (&[0x41, 0x23], "JUN 0x123"),
+            (&[0x5F, 0xFF], "JMS 0xFFF"),
+            (&[0x6A], "INC R10"),
+            (&[0x75, 0x10], "ISZ R5, 0x10"),
+            (&[0x81], "ADD R1"),
+            (&[0x92], "SUB R2"),
+            (&[0xA3], "LD R3"),
+            (&[0xB4], "XCH R4"),
+            (&[0xC1], "BBL 1"),
+            (&[0xDB], "LDM 11"),
+            (&[0xE0], "WRM"),
+            (&[0xE2], "WRR"),
+            (&[0xEF], "RD3"),
+            (&[0xF0], "CLB"),
+            (&[0xFD], "DCL"),
+            (&[0xFE], "DB 0xFE"),
+        ];
+        for (rom, expected) in cases {
+            let line = dis.disasm_one(rom, 0);
+            assert_eq!(line.text(), *expected);
+            assert_eq!(line.bytes, rom.to_vec());
+        }
+
+        // The 4004 runs the 4040 extension row as NOP
+        assert_eq!(text(&dis, &[0x01], 0), "NOP");
+    }
+
+    #[test]
+    fn test_4040_extensions() {
+        let dis = Disassembler::new(CpuType::I4040);
+        let rom = [0x01, 0x02, 0x03, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F];
+        let names: Vec<String> = dis.disasm_all(&rom).iter().map(|l| l.text()).collect();
+        assert_eq!(
+            names,
+            ["HLT", "BBS", "LCR", "DB0", "DB1", "SB0", "SB1", "EIN", "DIN", "RPM", "NOP"]
+        );
+        assert_eq!(text(&dis, &[0x04], 0), "OR4");
+        assert_eq!(text(&dis, &[0x07], 0), "AN7");
+        // The base set is unchanged
+        assert_eq!(text(&dis, &[0x20, 0x42], 0), "FIM P0, 0x42");
+    }
+
+    #[test]
+    fn test_page_crossing() {
+        let dis = Disassembler::new(CpuType::I4004);
+        let mut rom = vec![0u8; 0x200];
+
+        // JCN in the middle of a page stays there
+        rom[0x080] = 0x14;
+        rom[0x081] = 0x20;
+        assert_eq!(dis.disasm_one(&rom, 0x080).target, Some(0x020));
+
+        // JCN whose second byte ends the page jumps into the next page
+        rom[0x0FE] = 0x14;
+        rom[0x0FF] = 0x20;
+        assert_eq!(dis.disasm_one(&rom, 0x0FE).target, Some(0x120));
+
+        // ISZ straddling the page boundary
+        rom[0x1FF] = 0x73;
+        assert_eq!(dis.disasm_one(&rom, 0x1FF).bytes, vec![0x73, 0x00]);
+
+        // FIN/JIN in the last byte of a page use the next page
+        rom[0x0FF] = 0x30;
+        let fin = dis.disasm_one(&rom, 0x0FF);
+        assert_eq!(fin.indirect_page, Some(0x100));
+        assert_eq!(dis.disasm_one(&rom, 0x0FE).indirect_page, None);
+        rom[0x0FE] = 0x31;
+        assert_eq!(dis.disasm_one(&rom, 0x0FE).indirect_page, Some(0x000));
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut symbols: SymbolTable = [(0x010, "loop"), (0x200, "print")].into_iter().collect();
+        symbols.insert(0x005, "start");
+        assert_eq!(symbols.address_of("print"), Some(0x200));
+
+        let dis = Disassembler::with_symbols(CpuType::I4004, symbols);
+        // JMS print; ISZ R2, loop; JUN 0x300
+        let mut rom = vec![0x52, 0x00, 0x72, 0x10, 0x43, 0x00];
+        rom.resize(0x20, 0);
+        let lines = dis.disasm_range(&rom, 0, 5);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].text(), "JMS print");
+        assert_eq!(lines[1].text(), "ISZ R2, loop");
+        assert_eq!(lines[2].text(), "JUN 0x300");
+        assert_eq!(lines[2].next_address(), 6);
+
+        let listing = dis.format_listing(&dis.disasm_range(&rom, 0x010, 0x010));
+        assert_eq!(listing, "loop:\n    010  00     NOP\n");
+    }
+}
@@ -0,0 +1,63 @@
+//! Symbol table: program addresses to labels
+
+use std::collections::BTreeMap;
+
+/// Labels keyed by 12-bit program address
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    labels: BTreeMap<u16, String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name an address, replacing any existing label there
+    pub fn insert(&mut self, addr: u16, name: &str) {
+        self.labels.insert(addr & 0x0FFF, name.to_string());
+    }
+
+    /// Remove the label at an address
+    pub fn remove(&mut self, addr: u16) -> Option<String> {
+        self.labels.remove(&(addr & 0x0FFF))
+    }
+
+    /// Label at an address, if any
+    pub fn get(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&(addr & 0x0FFF)).map(String::as_str)
+    }
+
+    /// Address of a label, if defined
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.labels
+            .iter()
+            .find(|(_, label)| label.as_str() == name)
+            .map(|(&addr, _)| addr)
+    }
+
+    /// Iterate over (address, label) in address order
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> + '_ {
+        self.labels.iter().map(|(&addr, label)| (addr, label.as_str()))
+    }
+
+    /// Number of labels
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    /// Is the table empty?
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<(u16, S)> for SymbolTable {
+    fn from_iter<I: IntoIterator<Item = (u16, S)>>(iter: I) -> Self {
+        let mut table = Self::new();
+        for (addr, name) in iter {
+            table.insert(addr, name.as_ref());
+        }
+        table
+    }
+}
@@ -0,0 +1,67 @@
+//! Intel 3205 1-of-8 Binary Decoder
+//!
+//! Schottky bipolar 3-to-8 decoder (the Intel part for a 74138). In MCS-4
+//! systems with more than four RAM banks it sits on CM-RAM1-3: the code the
+//! CPU latched with DCL selects one of eight outputs, each strobing a bank
+//! of 4002s. Outputs are modelled active high.
+
+use rkyv::{Archive, Deserialize, Serialize};
+use mcs4_bus::BusCycle;
+
+/// Intel 3205: 1-of-8 binary decoder
+#[derive(Clone, Debug, Default, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+pub struct I3205 {
+    /// Active output (0-7), if the decoder is enabled
+    active: Option<u8>,
+}
+
+impl I3205 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drive the select inputs A0-A2 and the (combined) enable
+    ///
+    /// Returns the output that goes active, or `None` when disabled.
+    pub fn decode(&mut self, select: u8, enabled: bool) -> Option<u8> {
+        self.active = enabled.then_some(select & 0x07);
+        self.active
+    }
+
+    /// Is output `n` (0-7) active?
+    pub fn output(&self, n: u8) -> bool {
+        self.active == Some(n)
+    }
+}
+
+impl super::Chip for I3205 {
+    fn name(&self) -> &'static str {
+        "3205"
+    }
+
+    fn reset(&mut self) {
+        self.active = None;
+    }
+
+    fn tick(&mut self, _phase: BusCycle) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let mut dec = I3205::new();
+        assert_eq!(dec.decode(5, true), Some(5));
+        assert!(dec.output(5));
+        assert!(!dec.output(4));
+
+        // Only A0-A2 are inputs
+        assert_eq!(dec.decode(0x0B, true), Some(3));
+
+        assert_eq!(dec.decode(5, false), None);
+        assert!(!dec.output(5));
+    }
+}
@@ -3,10 +3,12 @@
 //! The 4001 is a 256x8-bit ROM with a 4-bit I/O port.
 //! Up to 16 4001 chips can be addressed in an MCS-4 system.
 
+use rkyv::{Archive, Deserialize, Serialize};
 use mcs4_bus::prelude::*;
 
 /// Intel 4001: 256x8 ROM with 4-bit I/O port
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct I4001 {
     /// ROM contents (256 bytes)
     rom: [u8; 256],
@@ -20,12 +22,21 @@ pub struct I4001 {
     /// Chip select ID (0-15), set at construction
     pub chip_id: u8,
 
+    /// CM-ROM line (0-3) this chip is wired to
+    pub cm_rom_line: u8,
+
     /// Latched address from A1/A2/A3 phases
     address: u8,
 
     /// Is this chip selected for current transaction?
     selected: bool,
 
+    /// Is this chip's I/O port the target of the last SRC?
+    io_selected: bool,
+
+    /// I/O command (OPA) latched at X1 of an I/O instruction
+    command: Option<u8>,
+
     /// Current phase tracking
     phase: BusCycle,
 }
@@ -38,12 +49,25 @@ impl I4001 {
             io_output: 0,
             io_input: 0,
             chip_id: chip_id & 0x0F,
+            cm_rom_line: 0,
             address: 0,
             selected: false,
+            io_selected: false,
+            command: None,
             phase: BusCycle::A1,
         }
     }
 
+    /// Create a 4001 on a given CM-ROM line (0-3)
+    ///
+    /// The 4040 fetches its second ROM bank over CM-ROM1.
+    pub fn with_cm_rom(chip_id: u8, line: u8) -> Self {
+        Self {
+            cm_rom_line: line & 0x03,
+            ..Self::new(chip_id)
+        }
+    }
+
     /// Load ROM contents from a byte slice
     pub fn load(&mut self, data: &[u8]) {
         let len = data.len().min(256);
@@ -94,6 +118,35 @@ impl I4001 {
         self.selected
     }
 
+    /// Set the SRC address (latched from the bus at X2 of SRC, or set
+    /// directly by instruction-level systems)
+    ///
+    /// The upper nibble picks the 4001 whose I/O port later I/O
+    /// instructions address.
+    pub fn set_src_address(&mut self, address: u8) {
+        self.io_selected = (address >> 4) == self.chip_id;
+    }
+
+    /// Is this chip's I/O port selected by the last SRC?
+    pub fn is_io_selected(&self) -> bool {
+        self.io_selected
+    }
+
+    /// Carry out an I/O command (OPA of an 0xE_ instruction) on the port
+    ///
+    /// WRR latches `value` on the outputs and RDR returns the inputs; the
+    /// RAM commands return `None`.
+    pub fn execute_io(&mut self, command: u8, value: u8) -> Option<u8> {
+        match command & 0x0F {
+            0x2 => {
+                self.io_output = value & 0x0F;
+                None
+            }
+            0xA => Some(self.io_input),
+            _ => None,
+        }
+    }
+
     /// Process a bus phase
     pub fn tick_bus(&mut self, phase: BusCycle, bus: &mut DataBus, ctrl: &ControlSignals) {
         self.phase = phase;
@@ -109,39 +162,46 @@ impl I4001 {
                 self.address = (self.address & 0x0F) | ((bus.read() & 0x0F) << 4);
             }
             BusCycle::A3 => {
-                // Check if we're selected by CM-ROM
-                let rom_select = ctrl.cm_rom();
-                self.selected = rom_select == self.chip_id;
+                // Selected when our CM-ROM line strobes our chip number
+                self.selected = ctrl.cm_rom_line(self.cm_rom_line as usize)
+                    && (bus.read() & 0x0F) == self.chip_id;
             }
             BusCycle::M1 => {
-                // Output OPA (lower nibble of instruction) if selected
+                // Output OPR (upper nibble of instruction) if selected
                 if self.selected {
                     let data = self.rom[self.address as usize];
-                    bus.write(data & 0x0F);
+                    bus.write((data >> 4) & 0x0F);
                 }
             }
             BusCycle::M2 => {
-                // Output OPR (upper nibble of instruction) if selected
+                // Output OPA (lower nibble of instruction) if selected
                 if self.selected {
                     let data = self.rom[self.address as usize];
-                    bus.write((data >> 4) & 0x0F);
+                    bus.write(data & 0x0F);
                 }
             }
             BusCycle::X1 => {
-                // I/O operations happen during X phases
+                // CM-ROM strobed at M2 marks an I/O instruction: latch OPA,
+                // still on the bus, as the command
+                self.command = (self.io_selected
+                    && ctrl.cm_rom_line(self.cm_rom_line as usize))
+                .then(|| bus.read() & 0x0F);
             }
             BusCycle::X2 => {
-                // WRR: Write ROM port (from accumulator via bus)
-                if self.selected && ctrl.is_io_write() {
-                    self.io_output = bus.read() & 0x0F;
+                // SRC: CM-ROM with the chip number on the bus selects the
+                // port for the following I/O instructions
+                if ctrl.cm_rom_line(self.cm_rom_line as usize) {
+                    self.set_src_address(bus.read() << 4);
                 }
-            }
-            BusCycle::X3 => {
-                // RDR: Read ROM port (to accumulator via bus)
-                if self.selected && ctrl.is_io_read() {
-                    bus.write(self.io_input);
+                // WRR takes the accumulator from the bus; RDR drives the
+                // port for the CPU to sample at X3
+                if let Some(command) = self.command.take() {
+                    if let Some(value) = self.execute_io(command, bus.read()) {
+                        bus.write(value);
+                    }
                 }
             }
+            BusCycle::X3 => {}
         }
     }
 }
@@ -161,6 +221,8 @@ impl super::Chip for I4001 {
         self.io_output = 0;
         self.address = 0;
         self.selected = false;
+        self.io_selected = false;
+        self.command = None;
         self.phase = BusCycle::A1;
     }
 
@@ -198,6 +260,93 @@ mod tests {
         assert_eq!(rom.io_input(), 0x0F);
     }
 
+    #[test]
+    fn test_fetch_over_bus() {
+        let mut rom = I4001::with_cm_rom(2, 1);
+        rom.write_direct(0x34, 0xA7);
+        let mut bus = DataBus::new();
+        let mut ctrl = ControlSignals::mcs40();
+
+        let mut fetch = |rom: &mut I4001, line: usize| {
+            let mut nibbles = Vec::new();
+            for (phase, nibble) in [(BusCycle::A1, 0x4), (BusCycle::A2, 0x3), (BusCycle::A3, 0x2)] {
+                bus.write(nibble);
+                if phase == BusCycle::A3 {
+                    ctrl.assert_cm_rom(line, 0);
+                }
+                rom.tick_bus(phase, &mut bus, &ctrl);
+            }
+            ctrl.deselect_rom(0);
+            for phase in [BusCycle::M1, BusCycle::M2] {
+                bus.write(0);
+                rom.tick_bus(phase, &mut bus, &ctrl);
+                nibbles.push(bus.read());
+            }
+            nibbles
+        };
+
+        // Wrong CM-ROM line: chip stays off the bus
+        assert_eq!(fetch(&mut rom, 0), vec![0, 0]);
+        // OPR then OPA
+        assert_eq!(fetch(&mut rom, 1), vec![0xA, 0x7]);
+    }
+
+    #[test]
+    fn test_io_commands_over_bus() {
+        let mut rom = I4001::new(3);
+        rom.set_io_input(0x6);
+        let mut bus = DataBus::new();
+        let mut ctrl = ControlSignals::mcs4();
+
+        let mut io = |rom: &mut I4001, opa: u8, acc: u8| {
+            ctrl.assert_cm_rom(0, 0);
+            bus.write(opa);
+            rom.tick_bus(BusCycle::X1, &mut bus, &ctrl);
+            ctrl.deselect_rom(0);
+            bus.write(acc);
+            rom.tick_bus(BusCycle::X2, &mut bus, &ctrl);
+            bus.read()
+        };
+
+        // Not selected by SRC: WRR is ignored
+        io(&mut rom, 0x2, 0x9);
+        assert_eq!(rom.io_output(), 0);
+
+        rom.set_src_address(0x35);
+        assert!(rom.is_io_selected());
+        io(&mut rom, 0x2, 0x9);
+        assert_eq!(rom.io_output(), 0x9);
+        // RDR drives the input port
+        assert_eq!(io(&mut rom, 0xA, 0x0), 0x6);
+        // WRM is a RAM command: port untouched
+        io(&mut rom, 0x0, 0x1);
+        assert_eq!(rom.io_output(), 0x9);
+    }
+
+    #[test]
+    fn test_src_over_bus() {
+        let mut rom = I4001::with_cm_rom(4, 1);
+        let mut bus = DataBus::new();
+        let mut ctrl = ControlSignals::mcs40();
+
+        let mut src = |rom: &mut I4001, line: usize, address: u8| {
+            bus.write(address >> 4);
+            ctrl.assert_cm_rom(line, 0);
+            rom.tick_bus(BusCycle::X2, &mut bus, &ctrl);
+            bus.write(address & 0x0F);
+            ctrl.deselect_rom(0);
+            rom.tick_bus(BusCycle::X3, &mut bus, &ctrl);
+        };
+
+        src(&mut rom, 1, 0x4C);
+        assert!(rom.is_io_selected());
+        // Same chip number on another CM-ROM line leaves the selection alone
+        src(&mut rom, 0, 0x3C);
+        assert!(rom.is_io_selected());
+        src(&mut rom, 1, 0x3C);
+        assert!(!rom.is_io_selected());
+    }
+
     #[test]
     fn test_chip_id() {
         let rom = I4001::new(7);
@@ -1,18 +1,20 @@
 //! Intel 4002 RAM + Output
 //!
 //! The 4002 is a 320-bit RAM with a 4-bit output port.
-//! Memory organization: 4 registers x 16 characters x 4 bits + 4 status characters x 4 bits
+//! Memory organization: 4 registers x (16 characters + 4 status characters) x 4 bits
 
+use rkyv::{Archive, Deserialize, Serialize};
 use mcs4_bus::prelude::*;
 
 /// Intel 4002: 320-bit RAM with 4-bit output port
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct I4002 {
     /// RAM: 4 registers x 16 nibbles (main memory)
     ram: [[u8; 16]; 4],
 
-    /// Status registers: 4 nibbles (one per register)
-    status: [u8; 4],
+    /// Status characters: 4 nibbles per register
+    status: [[u8; 4]; 4],
 
     /// Output port latch
     output: u8,
@@ -20,7 +22,8 @@ pub struct I4002 {
     /// Chip ID within bank (0-3)
     pub chip_id: u8,
 
-    /// Bank ID (0-3, selected by CM-RAM lines)
+    /// Bank ID: CM-RAM line 0-3 when wired directly, or 0-7 behind a
+    /// 3-to-8 decoder on CM-RAM1-3
     pub bank_id: u8,
 
     /// Latched register select from SRC command
@@ -29,25 +32,33 @@ pub struct I4002 {
     /// Latched character address from SRC command
     selected_char: u8,
 
-    /// Is this chip selected for current transaction?
+    /// Is this chip the target of the last SRC?
     selected: bool,
 
+    /// I/O command (OPA) latched at X1 of an I/O instruction
+    command: Option<u8>,
+
+    /// SRC high nibble seen at X2; the character follows at X3
+    src_pending: bool,
+
     /// Current phase tracking
     phase: BusCycle,
 }
 
 impl I4002 {
-    /// Create a new 4002 RAM with specified chip ID (0-3) and bank (0-3)
+    /// Create a new 4002 RAM with specified chip ID (0-3) and bank (0-7)
     pub fn new(chip_id: u8, bank_id: u8) -> Self {
         Self {
             ram: [[0; 16]; 4],
-            status: [0; 4],
+            status: [[0; 4]; 4],
             output: 0,
             chip_id: chip_id & 0x03,
-            bank_id: bank_id & 0x03,
+            bank_id: bank_id & 0x07,
             selected_register: 0,
             selected_char: 0,
             selected: false,
+            command: None,
+            src_pending: false,
             phase: BusCycle::A1,
         }
     }
@@ -67,14 +78,14 @@ impl I4002 {
         self.ram[(reg & 3) as usize][(char_idx & 0x0F) as usize] = value & 0x0F;
     }
 
-    /// Read status character (direct access)
-    pub fn read_status(&self, reg: u8) -> u8 {
-        self.status[(reg & 3) as usize] & 0x0F
+    /// Read status character `index` of a register (direct access)
+    pub fn read_status(&self, reg: u8, index: u8) -> u8 {
+        self.status[(reg & 3) as usize][(index & 3) as usize] & 0x0F
     }
 
-    /// Write status character (direct access)
-    pub fn write_status(&mut self, reg: u8, value: u8) {
-        self.status[(reg & 3) as usize] = value & 0x0F;
+    /// Write status character `index` of a register (direct access)
+    pub fn write_status(&mut self, reg: u8, index: u8, value: u8) {
+        self.status[(reg & 3) as usize][(index & 3) as usize] = value & 0x0F;
     }
 
     /// Get output port value
@@ -97,56 +108,99 @@ impl I4002 {
         self.selected
     }
 
-    /// Set the SRC address (called by system when CPU executes SRC)
+    /// Register and character picked by the last SRC that selected this chip
+    pub fn selected_address(&self) -> (u8, u8) {
+        (self.selected_register, self.selected_char)
+    }
+
+    /// Set the SRC address (latched from the bus by `tick_bus`, or set
+    /// directly by instruction-level systems)
+    ///
+    /// Only the addressed chip takes the register/character; every other
+    /// chip deselects itself until the next SRC.
     pub fn set_src_address(&mut self, chip: u8, reg: u8, char_addr: u8) {
-        if (chip & 0x03) == self.chip_id {
+        self.selected = (chip & 0x03) == self.chip_id;
+        if self.selected {
             self.selected_register = reg & 0x03;
             self.selected_char = char_addr & 0x0F;
         }
     }
 
     /// Process a bus phase
+    ///
+    /// SRC asserts CM-RAM at X2 with the chip/register nibble on the bus and
+    /// sends the character at X3; every chip in the bank latches it.
+    ///
+    /// An I/O instruction strobes CM-RAM at M2; the SRC-selected chip in the
+    /// strobed bank latches OPA at X1 and decodes it at X2: writes store the
+    /// accumulator the CPU put on the bus, reads drive the addressed nibble.
+    ///
+    /// The chip is wired straight to CM-RAM line `bank_id`; use
+    /// [`I4002::tick_bus_strobed`] when the bank strobe comes from a decoder.
     pub fn tick_bus(&mut self, phase: BusCycle, bus: &mut DataBus, ctrl: &ControlSignals) {
-        self.phase = phase;
+        self.tick_bus_strobed(phase, bus, ctrl.cm_ram_line(self.bank_id as usize));
+    }
 
-        // Check if we're selected by CM-RAM bank
-        let ram_bank = ctrl.cm_ram();
-        let bank_selected = ram_bank == self.bank_id;
+    /// Process a bus phase with this chip's bank strobe supplied externally
+    pub fn tick_bus_strobed(&mut self, phase: BusCycle, bus: &mut DataBus, cm_ram: bool) {
+        self.phase = phase;
 
         match phase {
             BusCycle::A1 | BusCycle::A2 | BusCycle::A3 | BusCycle::M1 | BusCycle::M2 => {
                 // Address and memory phases - RAM doesn't respond
             }
             BusCycle::X1 => {
-                // Check selection - in real hardware this would be from SRC
-                self.selected = bank_selected;
+                self.command = (self.selected && cm_ram).then(|| bus.read() & 0x0F);
             }
             BusCycle::X2 => {
-                // Write operations during X2
-                if self.selected {
-                    if ctrl.is_io_write() {
-                        // This could be WRM, WMP, or WRx
-                        // For WRM: write to RAM
-                        // For WMP: write to output port
-                        // For WRx: write to status register x
-                        // The exact operation is determined by the instruction decoder
-                        // For now, we store to RAM as the default
-                        let value = bus.read() & 0x0F;
-                        self.ram[self.selected_register as usize][self.selected_char as usize] = value;
-                    }
+                if cm_ram {
+                    let nibble = bus.read() & 0x0F;
+                    self.selected = (nibble >> 2) == self.chip_id;
+                    self.selected_register = nibble & 0x03;
+                    self.src_pending = true;
+                }
+                if let Some(command) = self.command.take() {
+                    self.execute_command(command, bus);
                 }
             }
             BusCycle::X3 => {
-                // Read operations during X3
-                if self.selected && ctrl.is_io_read() {
-                    // RDM: read from RAM
-                    let value = self.ram[self.selected_register as usize][self.selected_char as usize];
-                    bus.write(value);
+                if std::mem::take(&mut self.src_pending) {
+                    self.selected_char = bus.read() & 0x0F;
                 }
             }
         }
     }
 
+    /// Carry out a latched I/O command at X2
+    fn execute_command(&mut self, command: u8, bus: &mut DataBus) {
+        if let Some(value) = self.execute_io(command, bus.read()) {
+            bus.write(value);
+        }
+    }
+
+    /// Carry out an I/O command (OPA of an 0xE_ instruction) on the
+    /// SRC-selected character
+    ///
+    /// Writes store `value`; reads return the nibble for the CPU. Commands
+    /// for the 4001s return `None`.
+    pub fn execute_io(&mut self, command: u8, value: u8) -> Option<u8> {
+        match command & 0x0F {
+            // WRM
+            0x0 => self.wrm(value),
+            // WMP
+            0x1 => self.wmp(value),
+            // WR0-WR3
+            command @ 0x4..=0x7 => self.wrx(command - 0x4, value),
+            // SBM, RDM, ADM: the CPU does the arithmetic
+            0x8 | 0x9 | 0xB => return Some(self.rdm()),
+            // RD0-RD3
+            command @ 0xC..=0xF => return Some(self.rdx(command - 0xC)),
+            // WRR, WPM, RDR belong to the 4001s
+            _ => {}
+        }
+        None
+    }
+
     /// Write to RAM main memory (WRM instruction)
     pub fn wrm(&mut self, value: u8) {
         self.ram[self.selected_register as usize][self.selected_char as usize] = value & 0x0F;
@@ -162,14 +216,16 @@ impl I4002 {
         self.output = value & 0x0F;
     }
 
-    /// Write to status character (WR0-WR3 instructions)
+    /// Write to a status character of the selected register (WR0-WR3
+    /// instructions)
     pub fn wrx(&mut self, status_idx: u8, value: u8) {
-        self.status[(status_idx & 3) as usize] = value & 0x0F;
+        self.write_status(self.selected_register, status_idx, value);
     }
 
-    /// Read from status character (RD0-RD3 instructions)
+    /// Read from a status character of the selected register (RD0-RD3
+    /// instructions)
     pub fn rdx(&self, status_idx: u8) -> u8 {
-        self.status[(status_idx & 3) as usize] & 0x0F
+        self.read_status(self.selected_register, status_idx)
     }
 }
 
@@ -186,11 +242,13 @@ impl super::Chip for I4002 {
 
     fn reset(&mut self) {
         self.ram = [[0; 16]; 4];
-        self.status = [0; 4];
+        self.status = [[0; 4]; 4];
         self.output = 0;
         self.selected_register = 0;
         self.selected_char = 0;
         self.selected = false;
+        self.command = None;
+        self.src_pending = false;
         self.phase = BusCycle::A1;
     }
 
@@ -221,12 +279,18 @@ mod tests {
     fn test_status_registers() {
         let mut ram = I4002::new(0, 0);
 
-        ram.write_status(2, 0xC);
-        assert_eq!(ram.read_status(2), 0xC);
+        ram.write_status(1, 2, 0xC);
+        assert_eq!(ram.read_status(1, 2), 0xC);
+        assert_eq!(ram.read_status(0, 2), 0);
 
-        // Test via instruction-level API
+        // Test via instruction-level API: each register has its own four
+        ram.set_src_address(0, 3, 0);
         ram.wrx(3, 0x5);
         assert_eq!(ram.rdx(3), 0x5);
+        ram.set_src_address(0, 1, 0);
+        assert_eq!(ram.rdx(3), 0);
+        assert_eq!(ram.rdx(2), 0xC);
+        assert_eq!(ram.read_status(3, 3), 0x5);
     }
 
     #[test]
@@ -256,4 +320,70 @@ mod tests {
         assert_eq!(ram.rdm(), 0x7);
         assert_eq!(ram.read_direct(1, 8), 0x7);
     }
+
+    #[test]
+    fn test_io_commands_over_bus() {
+        let mut ram = I4002::new(1, 0);
+        let mut bus = DataBus::new();
+        let mut ctrl = ControlSignals::mcs4();
+
+        let mut io = |ram: &mut I4002, opa: u8, acc: u8| {
+            ctrl.assert_cm_ram(0, 0);
+            bus.write(opa);
+            ram.tick_bus(BusCycle::X1, &mut bus, &ctrl);
+            ctrl.deselect_ram(0);
+            bus.write(acc);
+            ram.tick_bus(BusCycle::X2, &mut bus, &ctrl);
+            bus.read()
+        };
+
+        // SRC addressed another chip: WRM is ignored
+        ram.set_src_address(0, 2, 3);
+        io(&mut ram, 0x0, 0x5);
+        assert_eq!(ram.read_direct(2, 3), 0);
+
+        ram.set_src_address(1, 2, 3);
+        io(&mut ram, 0x0, 0x5);
+        assert_eq!(ram.read_direct(2, 3), 0x5);
+        io(&mut ram, 0x6, 0xE);
+        assert_eq!(ram.read_status(2, 2), 0xE);
+        io(&mut ram, 0x1, 0x3);
+        assert_eq!(ram.output(), 0x3);
+
+        // Reads drive the selected nibble
+        assert_eq!(io(&mut ram, 0x9, 0x0), 0x5);
+        assert_eq!(io(&mut ram, 0xE, 0x0), 0xE);
+        // WRR is a ROM port command: RAM untouched
+        io(&mut ram, 0x2, 0x7);
+        assert_eq!(ram.read_direct(2, 3), 0x5);
+    }
+
+    #[test]
+    fn test_src_over_bus() {
+        let mut ram = I4002::new(2, 1);
+        ram.write_direct(3, 0xA, 0x6);
+        let mut bus = DataBus::new();
+        let mut ctrl = ControlSignals::mcs4();
+
+        let mut src = |ram: &mut I4002, line: usize, address: u8| {
+            bus.write(address >> 4);
+            ctrl.assert_cm_ram(line, 0);
+            ram.tick_bus(BusCycle::X2, &mut bus, &ctrl);
+            bus.write(address & 0x0F);
+            ctrl.deselect_ram(0);
+            ram.tick_bus(BusCycle::X3, &mut bus, &ctrl);
+        };
+
+        // Chip 2, register 3, character 0xA
+        src(&mut ram, 1, 0xBA);
+        assert!(ram.is_selected());
+        assert_eq!(ram.rdm(), 0x6);
+
+        // Another bank's SRC is ignored
+        src(&mut ram, 0, 0x00);
+        assert!(ram.is_selected());
+        // Another chip in our bank deselects us
+        src(&mut ram, 1, 0x4A);
+        assert!(!ram.is_selected());
+    }
 }
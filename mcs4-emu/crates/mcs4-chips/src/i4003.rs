@@ -1,23 +1,165 @@
-//! Intel 4003 Shift Register (stub)
+//! Intel 4003 Shift Register
+//!
+//! 10-bit serial-in, parallel-out shift register for output expansion
+//! (keyboard column scan, printer hammers, display digits). It is not on
+//! the data bus: its pins are wired to 4001/4002 output port bits, so a
+//! port write moves them. A rising edge on CP shifts DATA IN into Q0 and
+//! each stage into the next. Q9 drives SERIAL OUT, which feeds the DATA IN
+//! of a second 4003 sharing the clock. Q0-Q9 follow the register while E
+//! is high and are all low while it is low; SERIAL OUT is not gated.
+//! Levels are modelled active high.
+//!
+//! The model has no timing of its own and ignores the bus phases. The
+//! pins move only when something drives them, and mcs4-system samples
+//! the port bits at instruction boundaries: a shift lands at the end of
+//! the WRR or WMP that raises CP, not at the X2 where the port latches
+//! the new value. No instruction runs in between, so a program cannot
+//! tell the difference, but a phase-level trace of CP and Q0-Q9 would.
 
+use rkyv::{Archive, Deserialize, Serialize};
 use mcs4_bus::BusCycle;
 
 /// Intel 4003: 10-bit serial-in, parallel-out shift register
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct I4003 {
-    data: u16, // 10 bits
+    /// Q0 in bit 0 to Q9 in bit 9
+    data: u16,
+    /// CP level, for edge detection
+    clock: bool,
+    data_in: bool,
+    enable: bool,
+}
+
+impl Default for I4003 {
+    /// Empty, with the outputs enabled
+    fn default() -> Self {
+        Self {
+            data: 0,
+            clock: false,
+            data_in: false,
+            enable: true,
+        }
+    }
 }
 
 impl I4003 {
-    pub fn new() -> Self { Self::default() }
-    pub fn shift_in(&mut self, bit: bool) {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drive CP; a rising edge shifts in DATA IN. Returns whether it shifted.
+    pub fn set_clock(&mut self, level: bool) -> bool {
+        let rising = level && !self.clock;
+        self.clock = level;
+        if rising {
+            self.shift_in(self.data_in);
+        }
+        rising
+    }
+
+    pub fn set_data_in(&mut self, level: bool) {
+        self.data_in = level;
+    }
+
+    /// Drive E: high enables Q0-Q9
+    pub fn set_enable(&mut self, level: bool) {
+        self.enable = level;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enable
+    }
+
+    /// Shift `bit` into Q0 without a clock edge; returns the bit that
+    /// leaves Q9
+    pub fn shift_in(&mut self, bit: bool) -> bool {
+        let out = self.serial_out();
         self.data = ((self.data << 1) | (bit as u16)) & 0x3FF;
+        out
+    }
+
+    /// SERIAL OUT (Q9), whatever the enable
+    pub fn serial_out(&self) -> bool {
+        self.data & 0x200 != 0
+    }
+
+    /// The register, whatever the enable
+    pub fn contents(&self) -> u16 {
+        self.data
+    }
+
+    /// Q0-Q9 as driven on the pins
+    pub fn parallel_out(&self) -> u16 {
+        if self.enable {
+            self.data
+        } else {
+            0
+        }
     }
-    pub fn parallel_out(&self) -> u16 { self.data }
 }
 
 impl super::Chip for I4003 {
-    fn name(&self) -> &'static str { "4003" }
-    fn reset(&mut self) { self.data = 0; }
+    fn name(&self) -> &'static str {
+        "4003"
+    }
+
+    /// Clear the register; the pins stay as driven
+    fn reset(&mut self) {
+        self.data = 0;
+    }
+
+    /// Nothing happens on the bus phases; see the module docs for when
+    /// the pins move
     fn tick(&mut self, _phase: BusCycle) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_and_enable() {
+        let mut sr = I4003::new();
+        sr.set_data_in(true);
+        assert!(!sr.set_clock(false));
+        assert!(sr.set_clock(true));
+        // Held high or falling: no shift
+        assert!(!sr.set_clock(true));
+        assert!(!sr.set_clock(false));
+        sr.set_data_in(false);
+        sr.set_clock(true);
+        assert_eq!(sr.parallel_out(), 0b10);
+
+        sr.set_enable(false);
+        assert_eq!(sr.parallel_out(), 0);
+        assert_eq!(sr.contents(), 0b10);
+        sr.set_enable(true);
+
+        // Q9 leaves on the tenth shift
+        for _ in 0..8 {
+            assert!(!sr.shift_in(false));
+        }
+        assert!(sr.serial_out());
+        assert!(sr.shift_in(false));
+        assert_eq!(sr.contents(), 0);
+    }
+
+    #[test]
+    fn test_cascade() {
+        let mut chain = [I4003::new(), I4003::new()];
+        for bit in (0..20).map(|n| n == 0) {
+            for chip in chain.iter_mut() {
+                chip.set_clock(false);
+            }
+            chain[0].set_data_in(bit);
+            chain[1].set_data_in(chain[0].serial_out());
+            for chip in chain.iter_mut() {
+                chip.set_clock(true);
+            }
+        }
+        // The first bit in has moved through both
+        assert_eq!(chain[0].contents(), 0);
+        assert_eq!(chain[1].contents(), 0x200);
+    }
+}
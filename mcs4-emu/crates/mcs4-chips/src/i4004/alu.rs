@@ -1,7 +1,10 @@
 //! 4004 ALU (Arithmetic Logic Unit)
 
+use rkyv::{Archive, Deserialize, Serialize};
+
 /// 4-bit ALU with accumulator and carry
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct Alu {
     /// 4-bit accumulator
     acc: u8,
@@ -101,9 +104,11 @@ impl Alu {
 
     /// Subtract with borrow
     pub fn sub(&mut self, value: u8) {
-        // 4004 subtract: ACC = ACC + ~value + carry
+        // 4004 subtract: ACC = ACC + ~value + ~carry
+        // Carry is an inverted borrow: CY=1 means "no borrow", so CLC; SUB
+        // performs a plain subtraction.
         let complement = (!value) & 0x0F;
-        let result = (self.acc as u16) + (complement as u16) + (self.carry as u16);
+        let result = (self.acc as u16) + (complement as u16) + (!self.carry as u16);
         self.carry = result > 0x0F;
         self.acc = (result & 0x0F) as u8;
     }
@@ -137,6 +142,35 @@ impl Alu {
         self.carry = false;
     }
 
+    /// Transfer carry subtract: ACC = 9 + CY, then clear carry
+    pub fn tcs(&mut self) {
+        self.acc = if self.carry { 10 } else { 9 };
+        self.carry = false;
+    }
+
+    /// Logical OR into accumulator (4040 OR4/OR5), carry unaffected
+    pub fn or(&mut self, value: u8) {
+        self.acc = (self.acc | value) & 0x0F;
+    }
+
+    /// Logical AND into accumulator (4040 AN6/AN7), carry unaffected
+    pub fn and(&mut self, value: u8) {
+        self.acc = self.acc & value & 0x0F;
+    }
+
+    /// Evaluate a JCN condition nibble against ACC, CY and the TEST input
+    ///
+    /// C1 (bit 3) inverts, C2 tests ACC == 0, C3 tests CY == 1 and
+    /// C4 (bit 0) tests the TEST pin.
+    pub fn jcn_condition(&self, condition: u8, test_pin: bool) -> bool {
+        let invert = (condition & 0x08) != 0;
+        let result = ((condition & 0x04) != 0 && self.accumulator() == 0)
+            || ((condition & 0x02) != 0 && self.carry)
+            || ((condition & 0x01) != 0 && test_pin);
+
+        result != invert
+    }
+
     /// Keyboard process (convert to BCD)
     pub fn kbp(&mut self) {
         self.acc = match self.acc {
@@ -175,6 +209,42 @@ mod tests {
         assert!(alu.carry());
     }
 
+    #[test]
+    fn test_sub_borrow() {
+        let mut alu = Alu::new();
+
+        // CLC; SUB is a plain subtraction with no borrow out
+        alu.set_accumulator(7);
+        alu.set_carry(false);
+        alu.sub(3);
+        assert_eq!(alu.accumulator(), 4);
+        assert!(alu.carry());
+
+        // Borrow out clears carry; CMC turns it into a borrow in (CY=1)
+        alu.set_accumulator(2);
+        alu.set_carry(false);
+        alu.sub(5);
+        assert_eq!(alu.accumulator(), 13);
+        assert!(!alu.carry());
+        alu.cmc();
+        alu.set_accumulator(5);
+        alu.sub(1);
+        assert_eq!(alu.accumulator(), 3);
+        assert!(alu.carry());
+    }
+
+    #[test]
+    fn test_jcn_condition() {
+        let mut alu = Alu::new();
+        alu.set_accumulator(0);
+        assert!(alu.jcn_condition(0x4, false)); // JCN Z
+        assert!(!alu.jcn_condition(0xC, false)); // JCN NZ
+        alu.stc();
+        assert!(alu.jcn_condition(0x2, false)); // JCN C
+        assert!(alu.jcn_condition(0x1, true)); // JCN T
+        assert!(!alu.jcn_condition(0x9, true)); // JCN NT
+    }
+
     #[test]
     fn test_rotate() {
         let mut alu = Alu::new();
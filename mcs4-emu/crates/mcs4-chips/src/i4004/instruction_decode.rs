@@ -3,8 +3,11 @@
 //! The 4004 has 46 instructions encoded in 8 bits (OPR:OPA).
 //! Two-byte instructions fetch a second byte in the following cycle.
 
+use rkyv::{Archive, Deserialize, Serialize};
+
 /// All 4004 instructions
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub enum Instruction {
     // ========== Machine Control (OPR=0x0) ==========
     /// No operation
@@ -54,9 +57,9 @@ pub enum Instruction {
     // ========== I/O and RAM Control (OPR=0xE) ==========
     /// Write memory (RAM data)
     Wrm,
-    /// Write ROM port
+    /// Write RAM output port
     Wmp,
-    /// Write RAM port
+    /// Write ROM I/O port
     Wrr,
     /// Write program RAM (status char 0)
     Wpm,
@@ -112,7 +115,7 @@ pub enum Instruction {
     Daa,
     /// Keyboard process
     Kbp,
-    /// Designate command line (4040 extension, NOP on 4004)
+    /// Designate command line (CM-RAM bank select)
     Dcl,
 
     /// Invalid/unknown instruction
@@ -120,7 +123,8 @@ pub enum Instruction {
 }
 
 /// Instruction decoder for the 4004
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct InstructionDecoder {
     /// Current opcode (OPR) - upper nibble
     pub opr: u8,
@@ -349,7 +353,40 @@ impl Instruction {
         }
     }
 
+    /// Does this I/O instruction write the accumulator out to a chip?
+    pub fn is_io_write(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Wrm
+                | Instruction::Wmp
+                | Instruction::Wrr
+                | Instruction::Wpm
+                | Instruction::Wr0
+                | Instruction::Wr1
+                | Instruction::Wr2
+                | Instruction::Wr3
+        )
+    }
+
+    /// Does this I/O instruction read a nibble back from a chip?
+    pub fn is_io_read(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Sbm
+                | Instruction::Rdm
+                | Instruction::Rdr
+                | Instruction::Adm
+                | Instruction::Rd0
+                | Instruction::Rd1
+                | Instruction::Rd2
+                | Instruction::Rd3
+        )
+    }
+
     /// Get number of machine cycles
+    ///
+    /// FIN is a single byte but takes two cycles: the second one fetches
+    /// its table byte from ROM.
     pub fn cycles(&self) -> u8 {
         match self {
             Instruction::Jcn { .. }
@@ -357,8 +394,7 @@ impl Instruction {
             | Instruction::Jun { .. }
             | Instruction::Jms { .. }
             | Instruction::Isz { .. }
-            | Instruction::Fin { .. }
-            | Instruction::Jin { .. } => 2,
+            | Instruction::Fin { .. } => 2,
             _ => 1,
         }
     }
@@ -460,5 +496,10 @@ mod tests {
         assert_eq!(jun.length(), 2);
         assert_eq!(jun.cycles(), 2);
         assert_eq!(jun.mnemonic(), "JUN");
+
+        let fin = Instruction::Fin { pair: 1 };
+        assert_eq!((fin.length(), fin.cycles()), (1, 2));
+        let jin = Instruction::Jin { pair: 1 };
+        assert_eq!((jin.length(), jin.cycles()), (1, 1));
     }
 }
@@ -20,12 +20,19 @@ pub use alu::Alu;
 pub use registers::Registers;
 pub use instruction_decode::{InstructionDecoder, Instruction};
 pub use timing_io::TimingIo;
+pub(crate) use timing_io::PhaseBus;
 
+use rkyv::{Archive, Deserialize, Serialize};
 use mcs4_bus::prelude::*;
 #[allow(unused_imports)]
 use mcs4_core::prelude::*;
 
+use crate::stack_monitor::StackMonitor;
+use crate::InstructionBus;
+
 /// Intel 4004 CPU
+#[derive(Clone, Debug, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct I4004 {
     /// ALU (Arithmetic Logic Unit)
     pub alu: Alu,
@@ -39,26 +46,20 @@ pub struct I4004 {
     /// Timing and I/O control
     pub timing: TimingIo,
 
-    /// Current cycle state
-    cycle: CycleState,
-
-    /// Fetched instruction (OPR:OPA)
-    instruction_byte: u8,
-
-    /// Second byte of two-byte instruction
-    operand: u8,
-
     /// Currently selected RAM address (from SRC)
     ram_address: u8,
 
     /// Currently selected RAM chip
     ram_chip: u8,
 
+    /// CM-RAM bank code latched by DCL (0-7)
+    ram_bank: u8,
+
     /// Test pin input (directly readable)
     test_pin: bool,
 
-    /// Pending memory read/write data
-    io_data: u8,
+    /// Call depth diagnostics, off unless enabled
+    pub stack_monitor: Option<StackMonitor>,
 }
 
 impl I4004 {
@@ -69,21 +70,33 @@ impl I4004 {
             registers: Registers::new(),
             decoder: InstructionDecoder::new(),
             timing: TimingIo::new(),
-            cycle: CycleState::new(),
-            instruction_byte: 0,
-            operand: 0,
             ram_address: 0,
             ram_chip: 0,
+            ram_bank: 0,
             test_pin: false,
-            io_data: 0,
+            stack_monitor: None,
         }
     }
 
+    /// Track call depth and record JMS with all three levels in use and BBL
+    /// with none
+    ///
+    /// The stack still wraps as on silicon; the monitor only reports. Calls
+    /// already outstanding when it is enabled are not counted.
+    pub fn enable_stack_monitor(&mut self) {
+        self.stack_monitor = Some(StackMonitor::new(3));
+    }
+
     /// Set the test pin state
     pub fn set_test_pin(&mut self, state: bool) {
         self.test_pin = state;
     }
 
+    /// Get the test pin state
+    pub fn test_pin(&self) -> bool {
+        self.test_pin
+    }
+
     /// Get currently selected RAM address
     pub fn ram_address(&self) -> u8 {
         self.ram_address
@@ -94,6 +107,11 @@ impl I4004 {
         self.ram_chip
     }
 
+    /// Get the CM-RAM bank code set by DCL
+    pub fn ram_bank(&self) -> u8 {
+        self.ram_bank
+    }
+
     /// Get program counter
     pub fn pc(&self) -> u16 {
         self.registers.pc()
@@ -112,91 +130,125 @@ impl I4004 {
     /// Process one bus phase
     pub fn tick(&mut self, phase: BusCycle, bus: &mut DataBus, ctrl: &mut ControlSignals) {
         match phase {
-            BusCycle::A1 => self.phase_a1(bus, ctrl),
-            BusCycle::A2 => self.phase_a2(bus, ctrl),
-            BusCycle::A3 => self.phase_a3(bus, ctrl),
-            BusCycle::M1 => self.phase_m1(bus),
-            BusCycle::M2 => self.phase_m2(bus),
+            BusCycle::A1 | BusCycle::A2 | BusCycle::A3 => self.phase_address(phase, bus, ctrl),
+            BusCycle::M1 => self.timing.memory_phase(phase, bus, ctrl),
+            BusCycle::M2 => {
+                self.timing.memory_phase(phase, bus, ctrl);
+                self.timing.io_strobe(0, self.ram_bank, ctrl);
+            }
             BusCycle::X1 => self.phase_x1(bus, ctrl),
             BusCycle::X2 => self.phase_x2(bus, ctrl),
             BusCycle::X3 => self.phase_x3(bus, ctrl),
         }
-        self.cycle.advance();
-    }
-
-    fn phase_a1(&mut self, bus: &mut DataBus, ctrl: &mut ControlSignals) {
-        // Output address bits 0-3 and assert SYNC
-        let addr = self.registers.pc();
-        bus.write((addr & 0x0F) as u8);
-        ctrl.assert_sync(0);
-    }
-
-    fn phase_a2(&mut self, bus: &mut DataBus, ctrl: &mut ControlSignals) {
-        // Output address bits 4-7, deassert SYNC
-        let addr = self.registers.pc();
-        bus.write(((addr >> 4) & 0x0F) as u8);
-        ctrl.deassert_sync(0);
-    }
-
-    fn phase_a3(&mut self, bus: &mut DataBus, ctrl: &mut ControlSignals) {
-        // Output address bits 8-11, select ROM bank
-        let addr = self.registers.pc();
-        bus.write(((addr >> 8) & 0x0F) as u8);
-        ctrl.select_rom((addr >> 8) as u8 & 0x0F, 0);
-    }
-
-    fn phase_m1(&mut self, bus: &mut DataBus) {
-        // Read instruction OPA (bits 0-3)
-        let opa = bus.read();
-        self.instruction_byte = (self.instruction_byte & 0xF0) | (opa & 0x0F);
+        self.timing.advance();
     }
 
-    fn phase_m2(&mut self, bus: &mut DataBus) {
-        // Read instruction OPR (bits 4-7)
-        let opr = bus.read();
-        self.instruction_byte = (self.instruction_byte & 0x0F) | ((opr & 0x0F) << 4);
+    fn phase_address(&mut self, phase: BusCycle, bus: &mut DataBus, ctrl: &mut ControlSignals) {
+        // Output PC (or FIN's table address) nibble by nibble; the 4004 only
+        // has CM-ROM0 wired
+        let addr = self.timing.fetch_address(self.registers.pc());
+        self.timing.address_phase(phase, addr, 0, bus, ctrl);
+        if phase == BusCycle::A3 && !self.timing.indirect_cycle() {
+            // PC points at the next byte for the rest of the cycle, so
+            // same-page jumps use the page of the following instruction
+            self.registers.increment_pc();
+        }
     }
 
     fn phase_x1(&mut self, _bus: &mut DataBus, _ctrl: &mut ControlSignals) {
         // Decode the instruction
-        if self.cycle.second_cycle {
+        let byte = self.timing.fetched();
+        if self.timing.indirect_cycle() {
+            // FIN data byte, consumed at X2
+        } else if self.timing.second_cycle() {
             // Second byte of two-byte instruction
-            self.decoder.decode_second(self.instruction_byte);
+            self.decoder.decode_second(byte);
         } else {
-            self.decoder.decode_first(self.instruction_byte);
+            self.decoder.decode_first(byte);
+            if self.decoder.needs_second_byte() {
+                self.timing.start_two_cycle();
+            } else if let Some(Instruction::Fin { .. }) = self.decoder.get_instruction() {
+                // PC already points past FIN, so a FIN in the last byte of a
+                // page reads its table from the next page
+                let addr = (self.registers.pc() & 0xF00) | self.registers.get_pair(0) as u16;
+                self.timing.start_indirect_fetch(addr);
+            }
         }
     }
 
-    fn phase_x2(&mut self, bus: &mut DataBus, _ctrl: &mut ControlSignals) {
-        // Execute instruction (for single-cycle instructions)
-        if !self.decoder.needs_second_byte() {
-            if let Some(instr) = self.decoder.get_instruction() {
-                self.execute(instr, bus);
+    fn phase_x2(&mut self, bus: &mut DataBus, ctrl: &mut ControlSignals) {
+        self.timing.release_io(ctrl);
+        // Execute in the last cycle of the instruction; I/O writes drive
+        // the accumulator for the selected chip to latch
+        if let Some(instr) = self.current_instruction() {
+            if !instr.is_io_read() {
+                let opcode = self.opcode();
+                self.execute(instr, opcode, &mut PhaseBus::new(bus, self.timing.fetched()));
+            }
+            if let Instruction::Src { pair } = instr {
+                let address = self.registers.get_pair(pair);
+                self.timing.send_src(address, 0, self.ram_bank, bus, ctrl);
             }
         }
     }
 
-    fn phase_x3(&mut self, _bus: &mut DataBus, _ctrl: &mut ControlSignals) {
-        // Increment PC after execution
-        if let Some(instr) = self.decoder.get_instruction() {
-            // For two-byte instructions, only increment after second cycle
-            if instr.length() == 1 || self.cycle.second_cycle {
-                self.registers.increment_pc();
-            }
-            // Set up for second cycle if needed
-            if instr.length() == 2 && !self.cycle.second_cycle {
-                self.cycle.two_cycle = true;
-                self.cycle.second_cycle = true;
-                self.registers.increment_pc();
-            } else {
-                self.cycle.two_cycle = false;
-                self.cycle.second_cycle = false;
+    fn phase_x3(&mut self, bus: &mut DataBus, ctrl: &mut ControlSignals) {
+        self.timing.finish_src(bus, ctrl);
+        // I/O reads sample the nibble the selected chip drove at X2
+        if let Some(instr) = self.current_instruction() {
+            if instr.is_io_read() {
+                let opcode = self.opcode();
+                self.execute(instr, opcode, &mut PhaseBus::new(bus, self.timing.fetched()));
             }
         }
     }
 
+    /// Instruction completing in this machine cycle, if any
+    fn current_instruction(&self) -> Option<Instruction> {
+        if self.timing.last_cycle() {
+            self.decoder.get_instruction()
+        } else {
+            None
+        }
+    }
+
+    /// First byte of the decoded instruction
+    fn opcode(&self) -> u8 {
+        (self.decoder.opr << 4) | self.decoder.opa
+    }
+
+    /// Execute one instruction against an instruction-level bus
+    ///
+    /// Fetches the opcode (and operand byte) at PC, runs the instruction and
+    /// returns the number of machine cycles it takes on the bus (1 or 2).
+    pub fn step<B: InstructionBus + ?Sized>(&mut self, bus: &mut B) -> u8 {
+        let opcode = self.fetch(bus);
+        self.decoder.decode_first(opcode);
+        if self.decoder.needs_second_byte() {
+            let operand = self.fetch(bus);
+            self.decoder.decode_second(operand);
+        }
+        let instr = self
+            .decoder
+            .get_instruction()
+            .unwrap_or(Instruction::Invalid { opcode });
+        self.execute(instr, opcode, bus);
+        instr.cycles()
+    }
+
+    /// Fetch the byte at PC and advance PC
+    fn fetch<B: InstructionBus + ?Sized>(&mut self, bus: &mut B) -> u8 {
+        let byte = bus.fetch(0, self.registers.pc());
+        self.registers.increment_pc();
+        byte
+    }
+
     /// Execute a decoded instruction
-    fn execute(&mut self, instr: Instruction, bus: &mut DataBus) {
+    ///
+    /// PC already points past the instruction. On the phase path `bus` is a
+    /// [`PhaseBus`] over the data bus, which hands back FIN's second-cycle
+    /// byte and drives or samples the nibble for I/O instructions.
+    fn execute<B: InstructionBus + ?Sized>(&mut self, instr: Instruction, opcode: u8, bus: &mut B) {
         use Instruction::*;
         match instr {
             // Machine control
@@ -220,14 +272,13 @@ impl I4004 {
                 let addr = self.registers.get_pair(pair);
                 self.ram_address = addr & 0x0F;
                 self.ram_chip = (addr >> 4) & 0x0F;
+                bus.src(self.ram_bank, addr);
             }
             Fin { pair } => {
-                // Fetch indirect: use pair 0 as address into ROM page 0
-                let addr = self.registers.get_pair(0);
-                // In real hardware, this fetches from ROM[addr]
-                // For now, store address for bus to handle
-                self.io_data = addr;
-                let _ = pair; // Will be loaded with fetched data
+                // Byte read from the page-plus-P0 address in the second cycle
+                let addr = (self.registers.pc() & 0xF00) | self.registers.get_pair(0) as u16;
+                let data = bus.fetch(0, addr);
+                self.registers.set_pair(pair, data);
             }
             Jin { pair } => {
                 let addr = self.registers.get_pair(pair);
@@ -243,6 +294,12 @@ impl I4004 {
             }
             Jms { addr_high, addr_low } => {
                 let new_pc = ((addr_high as u16) << 8) | (addr_low as u16);
+                if let Some(monitor) = &mut self.stack_monitor {
+                    // The slot about to be written holds the oldest return
+                    // address once all three are in use
+                    let lost = self.registers.stack()[self.registers.stack_pointer() as usize];
+                    monitor.call(self.registers.pc().wrapping_sub(2), lost);
+                }
                 self.registers.call(new_pc);
             }
             Isz { reg, addr_low } => {
@@ -277,6 +334,9 @@ impl I4004 {
                 self.registers.set_r(reg, old_acc);
             }
             Bbl { data } => {
+                if let Some(monitor) = &mut self.stack_monitor {
+                    monitor.ret(self.registers.pc().wrapping_sub(1));
+                }
                 self.registers.ret();
                 self.alu.load(data);
             }
@@ -286,35 +346,14 @@ impl I4004 {
                 self.alu.load(data);
             }
 
-            // I/O and RAM control - these interact with the bus
-            Wrm => {
-                bus.write(self.alu.accumulator());
+            // I/O and RAM control - the SRC-selected chip decodes the
+            // command it latched at X1
+            Wrm | Wmp | Wrr | Wpm | Wr0 | Wr1 | Wr2 | Wr3 => {
+                bus.io(self.ram_bank, opcode, self.alu.accumulator());
             }
-            Wmp | Wrr | Wpm => {
-                bus.write(self.alu.accumulator());
-            }
-            Wr0 | Wr1 | Wr2 | Wr3 => {
-                bus.write(self.alu.accumulator());
-            }
-            Sbm => {
-                let value = bus.read();
-                self.alu.sub(value);
-            }
-            Rdm => {
-                let value = bus.read();
-                self.alu.load(value);
-            }
-            Rdr => {
-                let value = bus.read();
-                self.alu.load(value);
-            }
-            Adm => {
-                let value = bus.read();
-                self.alu.add(value);
-            }
-            Rd0 | Rd1 | Rd2 | Rd3 => {
-                let value = bus.read();
-                self.alu.load(value);
+            Sbm | Rdm | Rdr | Adm | Rd0 | Rd1 | Rd2 | Rd3 => {
+                let value = bus.io(self.ram_bank, opcode, self.alu.accumulator()) & 0x0F;
+                complete_io_read(&mut self.alu, instr, value);
             }
 
             // Accumulator group
@@ -327,18 +366,12 @@ impl I4004 {
             Rar => self.alu.rar(),
             Tcc => self.alu.tcc(),
             Dac => self.alu.dac(),
-            Tcs => {
-                // Transfer carry subtract: ACC = 9 + CY
-                let value = if self.alu.carry() { 10 } else { 9 };
-                self.alu.set_accumulator(value);
-                self.alu.set_carry(false);
-            }
+            Tcs => self.alu.tcs(),
             Stc => self.alu.stc(),
             Daa => self.alu.daa(),
             Kbp => self.alu.kbp(),
-            Dcl => {
-                // Designate command line (4040 only, NOP on 4004)
-            }
+            // Designate command line: ACC bits 0-2 pick the CM-RAM bank
+            Dcl => self.ram_bank = self.alu.accumulator() & 0x07,
 
             Invalid { opcode: _ } => {
                 // Invalid instruction - no operation
@@ -348,24 +381,19 @@ impl I4004 {
 
     /// Evaluate JCN condition
     fn evaluate_condition(&self, condition: u8) -> bool {
-        let invert = (condition & 0x08) != 0;
-        let test_acc_zero = (condition & 0x04) != 0;
-        let test_carry = (condition & 0x02) != 0;
-        let test_pin = (condition & 0x01) != 0;
-
-        let mut result = false;
-
-        if test_acc_zero && self.alu.accumulator() == 0 {
-            result = true;
-        }
-        if test_carry && self.alu.carry() {
-            result = true;
-        }
-        if test_pin && self.test_pin {
-            result = true;
-        }
+        self.alu.jcn_condition(condition, self.test_pin)
+    }
+}
 
-        if invert { !result } else { result }
+/// Fold a nibble returned by an I/O read instruction into the accumulator
+///
+/// SBM and ADM subtract/add with carry; RDM, RDR, RD0-RD3 (and the 4040
+/// RPM) simply load it.
+pub(crate) fn complete_io_read(alu: &mut Alu, instr: Instruction, value: u8) {
+    match instr {
+        Instruction::Sbm => alu.sub(value),
+        Instruction::Adm => alu.add(value),
+        _ => alu.load(value),
     }
 }
 
@@ -384,18 +412,19 @@ impl super::Chip for I4004 {
         self.alu = Alu::new();
         self.registers = Registers::new();
         self.decoder = InstructionDecoder::new();
-        self.cycle = CycleState::new();
-        self.instruction_byte = 0;
-        self.operand = 0;
+        self.timing = TimingIo::new();
         self.ram_address = 0;
         self.ram_chip = 0;
+        self.ram_bank = 0;
         self.test_pin = false;
-        self.io_data = 0;
+        if self.stack_monitor.is_some() {
+            self.enable_stack_monitor();
+        }
     }
 
     fn tick(&mut self, phase: BusCycle) {
         // Simplified tick without bus/control access
-        self.cycle.advance();
+        self.timing.advance();
         let _ = phase;
     }
 }
@@ -1,12 +1,15 @@
 //! 4004 Register File
 
+use rkyv::{Archive, Deserialize, Serialize};
+
 /// Register file for the 4004
 ///
 /// Contains:
 /// - 16 4-bit index registers (R0-R15), also addressable as 8 pairs (P0-P7)
 /// - 12-bit program counter
 /// - 3-level stack (12-bit entries)
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct Registers {
     /// Index registers R0-R15 (4-bit each)
     index: [u8; 16],
@@ -72,6 +75,16 @@ impl Registers {
         self.index[base + 1] = value & 0x0F;
     }
 
+    /// Stack contents, indexed by slot (not by depth)
+    pub fn stack(&self) -> [u16; 3] {
+        self.stack
+    }
+
+    /// Slot the next JMS pushes into (0-2)
+    pub fn stack_pointer(&self) -> u8 {
+        self.sp
+    }
+
     /// Push PC to stack and set new PC (for JMS)
     pub fn call(&mut self, addr: u16) {
         self.stack[self.sp as usize] = self.pc;
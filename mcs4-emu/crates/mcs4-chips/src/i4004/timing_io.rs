@@ -1,14 +1,223 @@
 //! 4004 Timing and I/O Control
+//!
+//! The CPU side of the machine-cycle protocol, shared by the 4004 and 4040:
+//! SYNC and the 12-bit ROM address on A1-A3 (with a CM-ROM line asserted at
+//! A3), OPR/OPA latched from the bus on M1/M2, and tracking of which cycle
+//! of a two-cycle instruction is currently on the bus.
+//!
+//! FIN's second cycle is an indirect fetch: the same A1..M2 sequence runs
+//! with the table address instead of the PC, and the byte read is data.
+//!
+//! SRC sends its address in the execute phases: the high nibble at X2 with
+//! CM-ROM and CM-RAM asserted, the low nibble at X3.
+
+use rkyv::{Archive, Deserialize, Serialize};
+use mcs4_bus::prelude::*;
+
+use crate::InstructionBus;
 
 /// Timing and I/O controller for the 4004
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct TimingIo {
-    // Placeholder for timing state
-    _state: u8,
+    /// Phase/cycle sequencing
+    pub cycle: CycleState,
+
+    /// Upper nibble latched at M1
+    opr: u8,
+
+    /// Lower nibble latched at M2
+    opa: u8,
+
+    /// Address fetched in the second cycle instead of the PC (FIN)
+    indirect: Option<u16>,
+
+    /// SRC address whose low nibble goes out at X3
+    src: Option<u8>,
 }
 
 impl TimingIo {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Drive one address phase (A1-A3) for a ROM fetch at `addr`
+    ///
+    /// SYNC marks A1; the CM-ROM line for `rom_line` is asserted with the
+    /// page nibble at A3 so the addressed ROM can compare it to its chip number.
+    pub fn address_phase(
+        &mut self,
+        phase: BusCycle,
+        addr: u16,
+        rom_line: usize,
+        bus: &mut DataBus,
+        ctrl: &mut ControlSignals,
+    ) {
+        match phase {
+            BusCycle::A1 => {
+                bus.write((addr & 0x0F) as u8);
+                ctrl.assert_sync(0);
+            }
+            BusCycle::A2 => {
+                bus.write(((addr >> 4) & 0x0F) as u8);
+                ctrl.deassert_sync(0);
+            }
+            BusCycle::A3 => {
+                bus.write(((addr >> 8) & 0x0F) as u8);
+                ctrl.assert_cm_rom(rom_line, 0);
+            }
+            _ => {}
+        }
+    }
+
+    /// Latch one memory phase (M1 = OPR, M2 = OPA) from the bus
+    pub fn memory_phase(&mut self, phase: BusCycle, bus: &DataBus, ctrl: &mut ControlSignals) {
+        match phase {
+            BusCycle::M1 => {
+                // CM-ROM is only a select strobe for A3
+                ctrl.deselect_rom(0);
+                self.opr = bus.read() & 0x0F;
+            }
+            BusCycle::M2 => {
+                self.opa = bus.read() & 0x0F;
+            }
+            _ => {}
+        }
+    }
+
+    /// Strobe CM-ROM and CM-RAM at M2 of an I/O instruction (OPR = 0xE)
+    ///
+    /// The chips selected by the last SRC see the lines at X1 and latch the
+    /// OPA still on the bus as their command.
+    /// `ram_bank` is the DCL register, decoded onto CM-RAM by
+    /// [`ControlSignals::select_ram`].
+    pub fn io_strobe(&self, rom_line: usize, ram_bank: u8, ctrl: &mut ControlSignals) {
+        if self.opr == 0xE && !self.second_cycle() {
+            ctrl.assert_cm_rom(rom_line, 0);
+            ctrl.select_ram(ram_bank, 0);
+        }
+    }
+
+    /// Strobe CM-ROM alone at M2 of a 4040 RPM (0x0E)
+    ///
+    /// RPM reads program memory through a 4289 on the ROM bank's line; the
+    /// 4002s on CM-RAM stay out of it.
+    pub fn rpm_strobe(&self, rom_line: usize, ctrl: &mut ControlSignals) {
+        if self.fetched() == 0x0E && !self.second_cycle() {
+            ctrl.assert_cm_rom(rom_line, 0);
+        }
+    }
+
+    /// Drop the CM lines once the I/O command has been latched (X2)
+    pub fn release_io(&self, ctrl: &mut ControlSignals) {
+        ctrl.deselect_rom(0);
+        ctrl.deselect_ram(0);
+    }
+
+    /// Start an SRC transfer at X2: chip/register nibble on the bus with the
+    /// CM lines asserted so every ROM and RAM on them latches it
+    pub fn send_src(
+        &mut self,
+        address: u8,
+        rom_line: usize,
+        ram_bank: u8,
+        bus: &mut DataBus,
+        ctrl: &mut ControlSignals,
+    ) {
+        bus.write(address >> 4);
+        ctrl.assert_cm_rom(rom_line, 0);
+        ctrl.select_ram(ram_bank, 0);
+        self.src = Some(address);
+    }
+
+    /// Finish an SRC transfer at X3: character nibble on the bus, CM released
+    pub fn finish_src(&mut self, bus: &mut DataBus, ctrl: &mut ControlSignals) {
+        if let Some(address) = self.src.take() {
+            bus.write(address & 0x0F);
+            self.release_io(ctrl);
+        }
+    }
+
+    /// Byte assembled from the last M1/M2 pair
+    pub fn fetched(&self) -> u8 {
+        (self.opr << 4) | self.opa
+    }
+
+    /// Is the bus carrying the second cycle of a two-cycle instruction?
+    pub fn second_cycle(&self) -> bool {
+        self.cycle.state == MachineState::Fetch2
+    }
+
+    /// Mark the instruction in flight as needing a second machine cycle
+    pub fn start_two_cycle(&mut self) {
+        self.cycle.set_two_cycle();
+    }
+
+    /// Run the second machine cycle as a data fetch from `addr` (FIN)
+    pub fn start_indirect_fetch(&mut self, addr: u16) {
+        self.cycle.set_two_cycle();
+        self.indirect = Some(addr & 0x0FFF);
+    }
+
+    /// Is the bus carrying an indirect (data) fetch rather than an opcode?
+    pub fn indirect_cycle(&self) -> bool {
+        self.indirect.is_some() && self.second_cycle()
+    }
+
+    /// Address to put on the bus this cycle: the PC, or FIN's table address
+    pub fn fetch_address(&self, pc: u16) -> u16 {
+        if self.indirect_cycle() {
+            self.indirect.unwrap_or(pc)
+        } else {
+            pc
+        }
+    }
+
+    /// Does the current machine cycle complete an instruction?
+    pub fn last_cycle(&self) -> bool {
+        !self.cycle.two_cycle || self.second_cycle()
+    }
+
+    /// Advance to the next bus phase
+    pub fn advance(&mut self) {
+        if self.cycle.phase == BusCycle::X3 && self.indirect_cycle() {
+            self.indirect = None;
+        }
+        self.cycle.advance();
+    }
+}
+
+/// Adapter that lets an instruction-level `execute` run at X2 of a bus cycle
+///
+/// I/O writes put the accumulator on the data bus at X2; reads sample what
+/// the selected chip drove at X2, so they are executed at X3. A program
+/// memory fetch (FIN) returns the byte latched during the indirect cycle.
+/// SRC is sent by the CPU itself through [`TimingIo::send_src`], since it
+/// needs the CM lines.
+pub(crate) struct PhaseBus<'a> {
+    bus: &'a mut DataBus,
+    fetched: u8,
+}
+
+impl<'a> PhaseBus<'a> {
+    pub(crate) fn new(bus: &'a mut DataBus, fetched: u8) -> Self {
+        Self { bus, fetched }
+    }
+}
+
+impl InstructionBus for PhaseBus<'_> {
+    fn fetch(&mut self, _bank: u8, _addr: u16) -> u8 {
+        self.fetched
+    }
+
+    fn src(&mut self, _command: u8, _address: u8) {}
+
+    fn io(&mut self, _command: u8, opcode: u8, acc: u8) -> u8 {
+        if (0xE0..=0xE7).contains(&opcode) {
+            self.bus.write(acc);
+            0
+        } else {
+            self.bus.read()
+        }
+    }
 }
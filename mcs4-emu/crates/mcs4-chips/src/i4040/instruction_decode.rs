@@ -1,9 +1,65 @@
-// 4040 instruction decode placeholder for new opcodes
-#[derive(Clone, Copy, Debug)]
+//! 4040 instruction decode for the 14 opcodes added to the 4004 set
+//!
+//! All of them live in the OPR=0x0 row that the 4004 treats as NOP. They are
+//! single-byte, single-cycle instructions.
+
+/// 4040-only instructions (OPR=0x0, OPA=0x1..0xE)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Opcode4040 {
-    Hlt, Bbs, Lcr, Or4, Or5, An6, An7, Db0, Db1, Sb0, Sb1, Ein, Din, Rpm,
+    /// Halt until an interrupt or reset
+    Hlt,
+    /// Branch back from interrupt and restore the saved SRC address
+    Bbs,
+    /// Load the command register (DCL value) into the accumulator
+    Lcr,
+    /// OR R4 into the accumulator
+    Or4,
+    /// OR R5 into the accumulator
+    Or5,
+    /// AND R6 into the accumulator
+    An6,
+    /// AND R7 into the accumulator
+    An7,
+    /// Designate ROM bank 0 (CM-ROM0)
+    Db0,
+    /// Designate ROM bank 1 (CM-ROM1)
+    Db1,
+    /// Select index register bank 0 (R0-R7)
+    Sb0,
+    /// Select index register bank 1 (R0*-R7*)
+    Sb1,
+    /// Enable interrupts
+    Ein,
+    /// Disable interrupts
+    Din,
+    /// Read program memory (through the 4289) into the accumulator
+    Rpm,
+}
+
+impl Opcode4040 {
+    /// Get instruction mnemonic
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode4040::Hlt => "HLT",
+            Opcode4040::Bbs => "BBS",
+            Opcode4040::Lcr => "LCR",
+            Opcode4040::Or4 => "OR4",
+            Opcode4040::Or5 => "OR5",
+            Opcode4040::An6 => "AN6",
+            Opcode4040::An7 => "AN7",
+            Opcode4040::Db0 => "DB0",
+            Opcode4040::Db1 => "DB1",
+            Opcode4040::Sb0 => "SB0",
+            Opcode4040::Sb1 => "SB1",
+            Opcode4040::Ein => "EIN",
+            Opcode4040::Din => "DIN",
+            Opcode4040::Rpm => "RPM",
+        }
+    }
 }
 
+/// Decode a 4040 extension opcode; returns `None` for anything the 4004
+/// decoder handles (including NOP at 0x00 and the unused 0x0F)
 pub fn decode_ext(op: u8) -> Option<Opcode4040> {
     match op {
         0x01 => Some(Opcode4040::Hlt),
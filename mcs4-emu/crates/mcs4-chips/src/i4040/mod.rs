@@ -1,57 +1,412 @@
-//! 4040 CPU core scaffold integrating stack and interrupts.
+//! Intel 4040 CPU Implementation
+//!
+//! The 4040 is a superset of the 4004: the 46 original instructions run
+//! unchanged through the shared [`Alu`] and [`InstructionDecoder`], and 14
+//! new ones occupy the OPR=0x0 row.
+//!
+//! ## Architecture
+//! - 24 4-bit index registers (R0-R7 banked by SB0/SB1, R8-R15 common)
+//! - 7-level stack
+//! - Two ROM banks (CM-ROM0/CM-ROM1) selected by DB0/DB1
+//! - Single-level interrupt vectoring to 0x003, HLT and STOP
+//!
+//! [`I4040::step`] executes a whole instruction against an
+//! [`InstructionBus`]; [`I4040::tick`] runs the same instruction set one bus
+//! phase at a time using the 4004's A1..X3 protocol.
 
 mod registers;
 mod stack;
 mod interrupt;
 mod instruction_decode;
 
-use registers::RegFile;
-use stack::CallStack;
-use interrupt::InterruptCtrl;
+pub use registers::RegFile;
+pub use stack::CallStack;
+pub use interrupt::InterruptCtrl;
+pub use instruction_decode::{decode_ext, Opcode4040};
 
-use crate::i4040::instruction_decode::decode_ext as decode_4040;
-// use mcs4_bus::BusCycle; // unused until tick() implemented
+use mcs4_bus::prelude::*;
+
+use crate::i4004::{complete_io_read, Alu, Instruction, InstructionDecoder, PhaseBus, TimingIo};
+use crate::stack_monitor::StackMonitor;
+use crate::InstructionBus;
 
 #[derive(Default)]
 pub struct I4040 {
+    /// ALU (shared with the 4004)
+    pub alu: Alu,
     pub regs: RegFile,
-    pub acc: u8,
-    pub carry: bool,
     pub pc: u16,
     pub stack: CallStack,
     pub intr: InterruptCtrl,
     pub halted: bool,
+
+    /// Instruction decoder (shared with the 4004)
+    pub decoder: InstructionDecoder,
+
+    /// Bus sequencing (shared with the 4004)
+    pub timing: TimingIo,
+
+    /// 4040-only instruction latched at X1 of the current cycle
+    ext: Option<Opcode4040>,
+
+    /// Stopped by the STOP input (as opposed to HLT)
+    stop_held: bool,
+
+    /// ROM bank designated by DB0/DB1
+    pub rom_bank: u8,
+
+    /// Command register loaded by DCL (CM-RAM selection), read back by LCR
+    pub command: u8,
+
+    /// Last address sent with SRC
+    pub src: u8,
+
+    /// Test pin input
+    test_pin: bool,
+
+    /// Call depth diagnostics, off unless enabled
+    pub stack_monitor: Option<StackMonitor>,
 }
 
 impl I4040 {
     pub fn new() -> Self { Self::default() }
 
-    /// Execute one instruction boundary: handle pending interrupts and HLT.
-    pub fn step(&mut self) {
-        if self.halted { return; }
-        if let Some(vec) = self.intr.service(self.current_src()) {
-            let _ = self.stack.push(self.pc);
+    /// Set the test pin state
+    pub fn set_test_pin(&mut self, state: bool) {
+        self.test_pin = state;
+    }
+
+    /// Get accumulator value
+    pub fn accumulator(&self) -> u8 {
+        self.alu.accumulator()
+    }
+
+    /// Get carry flag
+    pub fn carry(&self) -> bool {
+        self.alu.carry()
+    }
+
+    /// Process one bus phase
+    ///
+    /// Follows the 4004 protocol, fetching over the CM-ROM line of the
+    /// designated ROM bank. INT and STOP are sampled at the end of every
+    /// instruction, and STP is asserted while the CPU is stopped by HLT or
+    /// STOP. A stopped CPU keeps SYNC running but leaves the bus alone.
+    pub fn tick(&mut self, phase: BusCycle, bus: &mut DataBus, ctrl: &mut ControlSignals) {
+        if self.halted {
+            match phase {
+                BusCycle::A1 => ctrl.assert_sync(0),
+                BusCycle::A2 => ctrl.deassert_sync(0),
+                BusCycle::X3 => self.instruction_boundary(ctrl),
+                _ => {}
+            }
+        } else {
+            match phase {
+                BusCycle::A1 | BusCycle::A2 | BusCycle::A3 => {
+                    let addr = self.timing.fetch_address(self.pc);
+                    self.timing.address_phase(phase, addr, self.rom_bank as usize, bus, ctrl);
+                    if phase == BusCycle::A3 && !self.timing.indirect_cycle() {
+                        self.pc = (self.pc + 1) & 0x0FFF;
+                    }
+                }
+                BusCycle::M1 => self.timing.memory_phase(phase, bus, ctrl),
+                BusCycle::M2 => {
+                    self.timing.memory_phase(phase, bus, ctrl);
+                    self.timing.io_strobe(self.rom_bank as usize, self.command, ctrl);
+                    self.timing.rpm_strobe(self.rom_bank as usize, ctrl);
+                }
+                BusCycle::X1 => self.phase_x1(),
+                BusCycle::X2 => {
+                    self.timing.release_io(ctrl);
+                    if self.phase_x2(bus) {
+                        let rom_line = self.rom_bank as usize;
+                        self.timing.send_src(self.src, rom_line, self.command, bus, ctrl);
+                    }
+                }
+                BusCycle::X3 => {
+                    self.timing.finish_src(bus, ctrl);
+                    if self.timing.last_cycle() {
+                        self.phase_x3(bus);
+                        self.instruction_boundary(ctrl);
+                    }
+                }
+            }
+        }
+        self.timing.advance();
+    }
+
+    fn phase_x1(&mut self) {
+        let byte = self.timing.fetched();
+        if self.timing.indirect_cycle() {
+            return;
+        }
+        if self.timing.second_cycle() {
+            self.decoder.decode_second(byte);
+            return;
+        }
+        // Extended opcodes sit in the 4004's NOP row; decoding them anyway
+        // keeps a stale I/O read from completing at X3
+        self.decoder.decode_first(byte);
+        self.ext = decode_ext(byte);
+        if self.ext.is_none() {
+            if self.decoder.needs_second_byte() {
+                self.timing.start_two_cycle();
+            } else if let Some(Instruction::Fin { .. }) = self.decoder.get_instruction() {
+                let addr = (self.pc & 0xF00) | self.regs.pair_byte(0) as u16;
+                self.timing.start_indirect_fetch(addr);
+            }
+        }
+    }
+
+    /// Execute at X2; returns true if the instruction sends an SRC address
+    /// (SRC, or BBS restoring the one saved on interrupt)
+    fn phase_x2(&mut self, bus: &mut DataBus) -> bool {
+        let mut port = PhaseBus::new(bus, self.timing.fetched());
+        if let Some(op) = self.ext.take() {
+            // RPM, like the I/O reads, waits for the 4289 to drive the bus
+            if op == Opcode4040::Rpm {
+                self.ext = Some(op);
+                return false;
+            }
+            self.execute_ext(op, &mut port);
+            return op == Opcode4040::Bbs;
+        }
+        if !self.timing.last_cycle() {
+            return false;
+        }
+        // I/O reads wait for the chip to drive the bus at X2
+        match self.decoder.get_instruction().filter(|i| !i.is_io_read()) {
+            Some(instr) => {
+                let opcode = (self.decoder.opr << 4) | self.decoder.opa;
+                self.execute(instr, opcode, &mut port);
+                matches!(instr, Instruction::Src { .. })
+            }
+            None => false,
+        }
+    }
+
+    fn phase_x3(&mut self, bus: &mut DataBus) {
+        if let Some(op) = self.ext.take() {
+            self.execute_ext(op, &mut PhaseBus::new(bus, self.timing.fetched()));
+            return;
+        }
+        if let Some(instr) = self.decoder.get_instruction().filter(|i| i.is_io_read()) {
+            let opcode = (self.decoder.opr << 4) | self.decoder.opa;
+            self.execute(instr, opcode, &mut PhaseBus::new(bus, self.timing.fetched()));
+        }
+    }
+
+    /// Sample INT and STOP between instructions and drive STP
+    ///
+    /// An enabled interrupt wins and also wakes the CPU from HLT. STOP holds
+    /// the CPU stopped for as long as it is asserted; releasing it resumes
+    /// execution (so a STOP pulse also ends a HLT).
+    fn instruction_boundary(&mut self, ctrl: &mut ControlSignals) {
+        if ctrl.interrupt_pending() {
+            self.intr.request();
+        }
+        if let Some(vec) = self.intr.service(self.src) {
+            self.halted = false;
+            self.push(self.pc, self.pc);
             self.pc = vec;
+        } else if ctrl.stop_requested() {
+            self.halted = true;
+            self.stop_held = true;
+        } else if self.stop_held {
+            self.stop_held = false;
+            self.halted = false;
         }
-        // Minimal executor: handle control ops fetched from a byte at PC (stub)
-        let opcode: u8 = 0; // TODO fetch
-        if let Some(op) = decode_4040(opcode) {
-            use crate::i4040::instruction_decode::Opcode4040 as Op;
-            match op {
-                Op::Hlt => self.hlt(),
-                Op::Db0 => self.regs.db0(),
-                Op::Db1 => self.regs.db1(),
-                Op::Ein => self.intr.ein(),
-                Op::Din => self.intr.din(),
-                _ => {}
+        ctrl.set_stop_ack(self.halted, 0);
+    }
+
+    /// Execute one instruction against an instruction-level bus
+    ///
+    /// Pending interrupts are taken at the instruction boundary (waking the
+    /// CPU from HLT). Returns the number of machine cycles consumed; a halted
+    /// CPU idles for one cycle.
+    pub fn step<B: InstructionBus + ?Sized>(&mut self, bus: &mut B) -> u8 {
+        if let Some(vec) = self.intr.service(self.src) {
+            self.halted = false;
+            self.push(self.pc, self.pc);
+            self.pc = vec;
+        }
+        if self.halted {
+            return 1;
+        }
+
+        let opcode = self.fetch(bus);
+        if let Some(op) = decode_ext(opcode) {
+            self.execute_ext(op, bus);
+            return 1;
+        }
+
+        self.decoder.decode_first(opcode);
+        if self.decoder.needs_second_byte() {
+            let operand = self.fetch(bus);
+            self.decoder.decode_second(operand);
+        }
+        let instr = self
+            .decoder
+            .get_instruction()
+            .unwrap_or(Instruction::Invalid { opcode });
+        self.execute(instr, opcode, bus);
+        instr.cycles()
+    }
+
+    /// Fetch the byte at PC from the designated ROM bank and advance PC
+    fn fetch<B: InstructionBus + ?Sized>(&mut self, bus: &mut B) -> u8 {
+        let byte = bus.fetch(self.rom_bank, self.pc);
+        self.pc = (self.pc + 1) & 0x0FFF;
+        byte
+    }
+
+    /// Execute one of the 46 instructions inherited from the 4004
+    ///
+    /// PC already points past the instruction, so same-page jumps land in
+    /// the page of the next instruction, as on silicon.
+    fn execute<B: InstructionBus + ?Sized>(&mut self, instr: Instruction, opcode: u8, bus: &mut B) {
+        use Instruction::*;
+        match instr {
+            Nop => {}
+
+            Jcn { condition, addr_low } => {
+                if self.alu.jcn_condition(condition, self.test_pin) {
+                    self.pc = (self.pc & 0xF00) | addr_low as u16;
+                }
+            }
+
+            Fim { pair, data } => self.regs.set_pair_byte(pair as usize, data),
+            Src { pair } => {
+                self.src = self.regs.pair_byte(pair as usize);
+                bus.src(self.command, self.src);
+            }
+            Fin { pair } => {
+                let addr = (self.pc & 0xF00) | self.regs.pair_byte(0) as u16;
+                let data = bus.fetch(self.rom_bank, addr);
+                self.regs.set_pair_byte(pair as usize, data);
+            }
+            Jin { pair } => {
+                self.pc = (self.pc & 0xF00) | self.regs.pair_byte(pair as usize) as u16;
+            }
+
+            Jun { addr_high, addr_low } => {
+                self.pc = ((addr_high as u16) << 8) | addr_low as u16;
+            }
+            Jms { addr_high, addr_low } => {
+                self.push(self.pc.wrapping_sub(2), self.pc);
+                self.pc = ((addr_high as u16) << 8) | addr_low as u16;
+            }
+            Isz { reg, addr_low } => {
+                let value = (self.regs.get(reg as usize) + 1) & 0x0F;
+                self.regs.set(reg as usize, value);
+                if value != 0 {
+                    self.pc = (self.pc & 0xF00) | addr_low as u16;
+                }
+            }
+
+            Inc { reg } => {
+                let value = self.regs.get(reg as usize) + 1;
+                self.regs.set(reg as usize, value);
+            }
+            Add { reg } => self.alu.add(self.regs.get(reg as usize)),
+            Sub { reg } => self.alu.sub(self.regs.get(reg as usize)),
+            Ld { reg } => self.alu.load(self.regs.get(reg as usize)),
+            Xch { reg } => {
+                let old_acc = self.alu.xch(self.regs.get(reg as usize));
+                self.regs.set(reg as usize, old_acc);
+            }
+            Bbl { data } => {
+                self.pop(self.pc.wrapping_sub(1));
+                self.alu.load(data);
+            }
+
+            Ldm { data } => self.alu.load(data),
+
+            Wrm | Wmp | Wrr | Wpm | Wr0 | Wr1 | Wr2 | Wr3 => {
+                bus.io(self.command, opcode, self.alu.accumulator());
+            }
+            Sbm | Rdm | Rdr | Adm | Rd0 | Rd1 | Rd2 | Rd3 => {
+                let value = bus.io(self.command, opcode, self.alu.accumulator()) & 0x0F;
+                complete_io_read(&mut self.alu, instr, value);
             }
+
+            Clb => self.alu.clb(),
+            Clc => self.alu.set_carry(false),
+            Iac => self.alu.iac(),
+            Cmc => self.alu.cmc(),
+            Cma => self.alu.cma(),
+            Ral => self.alu.ral(),
+            Rar => self.alu.rar(),
+            Tcc => self.alu.tcc(),
+            Dac => self.alu.dac(),
+            Tcs => self.alu.tcs(),
+            Stc => self.alu.stc(),
+            Daa => self.alu.daa(),
+            Kbp => self.alu.kbp(),
+            Dcl => self.command = self.alu.accumulator() & 0x07,
+
+            Invalid { opcode: _ } => {}
         }
     }
 
-    #[inline]
-    fn current_src(&self) -> u8 {
-        // SRC register encoding from current pair selection (placeholder)
-        0
+    /// Execute one of the 14 4040-only instructions
+    fn execute_ext<B: InstructionBus + ?Sized>(&mut self, op: Opcode4040, bus: &mut B) {
+        use Opcode4040 as Op;
+        match op {
+            Op::Hlt => self.hlt(),
+            Op::Bbs => {
+                self.pop(self.pc.wrapping_sub(1));
+                self.src = self.intr.bbs_restore();
+                bus.src(self.command, self.src);
+            }
+            Op::Lcr => self.alu.load(self.command),
+            Op::Or4 => self.alu.or(self.regs.get(4)),
+            Op::Or5 => self.alu.or(self.regs.get(5)),
+            Op::An6 => self.alu.and(self.regs.get(6)),
+            Op::An7 => self.alu.and(self.regs.get(7)),
+            Op::Db0 => self.rom_bank = 0,
+            Op::Db1 => self.rom_bank = 1,
+            Op::Sb0 => self.regs.sb0(),
+            Op::Sb1 => self.regs.sb1(),
+            Op::Ein => self.intr.ein(),
+            Op::Din => self.intr.din(),
+            Op::Rpm => {
+                let value = bus.io(self.command, 0x0E, self.alu.accumulator());
+                self.alu.load(value);
+            }
+        }
+    }
+
+    /// Push a return address for the call at `from` (JMS and interrupt
+    /// entry). A full stack drops it.
+    fn push(&mut self, from: u16, addr: u16) {
+        let result = self.stack.push(addr);
+        if let Some(monitor) = &mut self.stack_monitor {
+            monitor.call(from, addr);
+        } else if let Err(e) = result {
+            tracing::warn!("4040 {} at PC {:03X}", e, from & 0x0FFF);
+        }
+    }
+
+    /// Pop a return address into PC for the return at `from` (BBL and BBS)
+    fn pop(&mut self, from: u16) {
+        let result = self.stack.pop();
+        if let Some(monitor) = &mut self.stack_monitor {
+            monitor.ret(from);
+        }
+        match result {
+            Ok(addr) => self.pc = addr,
+            Err(e) if self.stack_monitor.is_none() => {
+                tracing::warn!("4040 {} at PC {:03X}", e, from & 0x0FFF)
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Record overflow and underflow as [`crate::stack_monitor::StackFault`]s
+    /// instead of only logging them
+    pub fn enable_stack_monitor(&mut self) {
+        self.stack_monitor = Some(StackMonitor::with_depth(7, self.stack.depth() as u8));
     }
 
     #[inline]
@@ -60,20 +415,372 @@ impl I4040 {
     pub fn resume(&mut self) { self.halted = false; }
 }
 
+impl super::Chip for I4040 {
+    fn name(&self) -> &'static str {
+        "4040"
+    }
+
+    fn reset(&mut self) {
+        let monitor = self.stack_monitor.is_some();
+        *self = Self::new();
+        if monitor {
+            self.enable_stack_monitor();
+        }
+    }
+
+    fn tick(&mut self, phase: BusCycle) {
+        // Simplified tick without bus/control access
+        self.timing.advance();
+        let _ = phase;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::I4040;
+    use crate::i4001::I4001;
+    use crate::InstructionBus;
+    use mcs4_bus::prelude::*;
+
+    /// ROM image plus a log of SRC and I/O traffic
+    #[derive(Default)]
+    struct TestBus {
+        rom: Vec<u8>,
+        srcs: Vec<(u8, u8)>,
+        writes: Vec<(u8, u8)>,
+        read_value: u8,
+    }
+
+    impl TestBus {
+        fn with_rom(rom: &[u8]) -> Self {
+            Self { rom: rom.to_vec(), ..Default::default() }
+        }
+    }
+
+    impl InstructionBus for TestBus {
+        fn fetch(&mut self, bank: u8, addr: u16) -> u8 {
+            self.rom[..].fetch(bank, addr)
+        }
+        fn src(&mut self, command: u8, address: u8) {
+            self.srcs.push((command, address));
+        }
+        fn io(&mut self, _command: u8, opcode: u8, acc: u8) -> u8 {
+            self.writes.push((opcode, acc));
+            self.read_value
+        }
+    }
+
+    /// 4040 fetching from one 4001 per ROM bank over the phase-level bus
+    struct Board {
+        cpu: I4040,
+        roms: [I4001; 2],
+        bus: DataBus,
+        ctrl: ControlSignals,
+    }
+
+    impl Board {
+        fn new(bank0: &[u8], bank1: &[u8]) -> Self {
+            let mut roms = [I4001::with_cm_rom(0, 0), I4001::with_cm_rom(0, 1)];
+            roms[0].load(bank0);
+            roms[1].load(bank1);
+            Self { cpu: I4040::new(), roms, bus: DataBus::new(), ctrl: ControlSignals::mcs40() }
+        }
+
+        fn run_cycles(&mut self, cycles: usize) {
+            for _ in 0..cycles {
+                for phase in [
+                    BusCycle::A1, BusCycle::A2, BusCycle::A3, BusCycle::M1,
+                    BusCycle::M2, BusCycle::X1, BusCycle::X2, BusCycle::X3,
+                ] {
+                    if phase.is_memory_phase() {
+                        for rom in &mut self.roms {
+                            rom.tick_bus(phase, &mut self.bus, &self.ctrl);
+                        }
+                        self.cpu.tick(phase, &mut self.bus, &mut self.ctrl);
+                    } else {
+                        self.cpu.tick(phase, &mut self.bus, &mut self.ctrl);
+                        for rom in &mut self.roms {
+                            rom.tick_bus(phase, &mut self.bus, &self.ctrl);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn run(cpu: &mut I4040, bus: &mut TestBus, instructions: usize) {
+        for _ in 0..instructions {
+            cpu.step(bus);
+        }
+    }
 
     #[test]
     fn interrupt_vectors_and_bbs_restore() {
+        let mut rom = vec![0u8; 0x200];
+        rom[0x003] = 0x02; // BBS
+        let mut cpu = I4040::new();
+        cpu.pc = 0x100;
+        cpu.src = 0x5A;
+        cpu.intr.ein();
+        cpu.intr.request();
+        let mut bus = TestBus::with_rom(&rom);
+        cpu.step(&mut bus);
+        // Interrupt entry pushed 0x100 and ran BBS at the vector
+        assert_eq!(cpu.pc, 0x100);
+        assert_eq!(bus.srcs, vec![(0, 0x5A)]);
+        assert_eq!(cpu.src, 0x5A);
+    }
+
+    #[test]
+    fn interrupt_vectors_to_003() {
         let mut cpu = I4040::new();
         cpu.pc = 0x100;
         cpu.intr.ein();
         cpu.intr.request();
-        cpu.step();
-        assert_eq!(cpu.pc, 0x003);
-        // Simulate BBS restore
-        let saved = cpu.intr.bbs_restore();
-        assert_eq!(saved, 0);
+        cpu.step(&mut [0u8; 0x200][..]);
+        // NOP at 0x003 executed
+        assert_eq!(cpu.pc, 0x004);
+        assert_eq!(cpu.stack.peek(), Some(0x100));
+        assert!(!cpu.intr.enabled);
+    }
+
+    #[test]
+    fn runs_inherited_4004_program() {
+        // FIM P1,0x25; LD R3; ADD R2; XCH R8; JMS 0x010; JUN 0x00C
+        // 0x010: LDM 9; BBL 3
+        let mut rom = vec![0u8; 0x20];
+        rom[..10].copy_from_slice(&[0x22, 0x25, 0xA3, 0x82, 0xB8, 0x50, 0x10, 0x40, 0x0C, 0x00]);
+        rom[0x10] = 0xD9;
+        rom[0x11] = 0xC3;
+        let mut cpu = I4040::new();
+        let mut bus = TestBus::with_rom(&rom);
+        run(&mut cpu, &mut bus, 6);
+        assert_eq!(cpu.regs.get(8), 7);
+        assert_eq!(cpu.pc, 0x011);
+        assert_eq!(cpu.accumulator(), 9);
+        cpu.step(&mut bus);
+        assert_eq!((cpu.pc, cpu.accumulator()), (0x007, 3));
+        cpu.step(&mut bus);
+        assert_eq!(cpu.pc, 0x00C);
+    }
+
+    #[test]
+    fn short_jumps_use_page_of_next_instruction() {
+        // JCN at 0x0FE: second byte at 0x0FF, target lands in page 1
+        let mut rom = vec![0u8; 0x200];
+        rom[0xFE] = 0x14; // JCN Z
+        rom[0xFF] = 0x20;
+        let mut cpu = I4040::new();
+        cpu.pc = 0x0FE;
+        let mut bus = TestBus::with_rom(&rom);
+        assert_eq!(cpu.step(&mut bus), 2);
+        assert_eq!(cpu.pc, 0x120);
+    }
+
+    #[test]
+    fn fin_reads_table_from_current_page() {
+        let mut rom = vec![0u8; 0x200];
+        rom[0x100] = 0x32; // FIN P1
+        rom[0x142] = 0xBE;
+        let mut cpu = I4040::new();
+        cpu.pc = 0x100;
+        cpu.regs.set_pair_byte(0, 0x42);
+        let mut bus = TestBus::with_rom(&rom);
+        assert_eq!(cpu.step(&mut bus), 2);
+        assert_eq!(cpu.regs.pair_byte(1), 0xBE);
+        assert_eq!(cpu.pc, 0x101);
+    }
+
+    #[test]
+    fn fin_at_end_of_page_reads_next_page() {
+        let mut rom = vec![0u8; 0x300];
+        rom[0x1FF] = 0x34; // FIN P2
+        rom[0x110] = 0x11;
+        rom[0x210] = 0x22;
+        let mut cpu = I4040::new();
+        cpu.pc = 0x1FF;
+        cpu.regs.set_pair_byte(0, 0x10);
+        cpu.step(&mut rom[..]);
+        assert_eq!(cpu.regs.pair_byte(2), 0x22);
+    }
+
+    #[test]
+    fn logical_ops() {
+        // LDM 0xA; OR4; AN6; LDM 3; OR5; AN7
+        let rom = [0xDA, 0x04, 0x06, 0xD3, 0x05, 0x07];
+        let mut cpu = I4040::new();
+        cpu.regs.set(4, 0x5);
+        cpu.regs.set(5, 0x4);
+        cpu.regs.set(6, 0xC);
+        cpu.regs.set(7, 0x6);
+        cpu.alu.stc();
+        let mut bus = TestBus::with_rom(&rom);
+        run(&mut cpu, &mut bus, 3);
+        assert_eq!(cpu.accumulator(), 0xC);
+        run(&mut cpu, &mut bus, 3);
+        assert_eq!(cpu.accumulator(), 0x6);
+        assert!(cpu.carry(), "logical ops leave carry alone");
+    }
+
+    #[test]
+    fn register_and_rom_banks() {
+        // LDM 7; XCH R0; SB1; LDM 2; XCH R0; SB0; LD R0; DB1; NOP
+        let mut rom = vec![0u8; 0x2000];
+        rom[..9].copy_from_slice(&[0xD7, 0xB0, 0x0B, 0xD2, 0xB0, 0x0A, 0xA0, 0x09, 0x00]);
+        rom[0x1008] = 0xD5; // LDM 5 in bank 1
+        let mut cpu = I4040::new();
+        let mut bus = TestBus::with_rom(&rom);
+        run(&mut cpu, &mut bus, 7);
+        assert_eq!(cpu.accumulator(), 7);
+        cpu.regs.sb1();
+        assert_eq!(cpu.regs.get(0), 2);
+        cpu.regs.sb0();
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!(cpu.rom_bank, 1);
+        assert_eq!(cpu.accumulator(), 5);
+    }
+
+    #[test]
+    fn dcl_lcr_and_io_go_through_bus() {
+        // LDM 2; DCL; FIM P0,0x31; SRC P0; LDM 6; WRM; RDM; LCR; RPM
+        let rom = [0xD2, 0xFD, 0x20, 0x31, 0x21, 0xD6, 0xE0, 0xE9, 0x03, 0x0E];
+        let mut cpu = I4040::new();
+        let mut bus = TestBus::with_rom(&rom);
+        bus.read_value = 0xB;
+        run(&mut cpu, &mut bus, 7);
+        assert_eq!(bus.srcs, vec![(2, 0x31)]);
+        assert_eq!(bus.writes, vec![(0xE0, 6), (0xE9, 6)]);
+        assert_eq!(cpu.accumulator(), 0xB);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.accumulator(), 2);
+        cpu.step(&mut bus);
+        assert_eq!(bus.writes.last(), Some(&(0x0E, 2)));
+        assert_eq!(cpu.accumulator(), 0xB);
+    }
+
+    #[test]
+    fn dcl_keeps_three_bits() {
+        // LDM 0xA; DCL; LCR
+        let rom = [0xDA, 0xFD, 0x03];
+        let mut cpu = I4040::new();
+        run(&mut cpu, &mut TestBus::with_rom(&rom), 3);
+        assert_eq!(cpu.command, 2);
+        assert_eq!(cpu.accumulator(), 2);
+    }
+
+    #[test]
+    fn hlt_waits_for_interrupt() {
+        let mut rom = vec![0u8; 0x10];
+        rom[0] = 0x0C; // EIN
+        rom[1] = 0x01; // HLT
+        let mut cpu = I4040::new();
+        let mut bus = TestBus::with_rom(&rom);
+        run(&mut cpu, &mut bus, 4);
+        assert!(cpu.halted);
+        assert_eq!(cpu.pc, 0x002);
+
+        cpu.intr.request();
+        cpu.step(&mut bus);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.pc, 0x004);
+        assert_eq!(cpu.stack.peek(), Some(0x002));
+    }
+
+    #[test]
+    fn seven_level_stack() {
+        // Seven nested JMS, each followed by a BBL that unwinds one level
+        let mut rom = vec![0u8; 0x40];
+        for i in 0..7 {
+            let at = i * 3;
+            rom[at] = 0x50;
+            rom[at + 1] = (at + 3) as u8;
+            rom[at + 2] = 0xC0; // BBL 0
+        }
+        rom[21] = 0xC0;
+        let mut cpu = I4040::new();
+        let mut bus = TestBus::with_rom(&rom);
+        run(&mut cpu, &mut bus, 7);
+        assert!(cpu.stack.is_full());
+        run(&mut cpu, &mut bus, 7);
+        assert!(cpu.stack.is_empty());
+        assert_eq!(cpu.pc, 0x002);
+    }
+
+    #[test]
+    fn tick_runs_program_over_bus() {
+        // FIM P1,0x25; LD R3; ADD R2; JUN 0x010; 0x010: XCH R8
+        let mut rom = vec![0u8; 0x20];
+        rom[..6].copy_from_slice(&[0x22, 0x25, 0xA3, 0x82, 0x40, 0x10]);
+        rom[0x10] = 0xB8;
+        let mut board = Board::new(&rom, &[]);
+        board.run_cycles(2);
+        assert_eq!(board.cpu.regs.pair_byte(1), 0x25);
+        board.run_cycles(4);
+        assert_eq!(board.cpu.pc, 0x010);
+        assert_eq!(board.cpu.accumulator(), 7);
+        board.run_cycles(1);
+        assert_eq!(board.cpu.regs.get(8), 7);
+        assert_eq!(board.cpu.timing.cycle.cycle_count, 7);
+        assert_eq!(board.cpu.timing.cycle.instruction_count, 5);
+    }
+
+    #[test]
+    fn tick_fin_runs_second_cycle() {
+        // FIM P0,0x30; FIN P0; LD R1 (FIN replaces its own index pair)
+        let mut rom = vec![0u8; 0x40];
+        rom[..4].copy_from_slice(&[0x20, 0x30, 0x30, 0xA1]);
+        rom[0x30] = 0x9C;
+        let mut board = Board::new(&rom, &[]);
+        board.run_cycles(3);
+        assert_eq!(board.cpu.pc, 0x003);
+        board.run_cycles(1);
+        assert_eq!(board.cpu.regs.pair_byte(0), 0x9C);
+        assert_eq!(board.cpu.pc, 0x003);
+        board.run_cycles(1);
+        assert_eq!(board.cpu.accumulator(), 0xC);
+    }
+
+    #[test]
+    fn tick_fetches_designated_rom_bank() {
+        // DB1 in bank 0, LDM 5 at the next address in bank 1
+        let mut board = Board::new(&[0x09, 0xD1], &[0x00, 0xD5]);
+        board.run_cycles(2);
+        assert_eq!(board.cpu.rom_bank, 1);
+        assert_eq!(board.cpu.accumulator(), 5);
+    }
+
+    #[test]
+    fn tick_hlt_asserts_stp_until_interrupt() {
+        // EIN; HLT; ... 0x003: LDM 6
+        let mut board = Board::new(&[0x0C, 0x01, 0x00, 0xD6], &[]);
+        board.run_cycles(4);
+        assert!(board.cpu.halted);
+        assert!(board.ctrl.stop_acknowledged());
+        assert_eq!(board.cpu.pc, 0x002);
+
+        board.ctrl.set_interrupt(true, 0);
+        board.run_cycles(1);
+        assert!(!board.cpu.halted);
+        assert!(!board.ctrl.stop_acknowledged());
+        assert_eq!(board.cpu.pc, 0x003);
+        assert_eq!(board.cpu.stack.peek(), Some(0x002));
+        board.run_cycles(1);
+        assert_eq!(board.cpu.accumulator(), 6);
+    }
+
+    #[test]
+    fn tick_stop_input_freezes_cpu() {
+        // IAC forever
+        let mut board = Board::new(&[0xF2; 0x100], &[]);
+        board.ctrl.set_stop(true, 0);
+        board.run_cycles(3);
+        assert!(board.ctrl.stop_acknowledged());
+        assert_eq!((board.cpu.pc, board.cpu.accumulator()), (0x001, 1));
+
+        board.ctrl.set_stop(false, 0);
+        board.run_cycles(1);
+        assert!(!board.ctrl.stop_acknowledged());
+        board.run_cycles(2);
+        assert_eq!((board.cpu.pc, board.cpu.accumulator()), (0x003, 3));
     }
 }
@@ -29,11 +29,22 @@ impl RegFile {
         self.set(r + 1, lo);
     }
 
-    // Bank control
+    // Pair as a single byte (high register in the upper nibble), as FIM/SRC/FIN/JIN see it
     #[inline]
-    pub fn db0(&mut self) { self.bank = 0; }
+    pub fn pair_byte(&self, p: usize) -> u8 {
+        let (hi, lo) = self.get_pair(p);
+        (hi << 4) | lo
+    }
+    #[inline]
+    pub fn set_pair_byte(&mut self, p: usize, value: u8) {
+        self.set_pair(p, value >> 4, value & 0x0F);
+    }
+
+    // Bank control (SB0/SB1)
+    #[inline]
+    pub fn sb0(&mut self) { self.bank = 0; }
     #[inline]
-    pub fn db1(&mut self) { self.bank = 1; }
+    pub fn sb1(&mut self) { self.bank = 1; }
 }
 
 #[cfg(test)]
@@ -54,12 +65,12 @@ mod tests {
         rf.set(0, 0x3);
         assert_eq!(rf.get(0), 0x3);
         // Switch to bank 1: R0 maps to index 16
-        rf.db1();
+        rf.sb1();
         assert_eq!(rf.get(0), 0x0); // default value at index 16
         rf.set(0, 0x7);
         assert_eq!(rf.get(0), 0x7);
         // Back to bank 0: original value preserved at index 0
-        rf.db0();
+        rf.sb0();
         assert_eq!(rf.get(0), 0x3);
     }
 
@@ -67,9 +78,9 @@ mod tests {
     fn r8_r15_unaffected_by_bank() {
         let mut rf = RegFile::new();
         rf.set(8, 0x9);
-        rf.db1();
+        rf.sb1();
         assert_eq!(rf.get(8), 0x9);
-        rf.db0();
+        rf.sb0();
         assert_eq!(rf.get(8), 0x9);
     }
 
@@ -78,12 +89,15 @@ mod tests {
         let mut rf = RegFile::new();
         rf.set_pair(0, 0xA, 0x5);
         assert_eq!(rf.get_pair(0), (0xA, 0x5));
-        rf.db1();
+        rf.sb1();
         // Bank switch should change where P0 points; values are distinct per bank
         assert_eq!(rf.get_pair(0), (0x0, 0x0));
         rf.set_pair(0, 0x1, 0x2);
         assert_eq!(rf.get_pair(0), (0x1, 0x2));
-        rf.db0();
+        rf.sb0();
         assert_eq!(rf.get_pair(0), (0xA, 0x5));
+        assert_eq!(rf.pair_byte(0), 0xA5);
+        rf.set_pair_byte(7, 0x3C);
+        assert_eq!((rf.get(14), rf.get(15)), (0x3, 0xC));
     }
 }
@@ -1,12 +1,47 @@
-//! Intel 4101 RAM (stub)
+//! Intel 4101 RAM
+//!
+//! The 4101 is a 256x4-bit static RAM with a standard memory interface
+//! (8 address lines, chip enable, read/write). It does not speak the MCS-4
+//! bus protocol itself; in MCS-40 systems it sits behind a 4289.
+
 use mcs4_bus::BusCycle;
 
+/// Intel 4101: 256x4 static RAM
 #[derive(Clone, Debug)]
-pub struct I4101 { ram: [u8; 256] }
-impl I4101 { pub fn new() -> Self { Self { ram: [0; 256] } } }
-impl Default for I4101 { fn default() -> Self { Self::new() } }
+pub struct I4101 {
+    ram: [u8; 256],
+}
+
+impl I4101 {
+    pub fn new() -> Self {
+        Self { ram: [0; 256] }
+    }
+
+    /// Read the nibble at `addr`
+    pub fn read(&self, addr: u8) -> u8 {
+        self.ram[addr as usize] & 0x0F
+    }
+
+    /// Write a nibble at `addr`
+    pub fn write(&mut self, addr: u8, value: u8) {
+        self.ram[addr as usize] = value & 0x0F;
+    }
+}
+
+impl Default for I4101 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl super::Chip for I4101 {
-    fn name(&self) -> &'static str { "4101" }
-    fn reset(&mut self) { self.ram = [0; 256]; }
+    fn name(&self) -> &'static str {
+        "4101"
+    }
+
+    fn reset(&mut self) {
+        self.ram = [0; 256];
+    }
+
     fn tick(&mut self, _phase: BusCycle) {}
 }
@@ -1,11 +1,287 @@
-//! Intel 4289 Standard Memory Interface (stub)
-use mcs4_bus::BusCycle;
+//! Intel 4289 Standard Memory Interface
+//!
+//! The 4289 lets the 4040 fetch its program from standard memory parts
+//! instead of 4001s. It latches the 12-bit address from A1-A3, strobes the
+//! attached memory and returns OPR/OPA at M1/M2.
+//!
+//! Here it drives pairs of 4101s as program RAM: for each page, the first
+//! 4101 holds the upper nibble (OPR) and the second the lower (OPA).
+//!
+//! The CPU reaches the RAM as data with RPM and WPM. SRC on the 4289's
+//! CM-ROM line sets the low eight address bits and WRR the page. Each RPM
+//! or WPM moves one nibble at X2, the upper half first; a flip-flop
+//! toggles between halves and SRC resets it to the upper one.
+
+use mcs4_bus::prelude::*;
+
+use crate::i4101::I4101;
+
+/// I/O instructions the 4289 answers
+const RPM: u8 = 0x0E;
+const WPM: u8 = 0xE3;
+const WRR: u8 = 0xE2;
+
+/// Intel 4289 with attached 4101 program RAM
+#[derive(Clone, Debug)]
+pub struct I4289 {
+    /// First page (0-15) served by this interface
+    pub base_page: u8,
+
+    /// CM-ROM line (0-3) this interface is wired to
+    pub cm_rom_line: u8,
+
+    /// Attached 4101s, two per page (OPR then OPA)
+    ram: Vec<I4101>,
+
+    /// Latched address from A1/A2/A3 phases
+    address: u16,
+
+    /// Is this interface selected for current transaction?
+    selected: bool,
+
+    /// Instruction byte seen on the bus at M1/M2, whoever drove it
+    opcode: u8,
+
+    /// RPM, WPM or WRR strobed onto our CM-ROM line at M2
+    command: Option<u8>,
+
+    /// Low address bits from the last SRC; the high nibble arrives at X2
+    src: u8,
+
+    /// An SRC is in flight: its low nibble follows at X3
+    src_pending: bool,
+
+    /// Page loaded by WRR for RPM/WPM
+    page: u8,
+
+    /// RPM/WPM flip-flop: the next transfer is the lower nibble
+    lower_half: bool,
+}
+
+impl I4289 {
+    /// Create an interface serving `pages` pages starting at `base_page`
+    pub fn new(base_page: u8, pages: usize) -> Self {
+        let base_page = base_page & 0x0F;
+        let pages = pages.min(16 - base_page as usize);
+        Self {
+            base_page,
+            cm_rom_line: 0,
+            ram: vec![I4101::new(); pages * 2],
+            address: 0,
+            selected: false,
+            opcode: 0,
+            command: None,
+            src: 0,
+            src_pending: false,
+            page: 0,
+            lower_half: false,
+        }
+    }
+
+    /// Number of pages of program memory attached
+    pub fn pages(&self) -> usize {
+        self.ram.len() / 2
+    }
+
+    /// Number of 4101s attached
+    pub fn chip_count(&self) -> usize {
+        self.ram.len()
+    }
+
+    /// Does this interface serve the given 12-bit address?
+    pub fn contains(&self, addr: u16) -> bool {
+        let page = ((addr >> 8) & 0x0F) as usize;
+        page >= self.base_page as usize && page < self.base_page as usize + self.pages()
+    }
+
+    /// Read program memory at a 12-bit address (direct access for debugging)
+    pub fn read_direct(&self, addr: u16) -> Option<u8> {
+        let (hi, lo, offset) = self.locate(addr)?;
+        Some((self.ram[hi].read(offset) << 4) | self.ram[lo].read(offset))
+    }
+
+    /// Write program memory at a 12-bit address; ignored outside our pages
+    pub fn write_direct(&mut self, addr: u16, value: u8) {
+        if let Some((hi, lo, offset)) = self.locate(addr) {
+            self.ram[hi].write(offset, value >> 4);
+            self.ram[lo].write(offset, value & 0x0F);
+        }
+    }
+
+    /// Check if interface is currently selected
+    pub fn is_selected(&self) -> bool {
+        self.selected
+    }
+
+    /// Address RPM and WPM use: the WRR page and the SRC address
+    pub fn data_address(&self) -> u16 {
+        ((self.page as u16) << 8) | self.src as u16
+    }
+
+    /// Indices of the OPR/OPA 4101s and the offset within them
+    fn locate(&self, addr: u16) -> Option<(usize, usize, u8)> {
+        if !self.contains(addr) {
+            return None;
+        }
+        let page = ((addr >> 8) & 0x0F) as usize - self.base_page as usize;
+        Some((page * 2, page * 2 + 1, (addr & 0xFF) as u8))
+    }
+
+    /// Process a bus phase
+    pub fn tick_bus(&mut self, phase: BusCycle, bus: &mut DataBus, ctrl: &ControlSignals) {
+        match phase {
+            BusCycle::A1 => {
+                self.address = (self.address & 0xFF0) | (bus.read() & 0x0F) as u16;
+                self.selected = false;
+            }
+            BusCycle::A2 => {
+                self.address = (self.address & 0xF0F) | (((bus.read() & 0x0F) as u16) << 4);
+            }
+            BusCycle::A3 => {
+                self.address = (self.address & 0x0FF) | (((bus.read() & 0x0F) as u16) << 8);
+                self.selected = ctrl.cm_rom_line(self.cm_rom_line as usize)
+                    && self.contains(self.address);
+            }
+            BusCycle::M1 | BusCycle::M2 => {
+                if self.selected {
+                    if let Some(byte) = self.read_direct(self.address) {
+                        // OPR from the first 4101 at M1, OPA from the second at M2
+                        let nibble = if phase == BusCycle::M1 {
+                            byte >> 4
+                        } else {
+                            byte & 0x0F
+                        };
+                        bus.write(nibble);
+                    }
+                }
+                // Watch every instruction to recognize RPM, WPM and WRR
+                let nibble = bus.read() & 0x0F;
+                self.opcode = if phase == BusCycle::M1 {
+                    (self.opcode & 0x0F) | (nibble << 4)
+                } else {
+                    (self.opcode & 0xF0) | nibble
+                };
+            }
+            BusCycle::X1 => {
+                // CM-ROM is only still asserted if the CPU strobed it at M2
+                let strobed = ctrl.cm_rom_line(self.cm_rom_line as usize);
+                self.command =
+                    (strobed && matches!(self.opcode, RPM | WPM | WRR)).then_some(self.opcode);
+            }
+            BusCycle::X2 => {
+                // SRC: CM-ROM with the high address nibble on the bus
+                if ctrl.cm_rom_line(self.cm_rom_line as usize) {
+                    self.src = (bus.read() & 0x0F) << 4;
+                    self.src_pending = true;
+                    self.lower_half = false;
+                }
+                match self.command.take() {
+                    Some(WRR) => self.page = bus.read() & 0x0F,
+                    Some(WPM) => self.write_half(bus.read()),
+                    Some(RPM) => {
+                        if let Some(nibble) = self.read_half() {
+                            bus.write(nibble);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            BusCycle::X3 => {
+                if self.src_pending {
+                    self.src |= bus.read() & 0x0F;
+                    self.src_pending = false;
+                }
+            }
+        }
+    }
+
+    /// RPM: the current half of the byte at the data address
+    fn read_half(&mut self) -> Option<u8> {
+        let byte = self.read_direct(self.data_address())?;
+        let nibble = if self.lower_half {
+            byte & 0x0F
+        } else {
+            byte >> 4
+        };
+        self.lower_half = !self.lower_half;
+        Some(nibble)
+    }
+
+    /// WPM: store `value` in the current half of the byte at the data address
+    fn write_half(&mut self, value: u8) {
+        let address = self.data_address();
+        if let Some((hi, lo, offset)) = self.locate(address) {
+            let chip = if self.lower_half { lo } else { hi };
+            self.ram[chip].write(offset, value & 0x0F);
+        }
+        self.lower_half = !self.lower_half;
+    }
+}
+
+impl Default for I4289 {
+    fn default() -> Self {
+        Self::new(0, 0)
+    }
+}
 
-#[derive(Clone, Debug, Default)]
-pub struct I4289;
-impl I4289 { pub fn new() -> Self { Self } }
 impl super::Chip for I4289 {
-    fn name(&self) -> &'static str { "4289" }
-    fn reset(&mut self) {}
+    fn name(&self) -> &'static str {
+        "4289"
+    }
+
+    fn reset(&mut self) {
+        self.address = 0;
+        self.selected = false;
+        self.opcode = 0;
+        self.command = None;
+        self.src = 0;
+        self.src_pending = false;
+        self.page = 0;
+        self.lower_half = false;
+    }
+
     fn tick(&mut self, _phase: BusCycle) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_program_ram_pages() {
+        let mut smi = I4289::new(0xE, 4);
+        // Clamped to the end of the address space
+        assert_eq!(smi.pages(), 2);
+        assert_eq!(smi.chip_count(), 4);
+        assert!(smi.contains(0xE00));
+        assert!(!smi.contains(0xDFF));
+
+        smi.write_direct(0xF12, 0xB7);
+        assert_eq!(smi.read_direct(0xF12), Some(0xB7));
+        assert_eq!(smi.read_direct(0x012), None);
+    }
+
+    #[test]
+    fn test_fetch_over_bus() {
+        let mut smi = I4289::new(0x3, 1);
+        smi.write_direct(0x3C4, 0x5E);
+        let mut bus = DataBus::new();
+        let mut ctrl = ControlSignals::mcs40();
+
+        for (phase, nibble) in [
+            (BusCycle::A1, 0x4),
+            (BusCycle::A2, 0xC),
+            (BusCycle::A3, 0x3),
+        ] {
+            bus.write(nibble);
+            if phase == BusCycle::A3 {
+                ctrl.assert_cm_rom(0, 0);
+            }
+            smi.tick_bus(phase, &mut bus, &ctrl);
+        }
+        smi.tick_bus(BusCycle::M1, &mut bus, &ctrl);
+        assert_eq!(bus.read(), 0x5);
+        smi.tick_bus(BusCycle::M2, &mut bus, &ctrl);
+        assert_eq!(bus.read(), 0xE);
+    }
+}
@@ -1,13 +1,167 @@
-//! Intel 4308 ROM (stub)
-use mcs4_bus::BusCycle;
+//! Intel 4308 ROM
+//!
+//! The 4308 is a 1024x8-bit ROM that takes the place of four 4001s.
+//! It answers for four consecutive 256-byte pages: the upper two bits of the
+//! page nibble sent at A3 must match its chip number.
 
+use mcs4_bus::prelude::*;
+
+/// Intel 4308: 1Kx8 ROM
 #[derive(Clone, Debug)]
-#[allow(dead_code)]
-pub struct I4308 { rom: Vec<u8> }
-impl I4308 { pub fn new() -> Self { Self { rom: vec![0; 1024] } } }
-impl Default for I4308 { fn default() -> Self { Self::new() } }
+pub struct I4308 {
+    /// ROM contents (1024 bytes)
+    rom: Vec<u8>,
+
+    /// Chip number (0-3): pages `4*chip_id ..= 4*chip_id + 3`
+    pub chip_id: u8,
+
+    /// CM-ROM line (0-3) this chip is wired to
+    pub cm_rom_line: u8,
+
+    /// Latched 10-bit address from A1/A2/A3 phases
+    address: u16,
+
+    /// Is this chip selected for current transaction?
+    selected: bool,
+}
+
+impl I4308 {
+    /// Create a new 4308 with chip number 0-3 on CM-ROM0
+    pub fn new(chip_id: u8) -> Self {
+        Self {
+            rom: vec![0; 1024],
+            chip_id: chip_id & 0x03,
+            cm_rom_line: 0,
+            address: 0,
+            selected: false,
+        }
+    }
+
+    /// Create a 4308 on a given CM-ROM line (0-3)
+    pub fn with_cm_rom(chip_id: u8, line: u8) -> Self {
+        Self {
+            cm_rom_line: line & 0x03,
+            ..Self::new(chip_id)
+        }
+    }
+
+    /// First 12-bit address covered by this chip
+    pub fn base_address(&self) -> u16 {
+        (self.chip_id as u16) << 10
+    }
+
+    /// Does this chip hold the given 12-bit address?
+    pub fn contains(&self, addr: u16) -> bool {
+        ((addr & 0x0FFF) >> 10) as u8 == self.chip_id
+    }
+
+    /// Load ROM contents from a byte slice at an offset within the chip
+    pub fn load_at(&mut self, offset: usize, data: &[u8]) {
+        let end = (offset + data.len()).min(1024);
+        let len = end.saturating_sub(offset);
+        if len > 0 {
+            self.rom[offset..end].copy_from_slice(&data[..len]);
+        }
+    }
+
+    /// Read ROM at offset 0-1023 (direct access for debugging)
+    pub fn read_direct(&self, offset: u16) -> u8 {
+        self.rom[(offset & 0x3FF) as usize]
+    }
+
+    /// Write ROM at offset 0-1023 (for programming/testing)
+    pub fn write_direct(&mut self, offset: u16, value: u8) {
+        self.rom[(offset & 0x3FF) as usize] = value;
+    }
+
+    /// Check if chip is currently selected
+    pub fn is_selected(&self) -> bool {
+        self.selected
+    }
+
+    /// Process a bus phase
+    pub fn tick_bus(&mut self, phase: BusCycle, bus: &mut DataBus, ctrl: &ControlSignals) {
+        match phase {
+            BusCycle::A1 => {
+                self.address = (self.address & 0x3F0) | (bus.read() & 0x0F) as u16;
+                self.selected = false;
+            }
+            BusCycle::A2 => {
+                self.address = (self.address & 0x30F) | (((bus.read() & 0x0F) as u16) << 4);
+            }
+            BusCycle::A3 => {
+                let page = bus.read() & 0x0F;
+                self.address = (self.address & 0x0FF) | (((page & 0x03) as u16) << 8);
+                self.selected = ctrl.cm_rom_line(self.cm_rom_line as usize)
+                    && (page >> 2) == self.chip_id;
+            }
+            BusCycle::M1 => {
+                if self.selected {
+                    bus.write((self.rom[self.address as usize] >> 4) & 0x0F);
+                }
+            }
+            BusCycle::M2 => {
+                if self.selected {
+                    bus.write(self.rom[self.address as usize] & 0x0F);
+                }
+            }
+            BusCycle::X1 | BusCycle::X2 | BusCycle::X3 => {}
+        }
+    }
+}
+
+impl Default for I4308 {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
 impl super::Chip for I4308 {
-    fn name(&self) -> &'static str { "4308" }
-    fn reset(&mut self) {}
+    fn name(&self) -> &'static str {
+        "4308"
+    }
+
+    fn reset(&mut self) {
+        self.address = 0;
+        self.selected = false;
+    }
+
     fn tick(&mut self, _phase: BusCycle) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_decode() {
+        let rom = I4308::new(2);
+        assert_eq!(rom.base_address(), 0x800);
+        assert!(rom.contains(0x800));
+        assert!(rom.contains(0xBFF));
+        assert!(!rom.contains(0xC00));
+        assert!(!rom.contains(0x7FF));
+    }
+
+    #[test]
+    fn test_fetch_over_bus() {
+        let mut rom = I4308::new(1);
+        rom.write_direct(0x2A5, 0x3C);
+        let mut bus = DataBus::new();
+        let mut ctrl = ControlSignals::mcs4();
+
+        // Address 0x6A5 = page 6 = chip 1, offset 0x2A5
+        for (phase, nibble) in [(BusCycle::A1, 0x5), (BusCycle::A2, 0xA), (BusCycle::A3, 0x6)] {
+            bus.write(nibble);
+            if phase == BusCycle::A3 {
+                ctrl.assert_cm_rom(0, 0);
+            }
+            rom.tick_bus(phase, &mut bus, &ctrl);
+        }
+        assert!(rom.is_selected());
+        rom.tick_bus(BusCycle::M1, &mut bus, &ctrl);
+        assert_eq!(bus.read(), 0x3);
+        rom.tick_bus(BusCycle::M2, &mut bus, &ctrl);
+        assert_eq!(bus.read(), 0xC);
+    }
+}
@@ -15,6 +15,13 @@
 //! - [`i4201`] - Clock generator
 //! - [`i4289`] - Standard memory interface
 //! - [`i4308`] - 1Kx8 ROM
+//!
+//! ## Support Logic
+//! - [`i3205`] - 1-of-8 binary decoder (CM-RAM bank expansion)
+//!
+//! ## Tools
+//! - [`disasm`] - 4004/4040 disassembler
+//! - [`stack_monitor`] - call stack overflow/underflow diagnostics
 
 pub mod i4004;
 pub mod i4040;
@@ -28,6 +35,53 @@ pub mod i4201;
 pub mod i4289;
 pub mod i4308;
 
+// Support logic
+pub mod i3205;
+
+// Tools
+pub mod disasm;
+pub mod stack_monitor;
+
+/// Memory and I/O as seen by an instruction-level CPU model
+///
+/// The phase-accurate CPU models talk to the other chips over the
+/// [`mcs4_bus::DataBus`]. Instruction-stepping models skip the bus cycle and
+/// go through this trait instead: one call per program-memory fetch, per SRC
+/// and per I/O instruction.
+pub trait InstructionBus {
+    /// Fetch a program memory byte
+    ///
+    /// `bank` is the CM-ROM line in use (always 0 on the 4004, DB0/DB1 on the
+    /// 4040) and `addr` the 12-bit address within that bank.
+    fn fetch(&mut self, bank: u8, addr: u16) -> u8;
+
+    /// SRC: send an 8-bit address to the chips on the CM-RAM lines selected
+    /// by `command` (the DCL command register)
+    fn src(&mut self, command: u8, address: u8);
+
+    /// Execute an I/O instruction against the previously SRC-selected chip
+    ///
+    /// `opcode` is the full instruction byte (0xE0-0xEF, or 0x0E for the
+    /// 4040 RPM). Write instructions receive the accumulator in `acc`; read
+    /// instructions return the nibble read.
+    fn io(&mut self, command: u8, opcode: u8, acc: u8) -> u8;
+}
+
+/// A bare ROM image: bank N occupies bytes `N*4096..(N+1)*4096`.
+/// Reads outside the image return 0 and I/O goes nowhere.
+impl InstructionBus for [u8] {
+    fn fetch(&mut self, bank: u8, addr: u16) -> u8 {
+        let index = (bank as usize) * 0x1000 + (addr & 0x0FFF) as usize;
+        self.get(index).copied().unwrap_or(0)
+    }
+
+    fn src(&mut self, _command: u8, _address: u8) {}
+
+    fn io(&mut self, _command: u8, _opcode: u8, _acc: u8) -> u8 {
+        0
+    }
+}
+
 /// Common trait for all chips
 pub trait Chip: Send + Sync {
     /// Chip name (e.g., "4004", "4001")
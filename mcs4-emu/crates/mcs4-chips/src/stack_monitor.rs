@@ -0,0 +1,171 @@
+//! Call stack diagnostics
+//!
+//! The 4004 keeps three return addresses and wraps silently: a fourth
+//! nested JMS overwrites the oldest one, and a BBL with nothing to return to
+//! pops whatever the slot holds. That matches the silicon but hides the most
+//! common firmware bug. The 4040's 7-level [`crate::i4040::CallStack`]
+//! refuses both instead.
+//!
+//! A [`StackMonitor`] follows the number of valid return addresses held
+//! and records a [`StackFault`] for a call with every level in use or a
+//! return with none. Both CPUs report through it when one is attached.
+
+use std::fmt;
+
+use rkyv::{Archive, Deserialize, Serialize};
+
+/// Faults kept in [`StackMonitor::faults`]; older ones are dropped
+const MAX_FAULTS: usize = 256;
+
+/// A call or return the stack cannot honour
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+pub enum StackFault {
+    /// JMS (or 4040 interrupt entry) at `pc` with every level in use. `lost`
+    /// is the return address that no longer comes back: the oldest one,
+    /// overwritten on the 4004, or the new one, dropped on the 4040.
+    Overflow { pc: u16, lost: u16 },
+    /// BBL (or 4040 BBS) at `pc` with no call outstanding
+    Underflow { pc: u16 },
+}
+
+impl StackFault {
+    /// Address of the instruction that faulted
+    pub fn pc(&self) -> u16 {
+        match *self {
+            StackFault::Overflow { pc, .. } | StackFault::Underflow { pc } => pc,
+        }
+    }
+}
+
+impl fmt::Display for StackFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            StackFault::Overflow { pc, lost } => write!(
+                f,
+                "stack overflow at 0x{:03X}: return address 0x{:03X} lost",
+                pc, lost
+            ),
+            StackFault::Underflow { pc } => write!(f, "stack underflow at 0x{:03X}", pc),
+        }
+    }
+}
+
+/// Logical call depth tracker for a CPU's return stack
+#[derive(Clone, Debug, Default, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+pub struct StackMonitor {
+    /// Return addresses the stack holds
+    levels: u8,
+    /// Valid return addresses currently held
+    depth: u8,
+    /// Most recent faults, oldest first
+    faults: Vec<StackFault>,
+    /// Faults seen in total
+    count: u64,
+}
+
+impl StackMonitor {
+    /// Monitor a stack of `levels` return addresses, starting empty
+    pub fn new(levels: u8) -> Self {
+        Self::with_depth(levels, 0)
+    }
+
+    /// Monitor a stack that already holds `depth` return addresses
+    pub fn with_depth(levels: u8, depth: u8) -> Self {
+        Self {
+            levels,
+            depth: depth.min(levels),
+            faults: Vec::new(),
+            count: 0,
+        }
+    }
+
+    /// Valid return addresses currently held
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    /// A call at `pc` pushing over `lost` if the stack is full
+    pub fn call(&mut self, pc: u16, lost: u16) -> Option<StackFault> {
+        if self.depth < self.levels {
+            self.depth += 1;
+            return None;
+        }
+        self.record(StackFault::Overflow { pc: pc & 0x0FFF, lost: lost & 0x0FFF })
+    }
+
+    /// A return at `pc`
+    pub fn ret(&mut self, pc: u16) -> Option<StackFault> {
+        if self.depth > 0 {
+            self.depth -= 1;
+            return None;
+        }
+        self.record(StackFault::Underflow { pc: pc & 0x0FFF })
+    }
+
+    fn record(&mut self, fault: StackFault) -> Option<StackFault> {
+        tracing::warn!("{}", fault);
+        if self.faults.len() == MAX_FAULTS {
+            self.faults.remove(0);
+        }
+        self.faults.push(fault);
+        self.count += 1;
+        Some(fault)
+    }
+
+    /// The most recent faults, oldest first
+    pub fn faults(&self) -> &[StackFault] {
+        &self.faults
+    }
+
+    /// Faults seen since the monitor was attached, including dropped ones
+    pub fn fault_count(&self) -> u64 {
+        self.count
+    }
+
+    /// Remove and return the recorded faults
+    pub fn take_faults(&mut self) -> Vec<StackFault> {
+        std::mem::take(&mut self.faults)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_depth_and_faults() {
+        let mut monitor = StackMonitor::new(3);
+        for pc in [0x010, 0x020, 0x030] {
+            assert_eq!(monitor.call(pc, 0), None);
+        }
+        assert_eq!(monitor.depth(), 3);
+        let fault = monitor.call(0x040, 0x012).unwrap();
+        assert_eq!(fault, StackFault::Overflow { pc: 0x040, lost: 0x012 });
+        assert_eq!(fault.to_string(), "stack overflow at 0x040: return address 0x012 lost");
+        assert_eq!(monitor.depth(), 3);
+
+        for _ in 0..3 {
+            assert_eq!(monitor.ret(0x050), None);
+        }
+        assert_eq!(monitor.ret(0x051), Some(StackFault::Underflow { pc: 0x051 }));
+        assert_eq!(monitor.depth(), 0);
+        assert_eq!(monitor.fault_count(), 2);
+        assert_eq!(monitor.take_faults().len(), 2);
+        assert!(monitor.faults().is_empty());
+        assert_eq!(monitor.fault_count(), 2);
+    }
+
+    #[test]
+    fn test_fault_log_is_bounded() {
+        let mut monitor = StackMonitor::with_depth(7, 9);
+        assert_eq!(monitor.depth(), 7);
+        for pc in 0..300 {
+            monitor.call(pc, 0);
+        }
+        assert_eq!(monitor.faults().len(), MAX_FAULTS);
+        assert_eq!(monitor.faults()[0].pc(), 300 - MAX_FAULTS as u16);
+        assert_eq!(monitor.fault_count(), 300);
+    }
+}
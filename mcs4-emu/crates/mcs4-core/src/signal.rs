@@ -1,5 +1,6 @@
 //! Signal types and signal history tracking
 
+use rkyv::{Archive, Deserialize, Serialize};
 use crate::timing::Time;
 use smallvec::SmallVec;
 
@@ -18,7 +19,8 @@ impl SignalId {
 /// - Logic LOW = Vdd = -15V
 ///
 /// We abstract this to standard positive logic in the simulator.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 #[repr(u8)]
 pub enum SignalLevel {
     /// Logic low (0)
@@ -118,7 +120,8 @@ impl From<SignalLevel> for bool {
 }
 
 /// A signal with transition history for waveform display
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct Signal {
     /// Human-readable name (e.g., "PHI1", "D0", "SYNC")
     pub name: String,
@@ -127,7 +130,9 @@ pub struct Signal {
     pub current: SignalLevel,
 
     /// Transition history: (time, new_value)
-    /// Uses SmallVec to avoid allocation for signals with few transitions
+    /// Uses SmallVec to avoid allocation for signals with few transitions.
+    /// Waveform trace only, so snapshots leave it out.
+    #[with(rkyv::with::Skip)]
     history: SmallVec<[(Time, SignalLevel); 16]>,
 
     /// Maximum history length (for memory management)
@@ -1 +1,313 @@
-fn main() {}
+//! mcs4-emu: load a ROM image into an MCS-4 system and run it
+//!
+//! Without a front end, for firmware tests in CI: pick a board, apply a
+//! stimulus script, stop at a breakpoint, PC, halt or cycle limit, and
+//! dump the final state as JSON. The exit code says how the run ended:
+//! 0 for a requested stop (or all `--cycles` run when no stop was asked
+//! for), 2 for hitting the cycle limit first, 1 for any error.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, ValueEnum};
+use mcs4_chips::disasm::{CpuType, Disassembler};
+use mcs4_system::runner::{self, RunOptions, RunOutcome, Stimulus};
+use mcs4_system::{
+    BoardConfig, Break, Condition, ExecutionMode, LoadError, Lockstep, Mcs4System, RomFormat,
+    RomImage,
+};
+use serde_json::json;
+
+/// Cycle limit when a stop condition is given without `--cycles`
+const DEFAULT_LIMIT: u64 = 10_000_000;
+
+/// Exit code for a run that hit its cycle limit before a requested stop
+const EXIT_LIMIT: u8 = 2;
+
+/// Program memory covered by a `--disasm` listing: one 4K bank
+const DISASM_LIMIT: u32 = 0x1000;
+
+/// ROM image formats accepted on the command line
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Format {
+    Hex,
+    Srec,
+    Bin,
+}
+
+impl From<Format> for RomFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Hex => RomFormat::IntelHex,
+            Format::Srec => RomFormat::SRecord,
+            Format::Bin => RomFormat::Binary,
+        }
+    }
+}
+
+/// CPUs whose instruction set `--disasm` decodes
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Cpu {
+    #[value(name = "4004")]
+    I4004,
+    #[value(name = "4040")]
+    I4040,
+}
+
+impl From<Cpu> for CpuType {
+    fn from(cpu: Cpu) -> Self {
+        match cpu {
+            Cpu::I4004 => CpuType::I4004,
+            Cpu::I4040 => CpuType::I4040,
+        }
+    }
+}
+
+/// Board layouts
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Preset {
+    /// One 4001, one 4002
+    Minimal,
+    /// Four 4001s, two banks of 4002s
+    Standard,
+    /// Sixteen 4001s, four banks of 4002s
+    Maximal,
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "mcs4-emu", about = "Intel MCS-4 emulator")]
+struct Args {
+    /// ROM image (Intel HEX, Motorola S-record or raw binary)
+    #[arg(required_unless_present = "config")]
+    rom: Option<PathBuf>,
+
+    /// Image format; guessed from the file extension if omitted
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+
+    /// Board to load the image into
+    #[arg(long, value_enum, default_value_t = Preset::Maximal)]
+    preset: Preset,
+
+    /// Board description (TOML, or JSON by extension) instead of a preset;
+    /// the ROM image, if given, is loaded over the board's own images
+    #[arg(long, value_name = "FILE", conflicts_with = "preset")]
+    config: Option<PathBuf>,
+
+    /// Machine cycles to run after loading; with a stop condition, the
+    /// most to run (default 10,000,000)
+    #[arg(long)]
+    cycles: Option<u64>,
+
+    /// Stop at this address, optionally only when a condition holds
+    /// (`0x123` or `0x123:ACC==5`); repeatable
+    #[arg(long = "break", value_name = "ADDR[:COND]", value_parser = parse_break)]
+    breaks: Vec<(u16, Option<Condition>)>,
+
+    /// Stop when the PC reaches this address
+    #[arg(long, value_name = "ADDR", value_parser = parse_address)]
+    until_pc: Option<u16>,
+
+    /// Stop at a JUN to itself
+    #[arg(long)]
+    until_halt: bool,
+
+    /// Timed TEST pin and 4001 input port changes (`CYCLE test 0|1`,
+    /// `CYCLE port CHIP VALUE`)
+    #[arg(long, value_name = "FILE")]
+    stimulus: Option<PathBuf>,
+
+    /// Print the final registers, RAM and ports as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Run instruction by instruction instead of phase by phase
+    #[arg(long)]
+    fast: bool,
+
+    /// Run the cycles in both execution modes, stopping where they disagree
+    #[arg(long, conflicts_with = "fast", requires = "cycles")]
+    lockstep: bool,
+
+    /// Print a flow-analysed listing of the image instead of running it
+    #[arg(long, requires = "rom")]
+    disasm: bool,
+
+    /// Instruction set for --disasm; runs always use a 4004 board
+    #[arg(long, value_enum, default_value = "4004", requires = "disasm")]
+    cpu: Cpu,
+}
+
+/// Decimal, or hex with a `0x` prefix
+fn parse_address(text: &str) -> Result<u16, String> {
+    let address = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse(),
+    }
+    .map_err(|_| format!("invalid address '{}'", text))?;
+    if address > 0x0FFF {
+        return Err(format!("address 0x{:X} is outside program memory", address));
+    }
+    Ok(address)
+}
+
+fn parse_break(text: &str) -> Result<(u16, Option<Condition>), String> {
+    let (address, condition) = match text.split_once(':') {
+        Some((address, condition)) => (address, Some(condition)),
+        None => (text, None),
+    };
+    let condition = condition
+        .map(Condition::parse)
+        .transpose()
+        .map_err(|err| err.to_string())?;
+    Ok((parse_address(address)?, condition))
+}
+
+fn main() -> ExitCode {
+    // Usage errors exit with 1 like any other error: clap's own code, 2,
+    // would read as the cycle limit
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        Err(err) => {
+            let _ = err.print();
+            return if err.use_stderr() {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            };
+        }
+    };
+
+    let image = match &args.rom {
+        Some(path) => {
+            let format = args
+                .format
+                .map_or_else(|| RomFormat::from_path(path), RomFormat::from);
+            match RomImage::from_file_as(path, format) {
+                Ok(image) => Some((path, image)),
+                Err(err) => {
+                    eprintln!("mcs4-emu: {}: {}", path.display(), err);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        None => None,
+    };
+
+    if args.disasm {
+        let rom = match image {
+            Some((path, image)) => match image.end_address() {
+                Some(end) if end >= DISASM_LIMIT => {
+                    let err = LoadError::AddressOutOfRange {
+                        address: end,
+                        limit: DISASM_LIMIT,
+                    };
+                    eprintln!("mcs4-emu: {}: {}", path.display(), err);
+                    return ExitCode::FAILURE;
+                }
+                _ => image.flatten(),
+            },
+            None => Vec::new(),
+        };
+        let dis = Disassembler::new(args.cpu.into());
+        print!("{}", dis.analyze(&rom).listing(&dis, &rom));
+        return ExitCode::SUCCESS;
+    }
+
+    // Maximal by default: all 16 4001s fitted, so any 4 KB image has
+    // somewhere to go
+    let mut sys = match &args.config {
+        Some(path) => match BoardConfig::from_file(path).and_then(|config| config.build()) {
+            Ok(sys) => sys,
+            Err(err) => {
+                eprintln!("mcs4-emu: {}: {}", path.display(), err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => match args.preset {
+            Preset::Minimal => Mcs4System::minimal(),
+            Preset::Standard => Mcs4System::standard(),
+            Preset::Maximal => Mcs4System::maximal(),
+        },
+    };
+    if let Some((path, image)) = &image {
+        if let Err(err) = sys.load_image(image) {
+            eprintln!("mcs4-emu: {}: {}", path.display(), err);
+            return ExitCode::FAILURE;
+        }
+        if !args.json {
+            println!("Loaded {} bytes from {}", image.len(), path.display());
+        }
+    }
+
+    if args.lockstep {
+        let mut lockstep = Lockstep::new(sys);
+        let cycles = args.cycles.expect("clap requires --cycles with --lockstep");
+        return match lockstep.run_cycles(cycles) {
+            Ok(instructions) => {
+                println!(
+                    "{} instructions agree in both execution modes",
+                    instructions
+                );
+                ExitCode::SUCCESS
+            }
+            Err(divergence) => {
+                eprint!("mcs4-emu: {}", divergence);
+                ExitCode::FAILURE
+            }
+        };
+    }
+    if args.fast {
+        sys.set_mode(ExecutionMode::Instruction);
+    }
+
+    let stimulus = match &args.stimulus {
+        Some(path) => match Stimulus::from_file(path) {
+            Ok(stimulus) => stimulus,
+            Err(err) => {
+                eprintln!("mcs4-emu: {}: {}", path.display(), err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Stimulus::default(),
+    };
+    for (address, condition) in args.breaks.iter().cloned() {
+        let brk = Break::address(address);
+        sys.debugger_mut().add(match condition {
+            Some(condition) => brk.when(condition),
+            None => brk,
+        });
+    }
+    let stops = !args.breaks.is_empty() || args.until_pc.is_some() || args.until_halt;
+    let options = RunOptions {
+        max_cycles: args.cycles.unwrap_or(if stops { DEFAULT_LIMIT } else { 0 }),
+        until_pc: args.until_pc,
+        until_halt: args.until_halt,
+    };
+    if options.max_cycles == 0 && !args.json {
+        return ExitCode::SUCCESS;
+    }
+
+    let outcome = runner::run(&mut sys, &stimulus, &options);
+    let code = match outcome {
+        RunOutcome::Limit if stops => ExitCode::from(EXIT_LIMIT),
+        _ => ExitCode::SUCCESS,
+    };
+    if args.json {
+        let mut state = runner::state_json(&sys);
+        state["stop"] = json!({ "reason": outcome.reason(), "message": outcome.to_string() });
+        println!("{}", state);
+    } else {
+        if stops {
+            println!("{}", outcome);
+        }
+        println!(
+            "After {} cycles: PC=0x{:03X} ACC=0x{:X} CY={}",
+            sys.cycles(),
+            sys.pc(),
+            sys.accumulator(),
+            sys.carry() as u8
+        );
+    }
+    code
+}
@@ -5,6 +5,7 @@ use std::sync::{Arc,RwLock};
 #[derive(Clone, Copy, Debug)]
 pub enum BusCycle { A1, A2, A3, M1, M2, X1, X2, X3 }
 
+#[derive(Default)]
 pub struct SignalTrace {
     pub timestamps: Vec<u64>,
     pub phi1: Vec<bool>,
@@ -17,7 +18,8 @@ pub struct SignalTrace {
 }
 
 impl SignalTrace {
-    pub fn new() -> Self { Self { timestamps: vec![], phi1: vec![], phi2: vec![], sync: vec![], data_bus: vec![], cm_rom: vec![], cm_ram: vec![], phase: vec![] } }
+    pub fn new() -> Self { Self::default() }
+    #[allow(clippy::too_many_arguments)]
     pub fn capture(&mut self, tick: u64, phi1: bool, phi2: bool, sync: bool, data: u8, cm_rom: u8, cm_ram: u8, phase: BusCycle) {
         self.timestamps.push(tick);
         self.phi1.push(phi1);
@@ -0,0 +1,15 @@
+//! mcs4-dap: Debug Adapter Protocol server on stdin/stdout
+
+use std::process::ExitCode;
+
+use mcs4_system::DapServer;
+
+fn main() -> ExitCode {
+    match DapServer::new().run_stdio() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("mcs4-dap: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
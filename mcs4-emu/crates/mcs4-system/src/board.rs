@@ -0,0 +1,632 @@
+//! Board descriptions
+//!
+//! The presets ([`Mcs4System::minimal`] and friends) fit fixed sets of
+//! chips. A [`BoardConfig`] describes any other 4004 board in TOML or JSON:
+//!
+//! ```toml
+//! name = "scanner"
+//! clock_hz = 740000          # default 740 kHz
+//! ram_decoder = false        # 3205 on CM-RAM1-3 for banks 1-7
+//! image = "firmware.hex"     # optional; placed by address
+//!
+//! [[rom]]
+//! chip = 0
+//! [[rom]]
+//! chip = 1
+//! image = "tables.bin"       # optional; this chip's page only
+//!
+//! [[ram]]
+//! bank = 0
+//! chip = 0
+//!
+//! [[peripheral]]
+//! kind = "probe"             # log the changes of an output port
+//! port = { ram = { bank = 0, chip = 0 } }
+//!
+//! [[peripheral]]
+//! kind = "switches"          # fixed levels on a 4001 input port
+//! port = { rom = 1 }
+//! value = 0x5
+//!
+//! [[peripheral]]
+//! kind = "shift_register"    # 4003s clocked from output port bits
+//! clock = { port = { rom = 0 }, bit = 0 }
+//! data = { port = { rom = 0 }, bit = 1 }
+//! enable = { port = { ram = { bank = 0, chip = 0 } }, bit = 3 }  # optional
+//! chips = 2                  # default 1, at most 6
+//! ```
+//!
+//! The JSON form has the same keys (`"rom": [{ "chip": 0 }]`, `"port":
+//! {"rom": 1}`). Paths are relative to the file. A per-chip binary image
+//! fills the chip from offset 0; a HEX or S-record image may use offsets
+//! 0x00-0xFF or the chip's own addresses. [`BoardConfig::build`] rejects
+//! chips fitted twice, IDs and banks the bus cannot select, images that
+//! do not fit and ports wired to missing chips or driven twice.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use mcs4_bus::prelude::*;
+use mcs4_chips::{i3205::I3205, i4001::I4001, i4002::I4002};
+use serde::Deserialize;
+
+use crate::loader::{LoadError, RomImage};
+use crate::mcs4::Mcs4System;
+use crate::peripheral::{Port, PortBit, PortProbe, ShiftRegister, Switches};
+
+/// A 4001
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RomChip {
+    /// Chip number (0-15); the 256-byte page it answers for
+    pub chip: u8,
+    /// Image for this chip alone
+    #[serde(default)]
+    pub image: Option<PathBuf>,
+}
+
+/// A 4002
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RamChip {
+    /// Bank (CM-RAM line, or 3205 output with a decoder)
+    pub bank: u8,
+    /// Chip number within the bank (0-3)
+    pub chip: u8,
+}
+
+/// A device on the ports, by `kind`
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PeripheralConfig {
+    /// A [`PortProbe`] on an output port
+    Probe { port: Port },
+    /// [`Switches`] on a 4001 input port
+    Switches { port: Port, value: u8 },
+    /// A [`ShiftRegister`] chain of `chips` 4003s
+    ShiftRegister {
+        clock: PortBit,
+        data: PortBit,
+        #[serde(default)]
+        enable: Option<PortBit>,
+        #[serde(default = "default_chips")]
+        chips: usize,
+    },
+}
+
+fn default_chips() -> usize {
+    1
+}
+
+/// A board: its chips, ROM images, clock and port wiring
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoardConfig {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Clock frequency in Hz
+    #[serde(default = "default_clock_hz")]
+    pub clock_hz: u64,
+    /// Fit a 3205 decoding CM-RAM1-3 into banks 1-7
+    #[serde(default)]
+    pub ram_decoder: bool,
+    /// Program image placed by address across the 4001s
+    #[serde(default)]
+    pub image: Option<PathBuf>,
+    #[serde(default)]
+    pub rom: Vec<RomChip>,
+    #[serde(default)]
+    pub ram: Vec<RamChip>,
+    #[serde(default, rename = "peripheral")]
+    pub peripherals: Vec<PeripheralConfig>,
+    /// Directory relative image paths are resolved against
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+}
+
+fn default_clock_hz() -> u64 {
+    740_000
+}
+
+/// Error reading or building a board
+#[derive(Debug)]
+pub enum BoardError {
+    /// The file could not be read
+    Io(io::Error),
+    /// The file is not valid TOML or JSON for a board
+    Parse(String),
+    /// Two 4001s with the same chip number
+    DuplicateRom { chip: u8 },
+    /// Two 4002s at the same bank and chip number
+    DuplicateRam { bank: u8, chip: u8 },
+    /// A 4001 chip number outside 0-15
+    RomChipOutOfRange { chip: u8 },
+    /// A 4002 chip number outside 0-3
+    RamChipOutOfRange { bank: u8, chip: u8 },
+    /// A bank the CM-RAM lines (or the 3205) cannot strobe
+    BankOutOfRange { bank: u8, limit: u8 },
+    /// A clock the 4004 cannot run from
+    ClockOutOfRange { hz: u64 },
+    /// A ROM image could not be loaded
+    Image { path: PathBuf, error: LoadError },
+    /// A per-chip image has a byte outside the chip's page
+    ImageOutsideChip { path: PathBuf, chip: u8, address: u32 },
+    /// A peripheral is wired to a chip that is not fitted
+    MissingChip { port: Port },
+    /// A peripheral drives a port that cannot be driven
+    NotAnInput { port: Port },
+    /// Two peripherals drive the same input
+    ConflictingDrivers { port: Port },
+    /// A value does not fit a 4-bit port
+    ValueOutOfRange { port: Port, value: u8 },
+    /// A pin wired to a port bit outside 0-3
+    BitOutOfRange { pin: PortBit },
+    /// A 4003 chain of no chips, or more than [`ShiftRegister::MAX_CHIPS`]
+    ChainLength { chips: usize },
+}
+
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BoardError::Io(err) => write!(f, "cannot read board: {}", err),
+            BoardError::Parse(message) => write!(f, "{}", message),
+            BoardError::DuplicateRom { chip } => {
+                write!(f, "4001 chip {} is fitted more than once", chip)
+            }
+            BoardError::DuplicateRam { bank, chip } => {
+                write!(
+                    f,
+                    "4002 bank {} chip {} is fitted more than once",
+                    bank, chip
+                )
+            }
+            BoardError::RomChipOutOfRange { chip } => {
+                write!(f, "4001 chip {} is out of range (0-15)", chip)
+            }
+            BoardError::RamChipOutOfRange { bank, chip } => {
+                write!(
+                    f,
+                    "4002 bank {} chip {} is out of range (chips 0-3)",
+                    bank, chip
+                )
+            }
+            BoardError::BankOutOfRange { bank, limit } => write!(
+                f,
+                "RAM bank {} cannot be selected (banks 0-{}{})",
+                bank,
+                limit,
+                if *limit < 7 {
+                    "; banks 4-7 need ram_decoder"
+                } else {
+                    ""
+                }
+            ),
+            BoardError::ClockOutOfRange { hz } => write!(f, "clock of {} Hz is not usable", hz),
+            BoardError::Image { path, error } => write!(f, "{}: {}", path.display(), error),
+            BoardError::ImageOutsideChip {
+                path,
+                chip,
+                address,
+            } => write!(
+                f,
+                "{}: address 0x{:03X} is outside 4001 chip {}",
+                path.display(),
+                address,
+                chip
+            ),
+            BoardError::MissingChip { port } => {
+                write!(f, "peripheral wired to {}, which is not fitted", port)
+            }
+            BoardError::NotAnInput { port } => write!(f, "{} is not an input port", port),
+            BoardError::ConflictingDrivers { port } => {
+                write!(f, "{} is driven by more than one peripheral", port)
+            }
+            BoardError::ValueOutOfRange { port, value } => {
+                write!(f, "value 0x{:X} for {} does not fit 4 bits", value, port)
+            }
+            BoardError::BitOutOfRange { pin } => {
+                write!(f, "{} is not a port bit (bits 0-3)", pin)
+            }
+            BoardError::ChainLength { chips } => write!(
+                f,
+                "a chain of {} 4003s is not supported (1-{})",
+                chips,
+                ShiftRegister::MAX_CHIPS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BoardError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BoardError::Io(err) => Some(err),
+            BoardError::Image { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl BoardConfig {
+    /// Read a board from a `.json` file, or TOML for any other extension
+    pub fn from_file(path: &Path) -> Result<Self, BoardError> {
+        let text = fs::read_to_string(path).map_err(BoardError::Io)?;
+        let json = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let mut config = if json {
+            Self::from_json(&text)?
+        } else {
+            Self::from_toml(&text)?
+        };
+        config.base_dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        Ok(config)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, BoardError> {
+        toml::from_str(text)
+            .map_err(|err| BoardError::Parse(err.to_string().trim_end().to_string()))
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, BoardError> {
+        serde_json::from_str(text).map_err(|err| BoardError::Parse(err.to_string()))
+    }
+
+    /// Check the board and instantiate it
+    pub fn build(&self) -> Result<Mcs4System, BoardError> {
+        self.check()?;
+
+        let mut sys = Mcs4System::minimal();
+        sys.rom = self.rom.iter().map(|rom| I4001::new(rom.chip)).collect();
+        sys.ram = self
+            .ram
+            .iter()
+            .map(|ram| I4002::new(ram.chip, ram.bank))
+            .collect();
+        sys.ram_decoder = self.ram_decoder.then(I3205::new);
+        sys.clock = TwoPhaseClockTwoPhaseClock::new(ClockConfig::for_frequency(self.clock_hz));
+
+        if let Some(path) = &self.image {
+            let path = self.base_dir.join(path);
+            let image = RomImage::from_file(&path).map_err(|error| BoardError::Image {
+                path: path.clone(),
+                error,
+            })?;
+            sys.load_image(&image)
+                .map_err(|error| BoardError::Image { path, error })?;
+        }
+        for rom in &self.rom {
+            let Some(path) = &rom.image else {
+                continue;
+            };
+            let path = self.base_dir.join(path);
+            let image = RomImage::from_file(&path).map_err(|error| BoardError::Image {
+                path: path.clone(),
+                error,
+            })?;
+            let base = (rom.chip as u32) << 8;
+            for (address, byte) in image.iter() {
+                let address = match address >> 8 {
+                    0 => base | address,
+                    page if page == rom.chip as u32 => address,
+                    _ => {
+                        return Err(BoardError::ImageOutsideChip {
+                            path,
+                            chip: rom.chip,
+                            address,
+                        })
+                    }
+                };
+                sys.load_rom_at(address as u16, &[byte]);
+            }
+        }
+
+        for peripheral in &self.peripherals {
+            match *peripheral {
+                PeripheralConfig::Probe { port } => sys.attach(PortProbe::new(port)),
+                PeripheralConfig::Switches {
+                    port: Port::Rom(chip),
+                    value,
+                } => sys.attach(Switches { chip, value }),
+                PeripheralConfig::Switches { .. } => unreachable!("checked above"),
+                PeripheralConfig::ShiftRegister {
+                    clock,
+                    data,
+                    enable,
+                    chips,
+                } => {
+                    let chain = ShiftRegister::new(clock, data, chips);
+                    match enable {
+                        Some(enable) => sys.attach(chain.with_enable(enable)),
+                        None => sys.attach(chain),
+                    }
+                }
+            }
+        }
+        Ok(sys)
+    }
+
+    /// Reject boards the bus could not address or wiring that clashes
+    fn check(&self) -> Result<(), BoardError> {
+        if self.clock_hz == 0 {
+            return Err(BoardError::ClockOutOfRange { hz: self.clock_hz });
+        }
+
+        let mut roms = Vec::new();
+        for rom in &self.rom {
+            if rom.chip > 15 {
+                return Err(BoardError::RomChipOutOfRange { chip: rom.chip });
+            }
+            if roms.contains(&rom.chip) {
+                return Err(BoardError::DuplicateRom { chip: rom.chip });
+            }
+            roms.push(rom.chip);
+        }
+
+        let limit = if self.ram_decoder { 7 } else { 3 };
+        let mut rams = Vec::new();
+        for ram in &self.ram {
+            if ram.bank > limit {
+                return Err(BoardError::BankOutOfRange {
+                    bank: ram.bank,
+                    limit,
+                });
+            }
+            if ram.chip > 3 {
+                return Err(BoardError::RamChipOutOfRange {
+                    bank: ram.bank,
+                    chip: ram.chip,
+                });
+            }
+            if rams.contains(&(ram.bank, ram.chip)) {
+                return Err(BoardError::DuplicateRam {
+                    bank: ram.bank,
+                    chip: ram.chip,
+                });
+            }
+            rams.push((ram.bank, ram.chip));
+        }
+
+        let fitted = |port: Port| match port {
+            Port::Rom(chip) => roms.contains(&chip),
+            Port::Ram { bank, chip } => rams.contains(&(bank, chip)),
+        };
+        let mut drivers: HashMap<Port, usize> = HashMap::new();
+        for peripheral in &self.peripherals {
+            let (ports, driven) = match *peripheral {
+                PeripheralConfig::Probe { port } => (vec![port], false),
+                PeripheralConfig::Switches { port, value } => {
+                    if !matches!(port, Port::Rom(_)) {
+                        return Err(BoardError::NotAnInput { port });
+                    }
+                    if value > 0x0F {
+                        return Err(BoardError::ValueOutOfRange { port, value });
+                    }
+                    (vec![port], true)
+                }
+                PeripheralConfig::ShiftRegister {
+                    clock,
+                    data,
+                    enable,
+                    chips,
+                } => {
+                    if !(1..=ShiftRegister::MAX_CHIPS).contains(&chips) {
+                        return Err(BoardError::ChainLength { chips });
+                    }
+                    let pins: Vec<PortBit> = [clock, data].into_iter().chain(enable).collect();
+                    if let Some(&pin) = pins.iter().find(|pin| pin.bit > 3) {
+                        return Err(BoardError::BitOutOfRange { pin });
+                    }
+                    (pins.iter().map(|pin| pin.port).collect(), false)
+                }
+            };
+            for port in ports {
+                if !fitted(port) {
+                    return Err(BoardError::MissingChip { port });
+                }
+                if driven {
+                    let count = drivers.entry(port).or_default();
+                    *count += 1;
+                    if *count > 1 {
+                        return Err(BoardError::ConflictingDrivers { port });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOARD: &str = r#"
+name = "test"
+clock_hz = 500000
+
+[[rom]]
+chip = 0
+[[rom]]
+chip = 2
+
+[[ram]]
+bank = 1
+chip = 3
+
+[[peripheral]]
+kind = "probe"
+port = { ram = { bank = 1, chip = 3 } }
+
+[[peripheral]]
+kind = "switches"
+port = { rom = 2 }
+value = 0xA
+"#;
+
+    #[test]
+    fn test_build_from_toml_and_json() {
+        let config = BoardConfig::from_toml(BOARD).unwrap();
+        assert_eq!(config.name.as_deref(), Some("test"));
+        let mut sys = config.build().unwrap();
+        assert_eq!(
+            sys.rom.iter().map(|r| r.chip_id()).collect::<Vec<_>>(),
+            [0, 2]
+        );
+        assert_eq!((sys.ram[0].bank_id, sys.ram[0].chip_id), (1, 3));
+        assert_eq!(sys.clock.config.period, 2_000_000);
+
+        // DCL 1; FIM P0, 0xC0 (chip 3); SRC P0; LDM 9; WMP
+        // FIM P1, 0x20 (ROM chip 2); SRC P1; RDR
+        sys.load_rom(&[
+            0xD1, 0xFD, 0x20, 0xC0, 0x21, 0xD9, 0xE1, 0x22, 0x20, 0x23, 0xEA,
+        ]);
+        sys.run_cycles(12);
+        assert_eq!(sys.accumulator(), 0xA);
+        let probe = sys.peripheral::<PortProbe>().unwrap();
+        assert_eq!(probe.value(), Some(9));
+        assert_eq!(probe.changes().len(), 2);
+
+        let json = r#"{
+            "rom": [{ "chip": 0 }],
+            "ram": [{ "bank": 0, "chip": 0 }],
+            "peripheral": [{ "kind": "probe", "port": { "rom": 0 } }]
+        }"#;
+        let sys = BoardConfig::from_json(json).unwrap().build().unwrap();
+        assert_eq!(sys.peripherals().len(), 1);
+        assert_eq!(sys.clock.config.period, 1_000_000_000_000 / 740_000);
+    }
+
+    #[test]
+    fn test_rom_images() {
+        let dir = std::env::temp_dir().join(format!("mcs4-board-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("page1.bin"), [0xD7, 0x40, 0x00]).unwrap();
+        // One byte at 0x205, one at chip-relative 0x06
+        fs::write(
+            dir.join("page2.hex"),
+            ":01020500AB4D\n:01000600CD2C\n:00000001FF\n",
+        )
+        .unwrap();
+        fs::write(dir.join("main.hex"), ":01000000D22D\n:00000001FF\n").unwrap();
+        let board = "image = \"main.hex\"\n\
+                     [[rom]]\nchip = 0\n\
+                     [[rom]]\nchip = 1\nimage = \"page1.bin\"\n\
+                     [[rom]]\nchip = 2\nimage = \"page2.hex\"\n";
+        fs::write(dir.join("board.toml"), board).unwrap();
+        let sys = BoardConfig::from_file(&dir.join("board.toml"))
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(sys.read_rom(0x000), Some(0xD2));
+        assert_eq!(sys.read_rom(0x100), Some(0xD7));
+        assert_eq!(sys.read_rom(0x102), Some(0x00));
+        assert_eq!(sys.read_rom(0x205), Some(0xAB));
+        assert_eq!(sys.read_rom(0x206), Some(0xCD));
+        assert_eq!(sys.rom_image()[0x101], 0x40);
+
+        let board = "[[rom]]\nchip = 0\nimage = \"page2.hex\"\n";
+        fs::write(dir.join("bad.toml"), board).unwrap();
+        let err = BoardConfig::from_file(&dir.join("bad.toml"))
+            .unwrap()
+            .build()
+            .err()
+            .unwrap();
+        fs::remove_dir_all(&dir).ok();
+        assert!(err
+            .to_string()
+            .ends_with("address 0x205 is outside 4001 chip 0"));
+    }
+
+    #[test]
+    fn test_conflicts_are_rejected() {
+        let error = |text: &str| {
+            BoardConfig::from_toml(text)
+                .unwrap()
+                .build()
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            error("[[rom]]\nchip = 1\n[[rom]]\nchip = 1\n"),
+            "4001 chip 1 is fitted more than once"
+        );
+        assert_eq!(
+            error("[[rom]]\nchip = 16\n"),
+            "4001 chip 16 is out of range (0-15)"
+        );
+        assert_eq!(
+            error("[[ram]]\nbank = 2\nchip = 0\n[[ram]]\nbank = 2\nchip = 0\n"),
+            "4002 bank 2 chip 0 is fitted more than once"
+        );
+        assert_eq!(
+            error("[[ram]]\nbank = 4\nchip = 0\n"),
+            "RAM bank 4 cannot be selected (banks 0-3; banks 4-7 need ram_decoder)"
+        );
+        assert!(
+            BoardConfig::from_toml("ram_decoder = true\n[[ram]]\nbank = 4\nchip = 0\n")
+                .unwrap()
+                .build()
+                .is_ok()
+        );
+        assert_eq!(
+            error("[[ram]]\nbank = 0\nchip = 4\n"),
+            "4002 bank 0 chip 4 is out of range (chips 0-3)"
+        );
+        assert_eq!(
+            error("[[peripheral]]\nkind = \"probe\"\nport = { rom = 3 }\n"),
+            "peripheral wired to 4001 chip 3, which is not fitted"
+        );
+        let twice = "[[rom]]\nchip = 3\n\
+                     [[peripheral]]\nkind = \"switches\"\nport = { rom = 3 }\nvalue = 1\n\
+                     [[peripheral]]\nkind = \"switches\"\nport = { rom = 3 }\nvalue = 2\n";
+        assert_eq!(
+            error(twice),
+            "4001 chip 3 is driven by more than one peripheral"
+        );
+        assert_eq!(
+            error("[[ram]]\nbank = 0\nchip = 0\n[[peripheral]]\nkind = \"switches\"\nport = { ram = { bank = 0, chip = 0 } }\nvalue = 1\n"),
+            "4002 bank 0 chip 0 is not an input port"
+        );
+        assert_eq!(error("clock_hz = 0\n"), "clock of 0 Hz is not usable");
+
+        let chain = |clock: &str, chips: usize| {
+            format!(
+                "[[rom]]\nchip = 0\n\
+                 [[peripheral]]\nkind = \"shift_register\"\nchips = {}\n\
+                 clock = {{ port = {{ rom = 0 }}, bit = {} }}\n\
+                 data = {{ port = {{ rom = 0 }}, bit = 1 }}\n",
+                chips, clock
+            )
+        };
+        let sys = BoardConfig::from_toml(&chain("0", 2)).unwrap().build();
+        let sys = sys.ok().unwrap();
+        let chips = sys.peripheral::<ShiftRegister>().unwrap().chips().len();
+        assert_eq!(chips, 2);
+        assert_eq!(
+            error(&chain("4", 1)),
+            "4001 chip 0 bit 4 is not a port bit (bits 0-3)"
+        );
+        assert_eq!(
+            error(&chain("0", 7)),
+            "a chain of 7 4003s is not supported (1-6)"
+        );
+        assert_eq!(
+            error(&format!(
+                "{}enable = {{ port = {{ rom = 2 }}, bit = 0 }}\n",
+                chain("0", 1)
+            )),
+            "peripheral wired to 4001 chip 2, which is not fitted"
+        );
+
+        assert!(matches!(
+            BoardConfig::from_toml("[[rom]]\nchip = 0\nsize = 256\n"),
+            Err(BoardError::Parse(message)) if message.contains("unknown field `size`")
+        ));
+        assert!(BoardConfig::from_json("{\"rom\": [{}]}").is_err());
+    }
+}
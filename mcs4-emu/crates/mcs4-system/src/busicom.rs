@@ -0,0 +1,403 @@
+//! Busicom 141-PF printing calculator
+//!
+//! The machine the 4004 was designed for: a 4004, four 4001s holding the
+//! 1 KB firmware, two 4002s in bank 0, and 4003 shift registers that
+//! select a keyboard column and the printer hammers. The firmware image
+//! is not distributed here; load your own with [`Busicom::load_rom_file`].
+//!
+//! Port wiring:
+//!
+//! | Port        | Bits                                                        |
+//! |-------------|-------------------------------------------------------------|
+//! | 4001 #0 out | 0: keyboard shifter clock, 1: shifter data, 2: printer shifter clock |
+//! | 4001 #1 in  | keyboard rows of the selected column                       |
+//! | 4001 #2 in  | 0: drum index (sector 0), 3: paper feed button              |
+//! | 4002 #0 out | 0: red ribbon, 1: fire hammers, 3: advance paper           |
+//! | 4002 #1 out | lamps: 0 memory, 1 overflow, 2 minus                        |
+//! | TEST        | drum sector pulse                                          |
+//!
+//! One 4003 drives keyboard columns 0-9 (columns 8 and 9 read the decimal
+//! point and rounding switches); two chained 4003s drive the 20 printer
+//! columns, column 1 on the last bit shifted in. Both are
+//! [`ShiftRegister`]s, so the data bit must be written before the clock
+//! rises, and they shift at the end of the port write rather than at its
+//! X2. The keyboard scan relies only on the order of instructions: the
+//! column shifted in by one write is selected for the next RDR. The
+//! printer drum turns past 13 character sectors; each sector
+//! raises TEST for a short pulse, and the index bit marks sector 0.
+//! Firing the hammers prints the current sector's character in every
+//! column whose shifter bit is set; advancing the paper ends the line.
+
+use std::path::Path;
+
+use mcs4_chips::{i4001::I4001, i4002::I4002};
+
+use crate::loader::{LoadError, RomImage};
+use crate::mcs4::{ExecutionMode, Mcs4System};
+use crate::peripheral::{Peripheral, Port, PortBit, Ports, ShiftRegister};
+
+/// Clock the 141-PF runs at
+pub const CLOCK_HZ: u64 = 740_000;
+
+/// Machine cycles per millisecond at [`CLOCK_HZ`] (8 clocks per cycle)
+const CYCLES_PER_MS: f64 = CLOCK_HZ as f64 / 8.0 / 1000.0;
+
+/// Character sectors on the drum
+pub const SECTORS: u64 = 13;
+
+/// Characters in the digit columns (1-15), by sector
+const DIGIT_DRUM: [char; 13] = [
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', '.', '.', '-',
+];
+
+/// Characters in column 17, by sector
+const OPERATION_DRUM: [char; 13] = [
+    '◇', '+', '-', '×', '÷', 'M', 'M', '^', '=', '√', '%', 'C', 'R',
+];
+
+/// Characters in column 18, by sector
+const MARK_DRUM: [char; 13] = [
+    '#', '*', 'I', 'Ⅱ', 'Ⅲ', '+', '-', 'T', 'K', 'E', 'Ē', 'C', 'M',
+];
+
+/// Printer drum speed and sector pulse width
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DrumTiming {
+    /// Machine cycles per character sector
+    pub sector_cycles: u64,
+    /// Machine cycles TEST stays high at the start of each sector
+    pub pulse_cycles: u64,
+}
+
+impl Default for DrumTiming {
+    /// 28 ms sectors with 5 ms pulses at 740 kHz
+    fn default() -> Self {
+        Self {
+            sector_cycles: (28.0 * CYCLES_PER_MS) as u64,
+            pulse_cycles: (5.0 * CYCLES_PER_MS) as u64,
+        }
+    }
+}
+
+/// A key on the 141-PF keyboard
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Digit(u8),
+    Point,
+    DoubleZero,
+    TripleZero,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Equals,
+    Percent,
+    Sqrt,
+    /// Change sign
+    Sign,
+    /// Exchange operands
+    Exchange,
+    ClearEntry,
+    Clear,
+    MemoryAdd,
+    MemorySub,
+    /// `M=+`: equals, then add to memory
+    EqualsMemoryAdd,
+    /// `M=-`: equals, then subtract from memory
+    EqualsMemorySub,
+    MemoryRecall,
+    MemoryClear,
+    /// Sub-total (the diamond key by `/`)
+    SubTotal,
+    /// The second diamond key, by `000`
+    Diamond,
+}
+
+impl Key {
+    /// Keyboard column (shifter bit) and row (4001 #1 input bit)
+    pub fn position(self) -> (u8, u8) {
+        match self {
+            Key::MemoryClear => (0, 0),
+            Key::MemoryRecall => (0, 1),
+            Key::MemorySub => (0, 2),
+            Key::MemoryAdd => (0, 3),
+            Key::Sqrt => (1, 0),
+            Key::Percent => (1, 1),
+            Key::EqualsMemorySub => (1, 2),
+            Key::EqualsMemoryAdd => (1, 3),
+            Key::SubTotal => (2, 0),
+            Key::Div => (2, 1),
+            Key::Mul => (2, 2),
+            Key::Equals => (2, 3),
+            Key::Sub => (3, 0),
+            Key::Add => (3, 1),
+            Key::Diamond => (3, 2),
+            Key::TripleZero => (3, 3),
+            Key::Digit(9) => (4, 0),
+            Key::Digit(6) => (4, 1),
+            Key::Digit(3) => (4, 2),
+            Key::Point => (4, 3),
+            Key::Digit(8) => (5, 0),
+            Key::Digit(5) => (5, 1),
+            Key::Digit(2) => (5, 2),
+            Key::DoubleZero => (5, 3),
+            Key::Digit(7) => (6, 0),
+            Key::Digit(4) => (6, 1),
+            Key::Digit(1) => (6, 2),
+            Key::Digit(_) => (6, 3),
+            Key::Sign => (7, 0),
+            Key::Exchange => (7, 1),
+            Key::ClearEntry => (7, 2),
+            Key::Clear => (7, 3),
+        }
+    }
+
+    /// The key for a character: digits, `.`, `+`, `-`, `*`, `/`, `=`, `%`,
+    /// `C` (clear) and `E` (clear entry)
+    pub fn from_char(c: char) -> Option<Self> {
+        Some(match c {
+            '0'..='9' => Key::Digit(c as u8 - b'0'),
+            '.' => Key::Point,
+            '+' => Key::Add,
+            '-' => Key::Sub,
+            '*' => Key::Mul,
+            '/' => Key::Div,
+            '=' => Key::Equals,
+            '%' => Key::Percent,
+            'C' => Key::Clear,
+            'E' => Key::ClearEntry,
+            _ => return None,
+        })
+    }
+}
+
+/// A printed line
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PrintedLine {
+    /// Columns 15 to 1, column 16, then 17 and 18; blanks where nothing
+    /// was struck, trailing blanks trimmed
+    pub text: String,
+    /// Any character on the line was struck with the red ribbon
+    pub red: bool,
+}
+
+/// Keyboard, printer and lamps of the 141-PF, wired to the ports
+#[derive(Clone, Debug)]
+pub struct BusicomIo {
+    pub timing: DrumTiming,
+    /// Row bits held down in each keyboard column
+    keys: [u8; 10],
+    paper_feed: bool,
+    keyboard_shifter: ShiftRegister,
+    printer_shifter: ShiftRegister,
+    /// 4002 #0 output at the last update, for edge detection
+    last_printer: u8,
+    /// Characters struck on the current line, by column 1-18
+    line: [Option<char>; 18],
+    line_red: bool,
+    printed: Vec<PrintedLine>,
+    lamps: u8,
+}
+
+impl Default for BusicomIo {
+    fn default() -> Self {
+        let rom0 = |bit| PortBit::new(Port::Rom(0), bit);
+        Self {
+            timing: DrumTiming::default(),
+            keys: [0; 10],
+            paper_feed: false,
+            keyboard_shifter: ShiftRegister::new(rom0(0), rom0(1), 1),
+            printer_shifter: ShiftRegister::new(rom0(2), rom0(1), 2),
+            last_printer: 0,
+            line: [None; 18],
+            line_red: false,
+            printed: Vec::new(),
+            lamps: 0,
+        }
+    }
+}
+
+impl BusicomIo {
+    pub fn set_key(&mut self, key: Key, down: bool) {
+        let (column, row) = key.position();
+        if down {
+            self.keys[column as usize] |= 1 << row;
+        } else {
+            self.keys[column as usize] &= !(1 << row);
+        }
+    }
+
+    /// Row bits read in column 8: the decimal point switch
+    pub fn set_decimal_switch(&mut self, rows: u8) {
+        self.keys[8] = rows & 0x0F;
+    }
+
+    /// Row bits read in column 9: the rounding switch
+    pub fn set_rounding_switch(&mut self, rows: u8) {
+        self.keys[9] = rows & 0x0F;
+    }
+
+    pub fn set_paper_feed(&mut self, down: bool) {
+        self.paper_feed = down;
+    }
+
+    /// Lines printed so far, oldest first
+    pub fn printed(&self) -> &[PrintedLine] {
+        &self.printed
+    }
+
+    /// Lamp bits: 0 memory, 1 overflow, 2 minus
+    pub fn lamps(&self) -> u8 {
+        self.lamps
+    }
+
+    /// The drum sector under the hammers at `cycles`
+    pub fn sector(&self, cycles: u64) -> u64 {
+        (cycles / self.timing.sector_cycles) % SECTORS
+    }
+
+    /// Printer shifter outputs; bit `n` drives the hammer of column `n + 1`
+    pub fn hammers(&self) -> u32 {
+        self.printer_shifter.outputs() as u32
+    }
+
+    /// Keyboard shifter outputs; bit `n` selects column `n`
+    pub fn columns(&self) -> u16 {
+        self.keyboard_shifter.outputs() as u16
+    }
+
+    fn print(&mut self, control: u8, sector: u64) {
+        let rising = control & !self.last_printer;
+        self.last_printer = control;
+        if rising & 0b0010 != 0 {
+            let hammers = self.hammers();
+            for column in 1..=18 {
+                if hammers & 1 << (column - 1) == 0 {
+                    continue;
+                }
+                let drum = match column {
+                    1..=15 => &DIGIT_DRUM,
+                    17 => &OPERATION_DRUM,
+                    18 => &MARK_DRUM,
+                    _ => continue,
+                };
+                self.line[column - 1] = Some(drum[sector as usize]);
+                self.line_red |= control & 0b0001 != 0;
+            }
+        }
+        if rising & 0b1000 != 0 {
+            let columns = (1..=15).rev().chain(16..=18);
+            let text: String = columns.map(|c| self.line[c - 1].unwrap_or(' ')).collect();
+            self.printed.push(PrintedLine {
+                text: text.trim_end().to_string(),
+                red: self.line_red,
+            });
+            self.line = [None; 18];
+            self.line_red = false;
+        }
+    }
+}
+
+impl Peripheral for BusicomIo {
+    fn update(&mut self, ports: &mut Ports<'_>) {
+        let cycles = ports.cycles();
+        self.keyboard_shifter.update(ports);
+        self.printer_shifter.update(ports);
+
+        // Instruction granularity: the column the last port write shifted
+        // in drives the rows the next RDR reads
+        let columns = self.columns();
+        let rows = (0..10)
+            .filter(|column| columns & 1 << column != 0)
+            .fold(0, |rows, column| rows | self.keys[column]);
+        ports.set_input(1, rows);
+
+        let sector = self.sector(cycles);
+        let pulse = cycles % self.timing.sector_cycles < self.timing.pulse_cycles;
+        ports.set_test_pin(pulse);
+        let index = (pulse && sector == 0) as u8;
+        ports.set_input(2, index | (self.paper_feed as u8) << 3);
+
+        self.print(
+            ports.output(Port::Ram { bank: 0, chip: 0 }).unwrap_or(0),
+            sector,
+        );
+        self.lamps = ports.output(Port::Ram { bank: 0, chip: 1 }).unwrap_or(0);
+    }
+}
+
+/// A 141-PF: the system and its keyboard and printer
+pub struct Busicom {
+    pub sys: Mcs4System,
+    /// How long a key is held, and the pause after releasing it
+    pub key_ms: f64,
+}
+
+impl Default for Busicom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Busicom {
+    /// A 141-PF with empty ROMs
+    ///
+    /// It runs instruction by instruction: the results match the phase
+    /// model and seconds of calculator time run quickly.
+    pub fn new() -> Self {
+        let mut sys = Mcs4System::minimal();
+        sys.rom = (0..4).map(I4001::new).collect();
+        sys.ram = vec![I4002::new(0, 0), I4002::new(1, 0)];
+        sys.set_mode(ExecutionMode::Instruction);
+        sys.attach(BusicomIo::default());
+        Self { sys, key_ms: 60.0 }
+    }
+
+    /// Load the firmware (Intel HEX, S-record or binary, by extension)
+    pub fn load_rom_file(&mut self, path: &Path) -> Result<usize, LoadError> {
+        self.sys.load_rom_file(path)
+    }
+
+    pub fn load_image(&mut self, image: &RomImage) -> Result<(), LoadError> {
+        self.sys.load_image(image)
+    }
+
+    pub fn io(&self) -> &BusicomIo {
+        self.sys.peripheral().expect("141-PF I/O is attached")
+    }
+
+    pub fn io_mut(&mut self) -> &mut BusicomIo {
+        self.sys.peripheral_mut().expect("141-PF I/O is attached")
+    }
+
+    /// Run for `ms` milliseconds of calculator time
+    pub fn run_ms(&mut self, ms: f64) {
+        self.sys.run_cycles((ms * CYCLES_PER_MS) as usize);
+    }
+
+    /// Press and release a key
+    pub fn press(&mut self, key: Key) {
+        self.io_mut().set_key(key, true);
+        self.run_ms(self.key_ms);
+        self.io_mut().set_key(key, false);
+        self.run_ms(self.key_ms);
+    }
+
+    /// Press the keys for `text` (see [`Key::from_char`]); spaces are
+    /// skipped. Returns the first character without a key.
+    pub fn enter(&mut self, text: &str) -> Result<(), char> {
+        let keys = text
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| Key::from_char(c).ok_or(c))
+            .collect::<Result<Vec<_>, _>>()?;
+        for key in keys {
+            self.press(key);
+        }
+        Ok(())
+    }
+
+    pub fn printed(&self) -> &[PrintedLine] {
+        self.io().printed()
+    }
+}
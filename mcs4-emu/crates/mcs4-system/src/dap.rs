@@ -0,0 +1,984 @@
+//! Debug Adapter Protocol server
+//!
+//! [`DapServer`] lets a DAP-capable editor debug 4004 assembly at the
+//! source level. It speaks the protocol over stdin/stdout
+//! ([`DapServer::run_stdio`], the `mcs4-dap` binary) or any byte stream.
+//!
+//! `launch` takes:
+//!
+//! - `program`: ROM image (Intel HEX, S-record or binary);
+//! - `source`: the main assembly file;
+//! - `lineMap`: the assembler's line map (`mcs4-asm --lines`), one
+//!   `ADDR LINE [FILE]` per instruction, FILE relative to `source`;
+//! - `symbols`: optional symbol file (`mcs4-asm --symbols`), used to name
+//!   stack frames;
+//! - `preset`: `minimal`, `standard` (default) or `maximal`;
+//! - `mode`: `phase` (default) or `instruction`;
+//! - `stopOnEntry`: stop before the first instruction.
+//!
+//! Source breakpoints (with optional [`Condition`]s) land on the first
+//! instruction at or after the requested line. `next`, `stepIn` and
+//! `stepOut` move by source line; with `granularity: "instruction"` they
+//! move by instruction. The `stack` exception filter stops on call stack
+//! overflow and underflow. Variables show the registers, the pairs, the
+//! stack and the 4002 RAM by bank, chip and register; `evaluate` takes a
+//! condition expression such as `ACC + R3`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use crate::debugger::{Break, BreakEvent, BreakId, Condition, Step};
+use crate::mcs4::{ExecutionMode, Mcs4System};
+
+/// Cycles run between checks for requests while the program runs
+const RUN_SLICE: u64 = 10_000;
+
+/// Instructions a source-level step may take before giving up on finding
+/// a mapped line
+const STEP_LIMIT: usize = 10_000;
+
+/// Cycle budget for one `next` or `stepOut`
+const STEP_CYCLES: u64 = 10_000_000;
+
+/// Largest message body read; longer ones are skipped
+const MAX_MESSAGE: usize = 1 << 20;
+
+/// Variable references for the fixed scopes
+const REGISTERS: i64 = 1;
+const PAIRS: i64 = 2;
+const STACK: i64 = 3;
+const RAM: i64 = 4;
+/// RAM tree references: `BANK | bank`, `CHIP | bank << 2 | chip`,
+/// `REGISTER | bank << 4 | chip << 2 | register`
+const BANK: i64 = 0x100;
+const CHIP: i64 = 0x200;
+const REGISTER: i64 = 0x400;
+
+/// Instruction addresses mapped to source lines, plus symbol names
+#[derive(Clone, Debug, Default)]
+pub struct LineMap {
+    lines: BTreeMap<u16, (PathBuf, usize)>,
+    symbols: BTreeMap<u16, String>,
+}
+
+impl LineMap {
+    /// Parse a line map whose unnamed lines belong to `source`; named
+    /// files are taken relative to its directory
+    pub fn parse(text: &str, source: &Path) -> Result<Self, String> {
+        let dir = source.parent().unwrap_or(Path::new(""));
+        let mut lines = BTreeMap::new();
+        for (number, entry) in text.lines().enumerate() {
+            let mut fields = entry.split_whitespace();
+            let Some(address) = fields.next() else {
+                continue;
+            };
+            let parsed = u16::from_str_radix(address, 16)
+                .ok()
+                .zip(fields.next().and_then(|line| line.parse::<usize>().ok()));
+            let Some((address, line)) = parsed else {
+                return Err(format!(
+                    "line map line {}: expected ADDR LINE [FILE]",
+                    number + 1
+                ));
+            };
+            let file = match fields.next() {
+                Some(file) => dir.join(file),
+                None => source.to_path_buf(),
+            };
+            lines.insert(address & 0x0FFF, (file, line));
+        }
+        Ok(Self {
+            lines,
+            symbols: BTreeMap::new(),
+        })
+    }
+
+    /// Add the symbols of a symbol file (`ADDR NAME` per line)
+    pub fn add_symbols(&mut self, text: &str) -> Result<(), String> {
+        for (number, entry) in text.lines().enumerate() {
+            let mut fields = entry.split_whitespace();
+            let Some(value) = fields.next() else {
+                continue;
+            };
+            let Some(name) = fields.next() else {
+                return Err(format!(
+                    "symbol file line {}: expected ADDR NAME",
+                    number + 1
+                ));
+            };
+            // Constants outside the program space name no code
+            if let Some(address) = u16::from_str_radix(value, 16).ok().filter(|&a| a <= 0x0FFF) {
+                self.symbols
+                    .entry(address)
+                    .or_insert_with(|| name.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Source file and line of the instruction at `address`
+    pub fn location(&self, address: u16) -> Option<(&Path, usize)> {
+        self.lines
+            .get(&address)
+            .map(|(file, line)| (file.as_path(), *line))
+    }
+
+    /// First instruction at or after `line` of `file`, with its line
+    pub fn address(&self, file: &Path, line: usize) -> Option<(u16, usize)> {
+        self.lines
+            .iter()
+            .filter(|(_, (f, l))| same_file(f, file) && *l >= line)
+            .min_by_key(|(&address, (_, l))| (*l, address))
+            .map(|(&address, (_, l))| (address, *l))
+    }
+
+    /// Name of the closest symbol at or below `address`, with an offset
+    pub fn frame_name(&self, address: u16) -> String {
+        match self.symbols.range(..=address).next_back() {
+            Some((&at, name)) if at == address => name.clone(),
+            Some((&at, name)) => format!("{}+{}", name, address - at),
+            None => format!("0x{:03X}", address),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
+
+/// Paths name the same file, or the same file name if either is relative
+fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || ((a.is_relative() || b.is_relative()) && a.file_name() == b.file_name())
+}
+
+/// How a step moves
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StepKind {
+    In,
+    Over,
+    Out,
+}
+
+/// Debug Adapter Protocol server for an [`Mcs4System`]
+pub struct DapServer {
+    sys: Mcs4System,
+    lines: LineMap,
+    /// Sequence number of the next message sent
+    seq: i64,
+    /// Messages waiting to be written
+    outbox: Vec<Value>,
+    /// Breaks set by `setBreakpoints`, per source file
+    source_breaks: HashMap<PathBuf, Vec<BreakId>>,
+    /// Break set by the `stack` exception filter
+    stack_break: Option<BreakId>,
+    stop_on_entry: bool,
+    running: bool,
+    done: bool,
+}
+
+impl Default for DapServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DapServer {
+    pub fn new() -> Self {
+        Self {
+            sys: Mcs4System::standard(),
+            lines: LineMap::default(),
+            seq: 1,
+            outbox: Vec::new(),
+            source_breaks: HashMap::new(),
+            stack_break: None,
+            stop_on_entry: false,
+            running: false,
+            done: false,
+        }
+    }
+
+    pub fn system(&self) -> &Mcs4System {
+        &self.sys
+    }
+
+    /// Serve a session on stdin and stdout
+    pub fn run_stdio(self) -> io::Result<()> {
+        self.run(io::stdin(), io::stdout().lock())
+    }
+
+    /// Serve a session until `disconnect` or the end of `input`
+    ///
+    /// Requests are read on a separate thread so that `pause` reaches a
+    /// running program.
+    pub fn run<R, W>(mut self, input: R, mut output: W) -> io::Result<()>
+    where
+        R: Read + Send + 'static,
+        W: Write,
+    {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(input);
+            loop {
+                match read_message(&mut reader) {
+                    Ok(Some(message)) => {
+                        if tx.send(message).is_err() {
+                            break;
+                        }
+                    }
+                    // The whole body has been consumed, so the next
+                    // message is still in step
+                    Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                        tracing::warn!("dap: skipping message: {}", err)
+                    }
+                    Ok(None) | Err(_) => break,
+                }
+            }
+        });
+
+        loop {
+            let message = if self.running {
+                match rx.try_recv() {
+                    Ok(message) => Some(message),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match rx.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => return Ok(()),
+                }
+            };
+            if let Some(message) = message {
+                self.handle(&message);
+            }
+            if self.running {
+                self.run_slice();
+            }
+            for message in self.outbox.drain(..) {
+                write_message(&mut output, &message)?;
+            }
+            output.flush()?;
+            if self.done {
+                return Ok(());
+            }
+        }
+    }
+
+    fn send(&mut self, mut message: Value) {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        self.outbox.push(message);
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response);
+    }
+
+    fn stopped(&mut self, reason: &str, extra: Value) {
+        let mut body = json!({ "reason": reason, "threadId": 1, "allThreadsStopped": true });
+        if let (Some(body), Value::Object(extra)) = (body.as_object_mut(), extra) {
+            body.extend(extra);
+        }
+        self.event("stopped", body);
+    }
+
+    fn handle(&mut self, request: &Value) {
+        if request["type"] != "request" {
+            return;
+        }
+        let args = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or_default();
+        tracing::trace!("dap <- {}", command);
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsSteppingGranularity": true,
+                "supportsEvaluateForHovers": true,
+                "exceptionBreakpointFilters": [{
+                    "filter": "stack",
+                    "label": "Call stack overflow/underflow",
+                    "default": false,
+                }],
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setExceptionBreakpoints" => self.set_exception_breakpoints(args),
+            "configurationDone" => Ok(json!({})),
+            "threads" => Ok(json!({ "threads": [{ "id": 1, "name": "4004" }] })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "Pairs", "variablesReference": PAIRS, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK, "expensive": false },
+                { "name": "RAM", "variablesReference": RAM, "expensive": true },
+            ]})),
+            "variables" => Ok(json!({
+                "variables": self.variables(args["variablesReference"].as_i64().unwrap_or(0)),
+            })),
+            "evaluate" => self.evaluate(args),
+            "continue" => {
+                self.running = true;
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "pause" => Ok(json!({})),
+            "next" | "stepIn" | "stepOut" => Ok(json!({})),
+            "disconnect" => {
+                self.done = true;
+                Ok(json!({}))
+            }
+            _ => Err(format!("unsupported request '{}'", command)),
+        };
+        let ok = result.is_ok();
+        self.respond(request, result);
+
+        // Events that follow the response
+        match command {
+            "launch" if ok => self.event("initialized", json!({})),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    self.stopped("entry", json!({}));
+                } else {
+                    self.running = true;
+                }
+            }
+            "pause" => {
+                self.running = false;
+                self.stopped("pause", json!({}));
+            }
+            "next" | "stepIn" | "stepOut" => {
+                let kind = match command {
+                    "next" => StepKind::Over,
+                    "stepIn" => StepKind::In,
+                    _ => StepKind::Out,
+                };
+                let by_line = args["granularity"] != "instruction";
+                self.step(kind, by_line);
+            }
+            _ => {}
+        }
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let mut sys = match args["preset"].as_str().unwrap_or("standard") {
+            "minimal" => Mcs4System::minimal(),
+            "standard" => Mcs4System::standard(),
+            "maximal" => Mcs4System::maximal(),
+            other => return Err(format!("unknown preset '{}'", other)),
+        };
+        match args["mode"].as_str().unwrap_or("phase") {
+            "phase" => {}
+            "instruction" => sys.set_mode(ExecutionMode::Instruction),
+            other => return Err(format!("unknown mode '{}'", other)),
+        }
+        let program = args["program"]
+            .as_str()
+            .ok_or("launch needs a 'program' ROM image")?;
+        sys.load_rom_file(Path::new(program))
+            .map_err(|e| format!("{}: {}", program, e))?;
+
+        let mut lines = LineMap::default();
+        if let Some(map) = args["lineMap"].as_str() {
+            let source = args["source"]
+                .as_str()
+                .ok_or("'lineMap' needs the main 'source' file")?;
+            let text = fs::read_to_string(map).map_err(|e| format!("{}: {}", map, e))?;
+            lines =
+                LineMap::parse(&text, Path::new(source)).map_err(|e| format!("{}: {}", map, e))?;
+        }
+        if let Some(symbols) = args["symbols"].as_str() {
+            let text = fs::read_to_string(symbols).map_err(|e| format!("{}: {}", symbols, e))?;
+            lines
+                .add_symbols(&text)
+                .map_err(|e| format!("{}: {}", symbols, e))?;
+        }
+
+        // Frames come from the logical call depth
+        sys.enable_stack_monitor();
+        self.sys = sys;
+        self.lines = lines;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.source_breaks.clear();
+        self.stack_break = None;
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"]
+            .as_str()
+            .ok_or("setBreakpoints needs 'source.path'")?;
+        let path = PathBuf::from(path);
+        for id in self.source_breaks.remove(&path).unwrap_or_default() {
+            self.sys.debugger_mut().remove(id);
+        }
+
+        let mut ids = Vec::new();
+        let mut results = Vec::new();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let line = requested["line"].as_u64().unwrap_or(0) as usize;
+            let Some((address, actual)) = self.lines.address(&path, line) else {
+                results.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "no code at or after this line",
+                }));
+                continue;
+            };
+            let mut brk = Break::address(address);
+            if let Some(condition) = requested["condition"].as_str().filter(|c| !c.is_empty()) {
+                match Condition::parse(condition) {
+                    Ok(condition) => brk = brk.when(condition),
+                    Err(e) => {
+                        results.push(
+                            json!({ "verified": false, "line": line, "message": e.to_string() }),
+                        );
+                        continue;
+                    }
+                }
+            }
+            let id = self.sys.debugger_mut().add(brk);
+            ids.push(id);
+            results.push(json!({ "id": id, "verified": true, "line": actual }));
+        }
+        self.source_breaks.insert(path, ids);
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn set_exception_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let filters = args["filters"].as_array().cloned().unwrap_or_default();
+        let stack = filters.iter().any(|f| f == "stack");
+        match (stack, self.stack_break) {
+            (true, None) => {
+                self.stack_break = Some(self.sys.debugger_mut().add(Break::stack_fault()));
+            }
+            (false, Some(id)) => {
+                self.sys.debugger_mut().remove(id);
+                self.stack_break = None;
+            }
+            _ => {}
+        }
+        Ok(json!({}))
+    }
+
+    /// Run one slice of a `continue`
+    fn run_slice(&mut self) {
+        if let Some(stop) = self.sys.run_until_break(RUN_SLICE) {
+            self.running = false;
+            self.report_break(stop.id, &stop.event, &stop.to_string());
+        }
+    }
+
+    fn report_break(&mut self, id: BreakId, event: &BreakEvent, description: &str) {
+        match event {
+            BreakEvent::Stack(fault) => self.stopped(
+                "exception",
+                json!({ "description": fault.to_string(), "text": description }),
+            ),
+            _ => self.stopped("breakpoint", json!({ "hitBreakpointIds": [id] })),
+        }
+    }
+
+    fn step(&mut self, kind: StepKind, by_line: bool) {
+        let by_line = by_line && !self.lines.is_empty();
+        let mut kind = kind;
+        for _ in 0..STEP_LIMIT {
+            let step = match kind {
+                StepKind::In => {
+                    self.sys.step_instruction();
+                    Step::Done
+                }
+                StepKind::Over => self.sys.step_over(STEP_CYCLES),
+                StepKind::Out => self.sys.step_out(STEP_CYCLES),
+            };
+            match step {
+                Step::Break(stop) => {
+                    return self.report_break(stop.id, &stop.event, &stop.to_string())
+                }
+                Step::Limit => break,
+                Step::Done => {}
+            }
+            // Stop only where a source line starts; after a return that is
+            // usually mid-line, so finish the caller's line as a `next` would
+            if !by_line || self.lines.location(self.sys.pc()).is_some() {
+                break;
+            }
+            if kind == StepKind::Out {
+                kind = StepKind::Over;
+            }
+        }
+        self.stopped("step", json!({}));
+    }
+
+    fn frame(&self, id: usize, address: u16) -> Value {
+        let mut frame = json!({
+            "id": id,
+            "name": self.lines.frame_name(address),
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": format!("0x{:03X}", address),
+        });
+        if let Some((file, line)) = self.lines.location(address) {
+            frame["source"] = json!({
+                "name": file.file_name().map(|n| n.to_string_lossy()),
+                "path": file.to_string_lossy(),
+            });
+            frame["line"] = json!(line);
+            frame["column"] = json!(1);
+        }
+        frame
+    }
+
+    fn stack_trace(&self) -> Value {
+        let registers = &self.sys.cpu.registers;
+        let depth = self
+            .sys
+            .cpu
+            .stack_monitor
+            .as_ref()
+            .map_or(0, |monitor| monitor.depth() as usize);
+        let mut frames = vec![self.frame(0, self.sys.pc())];
+        let sp = registers.stack_pointer() as usize;
+        for level in 1..=depth.min(3) {
+            let ret = registers.stack()[(sp + 3 - level) % 3];
+            // Show the caller at its JMS
+            frames.push(self.frame(level, ret.wrapping_sub(2) & 0x0FFF));
+        }
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn variables(&self, reference: i64) -> Vec<Value> {
+        let sys = &self.sys;
+        let var = |name: String, value: String, reference: i64| {
+            json!({
+                "name": name,
+                "value": value,
+                "variablesReference": reference,
+            })
+        };
+        let ram = |bank: i64, chip: i64| {
+            sys.ram
+                .iter()
+                .find(|r| r.bank_id as i64 == bank && r.chip_id as i64 == chip)
+        };
+        match reference {
+            REGISTERS => {
+                let mut vars = vec![
+                    var("PC".into(), format!("0x{:03X}", sys.pc()), 0),
+                    var("ACC".into(), format!("0x{:X}", sys.accumulator()), 0),
+                    var("CY".into(), (sys.carry() as u8).to_string(), 0),
+                    var("BANK".into(), sys.cpu.ram_bank().to_string(), 0),
+                ];
+                vars.extend(
+                    (0..16).map(|r| var(format!("R{}", r), format!("0x{:X}", sys.register(r)), 0)),
+                );
+                vars
+            }
+            PAIRS => (0..8)
+                .map(|p| {
+                    var(
+                        format!("P{}", p),
+                        format!("0x{:02X}", sys.register_pair(p)),
+                        0,
+                    )
+                })
+                .collect(),
+            STACK => {
+                let registers = &sys.cpu.registers;
+                let mut vars = vec![var("SP".into(), registers.stack_pointer().to_string(), 0)];
+                if let Some(monitor) = &sys.cpu.stack_monitor {
+                    vars.push(var("Depth".into(), monitor.depth().to_string(), 0));
+                }
+                vars.extend(
+                    registers
+                        .stack()
+                        .iter()
+                        .enumerate()
+                        .map(|(i, addr)| var(format!("S{}", i), format!("0x{:03X}", addr), 0)),
+                );
+                vars
+            }
+            RAM => {
+                let mut banks: Vec<u8> = sys.ram.iter().map(|r| r.bank_id).collect();
+                banks.sort_unstable();
+                banks.dedup();
+                banks
+                    .into_iter()
+                    .map(|bank| var(format!("Bank {}", bank), String::new(), BANK | bank as i64))
+                    .collect()
+            }
+            r if r & !0x7 == BANK => {
+                let bank = r & 0x7;
+                let mut chips: Vec<u8> = sys
+                    .ram
+                    .iter()
+                    .filter(|ram| ram.bank_id as i64 == bank)
+                    .map(|ram| ram.chip_id)
+                    .collect();
+                chips.sort_unstable();
+                chips
+                    .into_iter()
+                    .map(|chip| {
+                        var(
+                            format!("Chip {}", chip),
+                            String::new(),
+                            CHIP | bank << 2 | chip as i64,
+                        )
+                    })
+                    .collect()
+            }
+            r if r & !0x1F == CHIP => {
+                let (bank, chip) = ((r >> 2) & 0x7, r & 0x3);
+                let Some(ram) = ram(bank, chip) else {
+                    return Vec::new();
+                };
+                let mut vars: Vec<Value> = (0..4u8)
+                    .map(|register| {
+                        let digits: String = (0..16)
+                            .map(|c| format!("{:X}", ram.read_direct(register, c)))
+                            .collect();
+                        let status: String = (0..4)
+                            .map(|i| format!("{:X}", ram.read_status(register, i)))
+                            .collect();
+                        var(
+                            format!("Register {}", register),
+                            format!("{} {}", digits, status),
+                            REGISTER | bank << 4 | chip << 2 | register as i64,
+                        )
+                    })
+                    .collect();
+                vars.push(var("Output".into(), format!("0x{:X}", ram.output()), 0));
+                vars
+            }
+            r if r & !0x7F == REGISTER => {
+                let (bank, chip, register) = ((r >> 4) & 0x7, (r >> 2) & 0x3, (r & 0x3) as u8);
+                ram(bank, chip).map_or_else(Vec::new, |ram| {
+                    let characters = (0..16).map(|c| {
+                        var(
+                            format!("C{}", c),
+                            format!("0x{:X}", ram.read_direct(register, c)),
+                            0,
+                        )
+                    });
+                    let status = (0..4).map(|i| {
+                        var(
+                            format!("S{}", i),
+                            format!("0x{:X}", ram.read_status(register, i)),
+                            0,
+                        )
+                    });
+                    characters.chain(status).collect()
+                })
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn evaluate(&self, args: &Value) -> Result<Value, String> {
+        let expression = args["expression"].as_str().unwrap_or_default();
+        let condition = Condition::parse(expression).map_err(|e| e.to_string())?;
+        let value = condition.value(&self.sys);
+        Ok(json!({ "result": format!("{} (0x{:X})", value, value), "variablesReference": 0 }))
+    }
+}
+
+/// Read one `Content-Length` framed message; `None` at end of input
+fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.unwrap_or(0);
+    if length > MAX_MESSAGE {
+        io::copy(&mut reader.take(length as u64), &mut io::sink())?;
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} byte message is too long", length),
+        ));
+    }
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const SOURCE: &str = "start:  fim r0r1, 0
+        src r0r1
+        ldm 5
+        jms store
+loop:   jun loop
+store:  wrm
+        iac
+        bbl 0
+";
+
+    /// Assemble [`SOURCE`] into a scratch directory: ROM, source, line map
+    /// and symbol file
+    fn files(name: &str) -> (PathBuf, Value) {
+        let dir = std::env::temp_dir().join(format!("mcs4-dap-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let program = mcs4_asm::assemble(SOURCE).unwrap();
+        let path = |file: &str| dir.join(file).to_string_lossy().into_owned();
+        fs::write(path("prog.bin"), program.to_binary()).unwrap();
+        fs::write(path("prog.asm"), SOURCE).unwrap();
+        fs::write(path("prog.lines"), program.line_map()).unwrap();
+        fs::write(path("prog.sym"), program.symbol_file()).unwrap();
+        let launch = json!({
+            "program": path("prog.bin"),
+            "source": path("prog.asm"),
+            "lineMap": path("prog.lines"),
+            "symbols": path("prog.sym"),
+            "stopOnEntry": true,
+        });
+        (dir, launch)
+    }
+
+    /// Run a session over canned requests and return everything sent back
+    fn session(requests: &[(&str, Value)]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for (seq, (command, arguments)) in requests.iter().enumerate() {
+            let request = json!({
+                "seq": seq + 1,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            });
+            write_message(&mut input, &request).unwrap();
+        }
+        let mut output = Vec::new();
+        DapServer::new()
+            .run(Cursor::new(input), &mut output)
+            .unwrap();
+
+        let mut reader = Cursor::new(output);
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn response(messages: &[Value], request_seq: i64) -> &Value {
+        messages
+            .iter()
+            .find(|m| m["type"] == "response" && m["request_seq"] == request_seq)
+            .unwrap()
+    }
+
+    fn events<'a>(messages: &'a [Value], event: &'a str) -> impl Iterator<Item = &'a Value> {
+        messages
+            .iter()
+            .filter(move |m| m["type"] == "event" && m["event"] == event)
+    }
+
+    fn variable(variables: &Value, name: &str) -> String {
+        variables["body"]["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|v| v["name"] == name)
+            .map(|v| v["value"].as_str().unwrap().to_string())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_line_map() {
+        let source = Path::new("/src/main.asm");
+        let mut map = LineMap::parse("000 2\n002 3\n010 7 lib/util.inc\n", source).unwrap();
+        map.add_symbols("000 start\n010 util\n1234 BIG\n").unwrap();
+        assert_eq!(map.location(0x002), Some((source, 3)));
+        assert_eq!(map.location(0x001), None);
+        assert_eq!(map.address(source, 1), Some((0x000, 2)));
+        assert_eq!(map.address(source, 3), Some((0x002, 3)));
+        assert_eq!(map.address(source, 4), None);
+        assert_eq!(map.address(Path::new("util.inc"), 7), Some((0x010, 7)));
+        assert_eq!(map.frame_name(0x000), "start");
+        assert_eq!(map.frame_name(0x003), "start+3");
+        assert_eq!(map.frame_name(0x011), "util+1");
+        assert!(LineMap::parse("xyz\n", source).is_err());
+        assert!(map.add_symbols("010\n").is_err());
+    }
+
+    #[test]
+    fn test_session() {
+        let (dir, launch) = files("session");
+        let source = dir.join("prog.asm").to_string_lossy().into_owned();
+        let messages = session(&[
+            ("initialize", json!({ "adapterID": "mcs4" })),
+            ("launch", launch),
+            (
+                "setBreakpoints",
+                json!({
+                    "source": { "path": source },
+                    "breakpoints": [
+                        { "line": 6 },
+                        { "line": 7, "condition": "ACC == 9" },
+                        { "line": 9 },
+                    ],
+                }),
+            ),
+            ("setExceptionBreakpoints", json!({ "filters": ["stack"] })),
+            ("configurationDone", json!({})),
+            ("continue", json!({ "threadId": 1 })),
+            ("stackTrace", json!({ "threadId": 1 })),
+            ("variables", json!({ "variablesReference": REGISTERS })),
+            ("next", json!({ "threadId": 1 })),
+            ("variables", json!({ "variablesReference": REGISTER })),
+            ("stepOut", json!({ "threadId": 1 })),
+            ("stackTrace", json!({ "threadId": 1 })),
+            ("evaluate", json!({ "expression": "ACC + R1 + 2" })),
+            ("variables", json!({ "variablesReference": RAM })),
+            ("variables", json!({ "variablesReference": CHIP })),
+            ("disconnect", json!({})),
+        ]);
+        fs::remove_dir_all(dir).ok();
+
+        assert!(response(&messages, 1)["body"]["supportsConfigurationDoneRequest"] == true);
+        assert!(response(&messages, 2)["success"] == true);
+        let breakpoints = &response(&messages, 3)["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["verified"], true);
+        assert_eq!(breakpoints[0]["line"], 6);
+        assert_eq!(breakpoints[1]["verified"], true);
+        assert_eq!(breakpoints[2]["verified"], false);
+        assert_eq!(events(&messages, "initialized").count(), 1);
+
+        let reasons: Vec<&Value> = events(&messages, "stopped")
+            .map(|e| &e["body"]["reason"])
+            .collect();
+        assert_eq!(reasons, ["entry", "breakpoint", "step", "step"]);
+        let hit = events(&messages, "stopped").nth(1).unwrap();
+        assert_eq!(hit["body"]["hitBreakpointIds"][0], breakpoints[0]["id"]);
+
+        // Stopped in the subroutine, called from line 4
+        let frames = &response(&messages, 7)["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "store");
+        assert_eq!(frames[0]["line"], 6);
+        assert_eq!(frames[0]["source"]["path"], source.as_str());
+        assert_eq!(frames[1]["name"], "start+4");
+        assert_eq!(frames[1]["line"], 4);
+        assert_eq!(variable(response(&messages, 8), "ACC"), "0x5");
+        assert_eq!(variable(response(&messages, 8), "PC"), "0x008");
+
+        // WRM stored the 5; stepping out lands on the loop
+        assert_eq!(variable(response(&messages, 10), "C0"), "0x5");
+        assert_eq!(variable(response(&messages, 10), "S0"), "0x0");
+        let frames = &response(&messages, 12)["body"]["stackFrames"];
+        assert_eq!(frames.as_array().unwrap().len(), 1);
+        assert_eq!(frames[0]["name"], "loop");
+        assert_eq!(frames[0]["line"], 5);
+        assert_eq!(response(&messages, 13)["body"]["result"], "2 (0x2)");
+
+        let banks = response(&messages, 14)["body"]["variables"]
+            .as_array()
+            .unwrap();
+        assert_eq!(banks[0]["name"], "Bank 0");
+        assert_eq!(
+            variable(response(&messages, 15), "Register 0"),
+            "5000000000000000 0000"
+        );
+        assert!(response(&messages, 16)["success"] == true);
+    }
+
+    #[test]
+    fn test_pause_and_launch_errors() {
+        let (dir, mut launch) = files("pause");
+        launch["stopOnEntry"] = json!(false);
+        let messages = session(&[
+            ("initialize", json!({})),
+            ("launch", launch),
+            ("configurationDone", json!({})),
+            ("pause", json!({ "threadId": 1 })),
+            ("stackTrace", json!({ "threadId": 1 })),
+            ("disconnect", json!({})),
+        ]);
+        let stops: Vec<&Value> = events(&messages, "stopped").collect();
+        assert_eq!(stops.len(), 1);
+        assert_eq!(stops[0]["body"]["reason"], "pause");
+        assert_eq!(response(&messages, 5)["body"]["stackFrames"][0]["line"], 5);
+
+        let messages = session(&[
+            (
+                "launch",
+                json!({ "program": dir.join("missing.bin").to_string_lossy() }),
+            ),
+            ("launch", json!({ "program": "x", "preset": "huge" })),
+            ("frobnicate", json!({})),
+        ]);
+        fs::remove_dir_all(dir).ok();
+        assert_eq!(response(&messages, 1)["success"], false);
+        assert!(response(&messages, 1)["message"]
+            .as_str()
+            .unwrap()
+            .contains("missing.bin"));
+        assert_eq!(response(&messages, 2)["message"], "unknown preset 'huge'");
+        assert_eq!(
+            response(&messages, 3)["message"],
+            "unsupported request 'frobnicate'"
+        );
+        assert_eq!(events(&messages, "initialized").count(), 0);
+    }
+
+    #[test]
+    fn test_bad_messages_are_skipped() {
+        let mut input = b"Content-Length: 5\r\n\r\n{bad}".to_vec();
+        let header = format!("Content-Length: {}\r\n\r\n", MAX_MESSAGE + 1);
+        input.extend_from_slice(header.as_bytes());
+        input.resize(input.len() + MAX_MESSAGE + 1, b' ');
+        let request = json!({ "seq": 1, "type": "request", "command": "initialize" });
+        write_message(&mut input, &request).unwrap();
+
+        let mut output = Vec::new();
+        DapServer::new()
+            .run(Cursor::new(input), &mut output)
+            .unwrap();
+        let mut reader = Cursor::new(output);
+        let reply = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(reply["command"], "initialize");
+        assert_eq!(reply["success"], true);
+    }
+}
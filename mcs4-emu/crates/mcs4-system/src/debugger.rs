@@ -0,0 +1,1130 @@
+//! Breakpoints, watchpoints and stop reasons
+//!
+//! A [`Debugger`] holds a list of [`Break`]s checked by
+//! [`Mcs4System::run_until_break`] at every instruction boundary:
+//!
+//! - code breaks stop before an instruction runs: at an address
+//!   ([`Break::address`]) or on any instruction of a kind
+//!   ([`Break::opcode`], e.g. any `JMS` or `WRR`);
+//! - data breaks stop after the instruction that accessed a 4002 character,
+//!   status character or output port ([`Break::watch`]) or a 4001 I/O port
+//!   ([`Break::rom_port`]), reading or writing;
+//! - stack breaks stop after a JMS with all three return addresses in use
+//!   or a BBL with none ([`Break::stack_fault`]), as recorded by the CPU's
+//!   stack monitor ([`Mcs4System::enable_stack_monitor`]).
+//!
+//! Any break can carry a [`Condition`] such as `ACC==5 && CY`, evaluated
+//! when it triggers. Each counts its hits and can be disabled without
+//! being removed; the one that stopped the run comes back as a
+//! [`StopReason`]. When several trigger at the same boundary, all count a
+//! hit and the one added first is reported.
+
+use std::fmt;
+use std::str::FromStr;
+
+use mcs4_chips::i4004::InstructionDecoder;
+use mcs4_chips::stack_monitor::StackFault;
+
+use crate::mcs4::Mcs4System;
+
+/// Identifies a break within its [`Debugger`]
+pub type BreakId = u32;
+
+/// Which accesses a data break stops on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// Reads and writes
+    Any,
+}
+
+impl Access {
+    fn matches(self, access: Access) -> bool {
+        self == Access::Any || self == access
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "written"),
+            Access::Any => write!(f, "accessed"),
+        }
+    }
+}
+
+/// A 4002 location a watchpoint covers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RamLocation {
+    /// Main memory character (WRM, RDM, ADM, SBM)
+    Character {
+        bank: u8,
+        chip: u8,
+        register: u8,
+        character: u8,
+    },
+    /// Status character of a register (WR0-WR3, RD0-RD3)
+    Status {
+        bank: u8,
+        chip: u8,
+        register: u8,
+        index: u8,
+    },
+    /// Output port (WMP)
+    Output { bank: u8, chip: u8 },
+}
+
+impl RamLocation {
+    /// Current value, or `None` if no 4002 is fitted there
+    pub fn read(&self, sys: &Mcs4System) -> Option<u8> {
+        let (bank, chip) = match *self {
+            RamLocation::Character { bank, chip, .. }
+            | RamLocation::Status { bank, chip, .. }
+            | RamLocation::Output { bank, chip } => (bank, chip),
+        };
+        let ram = sys
+            .ram
+            .iter()
+            .find(|r| r.bank_id() == bank && r.chip_id() == chip)?;
+        Some(match *self {
+            RamLocation::Character {
+                register,
+                character,
+                ..
+            } => ram.read_direct(register, character),
+            RamLocation::Status {
+                register, index, ..
+            } => ram.read_status(register, index),
+            RamLocation::Output { .. } => ram.output(),
+        })
+    }
+}
+
+impl fmt::Display for RamLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            RamLocation::Character {
+                bank,
+                chip,
+                register,
+                character,
+            } => write!(
+                f,
+                "RAM bank {} chip {} register {} character {}",
+                bank, chip, register, character
+            ),
+            RamLocation::Status {
+                bank,
+                chip,
+                register,
+                index,
+            } => write!(
+                f,
+                "RAM bank {} chip {} register {} status {}",
+                bank, chip, register, index
+            ),
+            RamLocation::Output { bank, chip } => {
+                write!(f, "RAM bank {} chip {} output", bank, chip)
+            }
+        }
+    }
+}
+
+/// What a break triggers on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BreakKind {
+    /// The instruction at this address is about to run
+    Address(u16),
+    /// An instruction with this mnemonic is about to run
+    Opcode(&'static str),
+    /// An instruction accessed a 4002 location
+    Watch {
+        location: RamLocation,
+        access: Access,
+    },
+    /// An instruction wrote (WRR) or read (RDR) a 4001 I/O port
+    RomPort { chip: u8, access: Access },
+    /// The CPU's stack monitor recorded an overflow or underflow
+    Stack,
+}
+
+impl BreakKind {
+    fn is_data(&self) -> bool {
+        matches!(self, BreakKind::Watch { .. } | BreakKind::RomPort { .. })
+    }
+}
+
+/// One breakpoint or watchpoint
+#[derive(Clone, Debug)]
+pub struct Break {
+    id: BreakId,
+    kind: BreakKind,
+    condition: Option<Condition>,
+    enabled: bool,
+    hits: u64,
+}
+
+impl Break {
+    fn new(kind: BreakKind) -> Self {
+        Self {
+            id: 0,
+            kind,
+            condition: None,
+            enabled: true,
+            hits: 0,
+        }
+    }
+
+    /// Stop before the instruction at `address`
+    pub fn address(address: u16) -> Self {
+        Self::new(BreakKind::Address(address & 0x0FFF))
+    }
+
+    /// Stop before any instruction with `mnemonic` (e.g. "JMS", "wrr")
+    ///
+    /// Returns `None` if no 4004 instruction has that mnemonic.
+    pub fn opcode(mnemonic: &str) -> Option<Self> {
+        (0..=0xFFu8)
+            .filter_map(|opcode| decode(opcode, 0))
+            .find(|known| *known != "???" && known.eq_ignore_ascii_case(mnemonic))
+            .map(|known| Self::new(BreakKind::Opcode(known)))
+    }
+
+    /// Stop after an instruction accesses `location`
+    pub fn watch(location: RamLocation, access: Access) -> Self {
+        Self::new(BreakKind::Watch { location, access })
+    }
+
+    /// Stop after an instruction accesses the I/O port of 4001 `chip`
+    pub fn rom_port(chip: u8, access: Access) -> Self {
+        Self::new(BreakKind::RomPort { chip, access })
+    }
+
+    /// Stop after a call or return the 3-level stack cannot honour
+    ///
+    /// Only triggers while the stack monitor is enabled.
+    pub fn stack_fault() -> Self {
+        Self::new(BreakKind::Stack)
+    }
+
+    /// Only stop when `condition` holds
+    pub fn when(mut self, condition: Condition) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    /// Id assigned by [`Debugger::add`]
+    pub fn id(&self) -> BreakId {
+        self.id
+    }
+
+    pub fn kind(&self) -> &BreakKind {
+        &self.kind
+    }
+
+    pub fn condition(&self) -> Option<&Condition> {
+        self.condition.as_ref()
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Times the break has stopped a run
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    fn condition_holds(&self, sys: &Mcs4System) -> bool {
+        self.condition
+            .as_ref()
+            .is_none_or(|condition| condition.eval(sys))
+    }
+}
+
+/// What happened at a stop
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BreakEvent {
+    /// Reached a breakpoint address
+    Address,
+    /// About to run an instruction of a watched kind
+    Opcode(&'static str),
+    /// A 4002 location was read or written
+    Watch {
+        location: RamLocation,
+        access: Access,
+        old: u8,
+        new: u8,
+    },
+    /// A 4001 port was written (the new output) or read (the input)
+    RomPort { chip: u8, access: Access, value: u8 },
+    /// A JMS overwrote a return address or a BBL had none to return to
+    Stack(StackFault),
+}
+
+/// Why [`Mcs4System::run_until_break`] stopped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StopReason {
+    /// The break that triggered
+    pub id: BreakId,
+    /// Address of the instruction about to run (code breaks) or that made
+    /// the access (data breaks)
+    pub address: u16,
+    pub event: BreakEvent,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.event {
+            BreakEvent::Address => write!(f, "breakpoint {} at 0x{:03X}", self.id, self.address),
+            BreakEvent::Opcode(mnemonic) => {
+                write!(
+                    f,
+                    "break {} at 0x{:03X}: {}",
+                    self.id, self.address, mnemonic
+                )
+            }
+            BreakEvent::Watch {
+                location,
+                access,
+                old,
+                new,
+            } => {
+                write!(
+                    f,
+                    "watchpoint {} at 0x{:03X}: {} {}",
+                    self.id, self.address, location, access
+                )?;
+                match access {
+                    Access::Write => write!(f, " 0x{:X} -> 0x{:X}", old, new),
+                    _ => write!(f, " 0x{:X}", old),
+                }
+            }
+            BreakEvent::RomPort {
+                chip,
+                access,
+                value,
+            } => write!(
+                f,
+                "break {} at 0x{:03X}: ROM {} port {} 0x{:X}",
+                self.id, self.address, chip, access, value
+            ),
+            BreakEvent::Stack(fault) => write!(f, "break {}: {}", self.id, fault),
+        }
+    }
+}
+
+/// How a step or run-to finished
+///
+/// `B` is what a break reports: a [`StopReason`] on [`Mcs4System`], the
+/// breakpoint address on [`crate::Mcs40System`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step<B = StopReason> {
+    /// Reached the point the step was aiming for
+    Done,
+    /// Stopped early at a break
+    Break(B),
+    /// Gave up after the cycle limit
+    Limit,
+}
+
+/// A data access an instruction is about to make, with the value before it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PendingAccess {
+    target: Target,
+    access: Access,
+    old: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Target {
+    Ram(RamLocation),
+    RomPort(u8),
+}
+
+/// The breaks of a system
+#[derive(Clone, Debug, Default)]
+pub struct Debugger {
+    breaks: Vec<Break>,
+    next_id: BreakId,
+}
+
+impl Debugger {
+    /// Add a break and return its id
+    pub fn add(&mut self, mut brk: Break) -> BreakId {
+        self.next_id += 1;
+        brk.id = self.next_id;
+        self.breaks.push(brk);
+        self.next_id
+    }
+
+    /// Remove a break; returns false if there is none with that id
+    pub fn remove(&mut self, id: BreakId) -> bool {
+        let before = self.breaks.len();
+        self.breaks.retain(|b| b.id != id);
+        self.breaks.len() != before
+    }
+
+    /// Remove every break
+    pub fn clear(&mut self) {
+        self.breaks.clear();
+    }
+
+    /// Enable or disable a break; returns false if there is none with that id
+    pub fn set_enabled(&mut self, id: BreakId, enabled: bool) -> bool {
+        match self.breaks.iter_mut().find(|b| b.id == id) {
+            Some(brk) => {
+                brk.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get(&self, id: BreakId) -> Option<&Break> {
+        self.breaks.iter().find(|b| b.id == id)
+    }
+
+    /// All breaks, in the order they were added
+    pub fn breaks(&self) -> &[Break] {
+        &self.breaks
+    }
+
+    pub(crate) fn remove_where(&mut self, mut f: impl FnMut(&Break) -> bool) {
+        self.breaks.retain(|b| !f(b));
+    }
+
+    /// Is the system at an enabled address breakpoint whose condition
+    /// holds? Hit counts are left alone.
+    pub(crate) fn at_breakpoint(&self, sys: &Mcs4System) -> bool {
+        let pc = sys.pc();
+        self.breaks
+            .iter()
+            .any(|b| b.enabled && b.kind == BreakKind::Address(pc) && b.condition_holds(sys))
+    }
+
+    /// Data accesses the instruction at the PC will make, if any data
+    /// break could see them
+    pub(crate) fn pending_accesses(&self, sys: &Mcs4System) -> Vec<PendingAccess> {
+        if !self.breaks.iter().any(|b| b.enabled && b.kind.is_data()) {
+            return Vec::new();
+        }
+        let opcode = sys.rom_image()[sys.pc() as usize & 0x0FFF];
+        if opcode >> 4 != 0xE {
+            return Vec::new();
+        }
+        let command = opcode & 0x0F;
+        match command {
+            // WRR, RDR
+            0x2 | 0xA => sys
+                .rom
+                .iter()
+                .filter(|rom| rom.is_io_selected())
+                .map(|rom| {
+                    let (access, old) = match command {
+                        0x2 => (Access::Write, rom.io_output()),
+                        _ => (Access::Read, rom.io_input()),
+                    };
+                    PendingAccess {
+                        target: Target::RomPort(rom.chip_id()),
+                        access,
+                        old,
+                    }
+                })
+                .collect(),
+            // WPM
+            0x3 => Vec::new(),
+            _ => {
+                let banks = sys.strobed_banks();
+                sys.ram
+                    .iter()
+                    .filter(|ram| ram.is_selected() && banks & (1 << ram.bank_id()) != 0)
+                    .map(|ram| {
+                        let (bank, chip) = (ram.bank_id(), ram.chip_id());
+                        let (register, character) = ram.selected_address();
+                        let (location, old) = match command {
+                            0x1 => (RamLocation::Output { bank, chip }, ram.output()),
+                            0x4..=0x7 | 0xC..=0xF => {
+                                let index = command & 0x3;
+                                (
+                                    RamLocation::Status {
+                                        bank,
+                                        chip,
+                                        register,
+                                        index,
+                                    },
+                                    ram.read_status(register, index),
+                                )
+                            }
+                            _ => (
+                                RamLocation::Character {
+                                    bank,
+                                    chip,
+                                    register,
+                                    character,
+                                },
+                                ram.read_direct(register, character),
+                            ),
+                        };
+                        let access = match command {
+                            0x0 | 0x1 | 0x4..=0x7 => Access::Write,
+                            _ => Access::Read,
+                        };
+                        PendingAccess {
+                            target: Target::Ram(location),
+                            access,
+                            old,
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Check every enabled break after the instruction at `address` ran,
+    /// made `accesses` and possibly caused a stack `fault`; counts a hit on
+    /// each that triggers and returns the first
+    pub(crate) fn check(
+        &mut self,
+        sys: &Mcs4System,
+        address: u16,
+        accesses: &[PendingAccess],
+        fault: Option<StackFault>,
+    ) -> Option<StopReason> {
+        let pc = sys.pc();
+        let mnemonic = decode(
+            sys.rom_image()[pc as usize],
+            sys.rom_image()[(pc as usize + 1) & 0x0FFF],
+        );
+        let mut stop = None;
+        for brk in self.breaks.iter_mut().filter(|b| b.enabled) {
+            let hit = match brk.kind {
+                BreakKind::Address(at) => (at == pc).then_some((pc, BreakEvent::Address)),
+                BreakKind::Opcode(name) => {
+                    (mnemonic == Some(name)).then_some((pc, BreakEvent::Opcode(name)))
+                }
+                BreakKind::Watch { location, access } => accesses
+                    .iter()
+                    .find(|a| a.target == Target::Ram(location) && access.matches(a.access))
+                    .map(|a| {
+                        let new = location.read(sys).unwrap_or(a.old);
+                        let event = BreakEvent::Watch {
+                            location,
+                            access: a.access,
+                            old: a.old,
+                            new,
+                        };
+                        (address, event)
+                    }),
+                BreakKind::RomPort { chip, access } => accesses
+                    .iter()
+                    .find(|a| a.target == Target::RomPort(chip) && access.matches(a.access))
+                    .map(|a| {
+                        let value = match a.access {
+                            Access::Write => sys
+                                .rom
+                                .iter()
+                                .find(|r| r.chip_id() == chip)
+                                .map_or(a.old, |r| r.io_output()),
+                            _ => a.old,
+                        };
+                        (
+                            address,
+                            BreakEvent::RomPort {
+                                chip,
+                                access: a.access,
+                                value,
+                            },
+                        )
+                    }),
+                BreakKind::Stack => fault.map(|fault| (fault.pc(), BreakEvent::Stack(fault))),
+            };
+            let Some((address, event)) = hit else {
+                continue;
+            };
+            if !brk.condition_holds(sys) {
+                continue;
+            }
+            brk.hits += 1;
+            stop.get_or_insert(StopReason {
+                id: brk.id,
+                address,
+                event,
+            });
+        }
+        stop
+    }
+}
+
+/// Mnemonic of the instruction starting with `opcode`
+fn decode(opcode: u8, second: u8) -> Option<&'static str> {
+    let mut decoder = InstructionDecoder::new();
+    decoder.decode_first(opcode);
+    if decoder.needs_second_byte() {
+        decoder.decode_second(second);
+    }
+    decoder.get_instruction().map(|instr| instr.mnemonic())
+}
+
+/// Error in a break condition
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConditionError {
+    /// Byte offset into the condition text
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.position + 1, self.message)
+    }
+}
+
+impl std::error::Error for ConditionError {}
+
+/// A boolean expression over the CPU state
+///
+/// Operands are `ACC`, `CY`, `PC`, `SP`, `BANK` (DCL code), `TEST`,
+/// `CYCLES`, `R0`-`R15`, `P0`-`P7` and decimal or `0x` hex numbers, in any
+/// case. Operators, loosest first: `||`, `&&`, comparisons (`==`, `!=`,
+/// `<`, `<=`, `>`, `>=`), `+`, `-` and `&`, then unary `!`; parentheses
+/// group. A bare value is true when non-zero.
+#[derive(Clone, Debug)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, ConditionError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            end: source.len(),
+        };
+        let expr = parser.or()?;
+        if let Some(&(position, _)) = parser.tokens.get(parser.pos) {
+            return Err(ConditionError {
+                position,
+                message: "unexpected token".into(),
+            });
+        }
+        Ok(Self {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+
+    /// Does the condition hold for `sys`?
+    pub fn eval(&self, sys: &Mcs4System) -> bool {
+        self.expr.eval(sys) != 0
+    }
+
+    /// Value of the expression for `sys`; comparisons and logic give 0 or 1
+    pub fn value(&self, sys: &Mcs4System) -> u64 {
+        self.expr.eval(sys)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = ConditionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Var {
+    Acc,
+    Carry,
+    Pc,
+    Sp,
+    Bank,
+    Test,
+    Cycles,
+    Register(u8),
+    Pair(u8),
+}
+
+impl Var {
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_uppercase();
+        let index = |prefix: &str, limit: u8| {
+            name.strip_prefix(prefix)
+                .and_then(|n| n.parse::<u8>().ok())
+                .filter(|&n| n < limit)
+        };
+        Some(match name.as_str() {
+            "ACC" => Var::Acc,
+            "CY" => Var::Carry,
+            "PC" => Var::Pc,
+            "SP" => Var::Sp,
+            "BANK" => Var::Bank,
+            "TEST" => Var::Test,
+            "CYCLES" => Var::Cycles,
+            _ => {
+                if let Some(r) = index("R", 16) {
+                    Var::Register(r)
+                } else {
+                    Var::Pair(index("P", 8)?)
+                }
+            }
+        })
+    }
+
+    fn eval(self, sys: &Mcs4System) -> u64 {
+        match self {
+            Var::Acc => sys.accumulator() as u64,
+            Var::Carry => sys.carry() as u64,
+            Var::Pc => sys.pc() as u64,
+            Var::Sp => sys.cpu.registers.stack_pointer() as u64,
+            Var::Bank => sys.cpu.ram_bank() as u64,
+            Var::Test => sys.cpu.test_pin() as u64,
+            Var::Cycles => sys.cycles(),
+            Var::Register(r) => sys.register(r) as u64,
+            Var::Pair(p) => sys.register_pair(p) as u64,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    BitAnd,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Value(u64),
+    Var(Var),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, sys: &Mcs4System) -> u64 {
+        match self {
+            Expr::Value(value) => *value,
+            Expr::Var(var) => var.eval(sys),
+            Expr::Not(inner) => (inner.eval(sys) == 0) as u64,
+            Expr::Binary(op, lhs, rhs) => {
+                let a = lhs.eval(sys);
+                // || and && short-circuit
+                match op {
+                    BinOp::Or if a != 0 => return 1,
+                    BinOp::And if a == 0 => return 0,
+                    _ => {}
+                }
+                let b = rhs.eval(sys);
+                match op {
+                    BinOp::Or | BinOp::And => (b != 0) as u64,
+                    BinOp::Eq => (a == b) as u64,
+                    BinOp::Ne => (a != b) as u64,
+                    BinOp::Lt => (a < b) as u64,
+                    BinOp::Le => (a <= b) as u64,
+                    BinOp::Gt => (a > b) as u64,
+                    BinOp::Ge => (a >= b) as u64,
+                    BinOp::Add => a.wrapping_add(b),
+                    BinOp::Sub => a.wrapping_sub(b),
+                    BinOp::BitAnd => a & b,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(u64),
+    Name(String),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 14] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "&", "!", "(", ")",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ConditionError> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        if c.is_ascii_whitespace() {
+            pos += 1;
+        } else if c.is_ascii_alphanumeric() || c == b'_' {
+            let start = pos;
+            while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_') {
+                pos += 1;
+            }
+            let word = &source[start..pos];
+            let token = if c.is_ascii_digit() {
+                let value = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => word.parse(),
+                };
+                Token::Number(value.map_err(|_| ConditionError {
+                    position: start,
+                    message: format!("invalid number '{}'", word),
+                })?)
+            } else {
+                Token::Name(word.to_string())
+            };
+            tokens.push((start, token));
+        } else if let Some(op) = OPERATORS.iter().find(|op| source[pos..].starts_with(**op)) {
+            tokens.push((pos, Token::Op(op)));
+            pos += op.len();
+        } else {
+            return Err(ConditionError {
+                position: pos,
+                message: format!(
+                    "unexpected character '{}'",
+                    source[pos..].chars().next().unwrap_or('?')
+                ),
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [(usize, Token)],
+    pos: usize,
+    /// Position reported for errors at the end of the text
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some((_, Token::Op(op))) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[(&str, BinOp)],
+        next: fn(&mut Self) -> Result<Expr, ConditionError>,
+        repeat: bool,
+    ) -> Result<Expr, ConditionError> {
+        let mut lhs = next(self)?;
+        while let Some(&(_, op)) = self
+            .peek_op()
+            .and_then(|t| ops.iter().find(|(s, _)| *s == t))
+        {
+            self.pos += 1;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(next(self)?));
+            if !repeat {
+                break;
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn or(&mut self) -> Result<Expr, ConditionError> {
+        self.binary(&[("||", BinOp::Or)], Self::and, true)
+    }
+
+    fn and(&mut self) -> Result<Expr, ConditionError> {
+        self.binary(&[("&&", BinOp::And)], Self::comparison, true)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ConditionError> {
+        let ops = [
+            ("==", BinOp::Eq),
+            ("!=", BinOp::Ne),
+            ("<", BinOp::Lt),
+            ("<=", BinOp::Le),
+            (">", BinOp::Gt),
+            (">=", BinOp::Ge),
+        ];
+        self.binary(&ops, Self::sum, false)
+    }
+
+    fn sum(&mut self) -> Result<Expr, ConditionError> {
+        let ops = [("+", BinOp::Add), ("-", BinOp::Sub), ("&", BinOp::BitAnd)];
+        self.binary(&ops, Self::unary, true)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ConditionError> {
+        let Some((position, token)) = self.tokens.get(self.pos) else {
+            return Err(ConditionError {
+                position: self.end,
+                message: "expected a value".into(),
+            });
+        };
+        self.pos += 1;
+        match token {
+            Token::Number(value) => Ok(Expr::Value(*value)),
+            Token::Name(name) => {
+                Var::from_name(name)
+                    .map(Expr::Var)
+                    .ok_or_else(|| ConditionError {
+                        position: *position,
+                        message: format!("unknown name '{}'", name),
+                    })
+            }
+            Token::Op("!") => Ok(Expr::Not(Box::new(self.unary()?))),
+            Token::Op("(") => {
+                let inner = self.or()?;
+                if self.peek_op() != Some(")") {
+                    let position = self.tokens.get(self.pos).map_or(self.end, |&(p, _)| p);
+                    return Err(ConditionError {
+                        position,
+                        message: "expected ')'".into(),
+                    });
+                }
+                self.pos += 1;
+                Ok(inner)
+            }
+            Token::Op(_) => Err(ConditionError {
+                position: *position,
+                message: "expected a value".into(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_condition() {
+        let mut sys = Mcs4System::minimal();
+        // LDM 5; STC; FIM P1, 0x3A
+        sys.load_rom(&[0xD5, 0xFA, 0x22, 0x3A]);
+        sys.run_cycles(4);
+
+        let holds = |text: &str| Condition::parse(text).unwrap().eval(&sys);
+        assert!(holds("ACC==5 && CY"));
+        assert!(holds("acc == 0x5 && !(cy == 0)"));
+        assert!(!holds("ACC==5 && !CY"));
+        assert!(holds("ACC == 4 || R3 == 10"));
+        assert!(holds("P1 == 0x3A && R2 + R3 == 13 && (R3 & 2)"));
+        assert!(holds("PC >= 4 && CYCLES == 4 && SP == 0 && !TEST"));
+        assert_eq!(Condition::parse(" ACC==5 ").unwrap().to_string(), "ACC==5");
+
+        let err = Condition::parse("ACC == X1").unwrap_err();
+        assert_eq!(err.position, 7);
+        assert_eq!(err.to_string(), "column 8: unknown name 'X1'");
+        assert_eq!(
+            Condition::parse("(ACC == 5").unwrap_err().message,
+            "expected ')'"
+        );
+        assert_eq!(Condition::parse("ACC ==").unwrap_err().position, 6);
+        assert!(Condition::parse("ACC $ 1").is_err());
+        assert!(Condition::parse("R16").is_err());
+        assert!(Condition::parse("ACC 1").is_err());
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut sys = Mcs4System::minimal();
+        // loop: IAC; JUN loop
+        sys.load_rom(&[0xF2, 0x40, 0x00]);
+        let id = sys
+            .debugger_mut()
+            .add(Break::address(0x000).when("ACC == 5".parse().unwrap()));
+
+        let stop = sys.run_until_break(1000).unwrap();
+        assert_eq!(
+            stop,
+            StopReason {
+                id,
+                address: 0x000,
+                event: BreakEvent::Address
+            }
+        );
+        assert_eq!(sys.accumulator(), 5);
+        assert_eq!(stop.to_string(), "breakpoint 1 at 0x000");
+
+        // Wraps back to 5 after 16 more increments
+        let start = sys.cycles();
+        assert_eq!(sys.run_until_break(1000).map(|s| s.id), Some(id));
+        assert_eq!(sys.cycles() - start, 16 * 3);
+        assert_eq!(sys.debugger().get(id).unwrap().hits(), 2);
+
+        assert!(sys.debugger_mut().set_enabled(id, false));
+        assert_eq!(sys.run_until_break(300), None);
+        assert_eq!(sys.debugger().get(id).unwrap().hits(), 2);
+    }
+
+    #[test]
+    fn test_ram_watchpoints() {
+        let mut sys = Mcs4System::standard();
+        // FIM P0, 0x63 (chip 1, register 2, char 3); SRC P0
+        // LDM 5; WRM; LDM 14; WR2; LDM 3; WMP; RDM; WRM; NOP
+        sys.load_rom(&[
+            0x20, 0x63, 0x21, 0xD5, 0xE0, 0xDE, 0xE6, 0xD3, 0xE1, 0xE9, 0xE0, 0x00,
+        ]);
+        let character = RamLocation::Character {
+            bank: 0,
+            chip: 1,
+            register: 2,
+            character: 3,
+        };
+        let debugger = sys.debugger_mut();
+        let write = debugger.add(Break::watch(character, Access::Write));
+        let status = debugger.add(Break::watch(
+            RamLocation::Status {
+                bank: 0,
+                chip: 1,
+                register: 2,
+                index: 2,
+            },
+            Access::Any,
+        ));
+        let output = debugger.add(Break::watch(
+            RamLocation::Output { bank: 0, chip: 1 },
+            Access::Write,
+        ));
+        let read = debugger.add(Break::watch(character, Access::Read));
+        // Status 2 of another register is a different location
+        let other_status = debugger.add(Break::watch(
+            RamLocation::Status {
+                bank: 0,
+                chip: 1,
+                register: 0,
+                index: 2,
+            },
+            Access::Any,
+        ));
+        // Same chip in the other bank is never strobed
+        let other = debugger.add(Break::watch(
+            RamLocation::Character {
+                bank: 1,
+                chip: 1,
+                register: 2,
+                character: 3,
+            },
+            Access::Any,
+        ));
+
+        let stop = sys.run_until_break(100).unwrap();
+        assert_eq!(stop.id, write);
+        assert_eq!(stop.address, 0x004);
+        assert_eq!(
+            stop.event,
+            BreakEvent::Watch {
+                location: character,
+                access: Access::Write,
+                old: 0,
+                new: 5
+            }
+        );
+        assert_eq!(sys.pc(), 0x005);
+        assert_eq!(
+            stop.to_string(),
+            "watchpoint 1 at 0x004: RAM bank 0 chip 1 register 2 character 3 written 0x0 -> 0x5"
+        );
+
+        let stop = sys.run_until_break(100).unwrap();
+        assert_eq!((stop.id, stop.address), (status, 0x006));
+        let stop = sys.run_until_break(100).unwrap();
+        assert_eq!((stop.id, stop.address), (output, 0x008));
+        let stop = sys.run_until_break(100).unwrap();
+        assert_eq!((stop.id, stop.address), (read, 0x009));
+        assert_eq!(
+            stop.to_string(),
+            "watchpoint 4 at 0x009: RAM bank 0 chip 1 register 2 character 3 read 0x5"
+        );
+
+        // Writing the same value still counts
+        let stop = sys.run_until_break(100).unwrap();
+        assert_eq!(
+            (stop.id, stop.address, stop.event),
+            (
+                write,
+                0x00A,
+                BreakEvent::Watch {
+                    location: character,
+                    access: Access::Write,
+                    old: 5,
+                    new: 5
+                }
+            )
+        );
+        assert_eq!(sys.run_until_break(10), None);
+        assert_eq!(sys.debugger().get(other).unwrap().hits(), 0);
+        assert_eq!(sys.debugger().get(other_status).unwrap().hits(), 0);
+        assert_eq!(sys.debugger().get(write).unwrap().hits(), 2);
+    }
+
+    #[test]
+    fn test_rom_port_and_opcode_breaks() {
+        let mut sys = Mcs4System::standard();
+        sys.set_mode(crate::mcs4::ExecutionMode::Instruction);
+        sys.rom[2].set_io_input(0x6);
+        // FIM P0, 0x20; SRC P0; LDM 9; WRR; NOP; JMS 0x010; RDR; ...; 0x010: BBL 0
+        let mut program = vec![0x20, 0x20, 0x21, 0xD9, 0xE2, 0x00, 0x50, 0x10, 0xEA, 0x00];
+        program.resize(0x10, 0x00);
+        program.push(0xC0);
+        sys.load_rom(&program);
+
+        assert!(Break::opcode("XYZ").is_none());
+        let debugger = sys.debugger_mut();
+        let port_write = debugger.add(Break::rom_port(2, Access::Write));
+        let jms = debugger.add(Break::opcode("jms").unwrap());
+        let port_read = debugger.add(Break::rom_port(2, Access::Read));
+        debugger.add(Break::rom_port(0, Access::Any));
+
+        let stop = sys.run_until_break(100).unwrap();
+        assert_eq!(
+            stop,
+            StopReason {
+                id: port_write,
+                address: 0x004,
+                event: BreakEvent::RomPort {
+                    chip: 2,
+                    access: Access::Write,
+                    value: 9
+                },
+            }
+        );
+        assert_eq!(stop.to_string(), "break 1 at 0x004: ROM 2 port written 0x9");
+
+        let stop = sys.run_until_break(100).unwrap();
+        assert_eq!((stop.id, stop.address), (jms, 0x006));
+        assert_eq!(stop.to_string(), "break 2 at 0x006: JMS");
+        assert_eq!(sys.pc(), 0x006);
+
+        let stop = sys.run_until_break(100).unwrap();
+        assert_eq!(
+            (stop.id, stop.event),
+            (
+                port_read,
+                BreakEvent::RomPort {
+                    chip: 2,
+                    access: Access::Read,
+                    value: 6
+                }
+            )
+        );
+        assert_eq!(sys.accumulator(), 6);
+
+        assert!(sys.debugger_mut().remove(jms));
+        assert!(!sys.debugger_mut().remove(jms));
+        assert_eq!(sys.debugger().breaks().len(), 3);
+    }
+}
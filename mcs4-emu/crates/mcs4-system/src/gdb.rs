@@ -0,0 +1,946 @@
+//! GDB remote serial protocol stub
+//!
+//! [`GdbStub`] serves an [`Mcs4System`] to a GDB-compatible debugger over
+//! any [`Connection`]: a TCP socket on localhost ([`GdbStub::listen_tcp`]),
+//! a Unix socket ([`GdbStub::listen_unix`]) or an in-memory stream in
+//! tests.
+//!
+//! The CPU is described by a custom target description (`target.xml`):
+//!
+//! | regnum | name             | bits | contents                     |
+//! |--------|------------------|------|------------------------------|
+//! | 0      | `pc`             | 16   | program counter              |
+//! | 1      | `acc`            | 8    | accumulator                  |
+//! | 2      | `cy`             | 8    | carry (0 or 1)               |
+//! | 3-18   | `r0`-`r15`       | 8    | index registers              |
+//! | 19     | `sp`             | 8    | stack pointer (0-2)          |
+//! | 20-22  | `stack0`-`stack2`| 16   | return address slots         |
+//!
+//! Registers go over the wire little-endian. Memory is one flat space
+//! holding one byte per ROM byte or RAM nibble:
+//!
+//! - `0x00000-0x00FFF`: 4001 program ROM (writes patch the 4001s);
+//! - `0x10000-0x10FFF`: 4002 RAM, at `0x10000 | bank << 9 | chip << 7 |
+//!   register << 5 | index`, where index 0-15 is a main character and
+//!   16-19 are the register's four status characters.
+//!
+//! Supported: `?`, `g`/`G`, `p`/`P`, `m`/`M`, `s`, `c`, `vCont`, `Z0`/`Z1`
+//! breakpoints and `Z2`-`Z4` RAM watchpoints (through the system's
+//! [`crate::Debugger`]), reverse `bs`/`bc` while rewind is enabled,
+//! `QStartNoAckMode`, `qXfer` for the target description and memory map,
+//! interrupt (`^C`) while running, `D` and `k`.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+
+use crate::debugger::{Access, Break, BreakEvent, BreakId, RamLocation};
+use crate::mcs4::Mcs4System;
+
+/// Start of the RAM window in the GDB address space
+pub const RAM_BASE: u32 = 0x10000;
+
+/// Size of the RAM window: 8 banks x 4 chips x 4 registers x 32 slots
+const RAM_SIZE: u32 = 0x1000;
+
+/// Largest packet advertised in `qSupported`
+const PACKET_SIZE: usize = 0x1000;
+
+/// Registers in the target description
+const REGISTER_COUNT: usize = 23;
+
+/// Cycles run between checks for an interrupt from the debugger
+const CONTINUE_SLICE: u64 = 10_000;
+
+/// Ctrl-C sent by the debugger to interrupt a running target
+const INTERRUPT: u8 = 0x03;
+
+/// A byte stream to a debugger
+pub trait Connection: Read + Write {
+    /// Has the debugger sent an interrupt (Ctrl-C) since the target
+    /// resumed? Must not block; consumes the interrupt byte.
+    fn interrupt_requested(&mut self) -> io::Result<bool>;
+}
+
+impl Connection for TcpStream {
+    fn interrupt_requested(&mut self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let result = poll_interrupt(self);
+        self.set_nonblocking(false)?;
+        result
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn interrupt_requested(&mut self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let result = poll_interrupt(self);
+        self.set_nonblocking(false)?;
+        result
+    }
+}
+
+/// Read a byte from a non-blocking stream, if one is waiting
+///
+/// GDB sends nothing but an interrupt while the target runs, so any other
+/// byte is dropped.
+fn poll_interrupt<R: Read>(conn: &mut R) -> io::Result<bool> {
+    let mut byte = [0u8];
+    match conn.read(&mut byte) {
+        Ok(1) => Ok(byte[0] == INTERRUPT),
+        Ok(_) => Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// What the stub does after a packet
+enum Action {
+    Reply(String),
+    /// Reply and end the session
+    Close(String),
+    /// End the session without replying (`k`)
+    Kill,
+}
+
+/// GDB remote serial protocol server for an [`Mcs4System`]
+pub struct GdbStub {
+    sys: Mcs4System,
+    /// Acknowledge packets with `+`; turned off by `QStartNoAckMode`
+    ack: bool,
+    /// Last packet sent, for resending on `-`
+    last_sent: Vec<u8>,
+    /// Watchpoints set through `Z2`-`Z4`, by type and address
+    watchpoints: HashMap<(u8, u32), BreakId>,
+}
+
+impl GdbStub {
+    pub fn new(sys: Mcs4System) -> Self {
+        Self {
+            sys,
+            ack: true,
+            last_sent: Vec::new(),
+            watchpoints: HashMap::new(),
+        }
+    }
+
+    pub fn system(&self) -> &Mcs4System {
+        &self.sys
+    }
+
+    pub fn system_mut(&mut self) -> &mut Mcs4System {
+        &mut self.sys
+    }
+
+    pub fn into_system(self) -> Mcs4System {
+        self.sys
+    }
+
+    /// Wait for one debugger on `127.0.0.1:port` and serve it until it
+    /// detaches or disconnects
+    pub fn listen_tcp(&mut self, port: u16) -> io::Result<()> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        tracing::info!("GDB stub listening on {}", listener.local_addr()?);
+        let (mut stream, peer) = listener.accept()?;
+        tracing::info!("GDB connected from {}", peer);
+        stream.set_nodelay(true)?;
+        self.serve(&mut stream)
+    }
+
+    /// Wait for one debugger on the Unix socket at `path` and serve it
+    /// until it detaches or disconnects
+    #[cfg(unix)]
+    pub fn listen_unix(&mut self, path: &Path) -> io::Result<()> {
+        let listener = UnixListener::bind(path)?;
+        tracing::info!("GDB stub listening on {}", path.display());
+        let (mut stream, _) = listener.accept()?;
+        self.serve(&mut stream)
+    }
+
+    /// Serve one debugger session on `conn`
+    ///
+    /// Returns when the debugger detaches (`D`), kills the target (`k`) or
+    /// closes the connection. A new session starts in acknowledgement mode.
+    pub fn serve<C: Connection>(&mut self, conn: &mut C) -> io::Result<()> {
+        self.ack = true;
+        loop {
+            let Some(byte) = read_byte(conn)? else {
+                return Ok(());
+            };
+            match byte {
+                b'$' => {}
+                b'-' => {
+                    conn.write_all(&self.last_sent)?;
+                    conn.flush()?;
+                    continue;
+                }
+                // Interrupt while stopped: report the stop again
+                INTERRUPT => {
+                    self.send(conn, "S02")?;
+                    continue;
+                }
+                // Acks and line noise
+                _ => continue,
+            }
+
+            let Some(packet) = read_packet(conn)? else {
+                return Ok(());
+            };
+            let Some(packet) = packet else {
+                if self.ack {
+                    conn.write_all(b"-")?;
+                    conn.flush()?;
+                }
+                continue;
+            };
+            if self.ack {
+                conn.write_all(b"+")?;
+            }
+            tracing::trace!("gdb <- {}", packet);
+            match self.handle(&packet, conn)? {
+                Action::Reply(reply) => self.send(conn, &reply)?,
+                Action::Close(reply) => {
+                    self.send(conn, &reply)?;
+                    return Ok(());
+                }
+                Action::Kill => return Ok(()),
+            }
+        }
+    }
+
+    fn send<C: Connection>(&mut self, conn: &mut C, reply: &str) -> io::Result<()> {
+        tracing::trace!("gdb -> {}", reply);
+        self.last_sent = format!("${}#{:02x}", reply, checksum(reply.as_bytes())).into_bytes();
+        conn.write_all(&self.last_sent)?;
+        conn.flush()
+    }
+
+    fn handle<C: Connection>(&mut self, packet: &str, conn: &mut C) -> io::Result<Action> {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => "S05".to_string(),
+            Some(b'g') => self.read_registers(),
+            Some(b'G') => self.write_registers(&packet[1..]),
+            Some(b'p') => self.read_register(&packet[1..]),
+            Some(b'P') => self.write_register(&packet[1..]),
+            Some(b'm') => self.read_memory(&packet[1..]),
+            Some(b'M') => self.write_memory(&packet[1..]),
+            Some(b's') => self.step(),
+            Some(b'c') => self.resume(conn)?,
+            Some(b'Z') => self.set_break(&packet[1..], true),
+            Some(b'z') => self.set_break(&packet[1..], false),
+            Some(b'b') if packet == "bs" => self.reverse_step(),
+            Some(b'b') if packet == "bc" => self.reverse_continue(),
+            Some(b'H') | Some(b'T') => "OK".to_string(),
+            Some(b'D') => return Ok(Action::Close("OK".to_string())),
+            Some(b'k') => return Ok(Action::Kill),
+            Some(b'v') => return self.handle_v(packet, conn),
+            Some(b'q') | Some(b'Q') => self.handle_query(packet),
+            _ => String::new(),
+        };
+        Ok(Action::Reply(reply))
+    }
+
+    fn handle_v<C: Connection>(&mut self, packet: &str, conn: &mut C) -> io::Result<Action> {
+        let reply = match packet {
+            "vCont?" => "vCont;c;s".to_string(),
+            "vKill" | "vKill;1" => return Ok(Action::Close("OK".to_string())),
+            _ => match packet.strip_prefix("vCont;") {
+                // One thread, so only the first action matters
+                Some(actions) => match actions.as_bytes().first() {
+                    Some(b'c') => self.resume(conn)?,
+                    Some(b's') => self.step(),
+                    _ => "E01".to_string(),
+                },
+                None => String::new(),
+            },
+        };
+        Ok(Action::Reply(reply))
+    }
+
+    fn handle_query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            let mut features = format!(
+                "PacketSize={:x};qXfer:features:read+;qXfer:memory-map:read+;QStartNoAckMode+",
+                PACKET_SIZE
+            );
+            if self.sys.rewind().is_some() {
+                features.push_str(";ReverseStep+;ReverseContinue+");
+            }
+            return features;
+        }
+        if let Some(request) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return xfer(&target_xml(), request);
+        }
+        if let Some(request) = packet.strip_prefix("qXfer:memory-map:read::") {
+            return xfer(&memory_map_xml(), request);
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.ack = false;
+                "OK".to_string()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn register_value(&self, regnum: usize) -> Option<u16> {
+        let registers = &self.sys.cpu.registers;
+        Some(match regnum {
+            0 => self.sys.pc(),
+            1 => self.sys.accumulator() as u16,
+            2 => self.sys.carry() as u16,
+            3..=18 => self.sys.register(regnum as u8 - 3) as u16,
+            19 => registers.stack_pointer() as u16,
+            20..=22 => registers.stack()[regnum - 20],
+            _ => return None,
+        })
+    }
+
+    /// Bytes of register `regnum` on the wire
+    fn register_size(regnum: usize) -> usize {
+        match regnum {
+            0 | 20..=22 => 2,
+            _ => 1,
+        }
+    }
+
+    /// Set a register; the stack pointer and stack slots are read-only
+    fn set_register_value(&mut self, regnum: usize, value: u16) -> bool {
+        let cpu = &mut self.sys.cpu;
+        match regnum {
+            0 => cpu.registers.set_pc(value),
+            1 => cpu.alu.set_accumulator(value as u8),
+            2 => cpu.alu.set_carry(value & 1 != 0),
+            3..=18 => cpu.registers.set_r(regnum as u8 - 3, value as u8),
+            _ => return false,
+        }
+        true
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTER_COUNT)
+            .map(|regnum| encode_register(regnum, self.register_value(regnum).unwrap_or(0)))
+            .collect()
+    }
+
+    fn write_registers(&mut self, data: &str) -> String {
+        let Some(bytes) = decode_hex(data) else {
+            return "E01".to_string();
+        };
+        let mut offset = 0;
+        for regnum in 0..REGISTER_COUNT {
+            let size = Self::register_size(regnum);
+            let Some(raw) = bytes.get(offset..offset + size) else {
+                break;
+            };
+            let value = raw.iter().rev().fold(0u16, |acc, &b| acc << 8 | b as u16);
+            // Read-only registers come back unchanged from GDB
+            self.set_register_value(regnum, value);
+            offset += size;
+        }
+        "OK".to_string()
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        match usize::from_str_radix(args, 16)
+            .ok()
+            .and_then(|regnum| Some((regnum, self.register_value(regnum)?)))
+        {
+            Some((regnum, value)) => encode_register(regnum, value),
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let Some((regnum, data)) = args.split_once('=') else {
+            return "E01".to_string();
+        };
+        let (Ok(regnum), Some(bytes)) = (usize::from_str_radix(regnum, 16), decode_hex(data))
+        else {
+            return "E01".to_string();
+        };
+        let value = bytes.iter().rev().fold(0u16, |acc, &b| acc << 8 | b as u16);
+        if self.set_register_value(regnum, value) {
+            "OK".to_string()
+        } else {
+            "E01".to_string()
+        }
+    }
+
+    /// The 4002 and index a RAM-window address refers to
+    fn ram_slot(&self, address: u32) -> Option<(usize, u8, u8)> {
+        let offset = address.checked_sub(RAM_BASE).filter(|&o| o < RAM_SIZE)?;
+        let (bank, chip) = ((offset >> 9) as u8, (offset >> 7 & 3) as u8);
+        let (register, index) = ((offset >> 5 & 3) as u8, (offset & 0x1F) as u8);
+        let ram = self
+            .sys
+            .ram
+            .iter()
+            .position(|r| r.bank_id == bank && r.chip_id == chip)?;
+        Some((ram, register, index))
+    }
+
+    fn read_byte(&self, address: u32) -> u8 {
+        if address < 0x1000 {
+            return self.sys.rom_image()[address as usize];
+        }
+        match self.ram_slot(address) {
+            Some((ram, register, index @ 0..=15)) => self.sys.ram[ram].read_direct(register, index),
+            Some((ram, register, index @ 16..=19)) => {
+                self.sys.ram[ram].read_status(register, index - 16)
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_byte(&mut self, address: u32, value: u8) -> bool {
+        if address < 0x1000 {
+            let page = (address >> 8) as u8;
+            if !self.sys.rom.iter().any(|r| r.chip_id == page) {
+                return false;
+            }
+            self.sys.load_rom_at(address as u16, &[value]);
+            return true;
+        }
+        match self.ram_slot(address) {
+            Some((ram, register, index @ 0..=15)) => {
+                self.sys.ram[ram].write_direct(register, index, value)
+            }
+            Some((ram, register, index @ 16..=19)) => {
+                self.sys.ram[ram].write_status(register, index - 16, value)
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Reply to `m`, cut short to fit one packet as the protocol allows
+    fn read_memory(&self, args: &str) -> String {
+        let Some((address, length)) = parse_address_length(args) else {
+            return "E01".to_string();
+        };
+        let length = length.min((PACKET_SIZE / 2) as u32);
+        (address..address.saturating_add(length))
+            .map(|a| format!("{:02x}", self.read_byte(a)))
+            .collect()
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let Some((range, data)) = args.split_once(':') else {
+            return "E01".to_string();
+        };
+        let (Some((address, length)), Some(bytes)) =
+            (parse_address_length(range), decode_hex(data))
+        else {
+            return "E01".to_string();
+        };
+        if bytes.len() != length as usize {
+            return "E01".to_string();
+        }
+        for (offset, byte) in bytes.into_iter().enumerate() {
+            if !self.write_byte(address + offset as u32, byte) {
+                return "E02".to_string();
+            }
+        }
+        "OK".to_string()
+    }
+
+    fn step(&mut self) -> String {
+        self.sys.step_instruction();
+        "S05".to_string()
+    }
+
+    /// Run until a break triggers or the debugger interrupts
+    fn resume<C: Connection>(&mut self, conn: &mut C) -> io::Result<String> {
+        loop {
+            if let Some(stop) = self.sys.run_until_break(CONTINUE_SLICE) {
+                return Ok(self.stop_reply(stop.id, &stop.event));
+            }
+            if conn.interrupt_requested()? {
+                return Ok("S02".to_string());
+            }
+        }
+    }
+
+    fn stop_reply(&self, id: BreakId, event: &BreakEvent) -> String {
+        let watch = self
+            .watchpoints
+            .iter()
+            .find(|(_, &watch_id)| watch_id == id)
+            .map(|(&key, _)| key);
+        match (event, watch) {
+            (BreakEvent::Watch { .. }, Some((kind, address))) => {
+                let name = match kind {
+                    2 => "watch",
+                    3 => "rwatch",
+                    _ => "awatch",
+                };
+                format!("T05{}:{:x};", name, address)
+            }
+            _ => "S05".to_string(),
+        }
+    }
+
+    fn reverse_step(&mut self) -> String {
+        if self.sys.step_back() {
+            "S05".to_string()
+        } else {
+            "T05replaylog:begin;".to_string()
+        }
+    }
+
+    fn reverse_continue(&mut self) -> String {
+        if self.sys.rewind().is_none() {
+            return "E01".to_string();
+        }
+        if self.sys.run_back_to_breakpoint() {
+            "S05".to_string()
+        } else {
+            "T05replaylog:begin;".to_string()
+        }
+    }
+
+    /// `Z`/`z` packets: `type,address,kind`
+    fn set_break(&mut self, args: &str, insert: bool) -> String {
+        let mut fields = args.split(',');
+        let (Some(kind), Some(address)) = (
+            fields.next().and_then(|f| f.parse::<u8>().ok()),
+            fields.next().and_then(|f| u32::from_str_radix(f, 16).ok()),
+        ) else {
+            return "E01".to_string();
+        };
+        match kind {
+            // Software and hardware breakpoints are the same to us
+            0 | 1 if address < 0x1000 => {
+                if insert {
+                    self.sys.add_breakpoint(address as u16);
+                } else {
+                    self.sys.remove_breakpoint(address as u16);
+                }
+                "OK".to_string()
+            }
+            2..=4 => {
+                let access = match kind {
+                    2 => Access::Write,
+                    3 => Access::Read,
+                    _ => Access::Any,
+                };
+                self.set_watchpoint(kind, address, access, insert)
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn set_watchpoint(&mut self, kind: u8, address: u32, access: Access, insert: bool) -> String {
+        if !insert {
+            if let Some(id) = self.watchpoints.remove(&(kind, address)) {
+                self.sys.debugger_mut().remove(id);
+            }
+            return "OK".to_string();
+        }
+        let Some((ram, register, index)) = self.ram_slot(address) else {
+            return "E01".to_string();
+        };
+        let (bank, chip) = (self.sys.ram[ram].bank_id, self.sys.ram[ram].chip_id);
+        let location = match (register, index) {
+            (_, 0..=15) => RamLocation::Character {
+                bank,
+                chip,
+                register,
+                character: index,
+            },
+            (_, 16..=19) => RamLocation::Status {
+                bank,
+                chip,
+                register,
+                index: index - 16,
+            },
+            _ => return "E01".to_string(),
+        };
+        if !self.watchpoints.contains_key(&(kind, address)) {
+            let id = self.sys.debugger_mut().add(Break::watch(location, access));
+            self.watchpoints.insert((kind, address), id);
+        }
+        "OK".to_string()
+    }
+}
+
+/// Read one byte; `None` at end of stream
+fn read_byte<R: Read>(conn: &mut R) -> io::Result<Option<u8>> {
+    let mut byte = [0u8];
+    loop {
+        match conn.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Read the rest of a packet after `$`
+///
+/// Returns `None` at end of stream and `Some(None)` for a bad checksum.
+fn read_packet<R: Read>(conn: &mut R) -> io::Result<Option<Option<String>>> {
+    let mut data = Vec::new();
+    loop {
+        match read_byte(conn)? {
+            None => return Ok(None),
+            Some(b'#') => break,
+            Some(byte) => data.push(byte),
+        }
+    }
+    let mut sum = [0u8; 2];
+    for digit in &mut sum {
+        match read_byte(conn)? {
+            Some(byte) => *digit = byte,
+            None => return Ok(None),
+        }
+    }
+    let expected = std::str::from_utf8(&sum)
+        .ok()
+        .and_then(|s| u8::from_str_radix(s, 16).ok());
+    if expected != Some(checksum(&data)) {
+        return Ok(Some(None));
+    }
+    Ok(Some(Some(
+        String::from_utf8_lossy(&unescape(&data)).into_owned(),
+    )))
+}
+
+/// Undo the `}` escaping of binary packet data
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => out.extend(bytes.next().map(|b| b ^ 0x20)),
+            _ => out.push(byte),
+        }
+    }
+    out
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn decode_hex(data: &str) -> Option<Vec<u8>> {
+    if !data.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..data.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(data.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn encode_register(regnum: usize, value: u16) -> String {
+    value.to_le_bytes()[..GdbStub::register_size(regnum)]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// `address,length` in hex
+fn parse_address_length(args: &str) -> Option<(u32, u32)> {
+    let (address, length) = args.split_once(',')?;
+    Some((
+        u32::from_str_radix(address, 16).ok()?,
+        u32::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+/// Answer a `qXfer` read of `document` at `offset,length`
+fn xfer(document: &str, request: &str) -> String {
+    let Some((offset, length)) = parse_address_length(request) else {
+        return "E01".to_string();
+    };
+    let bytes = document.as_bytes();
+    let start = (offset as usize).min(bytes.len());
+    let end = start.saturating_add(length as usize).min(bytes.len());
+    let marker = if end == bytes.len() { 'l' } else { 'm' };
+    format!("{}{}", marker, String::from_utf8_lossy(&bytes[start..end]))
+}
+
+fn target_xml() -> String {
+    let mut regs = String::new();
+    regs.push_str("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\" regnum=\"0\"/>");
+    regs.push_str("<reg name=\"acc\" bitsize=\"8\" type=\"uint8\"/>");
+    regs.push_str("<reg name=\"cy\" bitsize=\"8\" type=\"uint8\"/>");
+    for r in 0..16 {
+        regs.push_str(&format!(
+            "<reg name=\"r{}\" bitsize=\"8\" type=\"uint8\"/>",
+            r
+        ));
+    }
+    regs.push_str("<reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>");
+    for slot in 0..3 {
+        regs.push_str(&format!(
+            "<reg name=\"stack{}\" bitsize=\"16\" type=\"code_ptr\"/>",
+            slot
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><feature name=\"org.mcs4.cpu\">{}</feature></target>",
+        regs
+    )
+}
+
+fn memory_map_xml() -> String {
+    format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE memory-map PUBLIC \"+//IDN gnu.org//DTD GDB Memory Map V1.0//EN\" \
+         \"http://sourceware.org/gdb/gdb-memory-map.dtd\"><memory-map>\
+         <memory type=\"rom\" start=\"0x0\" length=\"0x1000\"/>\
+         <memory type=\"ram\" start=\"0x{:x}\" length=\"0x{:x}\"/></memory-map>",
+        RAM_BASE, RAM_SIZE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::rewind::RewindConfig;
+
+    /// A scripted debugger: bytes to send and everything the stub wrote
+    #[derive(Default)]
+    struct Script {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+    }
+
+    impl Script {
+        fn new(packets: &[&str]) -> Self {
+            let mut script = Self::default();
+            for packet in packets {
+                script.push(packet);
+            }
+            script
+        }
+
+        /// Queue a packet, preceded by the ack for the previous reply
+        fn push(&mut self, packet: &str) {
+            if packet.as_bytes() == [INTERRUPT] {
+                self.input.push_back(INTERRUPT);
+                return;
+            }
+            let framed = format!("+${}#{:02x}", packet, checksum(packet.as_bytes()));
+            self.input.extend(framed.bytes());
+        }
+
+        /// Packets the stub sent, checksums verified
+        fn replies(&self) -> Vec<String> {
+            let text = String::from_utf8(self.output.clone()).unwrap();
+            text.split('$')
+                .skip(1)
+                .map(|framed| {
+                    let (data, sum) = framed.split_once('#').unwrap();
+                    let sum = u8::from_str_radix(&sum[..2], 16).unwrap();
+                    assert_eq!(sum, checksum(data.as_bytes()), "{}", data);
+                    data.to_string()
+                })
+                .collect()
+        }
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.input.pop_front(), buf.first_mut()) {
+                (Some(byte), Some(slot)) => {
+                    *slot = byte;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Script {
+        fn interrupt_requested(&mut self) -> io::Result<bool> {
+            Ok(self.input.front() == Some(&INTERRUPT) && self.input.pop_front().is_some())
+        }
+    }
+
+    fn run(stub: &mut GdbStub, packets: &[&str]) -> Vec<String> {
+        let mut script = Script::new(packets);
+        stub.serve(&mut script).unwrap();
+        script.replies()
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let mut sys = Mcs4System::minimal();
+        // LDM 5; XCH R3; STC
+        sys.load_rom(&[0xD5, 0xB3, 0xFA]);
+        let mut stub = GdbStub::new(sys);
+        let replies = run(
+            &mut stub,
+            &[
+                "qSupported:multiprocess+;swbreak+",
+                "qXfer:features:read:target.xml:0,1000",
+                "?",
+                "s",
+                "s",
+                "s",
+                "g",
+                "p1",
+                "P1=09",
+                "p6",
+                "P14=01",
+                "m0,3",
+                "M10000,2:0307",
+                "M10010,1:0c",
+                "M10051,1:0a",
+                "m10000,2",
+                "M2000,1:00",
+                "m0,ffffffff",
+                "D",
+            ],
+        );
+        assert!(replies[0].contains("qXfer:features:read+"));
+        assert!(!replies[0].contains("ReverseStep"));
+        assert!(replies[1].starts_with('l'));
+        assert!(replies[1].contains("<reg name=\"r15\" bitsize=\"8\""));
+        assert_eq!(&replies[2..6], ["S05", "S05", "S05", "S05"]);
+        // pc=0x003, acc=0, cy=1, r3=5, sp=0, empty stack
+        let expected = format!(
+            "0300{}{}{}{}{}",
+            "0001",
+            "000000",
+            "05",
+            "00".repeat(12),
+            "00".to_string() + &"0000".repeat(3)
+        );
+        assert_eq!(replies[6], expected);
+        assert_eq!(&replies[7..9], ["00", "OK"]);
+        assert_eq!(replies[9], "05");
+        // The stack pointer is read-only
+        assert_eq!(replies[10], "E01");
+        assert_eq!(replies[11], "d5b3fa");
+        assert_eq!(&replies[12..15], ["OK", "OK", "OK"]);
+        assert_eq!(replies[15], "0307");
+        // No 4001 on page 0x2
+        assert_eq!(replies[16], "E02");
+        // Long reads are cut to one packet
+        assert_eq!(replies[17].len(), 0x1000);
+        assert_eq!(replies[18], "OK");
+
+        let sys = stub.into_system();
+        assert_eq!(sys.accumulator(), 9);
+        assert_eq!(sys.read_ram(0, 0, 0, 1), Some(7));
+        assert_eq!(sys.ram[0].read_status(0, 0), 0xC);
+        // Register 2, status 1
+        assert_eq!(sys.ram[0].read_status(2, 1), 0xA);
+        assert_eq!(sys.ram[0].read_status(0, 1), 0);
+    }
+
+    #[test]
+    fn test_breakpoints_watchpoints_and_interrupt() {
+        let mut sys = Mcs4System::minimal();
+        // 0x000 FIM P0,0x05; SRC P0; LDM 7; WRM; 0x005 JUN 0x005
+        sys.load_rom(&[0x20, 0x05, 0x21, 0xD7, 0xE0, 0x40, 0x05]);
+        let mut stub = GdbStub::new(sys);
+        let replies = run(
+            &mut stub,
+            &[
+                "Z0,3,1",
+                "c",
+                "p0",
+                "z0,3,1",
+                "Z2,10005,1",
+                "vCont;c",
+                "p0",
+                "z2,10005,1",
+                "c",
+                "\u{3}",
+                "p0",
+                "k",
+            ],
+        );
+        assert_eq!(&replies[..3], ["OK", "S05", "0300"]);
+        // Stops after the WRM that wrote character 5
+        assert_eq!(&replies[3..7], ["OK", "OK", "T05watch:10005;", "0500"]);
+        assert_eq!(replies[7], "OK");
+        assert_eq!(replies[8], "S02");
+        assert_eq!(replies[9], "0500");
+        assert_eq!(replies.len(), 10);
+        assert!(stub.system().debugger().breaks().is_empty());
+    }
+
+    #[test]
+    fn test_framing() {
+        let mut stub = GdbStub::new(Mcs4System::minimal());
+        let mut script = Script::default();
+        // Bad checksum, then a resend request, then no-ack mode
+        script.input.extend(b"$?#00".iter());
+        script.push("?");
+        script.input.push_back(b'-');
+        script.push("QStartNoAckMode");
+        script.input.extend(b"$qAttached#8f".iter());
+        stub.serve(&mut script).unwrap();
+        let text = String::from_utf8(script.output.clone()).unwrap();
+        assert_eq!(text, "-+$S05#b8$S05#b8+$OK#9a$1#31");
+    }
+
+    #[test]
+    fn test_reverse_step() {
+        let mut sys = Mcs4System::minimal();
+        sys.load_rom(&[0xD5, 0xF2, 0xF2]);
+        sys.enable_rewind(RewindConfig::default());
+        let mut stub = GdbStub::new(sys);
+        let replies = run(
+            &mut stub,
+            &["qSupported", "s", "s", "bs", "p0", "p1", "bs", "bs"],
+        );
+        assert!(replies[0].contains("ReverseStep+;ReverseContinue+"));
+        assert_eq!(&replies[3..6], ["S05", "0100", "05"]);
+        assert_eq!(replies[6], "S05");
+        assert_eq!(replies[7], "T05replaylog:begin;");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket_session() {
+        let (mut server, mut client) = UnixStream::pair().unwrap();
+        let handle = std::thread::spawn(move || {
+            let mut sys = Mcs4System::minimal();
+            sys.load_rom(&[0xD3]);
+            let mut stub = GdbStub::new(sys);
+            stub.serve(&mut server).unwrap();
+            stub.into_system()
+        });
+        client.write_all(b"$s#73").unwrap();
+        let mut reply = [0u8; 8];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"+$S05#b8");
+        client.write_all(b"+$D#44").unwrap();
+        let mut reply = [0u8; 7];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"+$OK#9a");
+        drop(client);
+        assert_eq!(handle.join().unwrap().accumulator(), 3);
+    }
+}
@@ -1,7 +1,36 @@
 //! Complete MCS-4/MCS-40 System Assembly
 
+pub mod board;
+pub mod busicom;
+pub mod dap;
+pub mod debugger;
+pub mod gdb;
+pub mod loader;
+pub mod lockstep;
 pub mod mcs4;
 pub mod mcs40;
+pub mod peripheral;
+pub mod rewind;
+pub mod runner;
+pub mod snapshot;
 
-pub use mcs4::Mcs4System;
+#[cfg(test)]
+mod test_util;
+
+pub use board::{BoardConfig, BoardError};
+pub use busicom::{Busicom, BusicomIo, DrumTiming, Key, PrintedLine};
+pub use dap::{DapServer, LineMap};
+pub use debugger::{
+    Access, Break, BreakEvent, BreakId, BreakKind, Condition, Debugger, RamLocation, Step,
+    StopReason,
+};
+pub use gdb::{Connection, GdbStub};
+pub use loader::{LoadError, RomFormat, RomImage};
+pub use lockstep::{Divergence, Lockstep};
+
+pub use mcs4::{ExecutionMode, Mcs4System};
 pub use mcs40::Mcs40System;
+pub use peripheral::{Peripheral, Port, PortBit, PortProbe, Ports, ShiftRegister, Switches};
+pub use rewind::{RewindBuffer, RewindConfig};
+pub use runner::{RunOptions, RunOutcome, Stimulus, StimulusError};
+pub use snapshot::{snapshot_export, snapshot_import, SnapshotError, SystemSnapshot};
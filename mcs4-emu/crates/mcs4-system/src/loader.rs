@@ -0,0 +1,429 @@
+//! ROM image loaders
+//!
+//! Parses Intel HEX, Motorola S-record and raw binary files into a
+//! [`RomImage`]: a sparse map from program address to byte. Records past
+//! [`PROGRAM_SPACE`] are rejected while parsing. Systems place the image
+//! into their ROM chips with `load_image`, which checks that every byte
+//! lands on a fitted chip.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Size of the largest program address space, the 4040's two 4K banks;
+/// images reaching past it are rejected while loading
+pub const PROGRAM_SPACE: u32 = 0x2000;
+
+/// Error raised while reading, parsing or placing a ROM image
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file could not be read
+    Io(io::Error),
+    /// A record is malformed
+    Syntax { line: usize, message: String },
+    /// A record's checksum does not match its contents
+    Checksum { line: usize, expected: u8, found: u8 },
+    /// A byte falls outside the CPU's program address space
+    AddressOutOfRange { address: u32, limit: u32 },
+    /// No ROM chip is fitted for the page a byte falls in
+    MissingRom { address: u32, page: u8 },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "cannot read ROM image: {}", err),
+            LoadError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            LoadError::Checksum { line, expected, found } => write!(
+                f,
+                "line {}: checksum mismatch (record says 0x{:02X}, computed 0x{:02X})",
+                line, found, expected
+            ),
+            LoadError::AddressOutOfRange { address, limit } => write!(
+                f,
+                "address 0x{:X} is outside program memory (0x000-0x{:X})",
+                address,
+                limit - 1
+            ),
+            LoadError::MissingRom { address, page } => write!(
+                f,
+                "address 0x{:03X} needs a ROM chip for page {:X}, but none is fitted",
+                address, page
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+/// On-disk ROM image format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RomFormat {
+    /// Intel HEX (`.hex`, `.ihx`)
+    IntelHex,
+    /// Motorola S-record (`.s19`, `.s28`, `.s37`, `.srec`, `.mot`)
+    SRecord,
+    /// Raw bytes starting at address 0
+    Binary,
+}
+
+impl RomFormat {
+    /// Guess the format from a file extension; anything unknown is binary
+    pub fn from_path(path: &Path) -> Self {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match ext.as_deref() {
+            Some("hex" | "ihx" | "ihex") => RomFormat::IntelHex,
+            Some("s19" | "s28" | "s37" | "srec" | "mot") => RomFormat::SRecord,
+            _ => RomFormat::Binary,
+        }
+    }
+}
+
+/// Program bytes keyed by address
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RomImage {
+    bytes: BTreeMap<u32, u8>,
+}
+
+impl RomImage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a file, picking the format from its extension
+    pub fn from_file(path: &Path) -> Result<Self, LoadError> {
+        Self::from_file_as(path, RomFormat::from_path(path))
+    }
+
+    /// Read a file in the given format
+    pub fn from_file_as(path: &Path, format: RomFormat) -> Result<Self, LoadError> {
+        let data = fs::read(path)?;
+        Self::parse(&data, format)
+    }
+
+    /// Parse file contents in the given format
+    pub fn parse(data: &[u8], format: RomFormat) -> Result<Self, LoadError> {
+        match format {
+            RomFormat::Binary if data.len() > PROGRAM_SPACE as usize => {
+                Err(LoadError::AddressOutOfRange {
+                    address: data.len() as u32 - 1,
+                    limit: PROGRAM_SPACE,
+                })
+            }
+            RomFormat::Binary => Ok(Self::from_binary(data, 0)),
+            RomFormat::IntelHex => Self::from_intel_hex(&text(data)?),
+            RomFormat::SRecord => Self::from_srec(&text(data)?),
+        }
+    }
+
+    /// Raw bytes placed from `base` upwards
+    pub fn from_binary(data: &[u8], base: u32) -> Self {
+        let mut image = Self::new();
+        for (i, &byte) in data.iter().enumerate() {
+            image.set(base + i as u32, byte);
+        }
+        image
+    }
+
+    /// Parse Intel HEX, honouring extended segment/linear address records
+    pub fn from_intel_hex(text: &str) -> Result<Self, LoadError> {
+        let mut image = Self::new();
+        let mut base = 0u32;
+
+        for (index, raw) in text.lines().enumerate() {
+            let line = index + 1;
+            let raw = raw.trim();
+            if raw.is_empty() {
+                continue;
+            }
+            let body = raw.strip_prefix(':').ok_or_else(|| LoadError::Syntax {
+                line,
+                message: "record does not start with ':'".into(),
+            })?;
+            let bytes = hex_bytes(body, line)?;
+            if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+                return Err(LoadError::Syntax { line, message: "record length mismatch".into() });
+            }
+            check_sum(&bytes, line, |sum| sum.wrapping_neg())?;
+
+            let data = &bytes[4..bytes.len() - 1];
+            let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+            match bytes[3] {
+                0x00 => image.set_record(base as u64 + offset as u64, data)?,
+                0x01 => break,
+                0x02 => base = (u16_field(data, line)? as u32) << 4,
+                0x04 => base = (u16_field(data, line)? as u32) << 16,
+                // Start addresses don't apply to a 4004/4040: execution starts at 0
+                0x03 | 0x05 => {}
+                kind => {
+                    return Err(LoadError::Syntax {
+                        line,
+                        message: format!("unknown record type 0x{:02X}", kind),
+                    })
+                }
+            }
+        }
+        Ok(image)
+    }
+
+    /// Parse Motorola S-records (S1/S2/S3 data, other records skipped)
+    pub fn from_srec(text: &str) -> Result<Self, LoadError> {
+        let mut image = Self::new();
+
+        for (index, raw) in text.lines().enumerate() {
+            let line = index + 1;
+            let raw = raw.trim();
+            if raw.is_empty() {
+                continue;
+            }
+            let mut chars = raw.chars();
+            let kind = match (chars.next(), chars.next()) {
+                (Some('S'), Some(kind)) if kind.is_ascii_digit() => kind,
+                _ => {
+                    return Err(LoadError::Syntax {
+                        line,
+                        message: "record does not start with 'S<type>'".into(),
+                    })
+                }
+            };
+            let bytes = hex_bytes(&raw[2..], line)?;
+            if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+                return Err(LoadError::Syntax { line, message: "record length mismatch".into() });
+            }
+            check_sum(&bytes, line, |sum| !sum)?;
+
+            let addr_len = match kind {
+                '1' => 2,
+                '2' => 3,
+                '3' => 4,
+                '0' | '5' | '6' | '7' | '8' | '9' => continue,
+                _ => {
+                    return Err(LoadError::Syntax {
+                        line,
+                        message: format!("unknown record type S{}", kind),
+                    })
+                }
+            };
+            if bytes.len() < addr_len + 2 {
+                return Err(LoadError::Syntax { line, message: "record too short".into() });
+            }
+            let address = bytes[1..=addr_len]
+                .iter()
+                .fold(0u32, |acc, &b| (acc << 8) | b as u32);
+            image.set_record(address as u64, &bytes[addr_len + 1..bytes.len() - 1])?;
+        }
+        Ok(image)
+    }
+
+    /// Place a data record at `address`, which must fit the program space
+    fn set_record(&mut self, address: u64, data: &[u8]) -> Result<(), LoadError> {
+        let end = address + data.len() as u64;
+        if end > PROGRAM_SPACE as u64 {
+            return Err(LoadError::AddressOutOfRange {
+                address: (end - 1).min(u32::MAX as u64) as u32,
+                limit: PROGRAM_SPACE,
+            });
+        }
+        for (i, &byte) in data.iter().enumerate() {
+            self.set(address as u32 + i as u32, byte);
+        }
+        Ok(())
+    }
+
+    /// Set the byte at an address
+    pub fn set(&mut self, address: u32, value: u8) {
+        self.bytes.insert(address, value);
+    }
+
+    /// Byte at an address, if the image defines one
+    pub fn get(&self, address: u32) -> Option<u8> {
+        self.bytes.get(&address).copied()
+    }
+
+    /// Number of bytes defined
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Does the image define no bytes at all?
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Highest address defined, if any
+    pub fn end_address(&self) -> Option<u32> {
+        self.bytes.keys().next_back().copied()
+    }
+
+    /// Iterate over (address, byte) in address order
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u8)> + '_ {
+        self.bytes.iter().map(|(&a, &b)| (a, b))
+    }
+
+    /// Contiguous bytes from address 0 to the end of the image, gaps zeroed
+    ///
+    /// Bytes [`set`](Self::set) past [`PROGRAM_SPACE`] are left out.
+    pub fn flatten(&self) -> Vec<u8> {
+        let bytes = self.bytes.range(..PROGRAM_SPACE);
+        let len = bytes.clone().next_back().map_or(0, |(&end, _)| end as usize + 1);
+        let mut flat = vec![0; len];
+        for (&address, &byte) in bytes {
+            flat[address as usize] = byte;
+        }
+        flat
+    }
+}
+
+fn text(data: &[u8]) -> Result<String, LoadError> {
+    String::from_utf8(data.to_vec()).map_err(|err| LoadError::Syntax {
+        line: 1 + data[..err.utf8_error().valid_up_to()].iter().filter(|&&b| b == b'\n').count(),
+        message: "file is not text".into(),
+    })
+}
+
+fn hex_bytes(digits: &str, line: usize) -> Result<Vec<u8>, LoadError> {
+    if !digits.is_ascii() {
+        return Err(LoadError::Syntax { line, message: "non-ASCII characters in record".into() });
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err(LoadError::Syntax { line, message: "odd number of hex digits".into() });
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| LoadError::Syntax {
+                line,
+                message: format!("invalid hex digits '{}'", &digits[i..i + 2]),
+            })
+        })
+        .collect()
+}
+
+/// Compare the last byte of a record with `finish(sum of the others)`
+fn check_sum(bytes: &[u8], line: usize, finish: impl Fn(u8) -> u8) -> Result<(), LoadError> {
+    let (found, body) = bytes.split_last().expect("record is not empty");
+    let expected = finish(body.iter().fold(0u8, |acc, &b| acc.wrapping_add(b)));
+    if expected != *found {
+        return Err(LoadError::Checksum { line, expected, found: *found });
+    }
+    Ok(())
+}
+
+fn u16_field(data: &[u8], line: usize) -> Result<u16, LoadError> {
+    match data {
+        [hi, lo] => Ok(u16::from_be_bytes([*hi, *lo])),
+        _ => Err(LoadError::Syntax { line, message: "address record needs 2 data bytes".into() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::SAMPLE_HEX;
+
+    #[test]
+    fn test_intel_hex_sample() {
+        let image = RomImage::from_intel_hex(SAMPLE_HEX).unwrap();
+        assert_eq!(image.len(), 170);
+        assert_eq!(image.end_address(), Some(0xA9));
+        assert_eq!(image.get(0x002), Some(0x40));
+        assert_eq!(image.get(0x0A9), Some(0xA3));
+    }
+
+    #[test]
+    fn test_intel_hex_extended_address() {
+        // Segment 0x0010 -> base 0x100, then linear 0x0000 -> base 0
+        let text = ":020000020010EC\n:02000400D5E045\n:020000040000FA\n:01000000F10E\n:00000001FF\n";
+        let image = RomImage::from_intel_hex(text).unwrap();
+        assert_eq!(image.get(0x104), Some(0xD5));
+        assert_eq!(image.get(0x105), Some(0xE0));
+        assert_eq!(image.get(0x000), Some(0xF1));
+    }
+
+    #[test]
+    fn test_intel_hex_errors() {
+        let empty = RomImage::from_intel_hex(":00000001FF\n").unwrap();
+        assert!(empty.is_empty());
+
+        match RomImage::from_intel_hex(":0100000000FE\n:01000000F10F\n") {
+            Err(LoadError::Checksum { line: 1, expected: 0xFF, found: 0xFE }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        match RomImage::from_intel_hex("0100000000FF\n") {
+            Err(LoadError::Syntax { line: 1, .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        match RomImage::from_intel_hex(":00000001FF\n:0200000000") {
+            // Records after EOF are ignored
+            Ok(image) => assert!(image.is_empty()),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_srec() {
+        // S1 at 0x0010, S2 at 0x000120, termination and header records
+        let text = "S00600004844521B\nS1060010D5E0F143\nS2060001200F00C9\nS9030000FC\n";
+        let image = RomImage::from_srec(text).unwrap();
+        assert_eq!(image.get(0x010), Some(0xD5));
+        assert_eq!(image.get(0x012), Some(0xF1));
+        assert_eq!(image.get(0x121), Some(0x00));
+        assert_eq!(image.len(), 5);
+
+        match RomImage::from_srec("S1060010D5E0F144\n") {
+            Err(LoadError::Checksum { line: 1, .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_address_past_program_space() {
+        // Linear base 0xFFFF0000, one byte at 0xFFFFFFFF
+        let text = ":02000004FFFFFC\n:01FFFF00D52C\n";
+        match RomImage::from_intel_hex(text) {
+            Err(LoadError::AddressOutOfRange { address: 0xFFFF_FFFF, limit: PROGRAM_SPACE }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        // S3 record at 0xFFFFFFF0
+        match RomImage::from_srec("S306FFFFFFF0D537\n") {
+            Err(LoadError::AddressOutOfRange { address: 0xFFFF_FFF0, .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        // The last byte of the 4040's second bank still fits
+        let image = RomImage::from_srec("S30600001FFFD506\n").unwrap();
+        assert_eq!(image.flatten().len(), 0x2000);
+        assert!(RomImage::parse(&[0; 0x2001], RomFormat::Binary).is_err());
+
+        let mut image = RomImage::new();
+        image.set(0xFFFF_FFFF, 0xD5);
+        assert!(image.flatten().is_empty());
+    }
+
+    #[test]
+    fn test_binary_and_format() {
+        let image = RomImage::parse(&[0xD5, 0x00, 0xF1], RomFormat::Binary).unwrap();
+        assert_eq!(image.flatten(), vec![0xD5, 0x00, 0xF1]);
+
+        assert_eq!(RomFormat::from_path(Path::new("a.HEX")), RomFormat::IntelHex);
+        assert_eq!(RomFormat::from_path(Path::new("a.s19")), RomFormat::SRecord);
+        assert_eq!(RomFormat::from_path(Path::new("a.bin")), RomFormat::Binary);
+    }
+}
@@ -0,0 +1,294 @@
+//! Lockstep differential checking of the two execution modes
+//!
+//! [`Lockstep`] runs a system phase by phase next to a copy of it in
+//! [`ExecutionMode::Instruction`], one instruction at a time, and compares
+//! the architectural state after every instruction: PC, ACC, carry, the 16
+//! index registers, the stack, the DCL bank, every 4002 character, status
+//! character and output port, the 4001 output ports and the cycle count.
+//! The first difference is reported as a [`Divergence`] with a disassembly
+//! of the instructions leading up to it.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use mcs4_chips::disasm::{CpuType, DisasmLine, Disassembler};
+
+use crate::mcs4::{ExecutionMode, Mcs4System};
+
+/// Instructions kept for the context window of a divergence
+const CONTEXT: usize = 8;
+
+/// A piece of architectural state compared after each instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Pc,
+    Accumulator,
+    Carry,
+    Cycles,
+    /// Index register R0-R15
+    Register(u8),
+    StackPointer,
+    /// Stack slot 0-2
+    Stack(u8),
+    /// CM-RAM bank code set by DCL
+    RamBank,
+    RamCharacter { bank: u8, chip: u8, register: u8, character: u8 },
+    RamStatus { bank: u8, chip: u8, register: u8, index: u8 },
+    RamOutput { bank: u8, chip: u8 },
+    /// 4001 I/O port output latch
+    RomOutput { chip: u8 },
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Field::Pc => write!(f, "PC"),
+            Field::Accumulator => write!(f, "ACC"),
+            Field::Carry => write!(f, "CY"),
+            Field::Cycles => write!(f, "cycles"),
+            Field::Register(r) => write!(f, "R{}", r),
+            Field::StackPointer => write!(f, "SP"),
+            Field::Stack(slot) => write!(f, "stack[{}]", slot),
+            Field::RamBank => write!(f, "DCL bank"),
+            Field::RamCharacter { bank, chip, register, character } => write!(
+                f,
+                "RAM bank {} chip {} register {} character {}",
+                bank, chip, register, character
+            ),
+            Field::RamStatus { bank, chip, register, index } => write!(
+                f,
+                "RAM bank {} chip {} register {} status {}",
+                bank, chip, register, index
+            ),
+            Field::RamOutput { bank, chip } => write!(f, "RAM bank {} chip {} output", bank, chip),
+            Field::RomOutput { chip } => write!(f, "ROM {} port", chip),
+        }
+    }
+}
+
+/// The first point where the two models disagree
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// Instructions completed before the one that diverged
+    pub instruction: u64,
+    /// Address of the instruction that diverged
+    pub address: u16,
+    pub field: Field,
+    /// Value in the phase-accurate system
+    pub phase: u64,
+    /// Value in the instruction-level system
+    pub fast: u64,
+    /// The last few instructions executed, ending with the diverging one
+    pub context: Vec<DisasmLine>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "divergence after {} instructions at 0x{:03X}: {} is 0x{:X} phase-accurate, 0x{:X} instruction-level",
+            self.instruction, self.address, self.field, self.phase, self.fast
+        )?;
+        for (i, line) in self.context.iter().enumerate() {
+            let marker = if i + 1 == self.context.len() { '>' } else { ' ' };
+            writeln!(f, "{} {}", marker, line)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+/// A system run in both execution modes side by side
+pub struct Lockstep {
+    phase: Mcs4System,
+    fast: Mcs4System,
+    disasm: Disassembler,
+    /// Addresses of the most recent instructions, oldest first
+    history: VecDeque<u16>,
+    instructions: u64,
+}
+
+impl Lockstep {
+    /// Check `sys` against an instruction-level copy of itself
+    ///
+    /// Both start from the state `sys` is in, after it finishes any
+    /// instruction in flight.
+    pub fn new(mut sys: Mcs4System) -> Self {
+        sys.set_mode(ExecutionMode::PhaseAccurate);
+        let mut fast = sys.clone();
+        fast.set_mode(ExecutionMode::Instruction);
+        Self {
+            phase: sys,
+            fast,
+            disasm: Disassembler::new(CpuType::I4004),
+            history: VecDeque::with_capacity(CONTEXT),
+            instructions: 0,
+        }
+    }
+
+    /// Label the context window with `disasm`'s symbols
+    pub fn with_disassembler(mut self, disasm: Disassembler) -> Self {
+        self.disasm = disasm;
+        self
+    }
+
+    /// The phase-accurate system
+    pub fn phase(&self) -> &Mcs4System {
+        &self.phase
+    }
+
+    /// The instruction-level system
+    pub fn fast(&self) -> &Mcs4System {
+        &self.fast
+    }
+
+    /// Both systems, phase-accurate first, for applying the same stimulus
+    /// (TEST pin, ROM port inputs) to each
+    pub fn systems_mut(&mut self) -> [&mut Mcs4System; 2] {
+        [&mut self.phase, &mut self.fast]
+    }
+
+    /// Instructions run so far
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Run one instruction on both systems and compare them
+    pub fn step(&mut self) -> Result<(), Divergence> {
+        let address = self.phase.pc();
+        if self.history.len() == CONTEXT {
+            self.history.pop_front();
+        }
+        self.history.push_back(address);
+
+        self.phase.step_instruction();
+        self.fast.step_instruction();
+        let phase = architectural_state(&self.phase);
+        let fast = architectural_state(&self.fast);
+        if let Some(((field, phase), (_, fast))) =
+            phase.into_iter().zip(fast).find(|((_, a), (_, b))| a != b)
+        {
+            let rom = self.phase.rom_image();
+            return Err(Divergence {
+                instruction: self.instructions,
+                address,
+                field,
+                phase,
+                fast,
+                context: self.history.iter().map(|&a| self.disasm.disasm_one(rom, a)).collect(),
+            });
+        }
+        self.instructions += 1;
+        Ok(())
+    }
+
+    /// Run up to `max_instructions`, stopping at the first divergence
+    ///
+    /// Returns the total number of instructions run when none was found.
+    pub fn run(&mut self, max_instructions: u64) -> Result<u64, Divergence> {
+        for _ in 0..max_instructions {
+            self.step()?;
+        }
+        Ok(self.instructions)
+    }
+
+    /// Run until the phase-accurate system has used `cycles` more machine
+    /// cycles, stopping at the first divergence
+    pub fn run_cycles(&mut self, cycles: u64) -> Result<u64, Divergence> {
+        let end = self.phase.cycles() + cycles;
+        while self.phase.cycles() < end {
+            self.step()?;
+        }
+        Ok(self.instructions)
+    }
+}
+
+/// Every compared field with its value, in reporting order
+fn architectural_state(sys: &Mcs4System) -> Vec<(Field, u64)> {
+    let registers = &sys.cpu.registers;
+    let mut state = vec![
+        (Field::Pc, sys.pc() as u64),
+        (Field::Accumulator, sys.accumulator() as u64),
+        (Field::Carry, sys.carry() as u64),
+    ];
+    state.extend((0..16).map(|r| (Field::Register(r), registers.get_r(r) as u64)));
+    state.push((Field::StackPointer, registers.stack_pointer() as u64));
+    state.extend((0..3).map(|slot| (Field::Stack(slot), registers.stack()[slot as usize] as u64)));
+    state.push((Field::RamBank, sys.cpu.ram_bank() as u64));
+
+    for ram in &sys.ram {
+        let (bank, chip) = (ram.bank_id(), ram.chip_id());
+        for register in 0..4 {
+            state.extend((0..16).map(|character| {
+                let field = Field::RamCharacter { bank, chip, register, character };
+                (field, ram.read_direct(register, character) as u64)
+            }));
+            state.extend((0..4).map(|index| {
+                let field = Field::RamStatus { bank, chip, register, index };
+                (field, ram.read_status(register, index) as u64)
+            }));
+        }
+        state.push((Field::RamOutput { bank, chip }, ram.output() as u64));
+    }
+    state.extend(sys.rom.iter().map(|rom| {
+        (Field::RomOutput { chip: rom.chip_id() }, rom.io_output() as u64)
+    }));
+    state.push((Field::Cycles, sys.cycles()));
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sample_system;
+
+    #[test]
+    fn test_sample_runs_in_lockstep() {
+        let mut lockstep = Lockstep::new(sample_system());
+        assert_eq!(lockstep.run(500), Ok(500));
+        assert_eq!(lockstep.phase().cycles(), lockstep.fast().cycles());
+    }
+
+    #[test]
+    fn test_reports_first_divergence() {
+        let mut sys = Mcs4System::minimal();
+        // FIM P0, 0x00; SRC P0; LDM 9; WRM; LDM 3; ADD R0; JUN 0x000
+        sys.load_rom(&[0x20, 0x00, 0x21, 0xD9, 0xE0, 0xD3, 0x80, 0x40, 0x00]);
+        let mut lockstep = Lockstep::new(sys);
+        assert_eq!(lockstep.run(3), Ok(3));
+
+        // Knock R0 out of step; the check after WRM catches it
+        let [_, fast] = lockstep.systems_mut();
+        fast.cpu.registers.set_r(0, 1);
+        let err = lockstep.run(10).unwrap_err();
+        assert_eq!(err.instruction, 3);
+        assert_eq!(err.address, 0x004);
+        assert_eq!(err.field, Field::Register(0));
+        assert_eq!((err.phase, err.fast), (0, 1));
+        let context: Vec<u16> = err.context.iter().map(|line| line.address).collect();
+        assert_eq!(context, [0x000, 0x002, 0x003, 0x004]);
+
+        let report = err.to_string();
+        assert!(report.starts_with("divergence after 3 instructions at 0x004: R0 is 0x0"), "{report}");
+        assert!(report.ends_with("> 004  E0     WRM\n"), "{report}");
+    }
+
+    #[test]
+    fn test_ram_divergence() {
+        let mut sys = Mcs4System::standard();
+        // FIM P0, 0x47 (chip 1, register 0, char 7); SRC P0; LDM 5; WRM
+        sys.load_rom(&[0x20, 0x47, 0x21, 0xD5, 0xE0]);
+        let mut lockstep = Lockstep::new(sys);
+        lockstep.run(2).unwrap();
+        let [_, fast] = lockstep.systems_mut();
+        fast.ram[1].write_direct(0, 8, 0x3);
+
+        let err = lockstep.run(3).unwrap_err();
+        assert_eq!(err.address, 0x003);
+        let field = Field::RamCharacter { bank: 0, chip: 1, register: 0, character: 8 };
+        assert_eq!(err.field, field);
+        assert_eq!((err.phase, err.fast), (0, 3));
+        assert!(err.to_string().contains("RAM bank 0 chip 1 register 0 character 8"));
+    }
+}
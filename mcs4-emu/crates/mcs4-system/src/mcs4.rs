@@ -3,11 +3,51 @@
 //! Complete system integration for Intel MCS-4 architecture.
 //! Wires together CPU (4004), ROM (4001), and RAM (4002) chips
 //! with proper bus protocol timing.
+//!
+//! The system runs in one of two [`ExecutionMode`]s: phase by phase over
+//! the data bus, or one instruction at a time over a flat copy of the ROM
+//! with SRC and I/O going straight to the chip models. Both share the
+//! 4004's ALU, register file and instruction semantics and count the same
+//! machine cycles.
+//!
+//! With rewind enabled (see [`crate::rewind`]) the system can also step
+//! backwards: [`Mcs4System::step_back`], [`Mcs4System::run_back_to_breakpoint`]
+//! and [`Mcs4System::run_back_until`].
+//!
+//! Devices on the I/O ports attach as [`Peripheral`]s (see
+//! [`crate::peripheral`]) and are updated at every instruction boundary.
+
+use std::path::Path;
 
 use mcs4_bus::prelude::*;
-use mcs4_chips::{i4004::I4004, i4001::I4001, i4002::I4002};
+use mcs4_chips::stack_monitor::StackFault;
+use mcs4_chips::{i3205::I3205, i4004::I4004, i4001::I4001, i4002::I4002, InstructionBus};
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::debugger::{Break, BreakKind, Debugger, Step, StopReason};
+use crate::loader::{LoadError, RomImage};
+use crate::peripheral::{Peripheral, Ports};
+use crate::rewind::{RewindBuffer, RewindConfig};
+use crate::snapshot::SystemSnapshot;
+
+/// Size of the 4004 program space
+const ROM_SPACE: usize = 0x1000;
+
+/// How [`Mcs4System`] executes programs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+pub enum ExecutionMode {
+    /// Every bus phase through every chip
+    #[default]
+    PhaseAccurate,
+    /// One instruction per step over the flat ROM image; the bus and
+    /// control lines are left idle
+    Instruction,
+}
 
 /// Complete MCS-4 system
+#[derive(Clone, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct Mcs4System {
     /// 4004 CPU
     pub cpu: I4004,
@@ -15,9 +55,14 @@ pub struct Mcs4System {
     /// ROM chips (up to 16 x 4001 = 4KB)
     pub rom: Vec<I4001>,
 
-    /// RAM chips (up to 4 banks x 4 chips = 16 x 4002)
+    /// RAM chips (up to 4 banks x 4 chips = 16 x 4002, or 8 banks with a
+    /// bank decoder)
     pub ram: Vec<I4002>,
 
+    /// Optional 3205 decoding CM-RAM1-3 into banks 1-7 (bank 0 stays on
+    /// CM-RAM0); without it each bank is wired to CM-RAM line `bank_id`
+    pub ram_decoder: Option<I3205>,
+
     /// 4-bit bidirectional data bus
     pub bus: DataBus,
 
@@ -33,8 +78,25 @@ pub struct Mcs4System {
     /// Total machine cycles executed
     total_cycles: u64,
 
-    /// Breakpoint addresses (stop when PC matches)
-    breakpoints: Vec<u16>,
+    /// Breakpoints and watchpoints; debugger settings, not part of a
+    /// snapshot
+    #[with(rkyv::with::Skip)]
+    debugger: Debugger,
+
+    /// Phase-accurate or instruction-level execution
+    mode: ExecutionMode,
+
+    /// Flat copy of the 4001 contents for instruction-level fetches; pages
+    /// without a 4001 read as 0
+    rom_image: Box<[u8]>,
+
+    /// Execution history for stepping backwards, when enabled
+    #[with(rkyv::with::Skip)]
+    rewind: Option<Box<RewindBuffer>>,
+
+    /// Devices on the I/O ports; not part of a snapshot
+    #[with(rkyv::with::Skip)]
+    peripherals: Vec<Box<dyn Peripheral>>,
 }
 
 impl Mcs4System {
@@ -44,12 +106,17 @@ impl Mcs4System {
             cpu: I4004::new(),
             rom: vec![I4001::new(0)],
             ram: vec![I4002::new(0, 0)],
+            ram_decoder: None,
             bus: DataBus::new(),
             control: ControlSignals::mcs4(),
             clock: TwoPhaseClockTwoPhaseClock::default_config(),
             cycle: CycleState::new(),
             total_cycles: 0,
-            breakpoints: Vec::new(),
+            debugger: Debugger::default(),
+            mode: ExecutionMode::PhaseAccurate,
+            rom_image: vec![0; ROM_SPACE].into_boxed_slice(),
+            rewind: None,
+            peripherals: Vec::new(),
         }
     }
 
@@ -75,12 +142,17 @@ impl Mcs4System {
                 I4002::new(2, 1),
                 I4002::new(3, 1),
             ],
+            ram_decoder: None,
             bus: DataBus::new(),
             control: ControlSignals::mcs4(),
             clock: TwoPhaseClockTwoPhaseClock::default_config(),
             cycle: CycleState::new(),
             total_cycles: 0,
-            breakpoints: Vec::new(),
+            debugger: Debugger::default(),
+            mode: ExecutionMode::PhaseAccurate,
+            rom_image: vec![0; ROM_SPACE].into_boxed_slice(),
+            rewind: None,
+            peripherals: Vec::new(),
         }
     }
 
@@ -102,15 +174,32 @@ impl Mcs4System {
             cpu: I4004::new(),
             rom,
             ram,
+            ram_decoder: None,
             bus: DataBus::new(),
             control: ControlSignals::mcs4(),
             clock: TwoPhaseClockTwoPhaseClock::default_config(),
             cycle: CycleState::new(),
             total_cycles: 0,
-            breakpoints: Vec::new(),
+            debugger: Debugger::default(),
+            mode: ExecutionMode::PhaseAccurate,
+            rom_image: vec![0; ROM_SPACE].into_boxed_slice(),
+            rewind: None,
+            peripherals: Vec::new(),
         }
     }
 
+    /// Create a maximal system with a 3205 bank decoder (16 ROM, 8 RAM banks)
+    pub fn maximal_decoded() -> Self {
+        let mut sys = Self::maximal();
+        for bank in 4..8 {
+            for chip in 0..4 {
+                sys.ram.push(I4002::new(chip, bank));
+            }
+        }
+        sys.ram_decoder = Some(I3205::new());
+        sys
+    }
+
     /// Load program into ROM starting at address 0
     pub fn load_rom(&mut self, data: &[u8]) {
         // Distribute across ROM chips (256 bytes each)
@@ -119,6 +208,8 @@ impl Mcs4System {
                 self.rom[i].load(chunk);
             }
         }
+        self.sync_rom_image();
+        self.clear_rewind();
     }
 
     /// Load program at specific ROM address
@@ -129,8 +220,112 @@ impl Mcs4System {
             let chip_addr = addr & 0xFF;
             if let Some(rom) = self.rom.iter_mut().find(|r| r.chip_id == chip_id as u8) {
                 rom.load_at(chip_addr, &[byte]);
+                self.rom_image[addr & 0x0FFF] = byte;
+            }
+        }
+        self.clear_rewind();
+    }
+
+    /// Load a parsed ROM image into the 4001s
+    ///
+    /// Every byte must fall in the 4 KB program space on a page with a 4001
+    /// fitted; nothing is written unless the whole image fits.
+    pub fn load_image(&mut self, image: &RomImage) -> Result<(), LoadError> {
+        for (address, _) in image.iter() {
+            if address > 0x0FFF {
+                return Err(LoadError::AddressOutOfRange { address, limit: 0x1000 });
+            }
+            let page = (address >> 8) as u8;
+            if !self.rom.iter().any(|r| r.chip_id == page) {
+                return Err(LoadError::MissingRom { address, page });
+            }
+        }
+        for (address, byte) in image.iter() {
+            self.load_rom_at(address as u16, &[byte]);
+        }
+        Ok(())
+    }
+
+    /// Read a ROM file (Intel HEX, S-record or raw binary, by extension)
+    /// and load it; returns the number of bytes loaded
+    pub fn load_rom_file(&mut self, path: &Path) -> Result<usize, LoadError> {
+        let image = RomImage::from_file(path)?;
+        self.load_image(&image)?;
+        Ok(image.len())
+    }
+
+    /// Rebuild the flat ROM image used by [`ExecutionMode::Instruction`]
+    ///
+    /// The load methods keep it up to date; call this after writing to the
+    /// 4001s directly.
+    pub fn sync_rom_image(&mut self) {
+        self.rom_image.fill(0);
+        for rom in &self.rom {
+            let base = (rom.chip_id as usize) << 8;
+            for offset in 0..=255u8 {
+                self.rom_image[base + offset as usize] = rom.read_direct(offset);
+            }
+        }
+    }
+
+    /// Flat 4 KB ROM image fetched from in instruction mode
+    pub fn rom_image(&self) -> &[u8] {
+        &self.rom_image
+    }
+
+    /// Current execution mode
+    pub fn mode(&self) -> ExecutionMode {
+        self.mode
+    }
+
+    /// Switch between phase-accurate and instruction-level execution
+    ///
+    /// A phase-accurate system first runs to the end of the instruction in
+    /// flight, so both models always hand over at an instruction boundary.
+    pub fn set_mode(&mut self, mode: ExecutionMode) {
+        if self.mode == ExecutionMode::PhaseAccurate {
+            while !self.at_instruction_boundary() {
+                self.step();
             }
         }
+        if mode == ExecutionMode::Instruction {
+            self.sync_rom_image();
+        }
+        self.mode = mode;
+    }
+
+    /// Is the system at A1 of the first cycle of an instruction?
+    pub fn at_instruction_boundary(&self) -> bool {
+        self.cycle.phase == BusCycle::A1 && !self.cpu.timing.second_cycle()
+    }
+
+    /// Run one whole instruction in the current mode
+    ///
+    /// Returns the machine cycles it took (2 for JCN, FIM, JUN, JMS, ISZ
+    /// and FIN, 1 otherwise). A phase-accurate system caught mid-instruction
+    /// only finishes that instruction.
+    pub fn step_instruction(&mut self) -> u8 {
+        let start = self.total_cycles;
+        match self.mode {
+            ExecutionMode::PhaseAccurate => {
+                self.step();
+                while !self.at_instruction_boundary() {
+                    self.step();
+                }
+            }
+            ExecutionMode::Instruction => {
+                self.record_rewind();
+                self.update_peripherals();
+                let mut port = InstructionPort {
+                    rom_image: &self.rom_image,
+                    rom: &mut self.rom,
+                    ram: &mut self.ram,
+                    ram_decoder: self.ram_decoder.as_mut(),
+                };
+                self.total_cycles += self.cpu.step(&mut port) as u64;
+            }
+        }
+        (self.total_cycles - start) as u8
     }
 
     /// Step one bus phase (1/8 of a machine cycle)
@@ -140,6 +335,10 @@ impl Mcs4System {
     /// - M1-M2: ROM outputs instruction data, CPU reads
     /// - X1-X3: CPU/RAM exchange data for I/O operations
     pub fn step(&mut self) {
+        if self.at_instruction_boundary() {
+            self.record_rewind();
+            self.update_peripherals();
+        }
         let phase = self.cycle.phase;
 
         match phase {
@@ -152,9 +351,7 @@ impl Mcs4System {
                     rom.tick_bus(phase, &mut self.bus, &self.control);
                 }
                 // RAM chips also see address phases (for SRC address)
-                for ram in &mut self.ram {
-                    ram.tick_bus(phase, &mut self.bus, &self.control);
-                }
+                self.tick_ram(phase);
             }
 
             // Memory phases: ROM outputs data, CPU reads
@@ -169,18 +366,15 @@ impl Mcs4System {
 
             // Execute phases: bidirectional data exchange
             BusCycle::X1 | BusCycle::X2 | BusCycle::X3 => {
-                // For X2 (writes): CPU puts data, RAM stores
-                // For X3 (reads): RAM puts data, CPU reads
-                // RAM responds first to handle reads
-                for ram in &mut self.ram {
-                    ram.tick_bus(phase, &mut self.bus, &self.control);
-                }
-                // ROM I/O ports also active during X phases
+                // CPU first: at X2 it drops CM and drives writes, at X3 it
+                // samples what the selected chip drove at X2
+                self.cpu.tick(phase, &mut self.bus, &mut self.control);
+                // RAM and ROM ports latch the I/O command at X1 and
+                // store or drive data at X2
+                self.tick_ram(phase);
                 for rom in &mut self.rom {
                     rom.tick_bus(phase, &mut self.bus, &self.control);
                 }
-                // CPU processes data
-                self.cpu.tick(phase, &mut self.bus, &mut self.control);
             }
         }
 
@@ -193,8 +387,40 @@ impl Mcs4System {
         }
     }
 
+    /// Tick the RAM chips, through the bank decoder if one is fitted
+    fn tick_ram(&mut self, phase: BusCycle) {
+        let Some(decoder) = self.ram_decoder.as_mut() else {
+            for ram in &mut self.ram {
+                ram.tick_bus(phase, &mut self.bus, &self.control);
+            }
+            return;
+        };
+
+        // CM-RAM0 strobes bank 0 directly; CM-RAM1-3 feed A0-A2 and any of
+        // them enables the decoder
+        let lines = self.control.cm_ram();
+        decoder.decode(lines >> 1, lines & 0b1110 != 0);
+        for ram in &mut self.ram {
+            let strobe = match ram.bank_id {
+                0 => lines & 1 != 0,
+                bank => decoder.output(bank),
+            };
+            ram.tick_bus_strobed(phase, &mut self.bus, strobe);
+        }
+    }
+
     /// Run for N machine cycles
+    ///
+    /// In instruction mode whole instructions run until at least N cycles
+    /// have passed, so a two-cycle instruction may finish one cycle over.
     pub fn run_cycles(&mut self, cycles: usize) {
+        if self.mode == ExecutionMode::Instruction {
+            let end = self.total_cycles + cycles as u64;
+            while self.total_cycles < end {
+                self.step_instruction();
+            }
+            return;
+        }
         for _ in 0..(cycles * 8) {
             self.step();
         }
@@ -203,41 +429,384 @@ impl Mcs4System {
     /// Run until a breakpoint or cycle limit is reached
     /// Returns true if breakpoint hit, false if limit reached
     pub fn run_until_breakpoint(&mut self, max_cycles: u64) -> bool {
+        self.run_until_break(max_cycles).is_some()
+    }
+
+    /// Run whole instructions until a break triggers or `max_cycles` have
+    /// passed
+    ///
+    /// Code breaks are checked before each instruction except the first,
+    /// so a run can resume from the break it stopped at; data breaks are
+    /// checked after the instruction that made the access. Returns `None`
+    /// at the cycle limit.
+    pub fn run_until_break(&mut self, max_cycles: u64) -> Option<StopReason> {
+        match self.run_step(max_cycles, |_, _| false) {
+            Step::Break(stop) => Some(stop),
+            _ => None,
+        }
+    }
+
+    /// Run until the instruction at the PC has finished, running any
+    /// subroutine it calls to completion
+    ///
+    /// Calls are matched by logical depth (each JMS one deeper, each BBL
+    /// one shallower) rather than by return address or stack pointer, so
+    /// a callee that nests deep enough to wrap the 3-level stack still
+    /// ends the step at its own BBL.
+    pub fn step_over(&mut self, max_cycles: u64) -> Step {
+        self.run_step(max_cycles, |_, depth| depth <= 0)
+    }
+
+    /// Run until the current subroutine returns with BBL
+    ///
+    /// Nested calls are counted as in [`Mcs4System::step_over`].
+    pub fn step_out(&mut self, max_cycles: u64) -> Step {
+        self.run_step(max_cycles, |_, depth| depth < 0)
+    }
+
+    /// Run until the PC reaches `address` at an instruction boundary
+    ///
+    /// At least one instruction runs, so running to the current address
+    /// goes round a loop once.
+    pub fn run_to(&mut self, address: u16, max_cycles: u64) -> Step {
+        let address = address & 0x0FFF;
+        self.run_step(max_cycles, |sys, _| sys.pc() == address)
+    }
+
+    /// Run whole instructions until `finished` (given the system and the
+    /// call depth relative to the start) says the step is complete, a
+    /// break triggers or `max_cycles` have passed
+    fn run_step(&mut self, max_cycles: u64, mut finished: impl FnMut(&Self, i32) -> bool) -> Step {
         let start = self.total_cycles;
+        let mut depth = 0;
         while self.total_cycles - start < max_cycles {
-            self.step();
+            let address = self.pc();
+            let accesses = if self.at_instruction_boundary() {
+                self.debugger.pending_accesses(self)
+            } else {
+                Vec::new()
+            };
+            let sp = self.cpu.registers.stack_pointer();
+            let faults = self.stack_fault_count();
+            self.step_instruction();
+            // One instruction moves the wrapping stack pointer by at most
+            // one slot either way
+            depth += match (self.cpu.registers.stack_pointer() + 3 - sp) % 3 {
+                1 => 1,
+                2 => -1,
+                _ => 0,
+            };
 
-            // Check breakpoints at start of each instruction fetch
-            if self.cycle.phase == BusCycle::A1 {
-                let pc = self.cpu.pc();
-                if self.breakpoints.contains(&pc) {
-                    return true;
-                }
+            // Breaks are checked first so that the instruction ending the
+            // step still reports them and counts its hits
+            let mut debugger = std::mem::take(&mut self.debugger);
+            let fault = if self.stack_fault_count() != faults {
+                self.stack_faults().last().copied()
+            } else {
+                None
+            };
+            let stop = debugger.check(self, address, &accesses, fault);
+            self.debugger = debugger;
+            if let Some(stop) = stop {
+                return Step::Break(stop);
+            }
+            if finished(self, depth) {
+                return Step::Done;
             }
         }
-        false
+        Step::Limit
+    }
+
+    /// Record JMS with all three stack levels in use and BBL with none
+    ///
+    /// See [`mcs4_chips::stack_monitor`]. Calls outstanding when the
+    /// monitor is enabled are not counted, so enable it before running.
+    /// It stays enabled across [`Mcs4System::reset`].
+    pub fn enable_stack_monitor(&mut self) {
+        self.cpu.enable_stack_monitor();
+    }
+
+    pub fn disable_stack_monitor(&mut self) {
+        self.cpu.stack_monitor = None;
+    }
+
+    /// Stack faults recorded by the monitor, oldest first
+    pub fn stack_faults(&self) -> &[StackFault] {
+        self.cpu
+            .stack_monitor
+            .as_ref()
+            .map_or(&[], |monitor| monitor.faults())
+    }
+
+    fn stack_fault_count(&self) -> u64 {
+        self.cpu
+            .stack_monitor
+            .as_ref()
+            .map_or(0, |monitor| monitor.fault_count())
+    }
+
+    /// Breakpoints and watchpoints
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
     }
 
     /// Add a breakpoint at the given address
     pub fn add_breakpoint(&mut self, addr: u16) {
-        if !self.breakpoints.contains(&addr) {
-            self.breakpoints.push(addr);
+        let exists = self.debugger.breaks().iter().any(|b| {
+            *b.kind() == BreakKind::Address(addr) && b.condition().is_none()
+        });
+        if !exists {
+            self.debugger.add(Break::address(addr));
         }
     }
 
-    /// Remove a breakpoint
+    /// Remove every breakpoint at an address
     pub fn remove_breakpoint(&mut self, addr: u16) {
-        self.breakpoints.retain(|&a| a != addr);
+        self.debugger.remove_where(|b| *b.kind() == BreakKind::Address(addr));
     }
 
-    /// Clear all breakpoints
+    /// Clear all breakpoints and watchpoints
     pub fn clear_breakpoints(&mut self) {
-        self.breakpoints.clear();
+        self.debugger.clear();
+    }
+
+    /// Bitmask of the RAM banks the current DCL code strobes
+    pub(crate) fn strobed_banks(&self) -> u8 {
+        // On a copy of the 3205: looking must not drive its outputs
+        strobed_banks(self.cpu.ram_bank(), self.ram_decoder.clone().as_mut())
+    }
+
+    /// Capture the complete system state
+    ///
+    /// Works at any phase: a snapshot taken mid-instruction restores into
+    /// the same point of the same machine cycle. The attached peripherals
+    /// are captured too.
+    pub fn snapshot(&self) -> SystemSnapshot {
+        let mut control = self.control.clone();
+        control.clear_history();
+        SystemSnapshot::new(Self {
+            cpu: self.cpu.clone(),
+            rom: self.rom.clone(),
+            ram: self.ram.clone(),
+            ram_decoder: self.ram_decoder.clone(),
+            bus: self.bus.clone(),
+            control,
+            clock: self.clock.clone(),
+            cycle: self.cycle.clone(),
+            total_cycles: self.total_cycles,
+            debugger: Debugger::default(),
+            mode: self.mode,
+            rom_image: self.rom_image.clone(),
+            rewind: None,
+            peripherals: self.peripherals.clone(),
+        })
+    }
+
+    /// Replace the whole system (chips, bus, clock, counters, mode and
+    /// peripherals) with a snapshot's; breakpoints are kept and the rewind
+    /// history restarts
+    ///
+    /// A snapshot read from an archive has no peripherals; the attached
+    /// ones are then kept as they are.
+    pub fn restore(&mut self, snapshot: &SystemSnapshot) {
+        let debugger = std::mem::take(&mut self.debugger);
+        let rewind = self.rewind.take();
+        let peripherals = std::mem::take(&mut self.peripherals);
+        *self = snapshot.system().clone();
+        self.debugger = debugger;
+        self.rewind = rewind;
+        if self.peripherals.is_empty() {
+            self.peripherals = peripherals;
+        }
+        self.clear_rewind();
+    }
+
+    /// Start recording execution so it can be stepped back through
+    ///
+    /// Recording begins at the next instruction boundary. Only the TEST pin
+    /// and 4001 port inputs are recorded as stimulus, sampled at
+    /// instruction boundaries; loading ROM, resetting or restoring a
+    /// snapshot clears the history.
+    pub fn enable_rewind(&mut self, config: RewindConfig) {
+        self.rewind = Some(Box::new(RewindBuffer::new(config)));
+    }
+
+    /// Stop recording and drop the history
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    /// The rewind history, if recording
+    pub fn rewind(&self) -> Option<&RewindBuffer> {
+        self.rewind.as_deref()
+    }
+
+    /// Go back to the start of the previous instruction, or of the one in
+    /// flight if stopped mid-instruction
+    ///
+    /// Returns false if there is no recorded history to go back into.
+    pub fn step_back(&mut self) -> bool {
+        let Some(mut rewind) = self.rewind.take() else {
+            return false;
+        };
+        let position = rewind.position();
+        let moved = position > rewind.oldest();
+        if moved {
+            self.rewind_to(&mut rewind, position - 1);
+        }
+        self.rewind = Some(rewind);
+        moved
+    }
+
+    /// Run backwards to the latest recorded instruction at an enabled
+    /// address breakpoint whose condition holds
+    ///
+    /// Returns true if one was found; otherwise the system is left at the
+    /// oldest recorded instruction.
+    pub fn run_back_to_breakpoint(&mut self) -> bool {
+        self.run_back_until(|sys| sys.debugger.at_breakpoint(sys))
+    }
+
+    /// Run backwards to the latest recorded instruction boundary where
+    /// `condition` holds
+    ///
+    /// The system is left just before the instruction that made the
+    /// condition stop holding, e.g. the write that changed a watched RAM
+    /// character. Returns true if one was found; otherwise the system is
+    /// left at the oldest recorded instruction.
+    pub fn run_back_until(&mut self, mut condition: impl FnMut(&Self) -> bool) -> bool {
+        let Some(rewind) = self.rewind.take() else {
+            return false;
+        };
+        // Replay one keyframe interval at a time, latest first
+        let mut end = rewind.position();
+        let mut hit = None;
+        for start in rewind.keyframe_indices().rev() {
+            if let Some((_, keyframe)) = rewind.keyframe_for(start) {
+                self.restore(keyframe);
+            }
+            for index in start..end {
+                if let Some(inputs) = rewind.inputs_at(index) {
+                    inputs.apply(self);
+                }
+                if condition(self) {
+                    hit = Some(index);
+                }
+                if index + 1 < end {
+                    self.step_instruction();
+                }
+            }
+            if hit.is_some() {
+                break;
+            }
+            end = start;
+        }
+        self.rewind = Some(rewind);
+        self.run_back_until_index(hit)
+    }
+
+    /// Go back to recorded boundary `hit`, or to the oldest one if `None`
+    fn run_back_until_index(&mut self, hit: Option<u64>) -> bool {
+        let Some(mut rewind) = self.rewind.take() else {
+            return false;
+        };
+        if rewind.position() > rewind.oldest() {
+            let target = hit.unwrap_or(rewind.oldest());
+            self.rewind_to(&mut rewind, target);
+        }
+        self.rewind = Some(rewind);
+        hit.is_some()
+    }
+
+    /// Restore the keyframe before boundary `target` and replay up to it,
+    /// then forget everything recorded from there on
+    fn rewind_to(&mut self, rewind: &mut RewindBuffer, target: u64) {
+        if let Some((start, keyframe)) = rewind.keyframe_for(target) {
+            let start = *start;
+            self.restore(keyframe);
+            for index in start..target {
+                if let Some(inputs) = rewind.inputs_at(index) {
+                    inputs.apply(self);
+                }
+                self.step_instruction();
+            }
+        }
+        if let Some(inputs) = rewind.inputs_at(target) {
+            inputs.apply(self);
+        }
+        rewind.truncate(target);
+    }
+
+    /// Attach a device to the I/O ports; it is first updated at the next
+    /// instruction boundary
+    ///
+    /// The rewind history restarts: it cannot go back to before the device
+    /// was there.
+    pub fn attach(&mut self, peripheral: impl Peripheral) {
+        self.peripherals.push(Box::new(peripheral));
+        self.clear_rewind();
+    }
+
+    /// The attached devices, in the order they are updated
+    pub fn peripherals(&self) -> &[Box<dyn Peripheral>] {
+        &self.peripherals
+    }
+
+    /// The first attached device of type `T`
+    pub fn peripheral<T: Peripheral>(&self) -> Option<&T> {
+        self.peripherals
+            .iter()
+            .find_map(|p| (p.as_ref() as &dyn std::any::Any).downcast_ref())
+    }
+
+    pub fn peripheral_mut<T: Peripheral>(&mut self) -> Option<&mut T> {
+        self.peripherals
+            .iter_mut()
+            .find_map(|p| (p.as_mut() as &mut dyn std::any::Any).downcast_mut())
+    }
+
+    /// Let every device see the ports and drive its inputs
+    fn update_peripherals(&mut self) {
+        if self.peripherals.is_empty() {
+            return;
+        }
+        let mut ports = Ports {
+            cpu: &mut self.cpu,
+            rom: &mut self.rom,
+            ram: &self.ram,
+            cycles: self.total_cycles,
+        };
+        for peripheral in &mut self.peripherals {
+            peripheral.update(&mut ports);
+        }
+    }
+
+    /// Record the instruction about to run, if rewind is enabled
+    fn record_rewind(&mut self) {
+        if let Some(mut rewind) = self.rewind.take() {
+            rewind.record(self);
+            self.rewind = Some(rewind);
+        }
+    }
+
+    /// Restart the rewind history from the current state
+    fn clear_rewind(&mut self) {
+        if let Some(rewind) = self.rewind.as_mut() {
+            **rewind = RewindBuffer::new(rewind.config());
+        }
     }
 
     /// Reset the system to initial state
     pub fn reset(&mut self) {
+        let stack_monitor = self.cpu.stack_monitor.is_some();
         self.cpu = I4004::new();
+        if stack_monitor {
+            self.cpu.enable_stack_monitor();
+        }
         self.bus = DataBus::new();
         self.control = ControlSignals::mcs4();
         self.cycle = CycleState::new();
@@ -245,6 +814,7 @@ impl Mcs4System {
         for ram in &mut self.ram {
             *ram = I4002::new(ram.chip_id, ram.bank_id);
         }
+        self.clear_rewind();
     }
 
     /// Set the CPU test pin
@@ -312,9 +882,77 @@ impl Default for Mcs4System {
     }
 }
 
+/// The system as seen by [`I4004::step`] in [`ExecutionMode::Instruction`]
+///
+/// Fetches come from the flat ROM image. SRC and I/O go straight to the
+/// 4001s on CM-ROM0 and to the 4002s in the banks the DCL code would strobe
+/// over CM-RAM (through the 3205 if one is fitted).
+struct InstructionPort<'a> {
+    rom_image: &'a [u8],
+    rom: &'a mut [I4001],
+    ram: &'a mut [I4002],
+    ram_decoder: Option<&'a mut I3205>,
+}
+
+/// Bitmask of the RAM banks strobed for DCL code `command`, through the
+/// 3205 if one is fitted
+fn strobed_banks(command: u8, decoder: Option<&mut I3205>) -> u8 {
+    // Same line encoding as `ControlSignals::select_ram`
+    let command = command & 0x07;
+    let lines = if command == 0 { 0b0001 } else { command << 1 };
+    match decoder {
+        None => lines,
+        Some(decoder) => {
+            let decoded = decoder.decode(lines >> 1, lines & 0b1110 != 0);
+            (lines & 1) | decoded.map_or(0, |bank| 1 << bank)
+        }
+    }
+}
+
+impl InstructionBus for InstructionPort<'_> {
+    fn fetch(&mut self, _bank: u8, addr: u16) -> u8 {
+        self.rom_image[(addr as usize) & (ROM_SPACE - 1)]
+    }
+
+    fn src(&mut self, command: u8, address: u8) {
+        for rom in self.rom.iter_mut().filter(|r| r.cm_rom_line == 0) {
+            rom.set_src_address(address);
+        }
+        let banks = strobed_banks(command, self.ram_decoder.as_deref_mut());
+        for ram in self.ram.iter_mut().filter(|r| banks >> r.bank_id & 1 != 0) {
+            ram.set_src_address(address >> 6, (address >> 4) & 0x03, address & 0x0F);
+        }
+    }
+
+    fn io(&mut self, command: u8, opcode: u8, acc: u8) -> u8 {
+        // Nobody driving the bus leaves OPA on it, as in the phase model;
+        // when several chips answer the 4001 wins, as it drives last
+        let opa = opcode & 0x0F;
+        let banks = strobed_banks(command, self.ram_decoder.as_deref_mut());
+        let ram = self
+            .ram
+            .iter_mut()
+            .filter(|r| banks >> r.bank_id & 1 != 0 && r.is_selected());
+        let rom = self
+            .rom
+            .iter_mut()
+            .filter(|r| r.cm_rom_line == 0 && r.is_io_selected());
+        let mut value = opa;
+        for read in ram.map(|r| r.execute_io(opa, acc)) {
+            value = read.unwrap_or(value);
+        }
+        for read in rom.map(|r| r.execute_io(opa, acc)) {
+            value = read.unwrap_or(value);
+        }
+        value
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::debugger::{Access, BreakEvent, RamLocation};
+    use crate::test_util::{sample_image, sample_system};
 
     #[test]
     fn test_minimal_system() {
@@ -358,6 +996,294 @@ mod tests {
         assert_eq!(sys.accumulator(), 5);
     }
 
+    #[test]
+    fn test_sub_borrow_chain() {
+        let mut sys = Mcs4System::minimal();
+
+        // 0x32 - 0x15, low digit first: carry is an inverted borrow, so
+        // CLC starts with no borrow and CMC turns a borrow out into a
+        // borrow in for the next digit
+        // LDM 5; XCH R0; LDM 1; XCH R1; CLC; LDM 2; SUB R0; XCH R2; CMC;
+        // LDM 3; SUB R1; XCH R3
+        sys.load_rom(&[
+            0xD5, 0xB0, 0xD1, 0xB1, 0xF1, 0xD2, 0x90, 0xB2, 0xF3, 0xD3, 0x91, 0xB3,
+        ]);
+        sys.run_cycles(14);
+
+        assert_eq!(sys.register(2), 0xD);
+        assert_eq!(sys.register(3), 0x1);
+        assert!(sys.carry());
+    }
+
+    #[test]
+    fn test_two_byte_instructions() {
+        let mut sys = Mcs4System::minimal();
+
+        // FIM P1, 0x25; JUN 0x010; 0x010: LD R3
+        let mut rom = [0u8; 0x20];
+        rom[..4].copy_from_slice(&[0x22, 0x25, 0x40, 0x10]);
+        rom[0x10] = 0xA3;
+        sys.load_rom(&rom);
+
+        sys.run_cycles(4);
+        assert_eq!(sys.register_pair(1), 0x25);
+        assert_eq!(sys.pc(), 0x010);
+
+        sys.run_cycles(1);
+        assert_eq!(sys.accumulator(), 5);
+    }
+
+    #[test]
+    fn test_fin_table_lookup() {
+        let mut sys = Mcs4System::standard();
+
+        // 0x000: FIM P0, 0x42; JUN 0x0FF
+        // 0x0FF: FIN P3 (last byte of page 0, so the table is in page 1)
+        // 0x100: LD R7
+        sys.load_rom_at(0x000, &[0x20, 0x42, 0x40, 0xFF]);
+        sys.load_rom_at(0x0FF, &[0x36, 0xA7]);
+        sys.load_rom_at(0x042, &[0x11]);
+        sys.load_rom_at(0x142, &[0x5A]);
+
+        // FIM, JUN, then both FIN cycles
+        sys.run_cycles(6);
+        assert_eq!(sys.register_pair(3), 0x5A);
+        assert_eq!(sys.register_pair(0), 0x42);
+        assert_eq!(sys.pc(), 0x100);
+
+        sys.run_cycles(1);
+        assert_eq!(sys.accumulator(), 0xA);
+    }
+
+    #[test]
+    fn test_ram_io_commands() {
+        let mut sys = Mcs4System::standard();
+
+        // FIM P0, 0x63 (chip 1, register 2, char 3); SRC P0
+        // LDM 5; WRM; LDM 14; WR2; LDM 3; WMP; LDM 0; RDM; RD2; ADM; SBM
+        sys.load_rom(&[
+            0x20, 0x63, 0x21, 0xD5, 0xE0, 0xDE, 0xE6, 0xD3, 0xE1, 0xD0, 0xE9, 0xEE, 0xEB, 0xE8,
+        ]);
+        sys.run_cycles(3);
+        assert!(sys.ram[1].is_selected());
+        assert!(!sys.ram[0].is_selected());
+
+        sys.run_cycles(6);
+        assert_eq!(sys.read_ram(0, 1, 2, 3), Some(0x5));
+        assert_eq!(sys.ram[1].read_status(2, 2), 0xE);
+        assert_eq!(sys.ram[1].read_status(0, 2), 0);
+        assert_eq!(sys.ram[1].output(), 0x3);
+        // Same chip number in bank 1 is not strobed
+        assert_eq!(sys.read_ram(1, 1, 2, 3), Some(0));
+
+        sys.run_cycles(2);
+        assert_eq!(sys.accumulator(), 0x5);
+        sys.run_cycles(1);
+        assert_eq!(sys.accumulator(), 0xE);
+
+        // ADM: 0xE + 5 = 0x13
+        sys.run_cycles(1);
+        assert_eq!(sys.accumulator(), 0x3);
+        assert!(sys.carry());
+        // SBM: 3 + !5 + !carry = 0xD, borrow out
+        sys.run_cycles(1);
+        assert_eq!(sys.accumulator(), 0xD);
+        assert!(!sys.carry());
+    }
+
+    #[test]
+    fn test_rom_port_io() {
+        let mut sys = Mcs4System::standard();
+        sys.rom[2].set_io_input(0x6);
+
+        // FIM P0, 0x20; SRC P0; LDM 9; WRR; RDR
+        sys.load_rom(&[0x20, 0x20, 0x21, 0xD9, 0xE2, 0xEA]);
+        sys.run_cycles(3);
+        assert!(sys.rom[2].is_io_selected());
+        assert!(!sys.rom[0].is_io_selected());
+
+        sys.run_cycles(2);
+        assert_eq!(sys.rom[2].io_output(), 0x9);
+        assert_eq!(sys.rom[0].io_output(), 0);
+        // RAM chips ignore the port command
+        assert_eq!(sys.read_ram(0, 0, 0, 0), Some(0));
+
+        sys.run_cycles(1);
+        assert_eq!(sys.accumulator(), 0x6);
+    }
+
+    #[test]
+    fn test_dcl_bank_select() {
+        let mut sys = Mcs4System::maximal();
+
+        // LDM 4; DCL (CM-RAM3 = bank 3); FIM P0, 0x20 (chip 0, register 2); SRC P0
+        // LDM 9; WRM; LDM 0; DCL; SRC P0; LDM 3; WRM
+        sys.load_rom(&[
+            0xD4, 0xFD, 0x20, 0x20, 0x21, 0xD9, 0xE0, 0xD0, 0xFD, 0x21, 0xD3, 0xE0,
+        ]);
+        sys.run_cycles(7);
+        assert_eq!(sys.cpu.ram_bank(), 4);
+        assert_eq!(sys.read_ram(3, 0, 2, 0), Some(0x9));
+        assert_eq!(sys.read_ram(0, 0, 2, 0), Some(0));
+
+        sys.run_cycles(5);
+        assert_eq!(sys.read_ram(0, 0, 2, 0), Some(0x3));
+        assert_eq!(sys.read_ram(3, 0, 2, 0), Some(0x9));
+    }
+
+    #[test]
+    fn test_decoded_ram_banks() {
+        let mut sys = Mcs4System::maximal_decoded();
+        assert_eq!(sys.ram.len(), 32);
+
+        // LDM 5; DCL; FIM P0, 0x10 (chip 0, register 1); SRC P0; LDM 7; WRM; LDM 0; RDM
+        sys.load_rom(&[0xD5, 0xFD, 0x20, 0x10, 0x21, 0xD7, 0xE0, 0xD0, 0xE9]);
+        sys.run_cycles(7);
+        assert_eq!(sys.read_ram(5, 0, 1, 0), Some(0x7));
+        // Directly wired, code 5 would also have hit banks 1 and 3
+        for bank in [0, 1, 3, 4] {
+            assert_eq!(sys.read_ram(bank, 0, 1, 0), Some(0));
+        }
+
+        sys.run_cycles(2);
+        assert_eq!(sys.accumulator(), 0x7);
+    }
+
+    #[test]
+    fn test_load_image() {
+        let mut sys = sample_system();
+        assert_eq!(sys.read_rom(0x002), Some(0x40));
+        assert_eq!(sys.read_rom(0x0A9), Some(0xA3));
+
+        // Page 4 has no 4001 in the standard system
+        let mut far = RomImage::new();
+        far.set(0x000, 0xD1);
+        far.set(0x412, 0xD2);
+        match sys.load_image(&far) {
+            Err(LoadError::MissingRom { address: 0x412, page: 4 }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        // Nothing was written
+        assert_eq!(sys.read_rom(0x000), Some(0x00));
+
+        let mut wide = RomImage::new();
+        wide.set(0x1000, 0x00);
+        assert!(matches!(
+            Mcs4System::maximal().load_image(&wide),
+            Err(LoadError::AddressOutOfRange { address: 0x1000, .. })
+        ));
+    }
+
+    /// Everything an instruction can change, for comparing the two modes
+    fn architectural_state(sys: &Mcs4System) -> Vec<u8> {
+        let mut state = vec![
+            (sys.pc() >> 8) as u8,
+            sys.pc() as u8,
+            sys.accumulator(),
+            sys.carry() as u8,
+            sys.cpu.ram_bank(),
+        ];
+        state.extend((0..16).map(|r| sys.register(r)));
+        for ram in &sys.ram {
+            for reg in 0..4 {
+                state.extend((0..16).map(|c| ram.read_direct(reg, c)));
+                state.extend((0..4).map(|i| ram.read_status(reg, i)));
+            }
+            state.push(ram.output());
+            state.push(ram.is_selected() as u8);
+        }
+        state.extend(sys.rom.iter().map(|r| r.io_output()));
+        state
+    }
+
+    /// Run `rom` in both modes one instruction at a time, checking they agree
+    fn assert_modes_agree(mut sys: Mcs4System, rom: &[u8], instructions: usize) {
+        sys.load_rom(rom);
+        let mut fast = sys.clone();
+        fast.set_mode(ExecutionMode::Instruction);
+        for rom in &mut fast.rom {
+            rom.set_io_input(rom.chip_id ^ 0x5);
+        }
+        for rom in &mut sys.rom {
+            rom.set_io_input(rom.chip_id ^ 0x5);
+        }
+
+        for n in 0..instructions {
+            let pc = sys.pc();
+            assert_eq!(sys.step_instruction(), fast.step_instruction(), "cycles at {:03X}", pc);
+            assert_eq!(
+                architectural_state(&sys),
+                architectural_state(&fast),
+                "instruction {} at {:03X}",
+                n,
+                pc
+            );
+        }
+        assert_eq!(sys.cycles(), fast.cycles());
+    }
+
+    #[test]
+    fn test_instruction_mode_matches_phase_mode() {
+        let mut sample = vec![0; 0x400];
+        for (address, byte) in sample_image().iter() {
+            sample[address as usize] = byte;
+        }
+        assert_modes_agree(Mcs4System::standard(), &sample, 300);
+
+        // RAM I/O, ROM ports, DCL banks, and a read with no chip selected
+        let io = [
+            0x20, 0x63, 0x21, 0xD5, 0xE0, 0xDE, 0xE6, 0xD3, 0xE1, 0xD0, 0xE9, 0xEE, 0xEB, 0xE8,
+            0x20, 0x20, 0x21, 0xD9, 0xE2, 0xEA, 0xD4, 0xFD, 0x21, 0xD9, 0xE0, 0xEC, 0x20, 0x50,
+            0x21, 0xEA, 0xD3, 0xFD, 0x21, 0xE9, 0x40, 0x00,
+        ];
+        assert_modes_agree(Mcs4System::maximal(), &io, 60);
+        assert_modes_agree(Mcs4System::maximal_decoded(), &io, 60);
+    }
+
+    #[test]
+    fn test_instruction_mode() {
+        let mut sys = Mcs4System::standard();
+        sys.set_mode(ExecutionMode::Instruction);
+
+        // FIM P1, 0x25; JUN 0x010; 0x010: LD R3
+        let mut rom = [0u8; 0x20];
+        rom[..4].copy_from_slice(&[0x22, 0x25, 0x40, 0x10]);
+        rom[0x10] = 0xA3;
+        sys.load_rom(&rom);
+
+        assert_eq!(sys.step_instruction(), 2);
+        assert_eq!(sys.register_pair(1), 0x25);
+        sys.run_cycles(3);
+        assert_eq!(sys.pc(), 0x011);
+        assert_eq!(sys.accumulator(), 5);
+        assert_eq!(sys.cycles(), 5);
+
+        sys.add_breakpoint(0x014);
+        assert!(sys.run_until_breakpoint(100));
+        assert_eq!(sys.cycles(), 8);
+    }
+
+    #[test]
+    fn test_mode_switch_finishes_instruction() {
+        let mut sys = Mcs4System::minimal();
+        // FIM P0, 0x42; LDM 7
+        sys.load_rom(&[0x20, 0x42, 0xD7]);
+
+        // Partway into FIM's second cycle
+        for _ in 0..10 {
+            sys.step();
+        }
+        sys.set_mode(ExecutionMode::Instruction);
+        assert_eq!(sys.register_pair(0), 0x42);
+        assert_eq!(sys.cycles(), 2);
+
+        sys.step_instruction();
+        assert_eq!(sys.accumulator(), 7);
+        sys.set_mode(ExecutionMode::PhaseAccurate);
+        sys.run_cycles(1);
+        assert_eq!(sys.pc(), 0x004);
+    }
+
     #[test]
     fn test_breakpoint() {
         let mut sys = Mcs4System::minimal();
@@ -370,4 +1296,161 @@ mod tests {
         assert!(hit);
         assert_eq!(sys.pc(), 4);
     }
+
+    #[test]
+    fn test_step_over_and_out() {
+        // 0x000 JMS 0x010; LDM 1
+        // 0x010 JMS 0x020; BBL 2
+        // 0x020 IAC; BBL 0
+        let mut rom = vec![0u8; 0x30];
+        rom[..3].copy_from_slice(&[0x50, 0x10, 0xD1]);
+        rom[0x10..0x13].copy_from_slice(&[0x50, 0x20, 0xC2]);
+        rom[0x20..0x22].copy_from_slice(&[0xF2, 0xC0]);
+
+        for mode in [ExecutionMode::PhaseAccurate, ExecutionMode::Instruction] {
+            let mut sys = Mcs4System::minimal();
+            sys.load_rom(&rom);
+            sys.set_mode(mode);
+            assert_eq!(sys.step_over(100), Step::Done);
+            assert_eq!((sys.pc(), sys.accumulator()), (0x002, 2));
+            assert_eq!(sys.cycles(), 2 + 2 + 1 + 1 + 1);
+            // Not a call: one instruction
+            assert_eq!(sys.step_over(100), Step::Done);
+            assert_eq!((sys.pc(), sys.accumulator()), (0x003, 1));
+
+            let mut sys = Mcs4System::minimal();
+            sys.load_rom(&rom);
+            sys.set_mode(mode);
+            sys.step_instruction();
+            assert_eq!(sys.step_over(100), Step::Done);
+            assert_eq!(sys.pc(), 0x012);
+            assert_eq!(sys.step_out(100), Step::Done);
+            assert_eq!(sys.pc(), 0x002);
+        }
+    }
+
+    #[test]
+    fn test_step_over_wrapped_stack() {
+        // Four nested calls: the last JMS overwrites the return to 0x002,
+        // so the outer BBL "returns" to 0x032 as on the real chip
+        // 0x000 JMS 0x010; 0x010 JMS 0x020; BBL 0; 0x020 JMS 0x030; BBL 0
+        // 0x030 JMS 0x040; BBL 0; 0x040 BBL 0
+        let mut rom = vec![0u8; 0x50];
+        rom[..2].copy_from_slice(&[0x50, 0x10]);
+        for at in [0x10, 0x20, 0x30] {
+            rom[at..at + 3].copy_from_slice(&[0x50, at as u8 + 0x10, 0xC0]);
+        }
+        rom[0x40] = 0xC0;
+        let mut sys = Mcs4System::minimal();
+        sys.load_rom(&rom);
+
+        assert_eq!(sys.step_over(100), Step::Done);
+        assert_eq!(sys.pc(), 0x032);
+        assert_eq!(sys.cycles(), 4 * 2 + 4);
+
+        // Same from inside: step_out of 0x010's frame ends at its BBL
+        let mut sys = Mcs4System::minimal();
+        sys.load_rom(&rom);
+        sys.set_mode(ExecutionMode::Instruction);
+        sys.step_instruction();
+        assert_eq!(sys.step_out(100), Step::Done);
+        assert_eq!(sys.pc(), 0x032);
+    }
+
+    #[test]
+    fn test_stack_monitor() {
+        // The wrapped-stack program from test_step_over_wrapped_stack: the
+        // JMS at 0x030 overwrites the return to 0x002 and the fourth BBL
+        // (at 0x012) has nothing left to return to
+        let mut rom = vec![0u8; 0x50];
+        rom[..2].copy_from_slice(&[0x50, 0x10]);
+        for at in [0x10, 0x20, 0x30] {
+            rom[at..at + 3].copy_from_slice(&[0x50, at as u8 + 0x10, 0xC0]);
+        }
+        rom[0x40] = 0xC0;
+        let overflow = StackFault::Overflow {
+            pc: 0x030,
+            lost: 0x002,
+        };
+        let underflow = StackFault::Underflow { pc: 0x012 };
+
+        for mode in [ExecutionMode::PhaseAccurate, ExecutionMode::Instruction] {
+            let mut sys = Mcs4System::minimal();
+            sys.load_rom(&rom);
+            sys.set_mode(mode);
+            sys.enable_stack_monitor();
+            assert_eq!(sys.step_over(100), Step::Done);
+            assert_eq!(sys.pc(), 0x032);
+            assert_eq!(sys.stack_faults(), &[overflow, underflow]);
+        }
+
+        // As breaks, reported after the faulting instruction
+        let mut sys = Mcs4System::minimal();
+        sys.load_rom(&rom);
+        sys.enable_stack_monitor();
+        let id = sys.debugger_mut().add(Break::stack_fault());
+        let stop = sys.run_until_break(100).unwrap();
+        assert_eq!(stop.event, BreakEvent::Stack(overflow));
+        assert_eq!((stop.address, sys.pc()), (0x030, 0x040));
+        assert_eq!(
+            stop.to_string(),
+            format!("break {}: stack overflow at 0x030: return address 0x002 lost", id)
+        );
+        let stop = sys.run_until_break(100).unwrap();
+        assert_eq!(stop.event, BreakEvent::Stack(underflow));
+        assert_eq!(sys.pc(), 0x032);
+
+        // Without the monitor nothing is recorded and the break never fires
+        sys.reset();
+        sys.disable_stack_monitor();
+        assert_eq!(sys.run_until_break(100), None);
+        assert!(sys.stack_faults().is_empty());
+    }
+
+    #[test]
+    fn test_run_to() {
+        let mut sys = Mcs4System::minimal();
+        // 0x000 LDM 3; 0x001 IAC; 0x002 JUN 0x001
+        sys.load_rom(&[0xD3, 0xF2, 0x40, 0x01]);
+        assert_eq!(sys.run_to(0x002, 100), Step::Done);
+        assert_eq!((sys.pc(), sys.accumulator()), (0x002, 4));
+        // Once round the loop
+        assert_eq!(sys.run_to(0x002, 100), Step::Done);
+        assert_eq!(sys.accumulator(), 5);
+        assert_eq!(sys.run_to(0x100, 30), Step::Limit);
+
+        // Breaks still stop the run
+        sys.add_breakpoint(0x001);
+        match sys.run_to(0x100, 30) {
+            Step::Break(stop) => assert_eq!(stop.address, 0x001),
+            other => panic!("expected a break, got {:?}", other),
+        }
+        // A break inside a stepped-over call stops the step
+        let mut sys = Mcs4System::minimal();
+        // JMS 0x004; NOP; NOP; 0x004 IAC; BBL 0
+        sys.load_rom(&[0x50, 0x04, 0x00, 0x00, 0xF2, 0xC0]);
+        sys.add_breakpoint(0x005);
+        assert!(matches!(sys.step_over(100), Step::Break(stop) if stop.address == 0x005));
+
+        // The instruction that ends the step still reports its break
+        let mut sys = Mcs4System::minimal();
+        // FIM P0, 0x00; SRC P0; LDM 5; WRM
+        sys.load_rom(&[0x20, 0x00, 0x21, 0xD5, 0xE0]);
+        for _ in 0..3 {
+            sys.step_instruction();
+        }
+        let location = RamLocation::Character {
+            bank: 0,
+            chip: 0,
+            register: 0,
+            character: 0,
+        };
+        let id = sys.debugger_mut().add(Break::watch(location, Access::Write));
+        match sys.step_over(100) {
+            Step::Break(stop) => assert_eq!((stop.id, stop.address), (id, 0x004)),
+            other => panic!("expected a break, got {:?}", other),
+        }
+        assert_eq!(sys.pc(), 0x005);
+        assert_eq!(sys.debugger().get(id).unwrap().hits(), 1);
+    }
 }
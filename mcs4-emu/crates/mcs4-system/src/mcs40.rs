@@ -1,20 +1,717 @@
-//! MCS-40 System (4040-based) - stub
+//! MCS-40 System (4040-based)
+//!
+//! Complete system integration for Intel MCS-40 architecture.
+//! Wires the 4040 CPU to 4001/4308 ROMs, 4002 RAMs and 4101 program RAM
+//! (behind a 4289) over the shared bus, with the 4040's INT, STOP and STP
+//! lines exposed.
+//!
+//! Program memory is split into two 4K banks selected by DB0/DB1: bank 0
+//! chips sit on CM-ROM0 and bank 1 chips on CM-ROM1.
 
-use mcs4_chips::i4040::I4040;
+use mcs4_bus::prelude::*;
+use mcs4_chips::stack_monitor::StackFault;
+use mcs4_chips::{i4001::I4001, i4002::I4002, i4040::I4040, i4289::I4289, i4308::I4308};
 
-/// Complete MCS-40 system (stub)
+use crate::debugger::Step;
+
+/// Complete MCS-40 system
 pub struct Mcs40System {
+    /// 4040 CPU
     pub cpu: I4040,
+
+    /// 4001 ROM chips (256 bytes each)
+    pub rom: Vec<I4001>,
+
+    /// 4308 ROM chips (1K each)
+    pub rom_4308: Vec<I4308>,
+
+    /// RAM chips (4 x 4002 per bank)
+    pub ram: Vec<I4002>,
+
+    /// 4289 standard memory interface with 4101 program RAM
+    pub program_ram: Option<I4289>,
+
+    /// 4-bit bidirectional data bus
+    pub bus: DataBus,
+
+    /// Control signals (SYNC, CM-ROM, CM-RAM, INT, STOP, STP)
+    pub control: ControlSignals,
+
+    /// Two-phase clock generator
+    pub clock: TwoPhaseClockTwoPhaseClock,
+
+    /// Current bus cycle phase
+    cycle: CycleState,
+
+    /// Total machine cycles executed
+    total_cycles: u64,
+
+    /// Breakpoint addresses (stop when PC matches)
+    breakpoints: Vec<u16>,
+
+    /// Stop stepping at a stack overflow or underflow
+    break_on_stack_fault: bool,
 }
 
 impl Mcs40System {
-    pub fn new() -> Self {
-        Self { cpu: I4040::new() }
+    fn with_chips(
+        rom: Vec<I4001>,
+        rom_4308: Vec<I4308>,
+        ram: Vec<I4002>,
+        program_ram: Option<I4289>,
+    ) -> Self {
+        Self {
+            cpu: I4040::new(),
+            rom,
+            rom_4308,
+            ram,
+            program_ram,
+            bus: DataBus::new(),
+            control: ControlSignals::mcs40(),
+            clock: TwoPhaseClockTwoPhaseClock::default_config(),
+            cycle: CycleState::new(),
+            total_cycles: 0,
+            breakpoints: Vec::new(),
+            break_on_stack_fault: false,
+        }
+    }
+
+    /// Create a minimal MCS-40 system (1 ROM, 1 RAM)
+    pub fn minimal() -> Self {
+        Self::with_chips(vec![I4001::new(0)], Vec::new(), vec![I4002::new(0, 0)], None)
+    }
+
+    /// Create a standard MCS-40 system
+    ///
+    /// Bank 0: 4 x 4001 (0x000-0x3FF) and a 4308 (0x400-0x7FF), plus one page
+    /// of 4101 program RAM at 0xF00. Bank 1: a 4308 (0x000-0x3FF).
+    /// RAM: 2 banks of 4 x 4002.
+    pub fn standard() -> Self {
+        let rom = (0..4).map(I4001::new).collect();
+        let rom_4308 = vec![I4308::new(1), I4308::with_cm_rom(0, 1)];
+
+        let mut ram = Vec::with_capacity(8);
+        for bank in 0..2 {
+            for chip in 0..4 {
+                ram.push(I4002::new(chip, bank));
+            }
+        }
+
+        Self::with_chips(rom, rom_4308, ram, Some(I4289::new(0xF, 1)))
+    }
+
+    /// Create a maximal MCS-40 system
+    ///
+    /// Bank 0: 4 x 4308 (4K). Bank 1: 3 x 4308 (0x000-0xBFF) and four pages
+    /// of 4101 program RAM (0xC00-0xFFF). RAM: 4 banks of 4 x 4002.
+    pub fn maximal() -> Self {
+        let mut rom_4308: Vec<I4308> = (0..4).map(I4308::new).collect();
+        rom_4308.extend((0..3).map(|chip| I4308::with_cm_rom(chip, 1)));
+
+        let mut ram = Vec::with_capacity(16);
+        for bank in 0..4 {
+            for chip in 0..4 {
+                ram.push(I4002::new(chip, bank));
+            }
+        }
+
+        let mut program_ram = I4289::new(0xC, 4);
+        program_ram.cm_rom_line = 1;
+
+        Self::with_chips(Vec::new(), rom_4308, ram, Some(program_ram))
+    }
+
+    /// Load program memory starting at bank 0, address 0
+    ///
+    /// Images larger than 4K continue into bank 1, matching the layout used
+    /// by the instruction-level [`mcs4_chips::InstructionBus`] ROM image.
+    pub fn load_rom(&mut self, data: &[u8]) {
+        for (bank, chunk) in data.chunks(0x1000).enumerate().take(2) {
+            self.load_rom_at(bank as u8, 0, chunk);
+        }
+    }
+
+    /// Load program memory at a specific address within a ROM bank (0-1)
+    ///
+    /// Bytes that land on an address with no chip behind it are dropped.
+    pub fn load_rom_at(&mut self, bank: u8, address: u16, data: &[u8]) {
+        for (offset, &byte) in data.iter().enumerate() {
+            let addr = address as usize + offset;
+            if addr > 0x0FFF {
+                break;
+            }
+            self.write_program(bank, addr as u16, byte);
+        }
+    }
+
+    fn write_program(&mut self, bank: u8, addr: u16, byte: u8) {
+        let page = ((addr >> 8) & 0x0F) as u8;
+        if let Some(rom) = self
+            .rom
+            .iter_mut()
+            .find(|r| r.cm_rom_line == bank && r.chip_id == page)
+        {
+            rom.write_direct((addr & 0xFF) as u8, byte);
+        } else if let Some(rom) = self
+            .rom_4308
+            .iter_mut()
+            .find(|r| r.cm_rom_line == bank && r.contains(addr))
+        {
+            rom.write_direct(addr - rom.base_address(), byte);
+        } else if let Some(smi) = self
+            .program_ram
+            .as_mut()
+            .filter(|s| s.cm_rom_line == bank && s.contains(addr))
+        {
+            smi.write_direct(addr, byte);
+        }
+    }
+
+    /// Read program memory at the given bank and address
+    pub fn read_rom(&self, bank: u8, addr: u16) -> Option<u8> {
+        let page = ((addr >> 8) & 0x0F) as u8;
+        if let Some(rom) = self
+            .rom
+            .iter()
+            .find(|r| r.cm_rom_line == bank && r.chip_id == page)
+        {
+            return Some(rom.read_direct((addr & 0xFF) as u8));
+        }
+        if let Some(rom) = self
+            .rom_4308
+            .iter()
+            .find(|r| r.cm_rom_line == bank && r.contains(addr))
+        {
+            return Some(rom.read_direct(addr - rom.base_address()));
+        }
+        self.program_ram
+            .as_ref()
+            .filter(|s| s.cm_rom_line == bank)
+            .and_then(|s| s.read_direct(addr))
+    }
+
+    /// Step one bus phase (1/8 of a machine cycle)
+    ///
+    /// Same ordering as [`crate::Mcs4System::step`]: the CPU drives the
+    /// address phases, program memory drives the memory phases, and RAM
+    /// responds before the CPU in the execution phases.
+    pub fn step(&mut self) {
+        let phase = self.cycle.phase;
+
+        match phase {
+            BusCycle::A1 | BusCycle::A2 | BusCycle::A3 => {
+                self.cpu.tick(phase, &mut self.bus, &mut self.control);
+                self.tick_program_memory(phase);
+                for ram in &mut self.ram {
+                    ram.tick_bus(phase, &mut self.bus, &self.control);
+                }
+            }
+
+            BusCycle::M1 | BusCycle::M2 => {
+                self.tick_program_memory(phase);
+                self.cpu.tick(phase, &mut self.bus, &mut self.control);
+            }
+
+            BusCycle::X1 | BusCycle::X2 | BusCycle::X3 => {
+                // CPU first so writes are on the bus at X2 and reads see
+                // what the chips drove at X2 when it samples at X3
+                self.cpu.tick(phase, &mut self.bus, &mut self.control);
+                for ram in &mut self.ram {
+                    ram.tick_bus(phase, &mut self.bus, &self.control);
+                }
+                self.tick_program_memory(phase);
+            }
+        }
+
+        self.cycle.advance();
+
+        if self.cycle.phase == BusCycle::A1 {
+            self.total_cycles += 1;
+        }
+    }
+
+    fn tick_program_memory(&mut self, phase: BusCycle) {
+        for rom in &mut self.rom {
+            rom.tick_bus(phase, &mut self.bus, &self.control);
+        }
+        for rom in &mut self.rom_4308 {
+            rom.tick_bus(phase, &mut self.bus, &self.control);
+        }
+        if let Some(smi) = self.program_ram.as_mut() {
+            smi.tick_bus(phase, &mut self.bus, &self.control);
+        }
+    }
+
+    /// Run for N machine cycles
+    pub fn run_cycles(&mut self, cycles: usize) {
+        for _ in 0..(cycles * 8) {
+            self.step();
+        }
+    }
+
+    /// Run until a breakpoint or cycle limit is reached
+    /// Returns true if breakpoint hit, false if limit reached
+    pub fn run_until_breakpoint(&mut self, max_cycles: u64) -> bool {
+        let start = self.total_cycles;
+        while self.total_cycles - start < max_cycles {
+            self.step();
+
+            if self.cycle.phase == BusCycle::A1 && !self.cpu.halted {
+                let pc = self.cpu.pc;
+                if self.breakpoints.contains(&pc) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Is the system at A1 of the first cycle of an instruction?
+    pub fn at_instruction_boundary(&self) -> bool {
+        self.cycle.phase == BusCycle::A1 && !self.cpu.timing.second_cycle()
+    }
+
+    /// Run one whole instruction (one idle cycle while halted)
+    ///
+    /// Returns the machine cycles it took. A system caught mid-instruction
+    /// only finishes that instruction.
+    pub fn step_instruction(&mut self) -> u8 {
+        let start = self.total_cycles;
+        self.step();
+        while !self.at_instruction_boundary() {
+            self.step();
+        }
+        (self.total_cycles - start) as u8
+    }
+
+    /// Run until the instruction at the PC has finished, running any
+    /// subroutine or interrupt handler it enters to completion
+    ///
+    /// Calls are matched by logical depth: JMS and interrupt entry one
+    /// deeper, BBL and BBS one shallower, including a JMS the full 7-level
+    /// stack refuses and a return from an empty one.
+    pub fn step_over(&mut self, max_cycles: u64) -> Step<u16> {
+        self.run_step(max_cycles, |_, depth| depth <= 0)
+    }
+
+    /// Run until the current subroutine or interrupt handler returns
+    pub fn step_out(&mut self, max_cycles: u64) -> Step<u16> {
+        self.run_step(max_cycles, |_, depth| depth < 0)
+    }
+
+    /// Run until the PC reaches `address` at an instruction boundary; at
+    /// least one instruction runs
+    pub fn run_to(&mut self, address: u16, max_cycles: u64) -> Step<u16> {
+        let address = address & 0x0FFF;
+        self.run_step(max_cycles, |sys, _| sys.pc() == address)
+    }
+
+    /// Run whole instructions until `finished` (given the system and the
+    /// call depth relative to the start) says the step is complete, a
+    /// breakpoint is reached or `max_cycles` have passed
+    fn run_step(&mut self, max_cycles: u64, mut finished: impl FnMut(&Self, i32) -> bool) -> Step<u16> {
+        let start = self.total_cycles;
+        let mut depth = 0;
+        while self.total_cycles - start < max_cycles {
+            let opcode = self
+                .at_instruction_boundary()
+                .then(|| self.read_rom(self.cpu.rom_bank, self.cpu.pc))
+                .flatten();
+            let before = self.cpu.stack.depth() as i32;
+            let (full, empty) = (self.cpu.stack.is_full(), self.cpu.stack.is_empty());
+            let faults = self.stack_fault_count();
+            self.step_instruction();
+
+            let moved = self.cpu.stack.depth() as i32 - before;
+            depth += match opcode {
+                // The stack refused the push or pop but the program still
+                // called or returned
+                Some(op) if moved == 0 && full && op >> 4 == 0x5 => 1,
+                Some(op) if moved == 0 && empty && (op >> 4 == 0xC || op == 0x02) => -1,
+                _ => moved,
+            };
+            if self.break_on_stack_fault && self.stack_fault_count() != faults {
+                if let Some(fault) = self.stack_faults().last() {
+                    return Step::Break(fault.pc());
+                }
+            }
+            if !self.cpu.halted && self.breakpoints.contains(&self.cpu.pc) {
+                return Step::Break(self.cpu.pc);
+            }
+            if finished(self, depth) {
+                return Step::Done;
+            }
+        }
+        Step::Limit
+    }
+
+    /// Add a breakpoint at the given address
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
+    }
+
+    /// Remove a breakpoint
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.retain(|&a| a != addr);
+    }
+
+    /// Clear all breakpoints
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Record JMS or interrupt entry with all seven stack levels in use and
+    /// BBL or BBS with none, instead of only logging them
+    ///
+    /// See [`mcs4_chips::stack_monitor`]. The monitor stays enabled across
+    /// [`Mcs40System::reset`].
+    pub fn enable_stack_monitor(&mut self) {
+        self.cpu.enable_stack_monitor();
+    }
+
+    pub fn disable_stack_monitor(&mut self) {
+        self.cpu.stack_monitor = None;
+        self.break_on_stack_fault = false;
+    }
+
+    /// Make [`Mcs40System::step_over`], [`Mcs40System::step_out`] and
+    /// [`Mcs40System::run_to`] stop with the faulting instruction's address
+    /// after a stack overflow or underflow; enables the monitor if needed
+    pub fn set_break_on_stack_fault(&mut self, enabled: bool) {
+        if enabled && self.cpu.stack_monitor.is_none() {
+            self.enable_stack_monitor();
+        }
+        self.break_on_stack_fault = enabled;
+    }
+
+    /// Stack faults recorded by the monitor, oldest first
+    pub fn stack_faults(&self) -> &[StackFault] {
+        self.cpu
+            .stack_monitor
+            .as_ref()
+            .map_or(&[], |monitor| monitor.faults())
+    }
+
+    fn stack_fault_count(&self) -> u64 {
+        self.cpu
+            .stack_monitor
+            .as_ref()
+            .map_or(0, |monitor| monitor.fault_count())
+    }
+
+    /// Drive the external interrupt request line (INT)
+    pub fn set_interrupt(&mut self, active: bool) {
+        self.control.set_interrupt(active, 0);
+    }
+
+    /// Drive the external STOP line
+    pub fn set_stop(&mut self, active: bool) {
+        self.control.set_stop(active, 0);
+    }
+
+    /// Is the CPU stopped (STP asserted by HLT or STOP)?
+    pub fn is_stopped(&self) -> bool {
+        self.control.stop_acknowledged()
+    }
+
+    /// Reset the system to initial state
+    pub fn reset(&mut self) {
+        let stack_monitor = self.cpu.stack_monitor.is_some();
+        self.cpu = I4040::new();
+        if stack_monitor {
+            self.cpu.enable_stack_monitor();
+        }
+        self.bus = DataBus::new();
+        self.control = ControlSignals::mcs40();
+        self.cycle = CycleState::new();
+        // Note: ROM and program RAM contents preserved, RAM and registers cleared
+        for ram in &mut self.ram {
+            *ram = I4002::new(ram.chip_id, ram.bank_id);
+        }
+    }
+
+    /// Set the CPU test pin
+    pub fn set_test_pin(&mut self, state: bool) {
+        self.cpu.set_test_pin(state);
+    }
+
+    /// Get current program counter
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc
+    }
+
+    /// Get accumulator value
+    pub fn accumulator(&self) -> u8 {
+        self.cpu.accumulator()
+    }
+
+    /// Get carry flag
+    pub fn carry(&self) -> bool {
+        self.cpu.carry()
+    }
+
+    /// Get total machine cycles executed
+    pub fn cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    /// Get current bus cycle phase
+    pub fn phase(&self) -> BusCycle {
+        self.cycle.phase
+    }
+
+    /// Read a register pair (0-7) in the current register bank
+    pub fn register_pair(&self, pair: u8) -> u8 {
+        self.cpu.regs.pair_byte(pair as usize & 0x07)
+    }
+
+    /// Read a single register (0-15) in the current register bank
+    pub fn register(&self, r: u8) -> u8 {
+        self.cpu.regs.get(r as usize & 0x0F)
+    }
+
+    /// Read RAM at given address (bank, chip, register, character)
+    pub fn read_ram(&self, bank: u8, chip: u8, reg: u8, char_addr: u8) -> Option<u8> {
+        self.ram
+            .iter()
+            .find(|r| r.bank_id == bank && r.chip_id == chip)
+            .map(|r| r.read_direct(reg, char_addr))
     }
 }
 
 impl Default for Mcs40System {
     fn default() -> Self {
-        Self::new()
+        Self::minimal()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_presets() {
+        let sys = Mcs40System::minimal();
+        assert_eq!(sys.rom.len(), 1);
+        assert_eq!(sys.ram.len(), 1);
+        assert!(sys.program_ram.is_none());
+
+        let sys = Mcs40System::standard();
+        assert_eq!((sys.rom.len(), sys.rom_4308.len(), sys.ram.len()), (4, 2, 8));
+        assert_eq!(sys.program_ram.as_ref().map(|s| s.chip_count()), Some(2));
+
+        let sys = Mcs40System::maximal();
+        assert_eq!((sys.rom.len(), sys.rom_4308.len(), sys.ram.len()), (0, 7, 16));
+        assert_eq!(sys.program_ram.as_ref().map(|s| s.chip_count()), Some(8));
+    }
+
+    #[test]
+    fn test_load_and_read_rom() {
+        let mut sys = Mcs40System::standard();
+        sys.load_rom_at(0, 0x0FE, &[0x11, 0x22, 0x33]);
+        sys.load_rom_at(0, 0x5A0, &[0x44]);
+        sys.load_rom_at(0, 0xF10, &[0x55]);
+        sys.load_rom_at(1, 0x3FF, &[0x66]);
+
+        assert_eq!(sys.read_rom(0, 0x0FF), Some(0x22));
+        assert_eq!(sys.read_rom(0, 0x100), Some(0x33));
+        assert_eq!(sys.read_rom(0, 0x5A0), Some(0x44));
+        assert_eq!(sys.read_rom(0, 0xF10), Some(0x55));
+        assert_eq!(sys.read_rom(1, 0x3FF), Some(0x66));
+        assert_eq!(sys.read_rom(0, 0x900), None);
+        assert_eq!(sys.read_rom(1, 0x400), None);
+    }
+
+    #[test]
+    fn test_ldm_instruction() {
+        let mut sys = Mcs40System::minimal();
+        sys.load_rom(&[0xD5]);
+        sys.run_cycles(1);
+        assert_eq!(sys.accumulator(), 5);
+    }
+
+    #[test]
+    fn test_runs_across_chip_types() {
+        let mut sys = Mcs40System::standard();
+
+        // 0x000 JUN 0x400 (4308); 0x400 JUN 0xF00 (4101 program RAM);
+        // 0xF00 JUN 0x010 (4001); 0x010 DB1; 0x011 in bank 1 (4308 on CM-ROM1)
+        sys.load_rom_at(0, 0x000, &[0x44, 0x00]);
+        sys.load_rom_at(0, 0x400, &[0x4F, 0x00]);
+        sys.load_rom_at(0, 0xF00, &[0x40, 0x10]);
+        sys.load_rom_at(0, 0x010, &[0x09]);
+        sys.load_rom_at(1, 0x011, &[0xD9]);
+
+        sys.run_cycles(4);
+        assert_eq!(sys.pc(), 0xF00);
+        sys.run_cycles(3);
+        assert_eq!(sys.pc(), 0x011);
+        assert_eq!(sys.cpu.rom_bank, 1);
+        sys.run_cycles(1);
+        assert_eq!(sys.accumulator(), 9);
+    }
+
+    #[test]
+    fn test_interrupt_line() {
+        let mut sys = Mcs40System::minimal();
+        // EIN; 0x001: JUN 0x001 ... 0x003 is the vector
+        let mut rom = [0u8; 0x10];
+        rom[..3].copy_from_slice(&[0x0C, 0x40, 0x01]);
+        rom[3] = 0xD7; // LDM 7 at the vector
+        sys.load_rom(&rom);
+        sys.run_cycles(5);
+        assert_eq!(sys.pc(), 0x001);
+
+        sys.set_interrupt(true);
+        sys.add_breakpoint(0x003);
+        assert!(sys.run_until_breakpoint(10));
+        sys.set_interrupt(false);
+        sys.run_cycles(1);
+        assert_eq!(sys.accumulator(), 7);
+        assert_eq!(sys.cpu.stack.peek(), Some(0x001));
+    }
+
+    #[test]
+    fn test_stop_line() {
+        let mut sys = Mcs40System::minimal();
+        // IAC repeated
+        sys.load_rom(&[0xF2; 0x100]);
+
+        sys.set_stop(true);
+        sys.run_cycles(4);
+        assert!(sys.is_stopped());
+        assert_eq!(sys.accumulator(), 1);
+
+        sys.set_stop(false);
+        sys.run_cycles(3);
+        assert!(!sys.is_stopped());
+        assert_eq!(sys.accumulator(), 3);
+    }
+
+    #[test]
+    fn test_io_commands() {
+        let mut sys = Mcs40System::minimal();
+        sys.rom[0].set_io_input(0xC);
+
+        // FIM P0, 0x04; SRC P0 (ROM 0; RAM chip 0, register 0, char 4)
+        // LDM 7; WRM; WRR; LDM 0; RDM; RDR
+        sys.load_rom(&[0x20, 0x04, 0x21, 0xD7, 0xE0, 0xE2, 0xD0, 0xE9, 0xEA]);
+        sys.run_cycles(6);
+        assert_eq!(sys.read_ram(0, 0, 0, 4), Some(0x7));
+        assert_eq!(sys.rom[0].io_output(), 0x7);
+
+        sys.run_cycles(2);
+        assert_eq!(sys.accumulator(), 0x7);
+        sys.run_cycles(1);
+        assert_eq!(sys.accumulator(), 0xC);
+    }
+
+    #[test]
+    fn test_program_ram_access() {
+        let mut sys = Mcs40System::standard();
+
+        // FIM P0, 0x20; SRC P0 (low address); LDM 0xF; WRR (page 0xF)
+        // LDM 0xA; WPM; LDM 5; WPM (upper then lower half)
+        // SRC P0; RPM; XCH R2; RPM; XCH R3
+        sys.load_rom(&[
+            0x20, 0x20, 0x21, 0xDF, 0xE2, 0xDA, 0xE3, 0xD5, 0xE3, 0x21, 0x0E, 0xB2, 0x0E, 0xB3,
+        ]);
+        sys.run_cycles(9);
+        assert_eq!(sys.read_rom(0, 0xF20), Some(0xA5));
+        assert_eq!(sys.program_ram.as_ref().unwrap().data_address(), 0xF20);
+
+        sys.run_cycles(5);
+        assert_eq!(sys.register(2), 0xA);
+        assert_eq!(sys.register(3), 0x5);
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut sys = Mcs40System::minimal();
+        sys.load_rom(&[0x00; 16]);
+        sys.add_breakpoint(4);
+
+        assert!(sys.run_until_breakpoint(100));
+        assert_eq!(sys.pc(), 4);
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let mut sys = Mcs40System::minimal();
+        // 0x000 JMS 0x010; LDM 1; 0x010 IAC; BBL 4
+        let mut rom = [0u8; 0x20];
+        rom[..3].copy_from_slice(&[0x50, 0x10, 0xD1]);
+        rom[0x10..0x12].copy_from_slice(&[0xF2, 0xC4]);
+        sys.load_rom(&rom);
+
+        assert_eq!(sys.step_over(100), Step::Done);
+        assert_eq!((sys.pc(), sys.accumulator()), (0x002, 4));
+        assert_eq!(sys.step_over(100), Step::Done);
+        assert_eq!(sys.accumulator(), 1);
+
+        sys.reset();
+        assert_eq!(sys.step_instruction(), 2);
+        assert_eq!(sys.pc(), 0x010);
+        assert_eq!(sys.step_out(100), Step::Done);
+        assert_eq!(sys.pc(), 0x002);
+
+        assert_eq!(sys.run_to(0x100, 20), Step::Limit);
+        sys.reset();
+        sys.add_breakpoint(0x011);
+        assert_eq!(sys.run_to(0x002, 20), Step::Break(0x011));
+        assert_eq!(sys.run_to(0x002, 20), Step::Done);
+    }
+
+    #[test]
+    fn test_step_over_full_stack() {
+        // Eight nested calls, one more than the stack holds: each JMS at
+        // 3n calls 3n+3 and is followed by BBL; 0x018 is the innermost BBL
+        let mut rom = [0u8; 0x20];
+        for i in 0..8 {
+            let at = i * 3;
+            rom[at] = 0x50;
+            rom[at + 1] = (at + 3) as u8;
+            rom[at + 2] = 0xC0;
+        }
+        rom[0x18] = 0xC0;
+        let mut sys = Mcs40System::minimal();
+        sys.load_rom(&rom);
+
+        // The refused eighth push leaves the stack one short, so the eighth
+        // BBL (at 0x002) finds it empty; the step still ends there
+        assert_eq!(sys.step_over(200), Step::Done);
+        assert_eq!(sys.pc(), 0x003);
+        assert!(sys.cpu.stack.is_empty());
+        assert_eq!(sys.cycles(), 8 * 2 + 8);
+    }
+
+    #[test]
+    fn test_break_on_stack_fault() {
+        // The program from test_step_over_full_stack: the eighth JMS (at
+        // 0x015) is refused and the eighth BBL (at 0x002) finds no return
+        let mut rom = [0u8; 0x20];
+        for i in 0..8 {
+            let at = i * 3;
+            rom[at] = 0x50;
+            rom[at + 1] = (at + 3) as u8;
+            rom[at + 2] = 0xC0;
+        }
+        rom[0x18] = 0xC0;
+        let mut sys = Mcs40System::minimal();
+        sys.load_rom(&rom);
+        sys.set_break_on_stack_fault(true);
+
+        assert_eq!(sys.step_over(200), Step::Break(0x015));
+        assert_eq!(sys.pc(), 0x018);
+        assert_eq!(sys.run_to(0x100, 200), Step::Break(0x002));
+        assert_eq!(
+            sys.stack_faults(),
+            &[
+                StackFault::Overflow { pc: 0x015, lost: 0x017 },
+                StackFault::Underflow { pc: 0x002 },
+            ]
+        );
     }
 }
@@ -0,0 +1,390 @@
+//! Devices wired to the chips' I/O ports
+//!
+//! A [`Peripheral`] attached to an [`crate::Mcs4System`] sees the 4001 and
+//! 4002 output ports and drives the 4001 input ports and the CPU TEST pin.
+//! The system updates every peripheral at each instruction boundary, in
+//! both execution modes, so a port write is seen before the next
+//! instruction runs and lockstep runs stay in step.
+//!
+//! A [`ShiftRegister`] is a chain of 4003s with its clock, data and enable
+//! pins on output port bits, as the Busicom 141-PF drives its keyboard
+//! columns and printer hammers.
+//!
+//! Peripherals are cloned into every [`crate::SystemSnapshot`], so
+//! restoring a snapshot or stepping back returns each device (a 4003
+//! chain's contents, a printer's output) to its state at that point.
+//! Snapshot archives leave them out.
+
+use std::any::Any;
+
+use mcs4_chips::{i4001::I4001, i4002::I4002, i4003::I4003, i4004::I4004};
+use serde::Deserialize;
+
+/// An I/O port by the chip that owns it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum Port {
+    /// The 4-bit port of the 4001 with this chip ID (input or output by
+    /// mask option)
+    Rom(u8),
+    /// The 4-bit output port of a 4002
+    Ram { bank: u8, chip: u8 },
+}
+
+impl std::fmt::Display for Port {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Port::Rom(chip) => write!(f, "4001 chip {}", chip),
+            Port::Ram { bank, chip } => write!(f, "4002 bank {} chip {}", bank, chip),
+        }
+    }
+}
+
+/// One bit (0-3) of an output port, wired to a pin
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortBit {
+    pub port: Port,
+    pub bit: u8,
+}
+
+impl PortBit {
+    pub fn new(port: Port, bit: u8) -> Self {
+        Self { port, bit }
+    }
+}
+
+impl std::fmt::Display for PortBit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bit {}", self.port, self.bit)
+    }
+}
+
+/// The pins a peripheral can reach during an update
+pub struct Ports<'a> {
+    pub(crate) cpu: &'a mut I4004,
+    pub(crate) rom: &'a mut [I4001],
+    pub(crate) ram: &'a [I4002],
+    pub(crate) cycles: u64,
+}
+
+impl Ports<'_> {
+    /// Machine cycles since reset
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Value on an output port, or `None` if the chip is not fitted
+    pub fn output(&self, port: Port) -> Option<u8> {
+        match port {
+            Port::Rom(chip) => self
+                .rom
+                .iter()
+                .find(|rom| rom.chip_id() == chip)
+                .map(|rom| rom.io_output()),
+            Port::Ram { bank, chip } => self
+                .ram
+                .iter()
+                .find(|ram| ram.bank_id == bank && ram.chip_id == chip)
+                .map(|ram| ram.output()),
+        }
+    }
+
+    /// Level of one output port bit; low if the chip is not fitted
+    pub fn output_bit(&self, pin: PortBit) -> bool {
+        self.output(pin.port)
+            .is_some_and(|value| value & 1 << pin.bit != 0)
+    }
+
+    /// Drive the input port of a 4001; `false` if it is not fitted
+    pub fn set_input(&mut self, chip: u8, value: u8) -> bool {
+        match self.rom.iter_mut().find(|rom| rom.chip_id() == chip) {
+            Some(rom) => {
+                rom.set_io_input(value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn test_pin(&self) -> bool {
+        self.cpu.test_pin()
+    }
+
+    pub fn set_test_pin(&mut self, level: bool) {
+        self.cpu.set_test_pin(level);
+    }
+}
+
+/// A device wired to the system's ports
+pub trait Peripheral: PeripheralClone + Any + Send {
+    /// React to the ports at an instruction boundary
+    fn update(&mut self, ports: &mut Ports<'_>);
+}
+
+/// Cloning for boxed peripherals, so systems with devices attached can be
+/// cloned; implemented for every `Peripheral + Clone`
+pub trait PeripheralClone {
+    fn clone_box(&self) -> Box<dyn Peripheral>;
+}
+
+impl<T: Peripheral + Clone> PeripheralClone for T {
+    fn clone_box(&self) -> Box<dyn Peripheral> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Peripheral> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Records every change of an output port
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortProbe {
+    pub port: Port,
+    /// `(cycle, value)` for each change, starting with the first value seen
+    changes: Vec<(u64, u8)>,
+}
+
+impl PortProbe {
+    pub fn new(port: Port) -> Self {
+        Self {
+            port,
+            changes: Vec::new(),
+        }
+    }
+
+    /// The port's changes as `(cycle, value)`, oldest first
+    pub fn changes(&self) -> &[(u64, u8)] {
+        &self.changes
+    }
+
+    /// The last value seen
+    pub fn value(&self) -> Option<u8> {
+        self.changes.last().map(|&(_, value)| value)
+    }
+}
+
+impl Peripheral for PortProbe {
+    fn update(&mut self, ports: &mut Ports<'_>) {
+        let Some(value) = ports.output(self.port) else {
+            return;
+        };
+        if self.value() != Some(value) {
+            self.changes.push((ports.cycles(), value));
+        }
+    }
+}
+
+/// Fixed levels on a 4001 input port, like a DIP switch
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Switches {
+    pub chip: u8,
+    pub value: u8,
+}
+
+impl Peripheral for Switches {
+    fn update(&mut self, ports: &mut Ports<'_>) {
+        ports.set_input(self.chip, self.value & 0x0F);
+    }
+}
+
+/// 4003s chained off output port bits
+///
+/// Every chip shares the clock and enable; the first takes its data from
+/// the data bit and each later one from the serial output before it. The
+/// pins are sampled at the instruction boundary after a port write, not
+/// at its X2, so a shift lands at the end of the write that raises the
+/// clock. As on the real part, data must be set up first: the edge shifts
+/// in the data level from before that write.
+#[derive(Clone, Debug)]
+pub struct ShiftRegister {
+    pub clock: PortBit,
+    pub data: PortBit,
+    /// Output enable; tied high when `None`
+    pub enable: Option<PortBit>,
+    chips: Vec<I4003>,
+}
+
+impl ShiftRegister {
+    /// Most 4003s in a chain: [`ShiftRegister::outputs`] holds 60 bits
+    pub const MAX_CHIPS: usize = 6;
+
+    pub fn new(clock: PortBit, data: PortBit, chips: usize) -> Self {
+        assert!((1..=Self::MAX_CHIPS).contains(&chips), "{} 4003s", chips);
+        Self {
+            clock,
+            data,
+            enable: None,
+            chips: vec![I4003::new(); chips],
+        }
+    }
+
+    pub fn with_enable(mut self, enable: PortBit) -> Self {
+        self.enable = Some(enable);
+        self
+    }
+
+    /// The chain, from the one on the data bit
+    pub fn chips(&self) -> &[I4003] {
+        &self.chips
+    }
+
+    /// Parallel outputs of the chain: Q0-Q9 of the first 4003 in bits
+    /// 0-9, of the second in bits 10-19, and so on
+    pub fn outputs(&self) -> u64 {
+        self.chips.iter().enumerate().fold(0, |outputs, (n, chip)| {
+            outputs | (chip.parallel_out() as u64) << (10 * n)
+        })
+    }
+
+    /// Serial output of the last 4003
+    pub fn serial_out(&self) -> bool {
+        self.chips.last().is_some_and(I4003::serial_out)
+    }
+}
+
+impl Peripheral for ShiftRegister {
+    fn update(&mut self, ports: &mut Ports<'_>) {
+        let clock = ports.output_bit(self.clock);
+        let enable = self.enable.is_none_or(|pin| ports.output_bit(pin));
+        for chip in &mut self.chips {
+            chip.set_clock(clock);
+            chip.set_enable(enable);
+        }
+        let mut data = ports.output_bit(self.data);
+        for chip in &mut self.chips {
+            chip.set_data_in(data);
+            data = chip.serial_out();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcs4::{ExecutionMode, Mcs4System};
+    use crate::rewind::RewindConfig;
+
+    #[test]
+    fn test_probe_and_switches() {
+        let mut sys = Mcs4System::standard();
+        sys.enable_rewind(RewindConfig::default());
+        sys.attach(Switches {
+            chip: 1,
+            value: 0x6,
+        });
+        sys.attach(PortProbe::new(Port::Ram { bank: 0, chip: 0 }));
+        // FIM P0, 0x10; SRC P0; RDR; FIM P1, 0x00; SRC P1; WMP; IAC; WMP
+        sys.load_rom(&[0x20, 0x10, 0x21, 0xEA, 0x22, 0x00, 0x23, 0xE1, 0xF2, 0xE1]);
+        let mut fast = sys.clone();
+        fast.set_mode(ExecutionMode::Instruction);
+        sys.run_cycles(11);
+        fast.run_cycles(11);
+        assert_eq!(sys.accumulator(), 0x7);
+
+        // The write is seen at the next instruction boundary
+        for sys in [&sys, &fast] {
+            let probe = sys.peripheral::<PortProbe>().unwrap();
+            assert_eq!(probe.changes(), [(0, 0), (8, 6), (10, 7)]);
+        }
+
+        // Stepping back takes the devices back too: at the boundary before
+        // the last NOP the probe has yet to see the second write
+        assert!(sys.step_back());
+        let probe = sys.peripheral::<PortProbe>().unwrap();
+        assert_eq!(probe.changes(), [(0, 0), (8, 6)]);
+        sys.step_instruction();
+        let probe = sys.peripheral::<PortProbe>().unwrap();
+        assert_eq!(probe.changes(), [(0, 0), (8, 6), (10, 7)]);
+
+        // Restoring a snapshot brings back the devices it captured
+        let snapshot = sys.snapshot();
+        sys.run_cycles(4);
+        sys.peripheral_mut::<Switches>().unwrap().value = 0x9;
+        sys.restore(&snapshot);
+        assert_eq!(sys.peripheral::<Switches>().unwrap().value, 0x6);
+        sys.peripheral_mut::<Switches>().unwrap().value = 0x2;
+        sys.reset();
+        sys.run_cycles(5);
+        assert_eq!(sys.accumulator(), 0x2);
+    }
+
+    #[test]
+    fn test_shift_register_chain() {
+        let rom0 = |bit| PortBit::new(Port::Rom(0), bit);
+        let mut sys = Mcs4System::standard();
+        sys.attach(
+            ShiftRegister::new(rom0(0), rom0(1), 2)
+                .with_enable(PortBit::new(Port::Ram { bank: 0, chip: 0 }, 0)),
+        );
+        // SRC ROM 0 and RAM 0; enable the outputs
+        // 12 times: data 1 with the clock low, clock high, then data 0
+        // Data and clock together: the 1 is not set up in time; then
+        // disable the outputs
+        let mut program = vec![0x20, 0x00, 0x21, 0xD1, 0xE1];
+        for _ in 0..12 {
+            program.extend([0xD2, 0xE2, 0xD3, 0xE2, 0xD0, 0xE2]);
+        }
+        program.extend([0xD3, 0xE2, 0xD0, 0xE2, 0xE1]);
+        let halt = program.len() as u8;
+        program.extend([0x40, halt]);
+        sys.load_rom(&program);
+        sys.run_until_break(200);
+
+        let chain = sys.peripheral::<ShiftRegister>().unwrap();
+        assert_eq!(chain.chips()[0].contents(), 0x3FE);
+        assert_eq!(chain.chips()[1].contents(), 0x7);
+        assert_eq!(chain.outputs(), 0);
+        assert!(!chain.serial_out());
+    }
+
+    #[test]
+    fn test_shift_register_rewind() {
+        let rom0 = |bit| PortBit::new(Port::Rom(0), bit);
+        let mut sys = Mcs4System::standard();
+        sys.enable_rewind(RewindConfig {
+            keyframe_interval: 16,
+            ..RewindConfig::default()
+        });
+        sys.attach(ShiftRegister::new(rom0(0), rom0(1), 1));
+        // FIM P0, 0x00; SRC P0; LDM 8; XCH R2
+        // 8 times: data 1, clock high, both low; then halt
+        sys.load_rom(&[
+            0x20, 0x00, 0x21, 0xD8, 0xB2, 0xD2, 0xE2, 0xD3, 0xE2, 0xD0, 0xE2, 0x72, 0x05, 0x40,
+            0x0D,
+        ]);
+        let fresh = sys.clone();
+        let mut straight = sys.clone();
+        straight.run_cycles(80);
+        let expected = straight.peripheral::<ShiftRegister>().unwrap().outputs();
+        assert_eq!(expected, 0xFF);
+
+        // Going back and running again does not shift the bits in twice
+        sys.run_cycles(60);
+        for _ in 0..20 {
+            assert!(sys.step_back());
+        }
+        let chain = sys.peripheral::<ShiftRegister>().unwrap().outputs();
+        assert!(chain < 0x1F, "{:03X}", chain);
+        sys.run_cycles((80 - sys.cycles()) as usize);
+        assert_eq!(
+            sys.peripheral::<ShiftRegister>().unwrap().outputs(),
+            expected
+        );
+
+        // Nor does restoring a snapshot
+        let mut sys = fresh;
+        sys.run_cycles(30);
+        let snapshot = sys.snapshot();
+        sys.run_cycles(50);
+        sys.restore(&snapshot);
+        sys.run_cycles(50);
+        assert_eq!(
+            sys.peripheral::<ShiftRegister>().unwrap().outputs(),
+            expected
+        );
+    }
+}
@@ -0,0 +1,379 @@
+//! Rewind buffer for reverse execution
+//!
+//! While rewind is enabled, [`Mcs4System`] records every instruction
+//! boundary it passes. Every `keyframe_interval` instructions the record is
+//! a full [`SystemSnapshot`]; in between it is a delta of the instruction's
+//! address plus any change to the external inputs (TEST pin, 4001 port
+//! inputs, execution mode). Going back restores the nearest earlier
+//! keyframe and replays forward with the recorded inputs, which reproduces
+//! the original run exactly because execution is deterministic. Keyframes
+//! hold the attached peripherals too, and they run again while replaying,
+//! so device state goes back with the chips. Inputs
+//! are sampled at instruction boundaries, so stimulus should change between
+//! instructions; anything else written into the chips from outside is not
+//! recorded.
+//!
+//! The oldest keyframes (and the deltas that depend on them) are dropped
+//! to stay within the memory budget, so how far back you can go depends on
+//! the budget and the keyframe interval.
+
+use std::collections::VecDeque;
+use std::mem::size_of;
+
+use mcs4_chips::{i4001::I4001, i4002::I4002};
+
+use crate::mcs4::{ExecutionMode, Mcs4System};
+use crate::snapshot::SystemSnapshot;
+
+/// Rewind buffer settings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RewindConfig {
+    /// Instructions between full snapshots; fewer means faster rewinding
+    /// but more memory per instruction
+    pub keyframe_interval: u64,
+    /// Approximate bytes the buffer may hold before dropping its oldest
+    /// keyframes
+    pub memory_budget: usize,
+}
+
+impl Default for RewindConfig {
+    fn default() -> Self {
+        Self {
+            keyframe_interval: 1000,
+            memory_budget: 16 << 20,
+        }
+    }
+}
+
+/// External inputs in effect for an instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Inputs {
+    pub test_pin: bool,
+    /// 4001 port inputs, one nibble per chip in `Mcs4System::rom` order
+    pub rom_inputs: u64,
+    pub mode: ExecutionMode,
+}
+
+impl Inputs {
+    pub fn capture(sys: &Mcs4System) -> Self {
+        let rom_inputs = sys
+            .rom
+            .iter()
+            .take(16)
+            .enumerate()
+            .fold(0, |acc, (i, rom)| acc | (rom.io_input() as u64) << (i * 4));
+        Self {
+            test_pin: sys.cpu.test_pin(),
+            rom_inputs,
+            mode: sys.mode(),
+        }
+    }
+
+    pub fn apply(&self, sys: &mut Mcs4System) {
+        sys.set_test_pin(self.test_pin);
+        for (i, rom) in sys.rom.iter_mut().take(16).enumerate() {
+            rom.set_io_input((self.rom_inputs >> (i * 4)) as u8 & 0x0F);
+        }
+        if sys.mode() != self.mode {
+            sys.set_mode(self.mode);
+        }
+    }
+}
+
+/// Recorded instruction boundaries of one run
+///
+/// Boundary `n` is the state just before the `n`th recorded instruction
+/// runs; [`RewindBuffer::position`] is the boundary the system is at (or
+/// the start of the instruction it is in the middle of, plus one).
+#[derive(Clone)]
+pub struct RewindBuffer {
+    config: RewindConfig,
+    /// Index of the first boundary in `pcs`
+    base: u64,
+    /// Address of each recorded instruction
+    pcs: VecDeque<u16>,
+    /// Boundary index and the input values from there on
+    inputs: VecDeque<(u64, Inputs)>,
+    /// Boundary index and the full state there; the first is at `base`
+    keyframes: VecDeque<(u64, SystemSnapshot)>,
+    keyframe_bytes: usize,
+}
+
+impl RewindBuffer {
+    pub(crate) fn new(config: RewindConfig) -> Self {
+        Self {
+            config: RewindConfig {
+                keyframe_interval: config.keyframe_interval.max(1),
+                ..config
+            },
+            base: 0,
+            pcs: VecDeque::new(),
+            inputs: VecDeque::new(),
+            keyframes: VecDeque::new(),
+            keyframe_bytes: 0,
+        }
+    }
+
+    /// Settings in use
+    pub fn config(&self) -> RewindConfig {
+        self.config
+    }
+
+    /// Earliest boundary that can still be reached
+    pub fn oldest(&self) -> u64 {
+        self.base
+    }
+
+    /// Number of instructions recorded so far (the next boundary index)
+    pub fn position(&self) -> u64 {
+        self.base + self.pcs.len() as u64
+    }
+
+    /// Address of the instruction recorded at boundary `index`
+    pub fn pc_at(&self, index: u64) -> Option<u16> {
+        let offset = index.checked_sub(self.base)?;
+        self.pcs.get(offset as usize).copied()
+    }
+
+    /// Approximate memory held by the buffer, in bytes
+    pub fn memory_used(&self) -> usize {
+        self.keyframe_bytes
+            + self.pcs.len() * size_of::<u16>()
+            + self.inputs.len() * size_of::<(u64, Inputs)>()
+    }
+
+    /// Record the boundary the system is at, before its next instruction
+    pub(crate) fn record(&mut self, sys: &Mcs4System) {
+        let index = self.position();
+        if self.keyframes.is_empty() || index.is_multiple_of(self.config.keyframe_interval) {
+            let snapshot = sys.snapshot();
+            self.keyframe_bytes += footprint(snapshot.system());
+            self.keyframes.push_back((index, snapshot));
+        }
+        let inputs = Inputs::capture(sys);
+        if self.inputs.back().map(|&(_, last)| last) != Some(inputs) {
+            self.inputs.push_back((index, inputs));
+        }
+        self.pcs.push_back(sys.pc());
+        self.trim();
+    }
+
+    /// Drop the oldest keyframes, with their deltas, while over budget
+    fn trim(&mut self) {
+        while self.keyframes.len() > 1 && self.memory_used() > self.config.memory_budget {
+            if let Some((_, dropped)) = self.keyframes.pop_front() {
+                self.keyframe_bytes -= footprint(dropped.system());
+            }
+            let new_base = self.keyframes[0].0;
+            self.pcs.drain(..(new_base - self.base) as usize);
+            self.base = new_base;
+            // Keep the inputs in effect at the new base
+            while self.inputs.len() > 1 && self.inputs[1].0 <= new_base {
+                self.inputs.pop_front();
+            }
+        }
+    }
+
+    /// Boundary indices of the keyframes, oldest first
+    pub(crate) fn keyframe_indices(&self) -> impl DoubleEndedIterator<Item = u64> + '_ {
+        self.keyframes.iter().map(|(at, _)| *at)
+    }
+
+    /// Latest keyframe at or before boundary `index`
+    pub(crate) fn keyframe_for(&self, index: u64) -> Option<&(u64, SystemSnapshot)> {
+        self.keyframes.iter().rev().find(|(at, _)| *at <= index)
+    }
+
+    /// Inputs in effect at boundary `index`
+    pub(crate) fn inputs_at(&self, index: u64) -> Option<Inputs> {
+        self.inputs
+            .iter()
+            .rev()
+            .find(|(at, _)| *at <= index)
+            .map(|&(_, inputs)| inputs)
+    }
+
+    /// Forget everything from boundary `index` on; execution from there
+    /// records afresh
+    pub(crate) fn truncate(&mut self, index: u64) {
+        let keep = index.saturating_sub(self.base) as usize;
+        self.pcs.truncate(keep);
+        self.inputs.retain(|(at, _)| *at < index);
+        while self.keyframes.back().is_some_and(|(at, _)| *at >= index) {
+            if let Some((_, dropped)) = self.keyframes.pop_back() {
+                self.keyframe_bytes -= footprint(dropped.system());
+            }
+        }
+        if self.keyframes.is_empty() {
+            self.base = index;
+            self.pcs.clear();
+            self.inputs.clear();
+        }
+    }
+}
+
+/// Approximate heap and inline size of a snapshot's system
+fn footprint(sys: &Mcs4System) -> usize {
+    size_of::<Mcs4System>()
+        + sys.rom.len() * size_of::<I4001>()
+        + sys.ram.len() * size_of::<I4002>()
+        + sys.rom_image().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{sample_system, state};
+
+    fn check_step_back(mode: ExecutionMode) {
+        let mut sys = sample_system();
+        sys.set_mode(mode);
+        sys.enable_rewind(RewindConfig {
+            keyframe_interval: 16,
+            ..RewindConfig::default()
+        });
+
+        let mut states = Vec::new();
+        for _ in 0..100 {
+            states.push(state(&sys));
+            sys.step_instruction();
+        }
+        for expected in states.iter().rev() {
+            assert!(sys.step_back());
+            assert_eq!(&state(&sys), expected);
+        }
+        assert!(!sys.step_back());
+
+        // Running forward again retraces the same path
+        for expected in &states[1..] {
+            sys.step_instruction();
+            assert_eq!(&state(&sys), expected);
+        }
+    }
+
+    #[test]
+    fn test_step_back_phase_accurate() {
+        check_step_back(ExecutionMode::PhaseAccurate);
+    }
+
+    #[test]
+    fn test_step_back_instruction_mode() {
+        check_step_back(ExecutionMode::Instruction);
+    }
+
+    #[test]
+    fn test_step_back_mid_instruction() {
+        let mut sys = Mcs4System::minimal();
+        // LDM 3; FIM P0, 0x42
+        sys.load_rom(&[0xD3, 0x20, 0x42]);
+        sys.enable_rewind(RewindConfig::default());
+        sys.step_instruction();
+        for _ in 0..11 {
+            sys.step();
+        }
+        // Back to the start of FIM, then before LDM
+        assert!(sys.step_back());
+        assert!(sys.at_instruction_boundary());
+        assert_eq!((sys.pc(), sys.accumulator()), (0x001, 3));
+        assert!(sys.step_back());
+        assert_eq!((sys.pc(), sys.accumulator()), (0x000, 0));
+    }
+
+    #[test]
+    fn test_find_last_write() {
+        let mut sys = Mcs4System::standard();
+        // FIM P0, 0x05; SRC P0; LDM 1; WRM; LDM 2; WRM; LDM 7; WRM; NOP x4
+        sys.load_rom(&[
+            0x20, 0x05, 0x21, 0xD1, 0xE0, 0xD2, 0xE0, 0xD7, 0xE0, 0x00, 0x00, 0x00, 0x00,
+        ]);
+        sys.enable_rewind(RewindConfig::default());
+        sys.run_cycles(13);
+        assert_eq!(sys.read_ram(0, 0, 0, 5), Some(7));
+
+        // Step back until the character had a different value: the next
+        // instruction is the one that wrote it
+        let value = sys.read_ram(0, 0, 0, 5);
+        assert!(sys.run_back_until(|s| s.read_ram(0, 0, 0, 5) != value));
+        assert_eq!(sys.pc(), 0x008);
+        assert_eq!(sys.read_ram(0, 0, 0, 5), Some(2));
+
+        assert!(!sys.run_back_until(|s| s.accumulator() == 9));
+        assert_eq!(sys.pc(), 0x000);
+    }
+
+    #[test]
+    fn test_run_back_to_breakpoint() {
+        let mut sys = sample_system();
+        sys.enable_rewind(RewindConfig {
+            keyframe_interval: 8,
+            ..RewindConfig::default()
+        });
+        sys.run_cycles(120);
+        let pcs: Vec<u16> = {
+            let rewind = sys.rewind().unwrap();
+            (0..rewind.position()).filter_map(|i| rewind.pc_at(i)).collect()
+        };
+        let target = pcs[30];
+        let last = pcs.iter().rposition(|&pc| pc == target).unwrap();
+        sys.add_breakpoint(target);
+        assert!(sys.run_back_to_breakpoint());
+        assert_eq!(sys.pc(), target);
+        assert_eq!(sys.rewind().unwrap().position(), last as u64);
+
+        sys.clear_breakpoints();
+        assert!(!sys.run_back_to_breakpoint());
+        assert_eq!(sys.cycles(), 0);
+    }
+
+    #[test]
+    fn test_replays_inputs() {
+        let mut sys = Mcs4System::standard();
+        sys.rom[1].set_io_input(0x4);
+        // FIM P0, 0x10; SRC P0; RDR; XCH R2; JCN T, 0x08; LDM 1; LDM 2; RDR
+        sys.load_rom(&[0x20, 0x10, 0x21, 0xEA, 0xB2, 0x11, 0x08, 0xD1, 0xD2, 0xEA]);
+        sys.enable_rewind(RewindConfig::default());
+        let mut states = Vec::new();
+        for _ in 0..4 {
+            states.push(state(&sys));
+            sys.step_instruction();
+        }
+        sys.set_test_pin(true);
+        sys.rom[1].set_io_input(0x9);
+        for _ in 0..3 {
+            states.push(state(&sys));
+            sys.step_instruction();
+        }
+        assert_eq!((sys.pc(), sys.accumulator(), sys.register(2)), (0x00A, 0x9, 0x4));
+
+        // Each replayed instruction sees the inputs it saw the first time
+        sys.set_test_pin(false);
+        for expected in states.iter().rev() {
+            assert!(sys.step_back());
+            assert_eq!(&state(&sys), expected);
+        }
+        assert!(!sys.cpu.test_pin());
+        assert_eq!(sys.rom[1].io_input(), 0x4);
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let mut sys = sample_system();
+        let keyframe = footprint(&sys);
+        sys.enable_rewind(RewindConfig {
+            keyframe_interval: 10,
+            memory_budget: keyframe * 3,
+        });
+        sys.run_cycles(400);
+
+        let rewind = sys.rewind().unwrap();
+        assert!(rewind.memory_used() <= keyframe * 3);
+        let oldest = rewind.oldest();
+        assert!(oldest > 0 && oldest.is_multiple_of(10));
+        let mut steps = 0;
+        while sys.step_back() {
+            steps += 1;
+        }
+        assert_eq!(sys.rewind().unwrap().position(), oldest);
+        assert!(steps >= 10);
+    }
+}
@@ -0,0 +1,380 @@
+//! Headless runs with scripted stimulus
+//!
+//! [`run`] drives an [`Mcs4System`] without a front end, for firmware
+//! tests: it applies a [`Stimulus`] script as the cycle count passes each
+//! event, and stops at a break, a target PC, a halt or a cycle limit.
+//! [`state_json`] dumps what the test wants to check afterwards.
+//!
+//! A stimulus file has one event per line, `CYCLE ACTION`, with `#`
+//! starting a comment:
+//!
+//! ```text
+//! # hold TEST high until the first key scan
+//! 0     test 1
+//! 1200  test 0
+//! 1500  port 2 0x5    # 4001 chip 2 input port
+//! ```
+//!
+//! Events are applied between instructions, at the first instruction
+//! boundary at or after their cycle.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::debugger::StopReason;
+use crate::mcs4::Mcs4System;
+
+/// A change to an external input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Drive the CPU TEST pin
+    Test(bool),
+    /// Drive the input port of the 4001 with this chip ID
+    RomPort { chip: u8, value: u8 },
+}
+
+/// An [`Action`] due at a machine cycle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub cycle: u64,
+    pub action: Action,
+}
+
+/// Error in a stimulus file
+#[derive(Debug)]
+pub enum StimulusError {
+    /// The file could not be read
+    Io(io::Error),
+    /// A line is malformed
+    Syntax { line: usize, message: String },
+}
+
+impl fmt::Display for StimulusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StimulusError::Io(err) => write!(f, "cannot read stimulus: {}", err),
+            StimulusError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for StimulusError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StimulusError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Timed input events, in cycle order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stimulus {
+    events: Vec<Event>,
+}
+
+impl Stimulus {
+    /// Parse a stimulus script; events on the same cycle keep file order
+    pub fn parse(text: &str) -> Result<Self, StimulusError> {
+        let mut events = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line_no = index + 1;
+            let syntax = |message: String| StimulusError::Syntax {
+                line: line_no,
+                message,
+            };
+            let code = line.split('#').next().unwrap_or_default();
+            let fields: Vec<&str> = code.split_whitespace().collect();
+            let Some((&cycle, rest)) = fields.split_first() else {
+                continue;
+            };
+            let cycle = parse_number(cycle)
+                .ok_or_else(|| syntax(format!("expected a cycle count, found '{}'", cycle)))?;
+            let action = match rest {
+                ["test", level] => match parse_number(level) {
+                    Some(0) => Action::Test(false),
+                    Some(1) => Action::Test(true),
+                    _ => {
+                        return Err(syntax(format!(
+                            "TEST level must be 0 or 1, found '{}'",
+                            level
+                        )))
+                    }
+                },
+                ["port", chip, value] => {
+                    let chip = parse_number(chip)
+                        .filter(|&chip| chip < 16)
+                        .ok_or_else(|| {
+                            syntax(format!("4001 chip ID must be 0-15, found '{}'", chip))
+                        })?;
+                    let value =
+                        parse_number(value)
+                            .filter(|&value| value < 16)
+                            .ok_or_else(|| {
+                                syntax(format!("port value must be 0-15, found '{}'", value))
+                            })?;
+                    Action::RomPort {
+                        chip: chip as u8,
+                        value: value as u8,
+                    }
+                }
+                _ => return Err(syntax("expected 'test LEVEL' or 'port CHIP VALUE'".into())),
+            };
+            events.push(Event { cycle, action });
+        }
+        events.sort_by_key(|event| event.cycle);
+        Ok(Self { events })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, StimulusError> {
+        Self::parse(&fs::read_to_string(path).map_err(StimulusError::Io)?)
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+}
+
+/// Decimal, or hex with a `0x` prefix
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// When a headless run stops
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RunOptions {
+    /// Machine cycles to run at most
+    pub max_cycles: u64,
+    /// Stop when the PC reaches this address
+    pub until_pc: Option<u16>,
+    /// Stop at a `JUN` to itself, the usual way 4004 firmware stops
+    pub until_halt: bool,
+}
+
+/// Why a headless run stopped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// A break in the system's debugger triggered
+    Break(StopReason),
+    /// The PC reached [`RunOptions::until_pc`]
+    Pc(u16),
+    /// The CPU is spinning on a `JUN` to this address
+    Halt(u16),
+    /// [`RunOptions::max_cycles`] passed first
+    Limit,
+}
+
+impl RunOutcome {
+    /// `break`, `pc`, `halt` or `limit`
+    pub fn reason(&self) -> &'static str {
+        match self {
+            RunOutcome::Break(_) => "break",
+            RunOutcome::Pc(_) => "pc",
+            RunOutcome::Halt(_) => "halt",
+            RunOutcome::Limit => "limit",
+        }
+    }
+}
+
+impl fmt::Display for RunOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunOutcome::Break(stop) => write!(f, "{}", stop),
+            RunOutcome::Pc(pc) => write!(f, "reached 0x{:03X}", pc),
+            RunOutcome::Halt(pc) => write!(f, "halted at 0x{:03X}", pc),
+            RunOutcome::Limit => write!(f, "cycle limit reached"),
+        }
+    }
+}
+
+/// Run `sys` under `stimulus` until one of `options`' conditions holds
+///
+/// Event cycles are absolute, counted from reset; events already due are
+/// applied before the first instruction. Breaks come from the system's
+/// [`crate::Debugger`].
+pub fn run(sys: &mut Mcs4System, stimulus: &Stimulus, options: &RunOptions) -> RunOutcome {
+    let end = sys.cycles().saturating_add(options.max_cycles);
+    let mut pending = stimulus.events.iter().peekable();
+    loop {
+        while let Some(event) = pending.next_if(|event| event.cycle <= sys.cycles()) {
+            apply(sys, event.action);
+        }
+        if options.until_halt && at_halt(sys) {
+            return RunOutcome::Halt(sys.pc());
+        }
+        if sys.cycles() >= end {
+            return RunOutcome::Limit;
+        }
+        // One instruction, with the debugger's breaks checked
+        if let Some(stop) = sys.run_until_break(1) {
+            return RunOutcome::Break(stop);
+        }
+        if options.until_pc == Some(sys.pc()) {
+            return RunOutcome::Pc(sys.pc());
+        }
+    }
+}
+
+fn apply(sys: &mut Mcs4System, action: Action) {
+    match action {
+        Action::Test(level) => sys.set_test_pin(level),
+        Action::RomPort { chip, value } => {
+            match sys.rom.iter_mut().find(|rom| rom.chip_id() == chip) {
+                Some(rom) => rom.set_io_input(value),
+                None => tracing::warn!("stimulus for 4001 chip {}, which is not fitted", chip),
+            }
+        }
+    }
+}
+
+/// Is the instruction at the PC a `JUN` to itself?
+fn at_halt(sys: &Mcs4System) -> bool {
+    let pc = sys.pc() as usize;
+    let rom = sys.rom_image();
+    let (first, second) = (rom[pc], rom[(pc + 1) & 0x0FFF]);
+    first >> 4 == 0x4 && ((first as usize & 0x0F) << 8 | second as usize) == pc
+}
+
+/// Registers, RAM and output ports as JSON
+///
+/// ```text
+/// { "cycles", "pc", "acc", "carry", "registers": [16], "sp", "stack": [3],
+///   "ram": [{ "bank", "chip", "registers": [[16] x4], "status": [[4] x4], "output" }],
+///   "rom_ports": [{ "chip", "output", "input" }] }
+/// ```
+pub fn state_json(sys: &Mcs4System) -> Value {
+    let ram: Vec<Value> = sys
+        .ram
+        .iter()
+        .map(|ram| {
+            let registers: Vec<Vec<u8>> = (0..4)
+                .map(|reg| (0..16).map(|c| ram.read_direct(reg, c)).collect())
+                .collect();
+            let status: Vec<Vec<u8>> = (0..4)
+                .map(|reg| (0..4).map(|i| ram.read_status(reg, i)).collect())
+                .collect();
+            json!({
+                "bank": ram.bank_id,
+                "chip": ram.chip_id,
+                "registers": registers,
+                "status": status,
+                "output": ram.output(),
+            })
+        })
+        .collect();
+    let rom_ports: Vec<Value> = sys
+        .rom
+        .iter()
+        .map(|rom| {
+            json!({
+                "chip": rom.chip_id(),
+                "output": rom.io_output(),
+                "input": rom.io_input(),
+            })
+        })
+        .collect();
+    json!({
+        "cycles": sys.cycles(),
+        "pc": sys.pc(),
+        "acc": sys.accumulator(),
+        "carry": sys.carry(),
+        "registers": (0..16).map(|r| sys.register(r)).collect::<Vec<u8>>(),
+        "sp": sys.cpu.registers.stack_pointer(),
+        "stack": sys.cpu.registers.stack(),
+        "ram": ram,
+        "rom_ports": rom_ports,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::Break;
+
+    #[test]
+    fn test_parse_stimulus() {
+        let stimulus = Stimulus::parse(
+            "# comment\n\
+             100 port 2 0x5  # trailing\n\
+             \n\
+             0x10 test 1\n\
+             100 test 0\n",
+        )
+        .unwrap();
+        assert_eq!(
+            stimulus.events(),
+            [
+                Event {
+                    cycle: 16,
+                    action: Action::Test(true)
+                },
+                Event {
+                    cycle: 100,
+                    action: Action::RomPort { chip: 2, value: 5 }
+                },
+                Event {
+                    cycle: 100,
+                    action: Action::Test(false)
+                },
+            ]
+        );
+
+        let err = Stimulus::parse("0 test 1\n5 port 16 1\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2: 4001 chip ID must be 0-15, found '16'"
+        );
+        assert!(Stimulus::parse("x test 1").is_err());
+        assert!(Stimulus::parse("1 test 2").is_err());
+        assert!(Stimulus::parse("1 hammer").is_err());
+    }
+
+    #[test]
+    fn test_run_with_stimulus() {
+        let mut sys = Mcs4System::standard();
+        // 000: JCN T, 000   wait for TEST low
+        // 002: FIM P0, 0x00 ; SRC P0
+        // 005: RDR          ; WRM       copy port 0 to RAM
+        // 007: JUN 007
+        sys.load_rom(&[0x11, 0x00, 0x20, 0x00, 0x21, 0xEA, 0xE0, 0x40, 0x07]);
+        let stimulus = Stimulus::parse("0 test 1\n0 port 0 9\n50 test 0\n").unwrap();
+
+        let mut options = RunOptions {
+            max_cycles: 40,
+            until_pc: None,
+            until_halt: true,
+        };
+        assert_eq!(run(&mut sys, &stimulus, &options), RunOutcome::Limit);
+        assert_eq!(sys.pc(), 0x000);
+
+        options.max_cycles = 1000;
+        assert_eq!(run(&mut sys, &stimulus, &options), RunOutcome::Halt(0x007));
+        assert!(sys.cycles() >= 50 && sys.cycles() < 60);
+        let state = state_json(&sys);
+        assert_eq!(state["pc"], 7);
+        assert_eq!(state["acc"], 9);
+        assert_eq!(state["ram"][0]["registers"][0][0], 9);
+        assert_eq!(state["rom_ports"][0]["input"], 9);
+
+        sys.reset();
+        options.until_pc = Some(0x005);
+        assert_eq!(
+            run(&mut sys, &Stimulus::default(), &options),
+            RunOutcome::Pc(0x005)
+        );
+        sys.reset();
+        options.until_pc = None;
+        let id = sys.debugger_mut().add(Break::address(0x006));
+        match run(&mut sys, &Stimulus::default(), &options) {
+            RunOutcome::Break(stop) => assert_eq!((stop.id, stop.address), (id, 0x006)),
+            other => panic!("expected a break, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! System snapshots
+//!
+//! A [`SystemSnapshot`] holds the complete state of an [`Mcs4System`]: CPU
+//! internals (including a half-finished instruction), every ROM and RAM
+//! chip, the data bus, control lines, clock, cycle counters and execution
+//! mode, and the attached peripherals. Restoring one continues bit-for-bit
+//! where it was taken. Signal transition history (waveform trace) and
+//! breakpoints are not saved.
+//!
+//! Peripherals are kept in memory only: archives hold the chips, and a
+//! snapshot read back from one restores with whatever devices are attached.
+//!
+//! Snapshot archives (`*.mcs4.rkyv`) hold a sequence of snapshots behind a
+//! 16-byte header: the magic `MCS4SNAP`, the format version and the payload
+//! length (both little-endian u32), followed by an rkyv archive of the
+//! snapshots, validated when read.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use rkyv::{AlignedVec, Archive, Deserialize, Serialize};
+
+use crate::mcs4::Mcs4System;
+
+/// Archive format version written by [`to_bytes`]
+///
+/// Bump it whenever a change to any archived type alters the layout.
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+/// File extension for snapshot archives
+pub const SNAPSHOT_EXTENSION: &str = "mcs4.rkyv";
+
+const MAGIC: &[u8; 8] = b"MCS4SNAP";
+const HEADER_LEN: usize = 16;
+
+/// Error raised while reading or writing a snapshot archive
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The file could not be read or written
+    Io(io::Error),
+    /// The data does not start with a snapshot header
+    NotASnapshot,
+    /// The archive was written by an incompatible format version
+    Version { found: u32, supported: u32 },
+    /// The payload is truncated or fails validation
+    Corrupt(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "cannot access snapshot: {}", err),
+            SnapshotError::NotASnapshot => write!(f, "not an MCS-4 snapshot archive"),
+            SnapshotError::Version { found, supported } => write!(
+                f,
+                "snapshot format version {} is not supported (expected {})",
+                found, supported
+            ),
+            SnapshotError::Corrupt(message) => write!(f, "corrupt snapshot: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+/// Complete state of an [`Mcs4System`] at one phase
+#[derive(Clone, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+pub struct SystemSnapshot {
+    system: Mcs4System,
+}
+
+impl SystemSnapshot {
+    pub(crate) fn new(system: Mcs4System) -> Self {
+        Self { system }
+    }
+
+    /// The captured system
+    pub fn system(&self) -> &Mcs4System {
+        &self.system
+    }
+
+    /// A new system in the captured state
+    pub fn into_system(self) -> Mcs4System {
+        self.system
+    }
+
+    /// Machine cycles executed when the snapshot was taken
+    pub fn cycles(&self) -> u64 {
+        self.system.cycles()
+    }
+
+    /// Program counter when the snapshot was taken
+    pub fn pc(&self) -> u16 {
+        self.system.pc()
+    }
+}
+
+/// Encode snapshots as an archive: header followed by the rkyv payload
+pub fn to_bytes(frames: &[SystemSnapshot]) -> Vec<u8> {
+    let payload = rkyv::to_bytes::<_, 4096>(&frames.to_vec())
+        .expect("serializing snapshots to memory cannot fail");
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+/// Decode and validate an archive written by [`to_bytes`]
+pub fn from_bytes(bytes: &[u8]) -> Result<Vec<SystemSnapshot>, SnapshotError> {
+    if bytes.len() < HEADER_LEN || &bytes[..8] != MAGIC {
+        return Err(SnapshotError::NotASnapshot);
+    }
+    let word =
+        |at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+    let version = word(8);
+    if version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::Version {
+            found: version,
+            supported: SNAPSHOT_VERSION,
+        });
+    }
+    let payload = &bytes[HEADER_LEN..];
+    let len = word(12) as usize;
+    if payload.len() != len {
+        return Err(SnapshotError::Corrupt(format!(
+            "payload is {} bytes, header says {}",
+            payload.len(),
+            len
+        )));
+    }
+
+    // The archive must be aligned in memory to be validated
+    let mut aligned = AlignedVec::with_capacity(len);
+    aligned.extend_from_slice(payload);
+    let archived = rkyv::check_archived_root::<Vec<SystemSnapshot>>(&aligned)
+        .map_err(|err| SnapshotError::Corrupt(err.to_string()))?;
+    Ok(archived
+        .deserialize(&mut rkyv::Infallible)
+        .unwrap_or_else(|never| match never {}))
+}
+
+/// Write snapshots to a `*.mcs4.rkyv` archive
+pub fn snapshot_export(path: &Path, frames: &[SystemSnapshot]) -> Result<(), SnapshotError> {
+    fs::write(path, to_bytes(frames))?;
+    Ok(())
+}
+
+/// Read all snapshots from a `*.mcs4.rkyv` archive
+pub fn snapshot_import(path: &Path) -> Result<Vec<SystemSnapshot>, SnapshotError> {
+    from_bytes(&fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcs4::ExecutionMode;
+    use crate::test_util::{sample_system, state};
+    use mcs4_bus::BusCycle;
+
+    #[test]
+    fn test_restore_mid_instruction() {
+        let mut sys = sample_system();
+        // Stop at X2, in the middle of a machine cycle
+        sys.run_cycles(300);
+        for _ in 0..14 {
+            sys.step();
+        }
+        assert_eq!(sys.phase(), BusCycle::X2);
+        let snapshot = sys.snapshot();
+
+        let mut restored = Mcs4System::minimal();
+        restored.add_breakpoint(0x123);
+        restored.restore(&from_bytes(&to_bytes(&[snapshot])).unwrap()[0]);
+        assert_eq!(state(&restored), state(&sys));
+        assert_eq!(restored.phase(), sys.phase());
+
+        for _ in 0..2000 {
+            sys.step();
+            restored.step();
+        }
+        assert_eq!(state(&restored), state(&sys));
+        assert_eq!(restored.cycles(), sys.cycles());
+    }
+
+    #[test]
+    fn test_restore_keeps_mode() {
+        let mut sys = sample_system();
+        sys.set_mode(ExecutionMode::Instruction);
+        sys.run_cycles(50);
+        let mut restored = sys.snapshot().into_system();
+        assert_eq!(restored.mode(), ExecutionMode::Instruction);
+
+        sys.run_cycles(200);
+        restored.run_cycles(200);
+        assert_eq!(state(&restored), state(&sys));
+    }
+
+    #[test]
+    fn test_archive_file() {
+        let mut sys = sample_system();
+        let mut frames = Vec::new();
+        for _ in 0..3 {
+            sys.run_cycles(40);
+            frames.push(sys.snapshot());
+        }
+        let path = std::env::temp_dir().join(format!(
+            "mcs4-snapshot-{}.{}",
+            std::process::id(),
+            SNAPSHOT_EXTENSION
+        ));
+        snapshot_export(&path, &frames).unwrap();
+        let loaded = snapshot_import(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), 3);
+        for (frame, original) in loaded.iter().zip(&frames) {
+            assert_eq!(frame.cycles(), original.cycles());
+            assert_eq!(frame.pc(), original.pc());
+            assert_eq!(state(frame.system()), state(original.system()));
+        }
+    }
+
+    #[test]
+    fn test_archive_errors() {
+        let mut bytes = to_bytes(&[Mcs4System::minimal().snapshot()]);
+        assert!(matches!(
+            from_bytes(b"MCS4"),
+            Err(SnapshotError::NotASnapshot)
+        ));
+
+        let mut future = bytes.clone();
+        future[8] = 99;
+        assert!(matches!(
+            from_bytes(&future),
+            Err(SnapshotError::Version {
+                found: 99,
+                supported: SNAPSHOT_VERSION
+            })
+        ));
+
+        let truncated = &bytes[..bytes.len() - 4];
+        assert!(matches!(
+            from_bytes(truncated),
+            Err(SnapshotError::Corrupt(_))
+        ));
+
+        // Break the root's relative pointer to its frames
+        let end = bytes.len();
+        for byte in &mut bytes[end - 8..] {
+            *byte = 0xFF;
+        }
+        assert!(matches!(from_bytes(&bytes), Err(SnapshotError::Corrupt(_))));
+    }
+}
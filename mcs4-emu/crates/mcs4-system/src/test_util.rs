@@ -0,0 +1,24 @@
+//! Fixtures shared by the unit tests
+
+use crate::loader::RomImage;
+use crate::mcs4::Mcs4System;
+use crate::snapshot::to_bytes;
+
+/// `docs/emulators/sample.hex`
+pub const SAMPLE_HEX: &str = include_str!("../../../../docs/emulators/sample.hex");
+
+pub fn sample_image() -> RomImage {
+    RomImage::from_intel_hex(SAMPLE_HEX).unwrap()
+}
+
+/// A standard system with the sample program loaded
+pub fn sample_system() -> Mcs4System {
+    let mut sys = Mcs4System::standard();
+    sys.load_image(&sample_image()).unwrap();
+    sys
+}
+
+/// Serialized state, for bit-for-bit comparison
+pub fn state(sys: &Mcs4System) -> Vec<u8> {
+    to_bytes(&[sys.snapshot()])
+}
@@ -0,0 +1,148 @@
+//! Busicom 141-PF calculator test
+//!
+//! Small firmwares check the keyboard and printer wiring. The arithmetic
+//! checks on the original firmware are ignored by default; run them with
+//! `BUSICOM_ROM` naming a dump (HEX, S-record or binary) and
+//! `cargo test --test busicom -- --ignored`.
+
+use std::path::Path;
+
+use mcs4_system::busicom::{Busicom, Key};
+
+/// Assemble `source` into a fresh 141-PF
+fn calculator(source: &str) -> Busicom {
+    let program = mcs4_asm::assemble(source).unwrap();
+    let mut calc = Busicom::new();
+    calc.sys.load_rom(&program.to_binary());
+    calc
+}
+
+/// Select keyboard column 6 (`7 4 1 0`) and read its rows
+const KEYBOARD: &str = "
+        fim p0, 0x00    ; 4001 #0: shifter clocks and data
+        src p0
+        ldm 2
+        jms kbit
+        ldm 10
+        xch r4
+zeros:  ldm 0
+        jms kbit
+        isz r4, zeros
+        fim p1, 0x10    ; 4001 #1: keyboard rows
+        src p1
+        rdr
+halt:   jun halt
+
+kbit:   wrr             ; data with the clock low, then raise it
+        iac
+        wrr
+        ldm 0
+        wrr
+        bbl 0
+";
+
+#[test]
+fn test_keyboard_scan() {
+    for (key, rows) in [
+        (None, 0x0),
+        (Some(Key::Digit(4)), 0x2),
+        (Some(Key::Digit(9)), 0x0),
+    ] {
+        let mut calc = calculator(KEYBOARD);
+        if let Some(key) = key {
+            calc.io_mut().set_key(key, true);
+        }
+        calc.run_ms(5.0);
+        assert_eq!(calc.sys.accumulator(), rows, "{:?}", key);
+    }
+}
+
+/// Select printer column 1, wait for sector 7 and strike it in red
+const PRINTER: &str = "
+        fim p0, 0x00    ; 4001 #0: shifter clocks and data
+        src p0
+        ldm 0           ; columns 20 to 2 blank
+        xch r4
+zeros:  ldm 0
+        jms pbit
+        isz r4, zeros
+        ldm 13
+        xch r4
+zeros2: ldm 0
+        jms pbit
+        isz r4, zeros2
+        ldm 2           ; column 1
+        jms pbit
+
+        fim p6, 0x20    ; 4001 #2: drum index
+        src p6
+index:  rdr
+        rar
+        jcn nc, index
+        ldm 9           ; count seven sector pulses
+        xch r5
+sector: jcn t, sector
+pulse:  jcn nt, pulse
+        isz r5, sector
+
+        fim p7, 0x00    ; 4002 #0: printer control
+        src p7
+        ldm 3           ; fire, red
+        wmp
+        ldm 0
+        wmp
+        ldm 8           ; advance paper
+        wmp
+        ldm 0
+        wmp
+halt:   jun halt
+
+pbit:   wrr             ; data with the clock low, then raise it
+        iac
+        iac
+        iac
+        iac
+        wrr
+        ldm 0
+        wrr
+        bbl 0
+";
+
+#[test]
+fn test_printer_drum() {
+    let mut calc = calculator(PRINTER);
+    calc.run_ms(600.0);
+    let printed = calc.printed();
+    assert_eq!(printed.len(), 1);
+    assert_eq!(printed[0].text, format!("{:>15}", "7"));
+    assert!(printed[0].red);
+    assert_eq!(calc.io().hammers(), 1);
+}
+
+/// The number in the digit columns (15 to 1), without the blanks
+/// between columns or a trailing point
+fn number(text: &str) -> String {
+    let digits: String = text.chars().take(15).filter(|c| *c != ' ').collect();
+    digits.trim_end_matches('.').to_string()
+}
+
+#[test]
+#[ignore = "needs BUSICOM_ROM"]
+fn test_firmware_arithmetic() {
+    let path =
+        std::env::var("BUSICOM_ROM").expect("BUSICOM_ROM must name a dump of the 141-PF firmware");
+    for (keys, result) in [("12+34=", "46"), ("7*6=", "42"), ("96/8=", "12")] {
+        let mut calc = Busicom::new();
+        calc.load_rom_file(Path::new(&path)).unwrap();
+        calc.run_ms(500.0);
+        calc.enter(keys).unwrap();
+        calc.run_ms(3000.0);
+        let printed = calc.printed();
+        assert!(
+            printed.iter().any(|line| number(&line.text) == result),
+            "{}: {:?}",
+            keys,
+            printed
+        );
+    }
+}